@@ -67,4 +67,7 @@ struct LfsServerConfig {
 
   // Load shedding config
   16: list<ratelimits.LoadShedLimit> loadshedding_limits;
+
+  // Whether to advertise the resumable ("tus") transfer adapter for uploads.
+  17: bool enable_resumable_uploads;
 }
@@ -99,4 +99,9 @@ struct MononokeRateLimits {
     4: RateLimitBody commits_per_author,
     // A rate limit for the number of files that can be changed
     5: optional RateLimitBody total_file_changes,
+    // If set, rate limiting counters are kept in this file and shared with
+    // every other server on the host that uses the same file, rather than
+    // being local to each process. Ignored by limiters that don't keep
+    // counters of their own.
+    6: optional string shared_counters_path,
 }
@@ -175,6 +175,12 @@ struct RawBlobstoreDisabled {}
 struct RawBlobstoreFilePath {
     1: string path,
 }
+struct RawBlobstoreFiles {
+    1: string path,
+    // Spread blobs over subdirectories (default false). Enabling this for an
+    // existing blobstore moves its blobs, and can't be undone.
+    2: optional bool fanout,
+}
 struct RawBlobstoreManifold {
     1: string manifold_bucket,
     2: string manifold_prefix,
@@ -231,6 +237,27 @@ struct RawBlobstoreS3 {
     // blobstore.
     5: optional i32 num_concurrent_operations,
 }
+// Keeps a bounded cache of blobs on local disk in front of another
+// blobstore, which survives restarts.
+struct RawBlobstoreDiskCache {
+    1: RawBlobstoreConfig blobstore (rust.box),
+    // Directory to keep the cached blobs in.
+    2: string path,
+    // Total size of the cached blobs, beyond which the least recently used
+    // ones are evicted.
+    3: i64 max_size_bytes,
+}
+// Encrypts every blob before storing it in another blobstore.
+struct RawBlobstoreEncrypted {
+    1: RawBlobstoreConfig blobstore (rust.box),
+    // JSON file with the keys to encrypt blobs with, see encryptedblob.
+    2: string keyring_path,
+}
+// Stores a checksum with every blob in another blobstore, and checks it when
+// the blob is read back.
+struct RawBlobstoreChecksummed {
+    1: RawBlobstoreConfig blobstore (rust.box),
+}
 
 // Configuration for a single blobstore. These are intended to be defined in a
 // separate blobstore.toml config file, and then referenced by name from a
@@ -240,7 +267,7 @@ struct RawBlobstoreS3 {
 // for a blobstore, it must remain unchanged.
 union RawBlobstoreConfig {
     1: RawBlobstoreDisabled disabled,
-    2: RawBlobstoreFilePath blob_files,
+    2: RawBlobstoreFiles blob_files,
     // 3: deleted
     4: RawBlobstoreFilePath blob_sqlite,
     5: RawBlobstoreManifold manifold,
@@ -250,6 +277,9 @@ union RawBlobstoreConfig {
     9: RawBlobstoreLogging logging,
     10: RawBlobstorePack pack,
     11: RawBlobstoreS3 s3,
+    12: RawBlobstoreDiskCache disk_cache,
+    13: RawBlobstoreEncrypted encrypted,
+    14: RawBlobstoreChecksummed checksummed,
 }
 
 // A write-mostly blobstore is one that is not read from in normal operation.
@@ -274,14 +304,23 @@ struct RawDbLocal {
     1: string local_db_path,
 }
 
+enum RawDbType {
+    MYSQL = 0,
+    POSTGRES = 1,
+}
+
 struct RawDbRemote {
     1: string db_address,
     // 2: deleted
+    // Defaults to MYSQL
+    3: optional RawDbType db_type,
 }
 
 struct RawDbShardedRemote {
     1: string shard_map,
     2: i32 shard_num,
+    // Defaults to MYSQL
+    3: optional RawDbType db_type,
 }
 
 union RawDbShardableRemote {
@@ -435,6 +474,41 @@ struct RawCommonConfig {
     5: bool enable_http_control_api,
 
     6: RawRedactionConfig redaction_config,
+
+    // Proxy that terminates TLS in front of Mononoke and forwards client
+    // details in headers on HTTP connections. Only used for peers that have
+    // one of its identities.
+    7: optional RawTrustedProxyConfig trusted_proxy,
+
+    // File in which time window counters are kept, to share them with the
+    // other Mononoke processes on the host. It should be on a tmpfs such as
+    // /dev/shm. If unset, each process keeps its own counters.
+    8: optional string time_window_counters_path,
+
+    // Region this deployment of Mononoke runs in. It is the destination
+    // region of the QPS counters, and defaults to "local".
+    9: optional string region,
+
+    // Directory that megarepo configs are kept in outside of fbcode. It may
+    // be shared by any number of Mononoke processes.
+    10: optional string megarepo_configs_path,
+}
+
+struct RawTrustedProxyConfig {
+    // Header with the client's certificate as PEM. It may be percent-encoded,
+    // as with nginx's $ssl_client_escaped_cert.
+    1: string client_cert_header,
+    // Header with the client's address (default: x-forwarded-for). If it
+    // lists several addresses, only the last, added by the proxy, is used.
+    2: optional string client_ip_header,
+    // Identities of the proxy, from the certificate it connects with. There
+    // must be at least one.
+    3: optional list<RawTrustedProxyIdentity> identities,
+}
+
+struct RawTrustedProxyIdentity {
+    1: string identity_type,
+    2: string identity_data,
 }
 
 struct RawCacheWarmupConfig {
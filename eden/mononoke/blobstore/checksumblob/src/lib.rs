@@ -0,0 +1,322 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+#![deny(warnings)]
+
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::{BufMut, Bytes, BytesMut};
+use stats::prelude::*;
+
+use blobstore::{
+    Blobstore, BlobstoreGetData, BlobstoreIsPresent, BlobstorePutOps, BlobstoreWithLink, ErrorKind,
+    OverwriteStatus, PutBehaviour,
+};
+use context::CoreContext;
+use mononoke_types::BlobstoreBytes;
+
+// Checksummed blobs are stored as | magic | version | Blake3 digest | value |
+const MAGIC: &[u8; 5] = b"MNCKS";
+const VERSION: u8 = 1;
+const DIGEST_LEN: usize = blake3::OUT_LEN;
+const HEADER_LEN: usize = MAGIC.len() + 1 + DIGEST_LEN;
+
+define_stats! {
+    prefix = "mononoke.blobstore.checksumblob";
+    mismatch: timeseries("mismatch"; Rate, Sum),
+    unverified: timeseries("unverified"; Rate, Sum),
+    repaired: timeseries("repaired"; Rate, Sum),
+}
+
+fn seal(value: BlobstoreBytes) -> BlobstoreBytes {
+    let value = value.as_bytes();
+    let mut sealed = BytesMut::with_capacity(HEADER_LEN + value.len());
+    sealed.put_slice(MAGIC);
+    sealed.put_u8(VERSION);
+    sealed.put_slice(blake3::hash(value).as_bytes());
+    sealed.put_slice(value);
+    BlobstoreBytes::from_bytes(sealed.freeze())
+}
+
+/// Returns the value stored in a blob, checking it against its digest.
+/// Blobs stored before checksumming was enabled have no digest, and are
+/// returned as they are.
+fn unseal(key: &str, sealed: &Bytes) -> Result<Bytes> {
+    if !sealed.starts_with(MAGIC) {
+        STATS::unverified.add_value(1);
+        return Ok(sealed.clone());
+    }
+    // Past the magic, anything unexpected is as likely to be corruption as
+    // a mismatched digest is.
+    let verified = sealed.len() >= HEADER_LEN && sealed[MAGIC.len()] == VERSION && {
+        let mut digest = [0; DIGEST_LEN];
+        digest.copy_from_slice(&sealed[MAGIC.len() + 1..HEADER_LEN]);
+        // Comparing the Hash is constant time
+        blake3::Hash::from(digest) == blake3::hash(&sealed[HEADER_LEN..])
+    };
+    if verified {
+        Ok(sealed.slice(HEADER_LEN..))
+    } else {
+        STATS::mismatch.add_value(1);
+        Err(ErrorKind::ChecksumMismatch(key.to_string()).into())
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ChecksumOptions {
+    /// Whether a put that is prevented because the blob is present checks
+    /// the stored copy, and replaces it if it is corrupt. This costs a get
+    /// per prevented put, so it is meant for the blobstore healer, whose puts
+    /// are to blobstores that may hold a corrupt copy.
+    pub repair_on_put: bool,
+}
+
+/// A layer over an existing blobstore that stores a Blake3 digest with every
+/// blob, and checks it on every get, so that corruption of the stored blobs
+/// is found when they are read rather than by a separate validation pass.
+/// Blobs that fail the check are an `ErrorKind::ChecksumMismatch` error.
+///
+/// With `ChecksumOptions::repair_on_put`, putting a blob whose stored copy is
+/// corrupt replaces it, even if the put behaviour is not to overwrite, so
+/// that corrupt blobs can be healed from good copies elsewhere.
+#[derive(Debug)]
+pub struct ChecksumBlob<T> {
+    inner: T,
+    options: ChecksumOptions,
+}
+
+impl<T> ChecksumBlob<T> {
+    pub fn new(inner: T, options: ChecksumOptions) -> Self {
+        Self { inner, options }
+    }
+}
+
+impl<T: std::fmt::Display> std::fmt::Display for ChecksumBlob<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ChecksumBlob<{}>", &self.inner)
+    }
+}
+
+impl<T: BlobstorePutOps> ChecksumBlob<T> {
+    async fn overwrite_if_corrupt(
+        &self,
+        ctx: &CoreContext,
+        key: String,
+        sealed: BlobstoreBytes,
+        status: OverwriteStatus,
+    ) -> Result<OverwriteStatus> {
+        if !self.options.repair_on_put || status != OverwriteStatus::Prevented {
+            return Ok(status);
+        }
+        let is_good = match self.inner.get(ctx, &key).await? {
+            Some(data) => unseal(&key, data.as_raw_bytes()).is_ok(),
+            // Gone since the put, so not ours to restore
+            None => true,
+        };
+        if is_good {
+            return Ok(status);
+        }
+        let status = self
+            .inner
+            .put_explicit(ctx, key, sealed, PutBehaviour::Overwrite)
+            .await?;
+        STATS::repaired.add_value(1);
+        Ok(status)
+    }
+}
+
+#[async_trait]
+impl<T: BlobstorePutOps> Blobstore for ChecksumBlob<T> {
+    async fn get<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: &'a str,
+    ) -> Result<Option<BlobstoreGetData>> {
+        let data = match self.inner.get(ctx, key).await? {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+        let value = unseal(key, data.as_raw_bytes())?;
+        Ok(Some(BlobstoreGetData::new(
+            data.as_meta().clone(),
+            BlobstoreBytes::from_bytes(value),
+        )))
+    }
+
+    async fn is_present<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: &'a str,
+    ) -> Result<BlobstoreIsPresent> {
+        self.inner.is_present(ctx, key).await
+    }
+
+    async fn put<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: String,
+        value: BlobstoreBytes,
+    ) -> Result<()> {
+        BlobstorePutOps::put_with_status(self, ctx, key, value).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<T: BlobstorePutOps> BlobstorePutOps for ChecksumBlob<T> {
+    async fn put_explicit<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: String,
+        value: BlobstoreBytes,
+        put_behaviour: PutBehaviour,
+    ) -> Result<OverwriteStatus> {
+        let sealed = seal(value);
+        let status = self
+            .inner
+            .put_explicit(ctx, key.clone(), sealed.clone(), put_behaviour)
+            .await?;
+        self.overwrite_if_corrupt(ctx, key, sealed, status).await
+    }
+
+    async fn put_with_status<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: String,
+        value: BlobstoreBytes,
+    ) -> Result<OverwriteStatus> {
+        let sealed = seal(value);
+        let status = self
+            .inner
+            .put_with_status(ctx, key.clone(), sealed.clone())
+            .await?;
+        self.overwrite_if_corrupt(ctx, key, sealed, status).await
+    }
+}
+
+#[async_trait]
+impl<T: BlobstoreWithLink> BlobstoreWithLink for ChecksumBlob<T> {
+    async fn link<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        existing_key: &'a str,
+        link_key: String,
+    ) -> Result<()> {
+        self.inner.link(ctx, existing_key, link_key).await
+    }
+
+    async fn unlink<'a>(&'a self, ctx: &'a CoreContext, key: &'a str) -> Result<()> {
+        self.inner.unlink(ctx, key).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use fbinit::FacebookInit;
+    use memblob::Memblob;
+
+    // Flips a bit in the stored value, as bit-rot would
+    async fn corrupt(inner: &Memblob, ctx: &CoreContext, key: &str) -> Result<()> {
+        let data = inner.get(ctx, key).await?.unwrap();
+        let mut bytes = data.into_raw_bytes().to_vec();
+        *bytes.last_mut().unwrap() ^= 1;
+        inner
+            .put_explicit(
+                ctx,
+                key.to_string(),
+                BlobstoreBytes::from_bytes(bytes),
+                PutBehaviour::Overwrite,
+            )
+            .await?;
+        Ok(())
+    }
+
+    fn is_mismatch(error: &anyhow::Error) -> bool {
+        matches!(
+            error.downcast_ref::<ErrorKind>(),
+            Some(ErrorKind::ChecksumMismatch(_))
+        )
+    }
+
+    #[fbinit::test]
+    async fn test_checksum(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let inner = Arc::new(Memblob::default());
+        let blobstore = ChecksumBlob::new(inner.clone(), ChecksumOptions::default());
+
+        let value = BlobstoreBytes::from_bytes("value");
+        blobstore
+            .put(&ctx, "key".to_string(), value.clone())
+            .await?;
+        assert_eq!(
+            blobstore.get(&ctx, "key").await?.map(|d| d.into_bytes()),
+            Some(value.clone())
+        );
+        assert_eq!(blobstore.get(&ctx, "missing").await?, None);
+
+        corrupt(&inner, &ctx, "key").await?;
+        let error = blobstore.get(&ctx, "key").await.unwrap_err();
+        assert!(is_mismatch(&error), "{:?}", error);
+
+        // Blobs from before checksumming are returned unchecked.
+        inner.put(&ctx, "plain".to_string(), value.clone()).await?;
+        assert_eq!(
+            blobstore.get(&ctx, "plain").await?.map(|d| d.into_bytes()),
+            Some(value)
+        );
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_put_repairs(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let inner = Arc::new(Memblob::new(PutBehaviour::IfAbsent));
+        let value = BlobstoreBytes::from_bytes("value");
+
+        // Puts only check the stored copy when asked to
+        let blobstore = ChecksumBlob::new(inner.clone(), ChecksumOptions::default());
+        blobstore
+            .put(&ctx, "key".to_string(), value.clone())
+            .await?;
+        corrupt(&inner, &ctx, "key").await?;
+        assert_eq!(
+            blobstore
+                .put_with_status(&ctx, "key".to_string(), value.clone())
+                .await?,
+            OverwriteStatus::Prevented
+        );
+        assert!(blobstore.get(&ctx, "key").await.is_err());
+
+        let blobstore = ChecksumBlob::new(
+            inner.clone(),
+            ChecksumOptions {
+                repair_on_put: true,
+            },
+        );
+        assert_ne!(
+            blobstore
+                .put_with_status(&ctx, "key".to_string(), value.clone())
+                .await?,
+            OverwriteStatus::Prevented
+        );
+        assert_eq!(
+            blobstore.get(&ctx, "key").await?.map(|d| d.into_bytes()),
+            Some(value.clone())
+        );
+        assert_eq!(
+            blobstore
+                .put_with_status(&ctx, "key".to_string(), value)
+                .await?,
+            OverwriteStatus::Prevented
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,351 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+#![deny(warnings)]
+
+mod store;
+
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use slog::warn;
+use stats::prelude::*;
+
+use blobstore::{
+    Blobstore, BlobstoreGetData, BlobstoreIsPresent, BlobstoreMetadata, BlobstorePutOps,
+    BlobstoreWithLink, OverwriteStatus, PutBehaviour,
+};
+use context::CoreContext;
+use mononoke_types::BlobstoreBytes;
+
+use crate::store::{DiskStore, Lookup};
+
+define_stats! {
+    prefix = "mononoke.blobstore.diskcache";
+    get_hit: timeseries("get_hit"; Rate, Sum),
+    get_miss: timeseries("get_miss"; Rate, Sum),
+    get_corrupt: timeseries("get_corrupt"; Rate, Sum),
+    presence_hit: timeseries("presence_hit"; Rate, Sum),
+    presence_miss: timeseries("presence_miss"; Rate, Sum),
+    put_err: timeseries("put_err"; Rate, Sum),
+    evicted: timeseries("evicted"; Rate, Sum),
+    size_bytes: singleton_counter("size_bytes"),
+}
+
+/// A blobstore that keeps a bounded cache of blobs on local disk in front of
+/// another blobstore, so that the cache survives restarts. Blobs are cached
+/// when they are fetched from or written to the inner blobstore, and the
+/// least recently used ones are evicted when the cache grows too large.
+///
+/// As a key is only ever associated with one value, cached blobs are only
+/// invalidated when their key is unlinked. Failing to use the cache is not an
+/// error: it falls back to the inner blobstore.
+#[derive(Clone)]
+pub struct DiskCacheBlob<T> {
+    inner: T,
+    store: Arc<DiskStore>,
+}
+
+impl<T> DiskCacheBlob<T> {
+    /// Cache blobs in `path`, using at most `max_size` bytes for them.
+    pub fn new(inner: T, path: &Path, max_size: u64) -> Result<Self> {
+        let store = DiskStore::open(path, max_size)?;
+        STATS::size_bytes.set_value(store.size() as i64);
+        Ok(Self {
+            inner,
+            store: Arc::new(store),
+        })
+    }
+
+    async fn cache_get(&self, ctx: &CoreContext, key: &str) -> Option<BlobstoreGetData> {
+        let store = self.store.clone();
+        let key_owned = key.to_string();
+        let lookup = tokio::task::spawn_blocking(move || store.get(&key_owned)).await;
+        match lookup {
+            Ok(Ok(Lookup::Hit(data))) => {
+                STATS::get_hit.add_value(1);
+                return Some(data);
+            }
+            Ok(Ok(Lookup::Miss)) => {}
+            Ok(Ok(Lookup::Corrupt)) => {
+                STATS::get_corrupt.add_value(1);
+                warn!(ctx.logger(), "Removed corrupt disk cache entry for {}", key);
+            }
+            Ok(Err(e)) => warn!(
+                ctx.logger(),
+                "Failed to read {} from disk cache: {:?}", key, e
+            ),
+            Err(e) => warn!(
+                ctx.logger(),
+                "Failed to read {} from disk cache: {:?}", key, e
+            ),
+        }
+        STATS::get_miss.add_value(1);
+        None
+    }
+
+    async fn cache_remove(&self, ctx: &CoreContext, key: &str) {
+        let store = self.store.clone();
+        let key_owned = key.to_string();
+        let remove = tokio::task::spawn_blocking(move || {
+            store.remove(&key_owned);
+            store.size()
+        })
+        .await;
+        match remove {
+            Ok(size) => STATS::size_bytes.set_value(size as i64),
+            Err(e) => warn!(
+                ctx.logger(),
+                "Failed to remove {} from disk cache: {:?}", key, e
+            ),
+        }
+    }
+
+    async fn cache_put(&self, ctx: &CoreContext, key: String, data: BlobstoreGetData) {
+        let store = self.store.clone();
+        let put = tokio::task::spawn_blocking(move || {
+            let result = store.put(&key, &data);
+            (key, result, store.size())
+        })
+        .await;
+        match put {
+            Ok((_, Ok(evicted), size)) => {
+                STATS::evicted.add_value(evicted as i64);
+                STATS::size_bytes.set_value(size as i64);
+            }
+            Ok((key, Err(e), _)) => {
+                STATS::put_err.add_value(1);
+                warn!(
+                    ctx.logger(),
+                    "Failed to write {} to disk cache: {:?}", key, e
+                );
+            }
+            Err(e) => {
+                STATS::put_err.add_value(1);
+                warn!(ctx.logger(), "Failed to write to disk cache: {:?}", e);
+            }
+        }
+    }
+}
+
+impl<T: std::fmt::Display> std::fmt::Display for DiskCacheBlob<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "DiskCacheBlob<{}>", &self.inner)
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for DiskCacheBlob<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DiskCacheBlob")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl<T: Blobstore + BlobstorePutOps> Blobstore for DiskCacheBlob<T> {
+    async fn get<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: &'a str,
+    ) -> Result<Option<BlobstoreGetData>> {
+        if let Some(data) = self.cache_get(ctx, key).await {
+            return Ok(Some(data));
+        }
+        let data = self.inner.get(ctx, key).await?;
+        if let Some(data) = &data {
+            self.cache_put(ctx, key.to_string(), data.clone()).await;
+        }
+        Ok(data)
+    }
+
+    async fn is_present<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: &'a str,
+    ) -> Result<BlobstoreIsPresent> {
+        if self.store.contains(key) {
+            STATS::presence_hit.add_value(1);
+            return Ok(BlobstoreIsPresent::Present);
+        }
+        STATS::presence_miss.add_value(1);
+        self.inner.is_present(ctx, key).await
+    }
+
+    async fn put<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: String,
+        value: BlobstoreBytes,
+    ) -> Result<()> {
+        BlobstorePutOps::put_with_status(self, ctx, key, value).await?;
+        Ok(())
+    }
+}
+
+impl<T: BlobstorePutOps> DiskCacheBlob<T> {
+    async fn put_impl<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: String,
+        value: BlobstoreBytes,
+        put_behaviour: Option<PutBehaviour>,
+    ) -> Result<OverwriteStatus> {
+        let status = if let Some(put_behaviour) = put_behaviour {
+            self.inner
+                .put_explicit(ctx, key.clone(), value.clone(), put_behaviour)
+                .await?
+        } else {
+            self.inner
+                .put_with_status(ctx, key.clone(), value.clone())
+                .await?
+        };
+        // If the put was prevented, the inner blobstore already has the value.
+        if status != OverwriteStatus::Prevented {
+            let data = BlobstoreGetData::new(BlobstoreMetadata::default(), value);
+            self.cache_put(ctx, key, data).await;
+        }
+        Ok(status)
+    }
+}
+
+#[async_trait]
+impl<T: BlobstorePutOps> BlobstorePutOps for DiskCacheBlob<T> {
+    async fn put_explicit<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: String,
+        value: BlobstoreBytes,
+        put_behaviour: PutBehaviour,
+    ) -> Result<OverwriteStatus> {
+        self.put_impl(ctx, key, value, Some(put_behaviour)).await
+    }
+
+    async fn put_with_status<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: String,
+        value: BlobstoreBytes,
+    ) -> Result<OverwriteStatus> {
+        self.put_impl(ctx, key, value, None).await
+    }
+}
+
+#[async_trait]
+impl<T: BlobstoreWithLink> BlobstoreWithLink for DiskCacheBlob<T> {
+    async fn link<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        existing_key: &'a str,
+        link_key: String,
+    ) -> Result<()> {
+        self.inner.link(ctx, existing_key, link_key).await
+    }
+
+    async fn unlink<'a>(&'a self, ctx: &'a CoreContext, key: &'a str) -> Result<()> {
+        let res = self.inner.unlink(ctx, key).await;
+        // Even if unlinking failed, the key may be gone from the inner
+        // blobstore, so don't keep serving it from the cache.
+        self.cache_remove(ctx, key).await;
+        res
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use fbinit::FacebookInit;
+    use memblob::Memblob;
+    use tempdir::TempDir;
+
+    #[fbinit::test]
+    async fn test_cache(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let dir = TempDir::new("diskcacheblob")?;
+        let inner = Arc::new(Memblob::default());
+        let value = BlobstoreBytes::from_bytes("value");
+
+        let blobstore = DiskCacheBlob::new(inner.clone(), dir.path(), 1 << 20)?;
+        // Blobs that are put are cached.
+        blobstore
+            .put(&ctx, "put".to_string(), value.clone())
+            .await?;
+        // As are blobs that are fetched.
+        inner.put(&ctx, "get".to_string(), value.clone()).await?;
+        assert_eq!(
+            blobstore.get(&ctx, "get").await?.map(|d| d.into_bytes()),
+            Some(value.clone())
+        );
+        assert_eq!(blobstore.get(&ctx, "missing").await?, None);
+        drop(blobstore);
+
+        // The cache survives being reopened, and is used even when the inner
+        // blobstore no longer has the blobs.
+        let blobstore = DiskCacheBlob::new(Memblob::default(), dir.path(), 1 << 20)?;
+        for key in &["put", "get"] {
+            assert!(blobstore.is_present(&ctx, key).await?.fail_if_unsure()?);
+            assert_eq!(
+                blobstore.get(&ctx, key).await?.map(|d| d.into_bytes()),
+                Some(value.clone())
+            );
+        }
+        assert!(!blobstore
+            .is_present(&ctx, "missing")
+            .await?
+            .fail_if_unsure()?);
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_unlink(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let dir = TempDir::new("diskcacheblob")?;
+        let blobstore = DiskCacheBlob::new(Memblob::default(), dir.path(), 1 << 20)?;
+
+        let value = BlobstoreBytes::from_bytes("value");
+        blobstore
+            .put(&ctx, "key".to_string(), value.clone())
+            .await?;
+        blobstore.link(&ctx, "key", "link".to_string()).await?;
+        blobstore.unlink(&ctx, "key").await?;
+
+        assert!(!blobstore.is_present(&ctx, "key").await?.fail_if_unsure()?);
+        assert_eq!(blobstore.get(&ctx, "key").await?, None);
+        assert_eq!(
+            blobstore.get(&ctx, "link").await?.map(|d| d.into_bytes()),
+            Some(value)
+        );
+        assert!(blobstore.unlink(&ctx, "key").await.is_err());
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_put_prevented(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let dir = TempDir::new("diskcacheblob")?;
+        let inner = Memblob::new(PutBehaviour::IfAbsent);
+        let blobstore = DiskCacheBlob::new(inner, dir.path(), 1 << 20)?;
+
+        let first = BlobstoreBytes::from_bytes("first");
+        let second = BlobstoreBytes::from_bytes("second");
+        blobstore
+            .put(&ctx, "key".to_string(), first.clone())
+            .await?;
+        let status = blobstore
+            .put_with_status(&ctx, "key".to_string(), second)
+            .await?;
+        assert_eq!(status, OverwriteStatus::Prevented);
+        assert_eq!(
+            blobstore.get(&ctx, "key").await?.map(|d| d.into_bytes()),
+            Some(first)
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,440 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! The blobs cached on disk. Each blob is a file named after the hash of its
+//! key, which also holds the key and a checksum of the contents, so that a
+//! file that was corrupted or only partially written is never returned.
+//!
+//! Which blobs are cached, and when they were last used, is kept in memory.
+//! When the cache is opened, it is rebuilt from the files, taking the time
+//! they were written as the time they were last used.
+//!
+//! Several processes can share a cache. Each one writes its temporary files
+//! in its own directory, named after its pid, so that cleaning up after an
+//! interrupted write never removes a file another process is still writing.
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use sha2::{Digest, Sha256};
+use tempfile::NamedTempFile;
+
+use blobstore::{BlobstoreGetData, BlobstoreMetadata};
+use mononoke_types::BlobstoreBytes;
+
+const MAGIC: &[u8; 8] = b"MNDCACHE";
+const CHECKSUM_LEN: usize = 32;
+/// Magic, checksum, ctime and key length.
+const HEADER_LEN: usize = MAGIC.len() + CHECKSUM_LEN + 8 + 4;
+/// Stands in for a missing ctime.
+const NO_CTIME: i64 = i64::MIN;
+const TMP_DIR: &str = "tmp";
+
+/// The outcome of looking up a blob.
+pub enum Lookup {
+    Hit(BlobstoreGetData),
+    Miss,
+    /// The file did not hold the blob it should, so it was removed.
+    Corrupt,
+}
+
+struct Entry {
+    size: u64,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Index {
+    entries: HashMap<String, Entry>,
+    /// Names of the cached files, least recently used first.
+    lru: BTreeMap<u64, String>,
+    clock: u64,
+    size: u64,
+}
+
+impl Index {
+    fn touch(&mut self, name: &str) -> bool {
+        let clock = self.clock + 1;
+        match self.entries.get_mut(name) {
+            Some(entry) => {
+                self.lru.remove(&entry.last_used);
+                entry.last_used = clock;
+                self.lru.insert(clock, name.to_string());
+                self.clock = clock;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn insert(&mut self, name: String, size: u64) {
+        self.remove(&name);
+        self.clock += 1;
+        self.lru.insert(self.clock, name.clone());
+        self.entries.insert(
+            name,
+            Entry {
+                size,
+                last_used: self.clock,
+            },
+        );
+        self.size += size;
+    }
+
+    fn remove(&mut self, name: &str) {
+        if let Some(entry) = self.entries.remove(name) {
+            self.lru.remove(&entry.last_used);
+            self.size -= entry.size;
+        }
+    }
+
+    /// Forget the least recently used entries until they fit in `max_size`,
+    /// returning their names.
+    fn evict(&mut self, max_size: u64) -> Vec<String> {
+        let mut evicted = Vec::new();
+        while self.size > max_size {
+            let name = match self.lru.values().next() {
+                Some(name) => name.clone(),
+                None => break,
+            };
+            self.remove(&name);
+            evicted.push(name);
+        }
+        evicted
+    }
+}
+
+pub struct DiskStore {
+    path: PathBuf,
+    tmp: PathBuf,
+    max_size: u64,
+    index: Mutex<Index>,
+}
+
+impl DiskStore {
+    /// Open the cache in `path`, creating it if needed, and evict blobs if
+    /// there are more than `max_size` bytes of them.
+    pub fn open(path: &Path, max_size: u64) -> Result<Self> {
+        let tmp_root = path.join(TMP_DIR);
+        let tmp = tmp_root.join(std::process::id().to_string());
+        fs::create_dir_all(&tmp).with_context(|| format!("Failed to create {}", tmp.display()))?;
+        remove_stale_tmp_dirs(&tmp_root)?;
+
+        let mut files = Vec::new();
+        for dir in fs::read_dir(path)? {
+            let dir = dir?;
+            if dir.file_name() == TMP_DIR || !dir.file_type()?.is_dir() {
+                continue;
+            }
+            for file in fs::read_dir(dir.path())? {
+                let file = file?;
+                let metadata = file.metadata()?;
+                match file.file_name().into_string() {
+                    Ok(name) if is_file_name(&name) => {
+                        files.push((metadata.modified()?, name, metadata.len()))
+                    }
+                    // Not ours, so leave it alone.
+                    _ => {}
+                }
+            }
+        }
+        files.sort();
+
+        let store = Self {
+            path: path.to_path_buf(),
+            tmp,
+            max_size,
+            index: Mutex::new(Index::default()),
+        };
+        {
+            let mut index = store.index.lock().expect("lock poisoned");
+            for (_, name, size) in files {
+                index.insert(name, size);
+            }
+        }
+        store.evict();
+        Ok(store)
+    }
+
+    /// The total size of the cached blobs.
+    pub fn size(&self) -> u64 {
+        self.index.lock().expect("lock poisoned").size
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        let name = file_name(key);
+        self.index
+            .lock()
+            .expect("lock poisoned")
+            .entries
+            .contains_key(&name)
+    }
+
+    pub fn get(&self, key: &str) -> Result<Lookup> {
+        let name = file_name(key);
+        if !self.index.lock().expect("lock poisoned").touch(&name) {
+            return Ok(Lookup::Miss);
+        }
+
+        let path = self.file_path(&name);
+        let mut contents = Vec::new();
+        match File::open(&path).and_then(|mut file| file.read_to_end(&mut contents)) {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                // Evicted while we were looking it up.
+                self.index.lock().expect("lock poisoned").remove(&name);
+                return Ok(Lookup::Miss);
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        match decode(key, contents) {
+            Some(data) => Ok(Lookup::Hit(data)),
+            None => {
+                self.remove_file(&name);
+                Ok(Lookup::Corrupt)
+            }
+        }
+    }
+
+    /// Stop caching the blob for `key`, if it is cached.
+    pub fn remove(&self, key: &str) {
+        self.remove_file(&file_name(key));
+    }
+
+    /// Cache a blob, replacing any cached value for its key, and evict the
+    /// least recently used blobs to make room for it. Returns how many blobs
+    /// were evicted.
+    pub fn put(&self, key: &str, data: &BlobstoreGetData) -> Result<usize> {
+        let contents = encode(key, data);
+        let size = contents.len() as u64;
+        if size > self.max_size {
+            return Ok(0);
+        }
+
+        let name = file_name(key);
+        let path = self.file_path(&name);
+        let dir = path.parent().expect("cache files are in a directory");
+        fs::create_dir_all(dir)?;
+
+        let mut file = NamedTempFile::new_in(&self.tmp)?;
+        file.write_all(&contents)?;
+        file.persist(&path)?;
+
+        self.index.lock().expect("lock poisoned").insert(name, size);
+        Ok(self.evict())
+    }
+
+    fn evict(&self) -> usize {
+        let evicted = self
+            .index
+            .lock()
+            .expect("lock poisoned")
+            .evict(self.max_size);
+        for name in &evicted {
+            let _ = fs::remove_file(self.file_path(name));
+        }
+        evicted.len()
+    }
+
+    fn remove_file(&self, name: &str) {
+        self.index.lock().expect("lock poisoned").remove(name);
+        let _ = fs::remove_file(self.file_path(name));
+    }
+
+    fn file_path(&self, name: &str) -> PathBuf {
+        // Spread the files over 256 directories, so that none gets too large.
+        self.path.join(&name[..2]).join(name)
+    }
+}
+
+/// Remove the temporary directories of processes that are no longer running,
+/// along with whatever their interrupted writes left behind.
+fn remove_stale_tmp_dirs(tmp_root: &Path) -> Result<()> {
+    for dir in fs::read_dir(tmp_root)? {
+        let dir = dir?;
+        let pid = match dir.file_name().to_str().and_then(|n| n.parse::<u32>().ok()) {
+            Some(pid) => pid,
+            // Not ours, so leave it alone.
+            None => continue,
+        };
+        if !is_running(pid) {
+            fs::remove_dir_all(dir.path())
+                .with_context(|| format!("Failed to clean up {}", dir.path().display()))?;
+        }
+    }
+    Ok(())
+}
+
+fn is_running(pid: u32) -> bool {
+    // Signal 0 only checks whether the process exists. EPERM means it does,
+    // but belongs to somebody else.
+    let res = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    res == 0 || io::Error::last_os_error().raw_os_error() != Some(libc::ESRCH)
+}
+
+fn file_name(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+/// Whether this is the name of a cache file, i.e. a hex SHA-256 hash.
+fn is_file_name(name: &str) -> bool {
+    name.len() == 64 && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// A cache file is the magic, the checksum of everything after it, the ctime,
+/// the key's length, the key and the value.
+fn encode(key: &str, data: &BlobstoreGetData) -> Vec<u8> {
+    let value = data.as_raw_bytes();
+    let mut contents = Vec::with_capacity(HEADER_LEN + key.len() + value.len());
+    contents.extend_from_slice(MAGIC);
+    contents.extend_from_slice(&[0; CHECKSUM_LEN]);
+    let ctime = data.as_meta().ctime().unwrap_or(NO_CTIME);
+    contents.extend_from_slice(&ctime.to_be_bytes());
+    contents.extend_from_slice(&(key.len() as u32).to_be_bytes());
+    contents.extend_from_slice(key.as_bytes());
+    contents.extend_from_slice(value);
+
+    let checksum_start = MAGIC.len();
+    let checksum = Sha256::digest(&contents[checksum_start + CHECKSUM_LEN..]);
+    contents[checksum_start..checksum_start + CHECKSUM_LEN].copy_from_slice(&checksum);
+    contents
+}
+
+fn decode(key: &str, contents: Vec<u8>) -> Option<BlobstoreGetData> {
+    if contents.len() < HEADER_LEN || !contents.starts_with(MAGIC) {
+        return None;
+    }
+    let checksum_start = MAGIC.len();
+    let body_start = checksum_start + CHECKSUM_LEN;
+    if Sha256::digest(&contents[body_start..]).as_slice() != &contents[checksum_start..body_start] {
+        return None;
+    }
+
+    let ctime = i64::from_be_bytes(contents[body_start..body_start + 8].try_into().ok()?);
+    let key_len = u32::from_be_bytes(contents[body_start + 8..HEADER_LEN].try_into().ok()?);
+    let value_start = HEADER_LEN + key_len as usize;
+    // Different keys could hash to the same file name.
+    if contents.get(HEADER_LEN..value_start)? != key.as_bytes() {
+        return None;
+    }
+
+    let ctime = if ctime == NO_CTIME { None } else { Some(ctime) };
+    let value = Bytes::from(contents).slice(value_start..);
+    Some(BlobstoreGetData::new(
+        BlobstoreMetadata::new(ctime, None),
+        BlobstoreBytes::from_bytes(value),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use tempdir::TempDir;
+
+    fn blob(value: &'static str, ctime: Option<i64>) -> BlobstoreGetData {
+        BlobstoreGetData::new(
+            BlobstoreMetadata::new(ctime, None),
+            BlobstoreBytes::from_bytes(value),
+        )
+    }
+
+    fn get(store: &DiskStore, key: &str) -> Option<BlobstoreGetData> {
+        match store.get(key).unwrap() {
+            Lookup::Hit(data) => Some(data),
+            Lookup::Miss | Lookup::Corrupt => None,
+        }
+    }
+
+    #[test]
+    fn test_encoding() {
+        let data = blob("value", Some(123));
+        let contents = encode("key", &data);
+        assert_eq!(decode("key", contents.clone()), Some(data));
+        assert_eq!(decode("other", contents.clone()), None);
+
+        let data = blob("", None);
+        assert_eq!(decode("key", encode("key", &data)), Some(data));
+
+        for i in 0..contents.len() {
+            let mut corrupted = contents.clone();
+            corrupted[i] ^= 1;
+            assert_eq!(decode("key", corrupted), None);
+        }
+        assert_eq!(decode("key", contents[..contents.len() - 1].to_vec()), None);
+    }
+
+    #[test]
+    fn test_eviction() -> Result<()> {
+        let dir = TempDir::new("diskcacheblob")?;
+        let size = encode("key0", &blob("value0", None)).len() as u64;
+        let store = DiskStore::open(dir.path(), 3 * size)?;
+
+        for i in 0..3 {
+            let evicted = store.put(&format!("key{}", i), &blob("value", None))?;
+            assert_eq!(evicted, 0);
+        }
+        assert_eq!(store.size(), 3 * size - 3);
+
+        // key0 was used more recently than key1, so key1 is evicted.
+        assert!(get(&store, "key0").is_some());
+        assert_eq!(store.put("key3", &blob("value3", None))?, 1);
+        assert!(!store.contains("key1"));
+        assert_eq!(get(&store, "key1"), None);
+        assert!(store.contains("key0"));
+
+        // Blobs that are larger than the whole cache aren't cached.
+        let large = BlobstoreGetData::from_bytes(vec![0; 3 * size as usize]);
+        assert_eq!(store.put("large", &large)?, 0);
+        assert!(!store.contains("large"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_reopen() -> Result<()> {
+        let dir = TempDir::new("diskcacheblob")?;
+        let store = DiskStore::open(dir.path(), 1 << 20)?;
+        store.put("key", &blob("value", Some(1)))?;
+        store.put("corrupt", &blob("value", None))?;
+        store.put("removed", &blob("value", None))?;
+        store.remove_file(&file_name("removed"));
+        let size = store.size();
+
+        let corrupt = dir
+            .path()
+            .join(&file_name("corrupt")[..2])
+            .join(file_name("corrupt"));
+        let mut contents = fs::read(&corrupt)?;
+        let last = contents.len() - 1;
+        contents[last] ^= 1;
+        fs::write(&corrupt, contents)?;
+        // Writes interrupted by a process that is gone are cleaned up, but
+        // those of running processes are left alone.
+        let dead = dir.path().join(TMP_DIR).join(i32::MAX.to_string());
+        fs::create_dir_all(&dead)?;
+        fs::write(dead.join("partial"), b"partial")?;
+        let running = store.tmp.join("partial");
+        fs::write(&running, b"partial")?;
+
+        let store = DiskStore::open(dir.path(), 1 << 20)?;
+        assert_eq!(store.size(), size);
+        assert!(!dead.exists());
+        assert!(running.exists());
+        assert_eq!(get(&store, "key"), Some(blob("value", Some(1))));
+        assert!(matches!(store.get("corrupt")?, Lookup::Corrupt));
+        assert!(!corrupt.exists());
+        assert!(matches!(store.get("removed")?, Lookup::Miss));
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! The envelope that encrypted blobs are stored in. Each blob is encrypted
+//! with its own random data key using AES-256-GCM, and the data key is
+//! stored alongside it, wrapped (RFC 3394) with a key-encryption key from the
+//! keyring, whose ID is also stored. The layout is:
+//!
+//! | magic | version | key ID length | key ID | wrapped data key | nonce | tag | ciphertext |
+//!
+//! Everything before the tag is authenticated along with the ciphertext, and
+//! so is the blobstore key the blob is stored under, so that the values of two
+//! keys can't be swapped without failing authentication. The blobstore key is
+//! not stored in the envelope: it must be supplied to decrypt the blob.
+
+use anyhow::{anyhow, bail, Result};
+use bytes::Bytes;
+use openssl::aes::{unwrap_key, wrap_key, AesKey};
+use openssl::rand::rand_bytes;
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+
+use crate::keyring::{Key, Keyring, KEY_LEN};
+
+const MAGIC: &[u8; 5] = b"MNENC";
+const VERSION: u8 = 2;
+/// Key wrapping adds 8 bytes to the key.
+const WRAPPED_KEY_LEN: usize = KEY_LEN + 8;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+pub struct Envelope<'a> {
+    pub key_id: &'a str,
+    wrapped_key: &'a [u8],
+    /// The authenticated header, which is everything up to the tag.
+    header: &'a [u8],
+    nonce: &'a [u8],
+    tag: &'a [u8],
+    ciphertext: &'a [u8],
+}
+
+impl<'a> Envelope<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Self> {
+        let mut pos = 0;
+        let mut take = |len: usize| -> Result<&'a [u8]> {
+            let taken = bytes
+                .get(pos..pos + len)
+                .ok_or_else(|| anyhow!("Encrypted blob is truncated"))?;
+            pos += len;
+            Ok(taken)
+        };
+
+        if take(MAGIC.len())? != MAGIC {
+            bail!("Blob is not encrypted");
+        }
+        let version = take(1)?[0];
+        if version != VERSION {
+            bail!("Unsupported encrypted blob version {}", version);
+        }
+        let key_id_len = take(1)?[0] as usize;
+        let key_id = std::str::from_utf8(take(key_id_len)?)
+            .map_err(|_| anyhow!("Encrypted blob has an invalid key ID"))?;
+        let wrapped_key = take(WRAPPED_KEY_LEN)?;
+        let nonce = take(NONCE_LEN)?;
+        let tag = take(TAG_LEN)?;
+        let header_len = MAGIC.len() + 2 + key_id_len + WRAPPED_KEY_LEN + NONCE_LEN;
+
+        Ok(Self {
+            key_id,
+            wrapped_key,
+            header: &bytes[..header_len],
+            nonce,
+            tag,
+            ciphertext: &bytes[header_len + TAG_LEN..],
+        })
+    }
+
+    /// Decrypt the blob stored under `blob_key`.
+    pub fn decrypt(&self, keyring: &Keyring, blob_key: &str) -> Result<Bytes> {
+        let kek = keyring
+            .get(self.key_id)
+            .ok_or_else(|| anyhow!("Blob is encrypted with unknown key {:?}", self.key_id))?;
+        let kek = AesKey::new_decrypt(&kek.0).map_err(|_| anyhow!("Invalid key"))?;
+        let mut data_key = [0; KEY_LEN];
+        unwrap_key(&kek, None, &mut data_key, self.wrapped_key).map_err(|_| {
+            anyhow!(
+                "Failed to unwrap the data key of a blob encrypted with key {:?}",
+                self.key_id
+            )
+        })?;
+
+        let plaintext = decrypt_aead(
+            Cipher::aes_256_gcm(),
+            &data_key,
+            Some(self.nonce),
+            &aad(self.header, blob_key),
+            self.ciphertext,
+            self.tag,
+        )
+        .map_err(|_| anyhow!("Encrypted blob failed authentication"))?;
+        Ok(Bytes::from(plaintext))
+    }
+}
+
+/// The authenticated data is the header followed by the blobstore key. The
+/// header's length is implied by its contents, so the two can't be confused.
+fn aad(header: &[u8], blob_key: &str) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(header.len() + blob_key.len());
+    aad.extend_from_slice(header);
+    aad.extend_from_slice(blob_key.as_bytes());
+    aad
+}
+
+/// Encrypt `plaintext` with the key-encryption key `kek`, whose ID is
+/// `key_id`, to be stored under `blob_key`.
+pub fn encrypt(key_id: &str, kek: &Key, blob_key: &str, plaintext: &[u8]) -> Result<Bytes> {
+    let mut data_key = [0; KEY_LEN];
+    rand_bytes(&mut data_key)?;
+    let mut nonce = [0; NONCE_LEN];
+    rand_bytes(&mut nonce)?;
+
+    let kek = AesKey::new_encrypt(&kek.0).map_err(|_| anyhow!("Invalid key"))?;
+    let mut wrapped_key = [0; WRAPPED_KEY_LEN];
+    wrap_key(&kek, None, &mut wrapped_key, &data_key)
+        .map_err(|_| anyhow!("Failed to wrap the data key"))?;
+
+    let mut envelope = Vec::with_capacity(
+        MAGIC.len() + 2 + key_id.len() + WRAPPED_KEY_LEN + NONCE_LEN + TAG_LEN + plaintext.len(),
+    );
+    envelope.extend_from_slice(MAGIC);
+    envelope.push(VERSION);
+    envelope.push(key_id.len() as u8);
+    envelope.extend_from_slice(key_id.as_bytes());
+    envelope.extend_from_slice(&wrapped_key);
+    envelope.extend_from_slice(&nonce);
+
+    let mut tag = [0; TAG_LEN];
+    let ciphertext = encrypt_aead(
+        Cipher::aes_256_gcm(),
+        &data_key,
+        Some(&nonce),
+        &aad(&envelope, blob_key),
+        plaintext,
+        &mut tag,
+    )?;
+    envelope.extend_from_slice(&tag);
+    envelope.extend_from_slice(&ciphertext);
+    Ok(Bytes::from(envelope))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn keyring(active: &str) -> Keyring {
+        Keyring::new(
+            active,
+            vec![
+                ("old".to_string(), Key([1; KEY_LEN])),
+                ("new".to_string(), Key([2; KEY_LEN])),
+            ],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_roundtrip() -> Result<()> {
+        let keyring = keyring("old");
+        let encrypted = encrypt("old", keyring.active_key(), "key", b"value")?;
+        assert!(!encrypted.windows(5).any(|w| w == b"value"));
+        // Each blob has its own data key.
+        assert_ne!(
+            encrypted,
+            encrypt("old", keyring.active_key(), "key", b"value")?
+        );
+
+        let envelope = Envelope::parse(&encrypted)?;
+        assert_eq!(envelope.key_id, "old");
+        assert_eq!(envelope.decrypt(&keyring, "key")?, Bytes::from("value"));
+        // Old keys can still decrypt after the active key changes.
+        assert_eq!(
+            envelope.decrypt(&self::keyring("new"), "key")?,
+            Bytes::from("value")
+        );
+
+        let empty = encrypt("old", keyring.active_key(), "key", b"")?;
+        assert_eq!(
+            Envelope::parse(&empty)?.decrypt(&keyring, "key")?,
+            Bytes::new()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_tampering() -> Result<()> {
+        let keyring = keyring("old");
+        let encrypted = encrypt("old", keyring.active_key(), "key", b"value")?;
+
+        for i in 0..encrypted.len() {
+            let mut tampered = encrypted.to_vec();
+            tampered[i] ^= 1;
+            let decrypted = Envelope::parse(&tampered).and_then(|e| e.decrypt(&keyring, "key"));
+            assert!(decrypted.is_err(), "tampering with byte {} undetected", i);
+        }
+        for len in 0..encrypted.len() {
+            let decrypted =
+                Envelope::parse(&encrypted[..len]).and_then(|e| e.decrypt(&keyring, "key"));
+            assert!(decrypted.is_err(), "truncation to {} undetected", len);
+        }
+
+        // The key ID is authenticated, so a blob can't claim another key.
+        let mut relabelled = encrypted.to_vec();
+        let key_id_start = MAGIC.len() + 2;
+        relabelled[key_id_start..key_id_start + 3].copy_from_slice(b"new");
+        assert!(Envelope::parse(&relabelled)?
+            .decrypt(&keyring, "key")
+            .is_err());
+
+        // The blob is bound to its blobstore key, so it can't be moved to
+        // another one.
+        assert!(Envelope::parse(&encrypted)?
+            .decrypt(&keyring, "other")
+            .is_err());
+        assert!(Envelope::parse(&encrypted)?
+            .decrypt(&keyring, "key2")
+            .is_err());
+
+        assert!(Envelope::parse(b"plaintext").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+/// The size of the keys, which are AES-256 keys.
+pub const KEY_LEN: usize = 32;
+
+/// A key-encryption key, which encrypts the keys that the blobs are
+/// encrypted with.
+#[derive(Clone)]
+pub struct Key(pub [u8; KEY_LEN]);
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never log the key material.
+        write!(f, "Key(..)")
+    }
+}
+
+/// The keys that blobs are encrypted with, each named by a key ID that is
+/// stored with the blobs it encrypts. New blobs are encrypted with the
+/// active key. To rotate keys, add a new key and make it the active one,
+/// but keep the old keys until every blob has been re-encrypted.
+#[derive(Clone, Debug)]
+pub struct Keyring {
+    active_key_id: String,
+    keys: HashMap<String, Key>,
+}
+
+/// A keyring file is JSON, with the keys in hex:
+///
+/// ```json
+/// {
+///   "active_key": "2021-10",
+///   "keys": {
+///     "2021-09": "<64 hex digits>",
+///     "2021-10": "<64 hex digits>"
+///   }
+/// }
+/// ```
+#[derive(Deserialize)]
+struct RawKeyring {
+    active_key: String,
+    keys: HashMap<String, String>,
+}
+
+impl Keyring {
+    pub fn new(
+        active_key_id: impl Into<String>,
+        keys: impl IntoIterator<Item = (String, Key)>,
+    ) -> Result<Self> {
+        let active_key_id = active_key_id.into();
+        let keys = keys.into_iter().collect::<HashMap<_, _>>();
+        for key_id in keys.keys() {
+            if key_id.is_empty() || key_id.len() > u8::MAX as usize {
+                bail!("Key ID {:?} must be between 1 and 255 bytes long", key_id);
+            }
+        }
+        if !keys.contains_key(&active_key_id) {
+            bail!("Active key {:?} is not in the keyring", active_key_id);
+        }
+        Ok(Self {
+            active_key_id,
+            keys,
+        })
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = std::fs::read(path)
+            .with_context(|| format!("Failed to read keyring {}", path.display()))?;
+        let raw: RawKeyring = serde_json::from_slice(&contents)
+            .with_context(|| format!("Failed to parse keyring {}", path.display()))?;
+
+        let keys = raw
+            .keys
+            .into_iter()
+            .map(|(key_id, hex_key)| {
+                let mut key = [0; KEY_LEN];
+                hex::decode_to_slice(hex_key.trim(), &mut key)
+                    .with_context(|| format!("Key {:?} is not {} bytes of hex", key_id, KEY_LEN))?;
+                Ok((key_id, Key(key)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Self::new(raw.active_key, keys)
+    }
+
+    pub fn active_key_id(&self) -> &str {
+        &self.active_key_id
+    }
+
+    pub fn active_key(&self) -> &Key {
+        &self.keys[&self.active_key_id]
+    }
+
+    pub fn get(&self, key_id: &str) -> Option<&Key> {
+        self.keys.get(key_id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Write;
+
+    #[test]
+    fn test_from_file() -> Result<()> {
+        let mut file = tempfile::NamedTempFile::new()?;
+        write!(
+            file,
+            r#"{{"active_key": "new", "keys": {{"old": "{}", "new": "{}"}}}}"#,
+            "00".repeat(KEY_LEN),
+            "ff".repeat(KEY_LEN),
+        )?;
+        let keyring = Keyring::from_file(file.path())?;
+        assert_eq!(keyring.active_key_id(), "new");
+        assert_eq!(keyring.active_key().0, [0xff; KEY_LEN]);
+        assert_eq!(keyring.get("old").map(|k| k.0), Some([0; KEY_LEN]));
+        assert!(keyring.get("other").is_none());
+        assert_eq!(format!("{:?}", keyring.active_key()), "Key(..)");
+
+        assert!(Keyring::new("missing", vec![("key".to_string(), Key([0; KEY_LEN]))]).is_err());
+        assert!(Keyring::new("", vec![("".to_string(), Key([0; KEY_LEN]))]).is_err());
+
+        let mut file = tempfile::NamedTempFile::new()?;
+        write!(file, r#"{{"active_key": "key", "keys": {{"key": "00"}}}}"#)?;
+        assert!(Keyring::from_file(file.path()).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,284 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+#![deny(warnings)]
+
+mod envelope;
+mod keyring;
+
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use stats::prelude::*;
+
+use blobstore::{
+    Blobstore, BlobstoreGetData, BlobstoreIsPresent, BlobstorePutOps, OverwriteStatus, PutBehaviour,
+};
+use context::CoreContext;
+use mononoke_types::BlobstoreBytes;
+
+use crate::envelope::{encrypt, Envelope};
+pub use crate::keyring::{Key, Keyring, KEY_LEN};
+
+define_stats! {
+    prefix = "mononoke.blobstore.encryptedblob";
+    reencrypted: timeseries("reencrypted"; Rate, Sum),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct EncryptionOptions {
+    /// Whether to re-encrypt blobs that are read and are not encrypted with
+    /// the active key, so that scrubbing all blobs migrates them to it.
+    pub reencrypt: bool,
+}
+
+/// A layer over an existing blobstore that encrypts every blob with the
+/// active key of a keyring, so that the inner blobstore only ever sees
+/// ciphertext.
+///
+/// Each blob is bound to the key it is stored under, so that blobs can't be
+/// swapped in the inner blobstore. As a consequence, keys can't share a blob
+/// through links, and this can't be used under packing.
+#[derive(Debug)]
+pub struct EncryptedBlob<T> {
+    inner: T,
+    keyring: Arc<Keyring>,
+    options: EncryptionOptions,
+}
+
+impl<T> EncryptedBlob<T> {
+    pub fn new(inner: T, keyring: Arc<Keyring>, options: EncryptionOptions) -> Self {
+        Self {
+            inner,
+            keyring,
+            options,
+        }
+    }
+}
+
+impl<T: std::fmt::Display> std::fmt::Display for EncryptedBlob<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "EncryptedBlob<{}>", &self.inner)
+    }
+}
+
+impl<T: BlobstorePutOps> EncryptedBlob<T> {
+    fn encrypt(&self, key: &str, value: BlobstoreBytes) -> Result<BlobstoreBytes> {
+        let encrypted = encrypt(
+            self.keyring.active_key_id(),
+            self.keyring.active_key(),
+            key,
+            value.as_bytes(),
+        )?;
+        Ok(BlobstoreBytes::from_bytes(encrypted))
+    }
+}
+
+#[async_trait]
+impl<T: BlobstorePutOps> Blobstore for EncryptedBlob<T> {
+    async fn get<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: &'a str,
+    ) -> Result<Option<BlobstoreGetData>> {
+        let data = match self.inner.get(ctx, key).await? {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+
+        let (plaintext, key_id) = {
+            let envelope = Envelope::parse(data.as_raw_bytes())
+                .with_context(|| format!("Failed to decrypt blob {}", key))?;
+            let plaintext = envelope
+                .decrypt(&self.keyring, key)
+                .with_context(|| format!("Failed to decrypt blob {}", key))?;
+            (plaintext, envelope.key_id.to_string())
+        };
+        let plaintext = BlobstoreBytes::from_bytes(plaintext);
+
+        if self.options.reencrypt && key_id != self.keyring.active_key_id() {
+            let encrypted = self.encrypt(key, plaintext.clone())?;
+            self.inner
+                .put_explicit(ctx, key.to_string(), encrypted, PutBehaviour::Overwrite)
+                .await
+                .with_context(|| format!("Failed to re-encrypt blob {}", key))?;
+            STATS::reencrypted.add_value(1);
+        }
+
+        Ok(Some(BlobstoreGetData::new(
+            data.as_meta().clone(),
+            plaintext,
+        )))
+    }
+
+    async fn is_present<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: &'a str,
+    ) -> Result<BlobstoreIsPresent> {
+        self.inner.is_present(ctx, key).await
+    }
+
+    async fn put<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: String,
+        value: BlobstoreBytes,
+    ) -> Result<()> {
+        BlobstorePutOps::put_with_status(self, ctx, key, value).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<T: BlobstorePutOps> BlobstorePutOps for EncryptedBlob<T> {
+    async fn put_explicit<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: String,
+        value: BlobstoreBytes,
+        put_behaviour: PutBehaviour,
+    ) -> Result<OverwriteStatus> {
+        let value = self.encrypt(&key, value)?;
+        self.inner
+            .put_explicit(ctx, key, value, put_behaviour)
+            .await
+    }
+
+    async fn put_with_status<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: String,
+        value: BlobstoreBytes,
+    ) -> Result<OverwriteStatus> {
+        let value = self.encrypt(&key, value)?;
+        self.inner.put_with_status(ctx, key, value).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use fbinit::FacebookInit;
+    use memblob::Memblob;
+
+    fn keyring(active: &str) -> Arc<Keyring> {
+        Arc::new(
+            Keyring::new(
+                active,
+                vec![
+                    ("old".to_string(), Key([1; KEY_LEN])),
+                    ("new".to_string(), Key([2; KEY_LEN])),
+                ],
+            )
+            .unwrap(),
+        )
+    }
+
+    async fn key_id(inner: &Memblob, ctx: &CoreContext, key: &str) -> Result<String> {
+        let data = inner.get(ctx, key).await?.unwrap();
+        Ok(Envelope::parse(data.as_raw_bytes())?.key_id.to_string())
+    }
+
+    #[fbinit::test]
+    async fn test_encryption(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let inner = Arc::new(Memblob::default());
+        let blobstore =
+            EncryptedBlob::new(inner.clone(), keyring("old"), EncryptionOptions::default());
+
+        let value = BlobstoreBytes::from_bytes("value");
+        blobstore
+            .put(&ctx, "key".to_string(), value.clone())
+            .await?;
+        assert_eq!(
+            blobstore.get(&ctx, "key").await?.map(|d| d.into_bytes()),
+            Some(value.clone())
+        );
+        assert_ne!(
+            inner.get(&ctx, "key").await?.map(|d| d.into_bytes()),
+            Some(value)
+        );
+        assert_eq!(blobstore.get(&ctx, "missing").await?, None);
+
+        // Blobs that were not encrypted are an error, not silently returned.
+        inner
+            .put(
+                &ctx,
+                "plain".to_string(),
+                BlobstoreBytes::from_bytes("value"),
+            )
+            .await?;
+        assert!(blobstore.get(&ctx, "plain").await.is_err());
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_bound_to_key(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let inner = Arc::new(Memblob::default());
+        let blobstore =
+            EncryptedBlob::new(inner.clone(), keyring("old"), EncryptionOptions::default());
+
+        let value = BlobstoreBytes::from_bytes("value");
+        blobstore
+            .put(&ctx, "key".to_string(), value.clone())
+            .await?;
+
+        // A blob that is moved to another key doesn't decrypt.
+        let encrypted = inner.get(&ctx, "key").await?.unwrap().into_bytes();
+        inner.put(&ctx, "moved".to_string(), encrypted).await?;
+        assert!(blobstore.get(&ctx, "moved").await.is_err());
+        assert_eq!(
+            blobstore.get(&ctx, "key").await?.map(|d| d.into_bytes()),
+            Some(value)
+        );
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_key_rotation(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let inner = Arc::new(Memblob::default());
+        let value = BlobstoreBytes::from_bytes("value");
+
+        let blobstore =
+            EncryptedBlob::new(inner.clone(), keyring("old"), EncryptionOptions::default());
+        blobstore
+            .put(&ctx, "key".to_string(), value.clone())
+            .await?;
+        assert_eq!(key_id(&inner, &ctx, "key").await?, "old");
+
+        // After rotation, old blobs can still be read, but aren't migrated.
+        let blobstore =
+            EncryptedBlob::new(inner.clone(), keyring("new"), EncryptionOptions::default());
+        assert_eq!(
+            blobstore.get(&ctx, "key").await?.map(|d| d.into_bytes()),
+            Some(value.clone())
+        );
+        assert_eq!(key_id(&inner, &ctx, "key").await?, "old");
+
+        // Until they are read with re-encryption.
+        let blobstore = EncryptedBlob::new(
+            inner.clone(),
+            keyring("new"),
+            EncryptionOptions { reencrypt: true },
+        );
+        assert_eq!(
+            blobstore.get(&ctx, "key").await?.map(|d| d.into_bytes()),
+            Some(value.clone())
+        );
+        assert_eq!(key_id(&inner, &ctx, "key").await?, "new");
+        assert_eq!(
+            blobstore.get(&ctx, "key").await?.map(|d| d.into_bytes()),
+            Some(value)
+        );
+        Ok(())
+    }
+}
@@ -7,21 +7,24 @@
 
 use anyhow::{bail, Context, Error};
 use blobstore::{
-    Blobstore, BlobstorePutOps, BlobstoreWithLink, DisabledBlob, ErrorKind, PutBehaviour,
-    DEFAULT_PUT_BEHAVIOUR,
+    Blobstore, BlobstoreEnumerableWithUnlink, BlobstorePutOps, BlobstoreWithLink, DisabledBlob,
+    ErrorKind, PutBehaviour, DEFAULT_PUT_BEHAVIOUR,
 };
 use blobstore_sync_queue::SqlBlobstoreSyncQueue;
 use cacheblob::CachelibBlobstoreOptions;
 use cached_config::ConfigStore;
 use chaosblob::{ChaosBlobstore, ChaosOptions};
+use checksumblob::{ChecksumBlob, ChecksumOptions};
 use delayblob::{DelayOptions, DelayedBlobstore};
+use diskcacheblob::DiskCacheBlob;
+use encryptedblob::{EncryptedBlob, EncryptionOptions, Keyring};
 use fbinit::FacebookInit;
-use fileblob::Fileblob;
+use fileblob::{Fileblob, Layout};
 use futures::future::{self, BoxFuture, FutureExt};
 use futures_watchdog::WatchdogExt;
 use logblob::LogBlob;
 use metaconfig_types::{
-    BlobConfig, BlobstoreId, DatabaseConfig, MultiplexId, MultiplexedStoreType,
+    BlobConfig, BlobstoreId, DatabaseConfig, MultiplexId, MultiplexedStoreType, RemoteDatabaseType,
     ShardableRemoteDatabaseConfig,
 };
 use multiplexedblob::{
@@ -54,6 +57,8 @@ pub struct BlobstoreOptions {
     pub put_behaviour: PutBehaviour,
     pub scrub_options: Option<ScrubOptions>,
     pub sqlblob_mysql_options: MysqlOptions,
+    pub encryption_options: EncryptionOptions,
+    pub checksum_options: ChecksumOptions,
 }
 
 impl BlobstoreOptions {
@@ -80,6 +85,8 @@ impl BlobstoreOptions {
             // These are added via the builder methods
             scrub_options: None,
             sqlblob_mysql_options,
+            encryption_options: EncryptionOptions::default(),
+            checksum_options: ChecksumOptions::default(),
         }
     }
 
@@ -123,6 +130,20 @@ impl BlobstoreOptions {
         }
     }
 
+    pub fn with_reencrypt(self, reencrypt: bool) -> Self {
+        Self {
+            encryption_options: EncryptionOptions { reencrypt },
+            ..self
+        }
+    }
+
+    pub fn with_checksum_repair(self, repair_on_put: bool) -> Self {
+        Self {
+            checksum_options: ChecksumOptions { repair_on_put },
+            ..self
+        }
+    }
+
     pub fn with_scrub_queue_peek_bound(self, queue_peek_bound_secs: u64) -> Self {
         if let Some(mut scrub_options) = self.scrub_options {
             scrub_options.queue_peek_bound = Some(Duration::from_secs(queue_peek_bound_secs));
@@ -189,22 +210,38 @@ pub async fn make_sql_blobstore<'a>(
         )
         .context(ErrorKind::StateOpen),
         Mysql { remote } => {
-            let (tier_name, shard_count) = match remote {
-                ShardableRemoteDatabaseConfig::Unsharded(config) => (config.db_address, None),
+            let (tier_name, shard_count, db_type) = match remote {
+                ShardableRemoteDatabaseConfig::Unsharded(config) => {
+                    (config.db_address, None, config.db_type)
+                }
                 ShardableRemoteDatabaseConfig::Sharded(config) => {
-                    (config.shard_map.clone(), Some(config.shard_num))
+                    (config.shard_map, Some(config.shard_num), config.db_type)
                 }
             };
-            make_sql_blobstore_xdb(
-                fb,
-                tier_name,
-                shard_count,
-                blobstore_options,
-                readonly_storage,
-                blobstore_options.put_behaviour,
-                config_store,
-            )
-            .await
+            match db_type {
+                RemoteDatabaseType::Mysql => {
+                    make_sql_blobstore_xdb(
+                        fb,
+                        tier_name,
+                        shard_count,
+                        blobstore_options,
+                        readonly_storage,
+                        blobstore_options.put_behaviour,
+                        config_store,
+                    )
+                    .await
+                }
+                RemoteDatabaseType::Postgres => {
+                    make_sql_blobstore_postgres(
+                        tier_name,
+                        shard_count,
+                        blobstore_options,
+                        readonly_storage,
+                        config_store,
+                    )
+                    .await
+                }
+            }
         }
         _ => bail!("Not an SQL blobstore"),
     }
@@ -248,6 +285,39 @@ pub async fn make_sql_blobstore_xdb<'a>(
     }
 }
 
+async fn make_sql_blobstore_postgres<'a>(
+    address: String,
+    shard_count: Option<NonZeroUsize>,
+    blobstore_options: &'a BlobstoreOptions,
+    readonly_storage: ReadOnlyStorage,
+    config_store: &'a ConfigStore,
+) -> Result<CountedSqlblob, Error> {
+    let mysql_options = blobstore_options.sqlblob_mysql_options.clone();
+    match shard_count {
+        None => {
+            Sqlblob::with_postgres_unsharded(
+                address,
+                mysql_options,
+                readonly_storage.0,
+                blobstore_options.put_behaviour,
+                config_store,
+            )
+            .await
+        }
+        Some(shard_num) => {
+            Sqlblob::with_postgres(
+                address,
+                shard_num,
+                mysql_options,
+                readonly_storage.0,
+                blobstore_options.put_behaviour,
+                config_store,
+            )
+            .await
+        }
+    }
+}
+
 /// Construct a PackBlob according to the spec; you are responsible for
 /// finding a PackBlob config
 pub async fn make_packblob<'a>(
@@ -327,22 +397,80 @@ async fn make_files_blobstore(
     blobconfig: BlobConfig,
     blobstore_options: &BlobstoreOptions,
 ) -> Result<Fileblob, Error> {
-    if let BlobConfig::Files { path } = blobconfig {
-        Fileblob::create(path.join("blobs"), blobstore_options.put_behaviour)
+    if let BlobConfig::Files { path, fanout } = blobconfig {
+        let layout = if fanout { Layout::FanOut } else { Layout::Flat };
+        Fileblob::create_with_layout(path.join("blobs"), blobstore_options.put_behaviour, layout)
             .context(ErrorKind::StateOpen)
     } else {
         bail!("Not a file blobstore")
     }
 }
 
-async fn make_blobstore_with_link<'a>(
+fn make_blobstore_with_link<'a>(
     fb: FacebookInit,
     blobconfig: BlobConfig,
     readonly_storage: ReadOnlyStorage,
     blobstore_options: &'a BlobstoreOptions,
     logger: &'a Logger,
     config_store: &'a ConfigStore,
-) -> Result<Arc<dyn BlobstoreWithLink>, Error> {
+) -> BoxFuture<'a, Result<Arc<dyn BlobstoreWithLink>, Error>> {
+    // NOTE: This needs to return a BoxFuture because it recurses.
+    async move {
+        use BlobConfig::*;
+        match blobconfig {
+            Sqlite { .. } | Mysql { .. } => make_sql_blobstore(
+                fb,
+                blobconfig,
+                readonly_storage,
+                blobstore_options,
+                config_store,
+            )
+            .watched(logger)
+            .await
+            .map(|store| Arc::new(store) as Arc<dyn BlobstoreWithLink>),
+            Manifold { .. } | ManifoldWithTtl { .. } => {
+                make_manifold_blobstore(fb, blobconfig, blobstore_options)
+                    .watched(logger)
+                    .await
+            }
+            Files { .. } => make_files_blobstore(blobconfig, blobstore_options)
+                .await
+                .map(|store| Arc::new(store) as Arc<dyn BlobstoreWithLink>),
+            // Encrypted blobs are bound to their key, so links can't share them, and every
+            // packed key would store its own copy of the pack.
+            Encrypted { .. } => bail!("Encrypted blobstores can't be packed, put Pack inside them"),
+            Checksummed { blobconfig } => {
+                let store = make_blobstore_with_link(
+                    fb,
+                    *blobconfig,
+                    readonly_storage,
+                    blobstore_options,
+                    logger,
+                    config_store,
+                )
+                .await?;
+                Ok(Arc::new(ChecksumBlob::new(
+                    store,
+                    blobstore_options.checksum_options.clone(),
+                )) as Arc<dyn BlobstoreWithLink>)
+            }
+            _ => bail!("Not a physical blobstore"),
+        }
+    }
+    .boxed()
+}
+
+/// Construct a physical blobstore that can enumerate and unlink its keys, e.g.
+/// for garbage collection; you are responsible for removing any wrapper
+/// stores (such as Pack) from the config first
+pub async fn make_blobstore_enumerable_with_unlink<'a>(
+    fb: FacebookInit,
+    blobconfig: BlobConfig,
+    readonly_storage: ReadOnlyStorage,
+    blobstore_options: &'a BlobstoreOptions,
+    logger: &'a Logger,
+    config_store: &'a ConfigStore,
+) -> Result<Arc<dyn BlobstoreEnumerableWithUnlink>, Error> {
     use BlobConfig::*;
     match blobconfig {
         Sqlite { .. } | Mysql { .. } => make_sql_blobstore(
@@ -354,16 +482,11 @@ async fn make_blobstore_with_link<'a>(
         )
         .watched(logger)
         .await
-        .map(|store| Arc::new(store) as Arc<dyn BlobstoreWithLink>),
-        Manifold { .. } | ManifoldWithTtl { .. } => {
-            make_manifold_blobstore(fb, blobconfig, blobstore_options)
-                .watched(logger)
-                .await
-        }
+        .map(|store| Arc::new(store) as Arc<dyn BlobstoreEnumerableWithUnlink>),
         Files { .. } => make_files_blobstore(blobconfig, blobstore_options)
             .await
-            .map(|store| Arc::new(store) as Arc<dyn BlobstoreWithLink>),
-        _ => bail!("Not a physical blobstore"),
+            .map(|store| Arc::new(store) as Arc<dyn BlobstoreEnumerableWithUnlink>),
+        _ => bail!("Blobstore does not support enumeration with unlink"),
     }
 }
 
@@ -432,14 +555,19 @@ fn make_blobstore_put_ops<'a>(
                 }
                 #[cfg(not(fbcode_build))]
                 {
-                    let _ = (
+                    ::s3blob::S3Blob::new(
                         bucket,
                         keychain_group,
                         region_name,
                         endpoint,
+                        blobstore_options.put_behaviour,
+                        logger,
                         num_concurrent_operations,
-                    );
-                    unimplemented!("This is implemented only for fbcode_build")
+                    )
+                    .watched(logger)
+                    .await
+                    .context(ErrorKind::StateOpen)
+                    .map(|store| Arc::new(store) as Arc<dyn BlobstorePutOps>)?
                 }
             }
 
@@ -506,6 +634,81 @@ fn make_blobstore_put_ops<'a>(
                     });
                 Arc::new(LogBlob::new(store, scuba, scuba_sample_rate)) as Arc<dyn BlobstorePutOps>
             }
+            DiskCache {
+                blobconfig,
+                path,
+                max_size_bytes,
+            } => {
+                needs_wrappers = false;
+                let store = make_blobstore_put_ops(
+                    fb,
+                    *blobconfig,
+                    mysql_options,
+                    readonly_storage,
+                    &blobstore_options,
+                    logger,
+                    config_store,
+                    scrub_handler,
+                    component_sampler,
+                    None,
+                )
+                .watched(logger)
+                .await?;
+
+                Arc::new(
+                    DiskCacheBlob::new(store, &path, max_size_bytes)
+                        .context(ErrorKind::StateOpen)?,
+                ) as Arc<dyn BlobstorePutOps>
+            }
+            Encrypted {
+                blobconfig,
+                keyring_path,
+            } => {
+                needs_wrappers = false;
+                let store = make_blobstore_put_ops(
+                    fb,
+                    *blobconfig,
+                    mysql_options,
+                    readonly_storage,
+                    &blobstore_options,
+                    logger,
+                    config_store,
+                    scrub_handler,
+                    component_sampler,
+                    None,
+                )
+                .watched(logger)
+                .await?;
+
+                let keyring = Keyring::from_file(&keyring_path)?;
+                Arc::new(EncryptedBlob::new(
+                    store,
+                    Arc::new(keyring),
+                    blobstore_options.encryption_options.clone(),
+                )) as Arc<dyn BlobstorePutOps>
+            }
+            Checksummed { blobconfig } => {
+                needs_wrappers = false;
+                let store = make_blobstore_put_ops(
+                    fb,
+                    *blobconfig,
+                    mysql_options,
+                    readonly_storage,
+                    &blobstore_options,
+                    logger,
+                    config_store,
+                    scrub_handler,
+                    component_sampler,
+                    None,
+                )
+                .watched(logger)
+                .await?;
+
+                Arc::new(ChecksumBlob::new(
+                    store,
+                    blobstore_options.checksum_options.clone(),
+                )) as Arc<dyn BlobstorePutOps>
+            }
             Pack { .. } => {
                 // NB packblob does not apply the wrappers internally
                 make_packblob(
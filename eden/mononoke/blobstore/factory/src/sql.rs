@@ -8,13 +8,14 @@
 use anyhow::{bail, Error};
 use fbinit::FacebookInit;
 use metaconfig_types::{
-    LocalDatabaseConfig, MetadataDatabaseConfig, ShardableRemoteDatabaseConfig,
+    LocalDatabaseConfig, MetadataDatabaseConfig, RemoteDatabaseType, ShardableRemoteDatabaseConfig,
 };
 use sql::Connection;
 use sql_construct::{
     SqlConstructFromMetadataDatabaseConfig, SqlShardableConstructFromMetadataDatabaseConfig,
 };
 use sql_ext::{
+    create_postgres_connections_unsharded,
     facebook::{create_mysql_connections_unsharded, MysqlOptions},
     open_sqlite_path, SqlConnections,
 };
@@ -89,13 +90,21 @@ impl MetadataSqlFactory {
                 open_sqlite_path(path.join("sqlite_dbs"), self.readonly.0)
                     .map(|conn| SqlConnections::new_single(Connection::with_sqlite(conn)))
             }
-            MetadataDatabaseConfig::Remote(config) => create_mysql_connections_unsharded(
-                self.fb,
-                self.mysql_options.clone(),
-                label,
-                config.primary.db_address.clone(),
-                self.readonly.0,
-            ),
+            MetadataDatabaseConfig::Remote(config) => match config.primary.db_type {
+                RemoteDatabaseType::Mysql => create_mysql_connections_unsharded(
+                    self.fb,
+                    self.mysql_options.clone(),
+                    label,
+                    config.primary.db_address.clone(),
+                    self.readonly.0,
+                ),
+                RemoteDatabaseType::Postgres => create_postgres_connections_unsharded(
+                    &self.mysql_options,
+                    label,
+                    config.primary.db_address.clone(),
+                    self.readonly.0,
+                ),
+            },
         }
     }
 }
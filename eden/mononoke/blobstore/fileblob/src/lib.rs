@@ -12,11 +12,12 @@ use std::convert::TryFrom;
 use std::fs::create_dir_all;
 use std::ops::RangeBounds;
 use std::path::{Path, PathBuf};
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 use anyhow::{bail, format_err, Result};
 use async_trait::async_trait;
-use percent_encoding::{percent_encode, AsciiSet, CONTROLS};
+use filetime::FileTime;
+use percent_encoding::{percent_decode_str, percent_encode, AsciiSet, CONTROLS};
 
 use blobstore::{
     Blobstore, BlobstoreEnumerationData, BlobstoreGetData, BlobstoreIsPresent, BlobstoreKeyParam,
@@ -24,51 +25,189 @@ use blobstore::{
     PutBehaviour,
 };
 use context::CoreContext;
-use mononoke_types::BlobstoreBytes;
-use tempfile::{NamedTempFile, PersistError};
+use mononoke_types::{hash::Context, BlobstoreBytes};
+use tempfile::{Builder, PersistError};
 use tokio::{
-    fs::{hard_link, remove_file, File},
+    fs::{hard_link, remove_file, rename, File},
     io::{self, AsyncReadExt, AsyncWriteExt},
 };
 
 use walkdir::WalkDir;
 
 const PREFIX: &str = "blob";
+const TEMP_PREFIX: &str = ".tmp";
+// Present in the base directory of stores with the fan-out layout
+const FANOUT_MARKER: &str = ".fanout";
+// Temporary files this old were left behind by writers that crashed
+const STALE_TEMP_AGE: Duration = Duration::from_secs(24 * 60 * 60);
 // https://url.spec.whatwg.org/#fragment-percent-encode-set
 const FRAGMENT: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'<').add(b'>').add(b'`');
 // https://url.spec.whatwg.org/#path-percent-encode-set
 const PATH: &AsciiSet = &FRAGMENT.add(b'#').add(b'?').add(b'{').add(b'}');
 
+/// How blobs are arranged in the base directory
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Layout {
+    /// Every blob is directly in the base directory
+    Flat,
+    /// Blobs are spread over 256 subdirectories by a hash of their key, so
+    /// that large repos don't make huge directories
+    FanOut,
+}
+
+/// A blobstore with a file per blob.
+///
+/// Puts write to a temporary file which is synced before being renamed into
+/// place, and the directory is synced after every rename, so a crash leaves
+/// either the old blob or the new blob, never a partial one.
 #[derive(Debug, Clone)]
 pub struct Fileblob {
     base: PathBuf,
     put_behaviour: PutBehaviour,
+    layout: Layout,
 }
 
 impl Fileblob {
     pub fn open<P: AsRef<Path>>(base: P, put_behaviour: PutBehaviour) -> Result<Self> {
+        Self::open_with_layout(base, put_behaviour, Layout::Flat)
+    }
+
+    pub fn create<P: AsRef<Path>>(base: P, put_behaviour: PutBehaviour) -> Result<Self> {
+        Self::create_with_layout(base, put_behaviour, Layout::Flat)
+    }
+
+    /// Opens the store in `base`. Opening a store with a flat layout as
+    /// `Layout::FanOut` moves its blobs into the fan-out layout, after which
+    /// it can't be opened as `Layout::Flat`.
+    pub fn open_with_layout<P: AsRef<Path>>(
+        base: P,
+        put_behaviour: PutBehaviour,
+        layout: Layout,
+    ) -> Result<Self> {
         let base = base.as_ref();
 
         if !base.is_dir() {
             bail!("Base {:?} doesn't exist or is not directory", base);
         }
 
+        match layout {
+            Layout::Flat => {
+                if base.join(FANOUT_MARKER).exists() {
+                    bail!("Base {:?} has a fan-out layout", base);
+                }
+            }
+            Layout::FanOut => convert_to_fanout(base)?,
+        }
+        remove_stale_temp_files(base)?;
+
         Ok(Self {
             base: base.to_owned(),
             put_behaviour,
+            layout,
         })
     }
 
-    pub fn create<P: AsRef<Path>>(base: P, put_behaviour: PutBehaviour) -> Result<Self> {
+    pub fn create_with_layout<P: AsRef<Path>>(
+        base: P,
+        put_behaviour: PutBehaviour,
+        layout: Layout,
+    ) -> Result<Self> {
         let base = base.as_ref();
         create_dir_all(base)?;
-        Self::open(base, put_behaviour)
+        Self::open_with_layout(base, put_behaviour, layout)
     }
 
     fn path(&self, key: &str) -> PathBuf {
-        let key = percent_encode(key.as_bytes(), PATH);
-        self.base.join(format!("{}-{}", PREFIX, key))
+        blob_path(&self.base, self.layout, key)
+    }
+
+    fn temp_file(&self) -> Result<tempfile::NamedTempFile> {
+        Ok(Builder::new().prefix(TEMP_PREFIX).tempfile_in(&self.base)?)
+    }
+}
+
+fn blob_path(base: &Path, layout: Layout, key: &str) -> PathBuf {
+    let file_name = format!("{}-{}", PREFIX, percent_encode(key.as_bytes(), PATH));
+    match layout {
+        Layout::Flat => base.join(file_name),
+        Layout::FanOut => base.join(fanout_dir(key)).join(file_name),
+    }
+}
+
+fn fanout_dir(key: &str) -> String {
+    let mut context = Context::new(b"fileblob");
+    context.update(key);
+    format!("{:02x}", context.finish().as_ref()[0])
+}
+
+// Renames and links are only durable once the directory holding them is synced
+async fn sync_parent(path: &Path) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        File::open(dir).await?.sync_all().await?;
+    }
+    Ok(())
+}
+
+// The marker is written first, so that if this is interrupted the store can
+// only be reopened as fan-out, which finishes moving the blobs.
+fn convert_to_fanout(base: &Path) -> Result<()> {
+    let marker = base.join(FANOUT_MARKER);
+    let mut changed = false;
+    if !marker.exists() {
+        std::fs::File::create(&marker)?.sync_all()?;
+        changed = true;
+    }
+    for dir in 0..=u8::MAX {
+        let dir = base.join(format!("{:02x}", dir));
+        if !dir.is_dir() {
+            std::fs::create_dir(&dir)?;
+            changed = true;
+        }
+    }
+
+    for entry in std::fs::read_dir(base)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        if let Some(key) = entry.file_name().to_str().and_then(key_from_file_name) {
+            let path = blob_path(base, Layout::FanOut, &key);
+            std::fs::rename(entry.path(), &path)?;
+            std::fs::File::open(path.parent().unwrap_or(base))?.sync_all()?;
+            changed = true;
+        }
+    }
+
+    if changed {
+        std::fs::File::open(base)?.sync_all()?;
+    }
+    Ok(())
+}
+
+// Best effort, as a failure to clean up shouldn't stop the store opening.
+fn remove_stale_temp_files(base: &Path) -> Result<()> {
+    for entry in std::fs::read_dir(base)? {
+        let entry = entry?;
+        let is_temp =
+            matches!(entry.file_name().to_str(), Some(name) if name.starts_with(TEMP_PREFIX));
+        let is_stale = || -> Result<bool> {
+            let age = entry.metadata()?.modified()?.elapsed()?;
+            Ok(age > STALE_TEMP_AGE)
+        };
+        if is_temp && is_stale().unwrap_or(false) {
+            let _ = std::fs::remove_file(entry.path());
+        }
     }
+    Ok(())
+}
+
+// Reverses `blob_path`, ignoring files that are not blobs (e.g. temporary files)
+fn key_from_file_name(file_name: &str) -> Option<String> {
+    let key = file_name.strip_prefix(PREFIX)?.strip_prefix('-')?;
+    percent_decode_str(key)
+        .decode_utf8()
+        .ok()
+        .map(|key| key.into_owned())
 }
 
 impl std::fmt::Display for Fileblob {
@@ -96,7 +235,7 @@ impl BlobstorePutOps for Fileblob {
         let p = self.path(&key);
         // block_in_place on tempfile would be ideal here, but it interacts
         // badly with tokio_compat
-        let tempfile = NamedTempFile::new_in(&self.base)?;
+        let tempfile = self.temp_file()?;
         let new_file = tempfile.as_file().try_clone()?;
         let mut tokio_file = File::from_std(new_file);
         tokio_file.write_all(value.as_bytes().as_ref()).await?;
@@ -120,6 +259,8 @@ impl BlobstorePutOps for Fileblob {
                             f.persist(&p)?;
                             OverwriteStatus::Overwrote
                         } else {
+                            // Refresh the ctime so that blobstore_gc does not sweep it
+                            filetime::set_file_mtime(&p, FileTime::now())?;
                             OverwriteStatus::Prevented
                         }
                     }
@@ -127,6 +268,9 @@ impl BlobstorePutOps for Fileblob {
                 }
             }
         };
+        if status != OverwriteStatus::Prevented {
+            sync_parent(&p).await?;
+        }
 
         Ok(status)
     }
@@ -209,17 +353,19 @@ impl BlobstoreWithLink for Fileblob {
         // from std::fs::hard_link: The dst path will be a link pointing to the src path
         let src_path = self.path(existing_key);
         let dst_path = self.path(&link_key);
-        // hard_link will fail if dst_path exists. Race it in a task of its own
-        Ok(tokio::task::spawn(async move {
-            let _ = remove_file(&dst_path).await;
-            hard_link(src_path, dst_path).await
-        })
-        .await??)
+        // hard_link will fail if dst_path exists, so link to a temporary path
+        // and rename that over dst_path, which replaces it atomically.
+        let temp_path = self.temp_file()?.into_temp_path();
+        remove_file(&temp_path).await?;
+        hard_link(&src_path, &temp_path).await?;
+        rename(&temp_path, &dst_path).await?;
+        sync_parent(&dst_path).await
     }
 
     async fn unlink<'a>(&'a self, _ctx: &'a CoreContext, key: &'a str) -> Result<()> {
         let path = self.path(key);
-        Ok(remove_file(path).await?)
+        remove_file(&path).await?;
+        sync_parent(&path).await
     }
 }
 
@@ -237,12 +383,13 @@ impl BlobstoreKeySource for Fileblob {
                     next_token: None,
                 };
                 WalkDir::new(&self.base)
+                    .min_depth(1)
+                    .max_depth(2)
                     .into_iter()
                     .filter_map(|v| v.ok())
+                    .filter(|entry| entry.file_type().is_file())
                     .for_each(|entry| {
-                        let entry = entry.path().to_str();
-                        if let Some(data) = entry {
-                            let key = data.to_string();
+                        if let Some(key) = entry.file_name().to_str().and_then(key_from_file_name) {
                             if range.contains(&key) {
                                 enum_data.keys.insert(key);
                             }
@@ -268,6 +415,7 @@ mod test {
         let blob = Fileblob {
             base: PathBuf::from("/mononoke/fileblob/test/path/should/not/exist"),
             put_behaviour: PutBehaviour::IfAbsent,
+            layout: Layout::Flat,
         };
 
         let ret = blob
@@ -278,4 +426,160 @@ mod test {
 
         Ok(())
     }
+
+    #[fbinit::test]
+    async fn test_enumerate(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let dir = tempfile::tempdir()?;
+        let blob = Fileblob::create(dir.path(), PutBehaviour::IfAbsent)?;
+
+        for key in &["repo0000.a", "repo0000.b c", "repo0000.d#e"] {
+            blob.put(&ctx, key.to_string(), BlobstoreBytes::from_bytes("value"))
+                .await?;
+        }
+        // Not a blob, so not enumerated
+        std::fs::write(dir.path().join("other"), "value")?;
+
+        let all = blob.enumerate(&ctx, &BlobstoreKeyParam::from(..)).await?;
+        let expected: HashSet<_> = vec!["repo0000.a", "repo0000.b c", "repo0000.d#e"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(all.keys, expected);
+        assert_eq!(all.next_token, None);
+
+        let some = blob
+            .enumerate(
+                &ctx,
+                &BlobstoreKeyParam::from("repo0000.b".to_string()..="repo0000.c".to_string()),
+            )
+            .await?;
+        assert_eq!(
+            some.keys,
+            vec!["repo0000.b c".to_string()].into_iter().collect()
+        );
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_fanout(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let dir = tempfile::tempdir()?;
+        let blob =
+            Fileblob::create_with_layout(dir.path(), PutBehaviour::IfAbsent, Layout::FanOut)?;
+        let value = BlobstoreBytes::from_bytes("value");
+
+        blob.put(&ctx, "key".to_string(), value.clone()).await?;
+        assert!(dir
+            .path()
+            .join(fanout_dir("key"))
+            .join("blob-key")
+            .is_file());
+        assert_eq!(
+            blob.get(&ctx, "key").await?.map(|d| d.into_bytes()),
+            Some(value.clone())
+        );
+
+        blob.link(&ctx, "key", "link".to_string()).await?;
+        blob.unlink(&ctx, "key").await?;
+        assert_eq!(blob.get(&ctx, "key").await?, None);
+        assert_eq!(
+            blob.get(&ctx, "link").await?.map(|d| d.into_bytes()),
+            Some(value)
+        );
+
+        let all = blob.enumerate(&ctx, &BlobstoreKeyParam::from(..)).await?;
+        assert_eq!(all.keys, vec!["link".to_string()].into_iter().collect());
+
+        assert!(Fileblob::open(dir.path(), PutBehaviour::IfAbsent).is_err());
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_convert_to_fanout(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let dir = tempfile::tempdir()?;
+        let value = BlobstoreBytes::from_bytes("value");
+
+        let flat = Fileblob::create(dir.path(), PutBehaviour::IfAbsent)?;
+        for key in &["a", "b"] {
+            flat.put(&ctx, key.to_string(), value.clone()).await?;
+        }
+
+        let blob = Fileblob::open_with_layout(dir.path(), PutBehaviour::IfAbsent, Layout::FanOut)?;
+        for key in &["a", "b"] {
+            assert_eq!(
+                blob.get(&ctx, key).await?.map(|d| d.into_bytes()),
+                Some(value.clone())
+            );
+        }
+
+        // A blob the conversion was interrupted before moving is moved when
+        // the store is next opened.
+        std::fs::write(dir.path().join("blob-c"), "value")?;
+        let blob = Fileblob::open_with_layout(dir.path(), PutBehaviour::IfAbsent, Layout::FanOut)?;
+        assert_eq!(
+            blob.get(&ctx, "c").await?.map(|d| d.into_bytes()),
+            Some(value)
+        );
+        let all = blob.enumerate(&ctx, &BlobstoreKeyParam::from(..)).await?;
+        assert_eq!(all.keys.len(), 3);
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_crashed_writes(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let dir = tempfile::tempdir()?;
+        let blob = Fileblob::create(dir.path(), PutBehaviour::IfAbsent)?;
+        let value = BlobstoreBytes::from_bytes("value");
+        blob.put(&ctx, "key".to_string(), value.clone()).await?;
+
+        // Writers that crash leave partial temporary files, which are not
+        // blobs, and don't affect the blob they were writing.
+        let stale = dir.path().join(".tmpstale");
+        let recent = dir.path().join(".tmprecent");
+        std::fs::write(&stale, "val")?;
+        std::fs::write(&recent, "val")?;
+        let old = SystemTime::now() - 2 * STALE_TEMP_AGE;
+        filetime::set_file_mtime(&stale, filetime::FileTime::from_system_time(old))?;
+
+        let all = blob.enumerate(&ctx, &BlobstoreKeyParam::from(..)).await?;
+        assert_eq!(all.keys, vec!["key".to_string()].into_iter().collect());
+        assert_eq!(
+            blob.get(&ctx, "key").await?.map(|d| d.into_bytes()),
+            Some(value)
+        );
+
+        // Only temporary files too old to have a live writer are removed.
+        Fileblob::open(dir.path(), PutBehaviour::IfAbsent)?;
+        assert!(!stale.exists());
+        assert!(recent.exists());
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_prevented_put_refreshes_ctime(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let dir = tempfile::tempdir()?;
+        let blob = Fileblob::create(dir.path(), PutBehaviour::IfAbsent)?;
+        let value = BlobstoreBytes::from_bytes("value");
+        blob.put(&ctx, "key".to_string(), value.clone()).await?;
+
+        filetime::set_file_mtime(blob.path("key"), filetime::FileTime::zero())?;
+        let ctime = blob
+            .get(&ctx, "key")
+            .await?
+            .and_then(|d| d.as_meta().ctime());
+        assert_eq!(ctime, Some(0));
+
+        let status = blob.put_with_status(&ctx, "key".to_string(), value).await?;
+        assert_eq!(status, OverwriteStatus::Prevented);
+        let ctime = blob
+            .get(&ctx, "key")
+            .await?
+            .and_then(|d| d.as_meta().ctime());
+        assert!(ctime > Some(0));
+        Ok(())
+    }
 }
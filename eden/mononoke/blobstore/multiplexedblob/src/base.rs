@@ -8,7 +8,8 @@
 use anyhow::{anyhow, Error, Result};
 use async_trait::async_trait;
 use blobstore::{
-    Blobstore, BlobstoreGetData, BlobstoreIsPresent, BlobstorePutOps, OverwriteStatus, PutBehaviour,
+    Blobstore, BlobstoreGetData, BlobstoreIsPresent, BlobstorePutOps,
+    ErrorKind as BlobstoreErrorKind, OverwriteStatus, PutBehaviour,
 };
 use blobstore_stats::{record_get_stats, record_put_stats, OperationType};
 use blobstore_sync_queue::OperationKey;
@@ -24,6 +25,7 @@ use itertools::{Either, Itertools};
 use metaconfig_types::{BlobstoreId, MultiplexId};
 use mononoke_types::BlobstoreBytes;
 use scuba_ext::MononokeScubaSampleBuilder;
+use slog::warn;
 use std::{
     borrow::Borrow,
     collections::{hash_map::RandomState, HashMap, HashSet},
@@ -140,6 +142,15 @@ impl std::fmt::Display for MultiplexedBlobstoreBase {
     }
 }
 
+/// Whether a blobstore found that its copy of a blob is corrupt, in which case
+/// other blobstores may still have a good copy.
+fn is_checksum_mismatch(error: &Error) -> bool {
+    matches!(
+        error.downcast_ref::<BlobstoreErrorKind>(),
+        Some(BlobstoreErrorKind::ChecksumMismatch(_))
+    )
+}
+
 fn write_mostly_error(
     blobstores: &[(BlobstoreId, Arc<dyn BlobstorePutOps>)],
     errors: HashMap<BlobstoreId, Error>,
@@ -246,15 +257,19 @@ impl MultiplexedBlobstoreBase {
         )
         .await;
 
-        let (successes, errors): (HashMap<_, _>, HashMap<_, _>) = results
+        let (successes, failures): (HashMap<_, _>, HashMap<_, _>) = results
             .into_iter()
             .partition_map(|(write_mostly_flag, (id, r))| match r {
                 Ok(v) => Either::Left((id, (write_mostly_flag, v))),
-                Err(v) => Either::Right((id, v)),
+                Err(v) => Either::Right((id, (write_mostly_flag, v))),
             });
 
         if successes.is_empty() {
-            return Err(ErrorKind::AllFailed(errors.into()));
+            let errors = failures
+                .into_iter()
+                .map(|(id, (_, error))| (id, error))
+                .collect();
+            return Err(ErrorKind::AllFailed(Arc::new(errors)));
         }
 
         let mut all_values = HashMap::new();
@@ -284,6 +299,21 @@ impl MultiplexedBlobstoreBase {
             }
         }
 
+        // A corrupt copy is as good as missing if there is a good one, so
+        // that it gets repaired from that.
+        let mut errors = HashMap::new();
+        for (blobstore_id, (write_mostly_flag, error)) in failures {
+            if !all_values.is_empty() && is_checksum_mismatch(&error) {
+                if write_mostly_flag {
+                    missing_write_mostly.insert(blobstore_id);
+                } else {
+                    missing_main.insert(blobstore_id);
+                }
+            } else {
+                errors.insert(blobstore_id, error);
+            }
+        }
+
         match all_values.len() {
             0 => {
                 if errors.is_empty() {
@@ -370,6 +400,8 @@ async fn blobstore_get<'a>(
     ctx: &'a CoreContext,
     blobstores: Arc<[(BlobstoreId, Arc<dyn BlobstorePutOps>)]>,
     write_mostly_blobstores: Arc<[(BlobstoreId, Arc<dyn BlobstorePutOps>)]>,
+    handler: Arc<dyn MultiplexedBlobstorePutHandler>,
+    multiplex_id: MultiplexId,
     key: &'a str,
     scuba: MononokeScubaSampleBuilder,
 ) -> Result<Option<BlobstoreGetData>, Error> {
@@ -382,7 +414,7 @@ async fn blobstore_get<'a>(
             ctx.perf_counters()
                 .increment_counter(PerfCounterType::BlobGets);
 
-            let main_requests: FuturesUnordered<_> = multiplexed_get(
+            let mut main_requests: FuturesUnordered<_> = multiplexed_get(
                 ctx.clone(),
                 blobstores.as_ref(),
                 key.to_owned(),
@@ -390,26 +422,66 @@ async fn blobstore_get<'a>(
                 scuba.clone(),
             )
             .collect();
-            let write_mostly_requests: FuturesUnordered<_> = multiplexed_get(
+            let mut write_mostly_requests: FuturesUnordered<_> = multiplexed_get(
                 ctx.clone(),
                 write_mostly_blobstores.as_ref(),
                 key.to_owned(),
                 OperationType::Get,
-                scuba,
+                scuba.clone(),
             )
             .collect();
 
-            // `chain` here guarantees that `main_requests` is empty before it starts
-            // polling anything in `write_mostly_requests`
-            let mut requests = main_requests.chain(write_mostly_requests);
-            while let Some(result) = requests.next().await {
+            // Main blobstores are polled until they are all done before we start polling
+            // anything in `write_mostly_requests`
+            loop {
+                let result = match main_requests.next().await {
+                    Some(result) => result,
+                    None => match write_mostly_requests.next().await {
+                        Some(result) => result,
+                        None => break,
+                    },
+                };
                 match result {
-                    (_, Ok(Some(mut value))) => {
-                        if is_logged {
-                            // Allow the other requests to complete so that we can record some
-                            // metrics for the blobstore. This will also log metrics for write-mostly
-                            // blobstores, which helps us decide whether they're good
-                            tokio::spawn(requests.for_each(|_| async {}));
+                    (blobstore_id, Ok(Some(mut value))) => {
+                        let blobstore_type = blobstores
+                            .iter()
+                            .chain(write_mostly_blobstores.iter())
+                            .find(|(id, _)| *id == blobstore_id)
+                            .map_or_else(String::new, |(_, store)| store.to_string());
+                        let heal = HealCorrupt {
+                            ctx: ctx.clone(),
+                            scuba: scuba.clone(),
+                            handler: handler.clone(),
+                            blobstore_id,
+                            blobstore_type,
+                            multiplex_id,
+                            key: key.to_owned(),
+                            size: value.as_bytes().len() as u64,
+                        };
+                        let mut heal = if errors.values().any(is_checksum_mismatch) {
+                            heal.queue().await;
+                            None
+                        } else {
+                            Some(heal)
+                        };
+                        if heal.is_some() || is_logged {
+                            // A main blobstore that hasn't answered yet may still report a
+                            // corrupt copy, so finish those requests in the background and
+                            // queue healing if one does. If we're logging, also allow the
+                            // write-mostly requests to complete so that we can record some
+                            // metrics for them, which helps us decide whether they're good
+                            tokio::spawn(async move {
+                                while let Some((_, result)) = main_requests.next().await {
+                                    if result.as_ref().err().map_or(false, is_checksum_mismatch) {
+                                        if let Some(heal) = heal.take() {
+                                            heal.queue().await;
+                                        }
+                                    }
+                                }
+                                if is_logged {
+                                    write_mostly_requests.for_each(|_| async {}).await;
+                                }
+                            });
                         }
                         // Return the blob that won the race
                         value.remove_ctime();
@@ -442,6 +514,44 @@ async fn blobstore_get<'a>(
     Ok(result?)
 }
 
+/// Healing to queue for a blob that some blobstore has a corrupt copy of: the
+/// blobstore that returned a good copy is recorded as having it, so that the
+/// healer puts it to the others, replacing their corrupt copies.
+struct HealCorrupt {
+    ctx: CoreContext,
+    scuba: MononokeScubaSampleBuilder,
+    handler: Arc<dyn MultiplexedBlobstorePutHandler>,
+    blobstore_id: BlobstoreId,
+    blobstore_type: String,
+    multiplex_id: MultiplexId,
+    key: String,
+    size: u64,
+}
+
+impl HealCorrupt {
+    async fn queue(self) {
+        let res = self
+            .handler
+            .on_put(
+                &self.ctx,
+                self.scuba,
+                self.blobstore_id,
+                self.blobstore_type,
+                self.multiplex_id,
+                &OperationKey::gen(),
+                &self.key,
+                Some(self.size),
+            )
+            .await;
+        if let Err(err) = res {
+            warn!(
+                self.ctx.logger(),
+                "Failed to queue healing of corrupt blob {}: {:?}", self.key, err
+            );
+        }
+    }
+}
+
 fn spawn_stream_completion(s: impl StreamExt + Send + 'static) {
     tokio::spawn(s.for_each(|_| async {}));
 }
@@ -515,7 +625,16 @@ impl Blobstore for MultiplexedBlobstoreBase {
         let write_mostly_blobstores = self.write_mostly_blobstores.clone();
         scuba.sampled(self.scuba_sample_rate);
 
-        blobstore_get(ctx, blobstores, write_mostly_blobstores, key, scuba).await
+        blobstore_get(
+            ctx,
+            blobstores,
+            write_mostly_blobstores,
+            self.handler.clone(),
+            self.multiplex_id,
+            key,
+            scuba,
+        )
+        .await
     }
 
     async fn is_present<'a>(
@@ -14,7 +14,7 @@ use std::{
     time::{Duration, Instant, SystemTime},
 };
 
-use crate::base::{MultiplexedBlobstoreBase, MultiplexedBlobstorePutHandler};
+use crate::base::{ErrorKind, MultiplexedBlobstoreBase, MultiplexedBlobstorePutHandler};
 use crate::queue::MultiplexedBlobstore;
 use crate::scrub::{
     LoggingScrubHandler, ScrubAction, ScrubBlobstore, ScrubHandler, ScrubOptions, ScrubWriteMostly,
@@ -30,6 +30,7 @@ use blobstore_sync_queue::{
 };
 use borrowed::borrowed;
 use bytes::Bytes;
+use checksumblob::{ChecksumBlob, ChecksumOptions};
 use cloned::cloned;
 use context::{CoreContext, SessionClass, SessionContainer};
 use fbinit::FacebookInit;
@@ -39,7 +40,7 @@ use futures::{
     task::{Context, Poll},
 };
 use lock_ext::LockExt;
-use maplit::hashmap;
+use maplit::{hashmap, hashset};
 use memblob::Memblob;
 use metaconfig_types::{BlobstoreId, MultiplexId};
 use mononoke_types::{BlobstoreBytes, DateTime};
@@ -1968,3 +1969,165 @@ async fn test_dont_wait_for_slowest_blobstore_in_background_mode(fb: FacebookIni
 
     Ok(())
 }
+
+#[fbinit::test]
+async fn checksum_mismatch(fb: FacebookInit) -> Result<()> {
+    let ctx = CoreContext::test_mock(fb);
+    borrowed!(ctx);
+
+    let corrupt_inner = Arc::new(Memblob::default());
+    let bs0 = Arc::new(ChecksumBlob::new(
+        corrupt_inner.clone(),
+        ChecksumOptions::default(),
+    ));
+    let good_inner: Arc<Tickable<(BlobstoreBytes, u64)>> = Arc::new(Tickable::new());
+    let bs1 = Arc::new(ChecksumBlob::new(
+        good_inner.clone(),
+        ChecksumOptions::default(),
+    ));
+    let log = Arc::new(LogHandler::new());
+    let bs = MultiplexedBlobstoreBase::new(
+        MultiplexId::new(1),
+        vec![
+            (BlobstoreId::new(0), bs0.clone()),
+            (BlobstoreId::new(1), bs1.clone()),
+        ],
+        vec![],
+        nonzero!(1usize),
+        log.clone(),
+        MononokeScubaSampleBuilder::with_discard(),
+        nonzero!(1u64),
+    );
+
+    let k = "k";
+    let v = make_value("v");
+    bs0.put(ctx, k.to_owned(), v.clone()).await?;
+    {
+        let mut put_fut = bs1
+            .put(ctx, k.to_owned(), v.clone())
+            .map_err(|_| ())
+            .boxed();
+        assert_eq!(PollOnce::new(Pin::new(&mut put_fut)).await, Poll::Pending);
+        good_inner.tick(None);
+        put_fut.await.expect("put failed");
+    }
+
+    // Flip a bit in the copy in bs0
+    let mut corrupt = corrupt_inner
+        .get(ctx, k)
+        .await?
+        .expect("value missing")
+        .into_raw_bytes()
+        .to_vec();
+    *corrupt.last_mut().unwrap() ^= 1;
+    corrupt_inner
+        .put_explicit(
+            ctx,
+            k.to_owned(),
+            BlobstoreBytes::from_bytes(corrupt),
+            PutBehaviour::Overwrite,
+        )
+        .await?;
+
+    // Get falls back to the good copy, and records it for the healer
+    {
+        let mut get_fut = bs.get(ctx, k).map_err(|_| ()).boxed();
+        assert_eq!(PollOnce::new(Pin::new(&mut get_fut)).await, Poll::Pending);
+        good_inner.tick(None);
+        assert_eq!(
+            get_fut.await.map(|v| v.map(|v| v.into_bytes())),
+            Ok(Some(v.clone()))
+        );
+        log.log
+            .with(|log| assert_eq!(log, &vec![(BlobstoreId::new(1), k.to_owned())]));
+        log.clear();
+    }
+
+    // Scrub treats the corrupt copy as missing, so that it gets repaired
+    {
+        let mut get_fut = bs
+            .scrub_get(ctx, k, ScrubWriteMostly::Scrub)
+            .map(|res| match res {
+                Err(ErrorKind::SomeMissingItem {
+                    missing_main,
+                    value: Some(value),
+                    ..
+                }) => Some((missing_main, value.into_bytes())),
+                _ => None,
+            })
+            .boxed();
+        assert_eq!(PollOnce::new(Pin::new(&mut get_fut)).await, Poll::Pending);
+        good_inner.tick(None);
+        let (missing_main, value) = get_fut.await.expect("Expected SomeMissingItem");
+        assert_eq!(*missing_main, hashset! {BlobstoreId::new(0)});
+        assert_eq!(value, v);
+    }
+    Ok(())
+}
+
+#[fbinit::test]
+async fn checksum_mismatch_after_good_value(fb: FacebookInit) -> Result<()> {
+    let ctx = CoreContext::test_mock(fb);
+    borrowed!(ctx);
+
+    let corrupt_inner: Arc<Tickable<(BlobstoreBytes, u64)>> = Arc::new(Tickable::new());
+    let bs0 = Arc::new(ChecksumBlob::new(
+        corrupt_inner.clone(),
+        ChecksumOptions::default(),
+    ));
+    let good_inner = Arc::new(Memblob::default());
+    let bs1 = Arc::new(ChecksumBlob::new(
+        good_inner.clone(),
+        ChecksumOptions::default(),
+    ));
+    let log = Arc::new(LogHandler::new());
+    let bs = MultiplexedBlobstoreBase::new(
+        MultiplexId::new(1),
+        vec![
+            (BlobstoreId::new(0), bs0.clone()),
+            (BlobstoreId::new(1), bs1.clone()),
+        ],
+        vec![],
+        nonzero!(1usize),
+        log.clone(),
+        MononokeScubaSampleBuilder::with_discard(),
+        nonzero!(1u64),
+    );
+
+    let k = "k";
+    let v = make_value("v");
+    bs1.put(ctx, k.to_owned(), v.clone()).await?;
+    {
+        let mut put_fut = bs0
+            .put(ctx, k.to_owned(), v.clone())
+            .map_err(|_| ())
+            .boxed();
+        assert_eq!(PollOnce::new(Pin::new(&mut put_fut)).await, Poll::Pending);
+        corrupt_inner.tick(None);
+        put_fut.await.expect("put failed");
+    }
+
+    // Flip a bit in the copy in bs0
+    let mut corrupt = corrupt_inner
+        .get_bytes(k)
+        .expect("value missing")
+        .into_bytes()
+        .to_vec();
+    *corrupt.last_mut().unwrap() ^= 1;
+    corrupt_inner.add_bytes(k.to_owned(), BlobstoreBytes::from_bytes(corrupt));
+
+    // The good copy wins the race, and is recorded for the healer once the
+    // corrupt copy turns up
+    assert_eq!(
+        bs.get(ctx, k).await?.map(|v| v.into_bytes()),
+        Some(v.clone())
+    );
+    log.log.with(|log| assert!(log.is_empty()));
+    corrupt_inner.tick(None);
+    while log.log.with(|log| log.is_empty()) {
+        tokio::task::yield_now().await;
+    }
+    log.log
+        .with(|log| assert_eq!(log, &vec![(BlobstoreId::new(1), k.to_owned())]));
+    Ok(())
+}
@@ -36,6 +36,10 @@ struct ZstdFromDictValue {
 union PackedValue {
   1: SingleValue Single;
   2: ZstdFromDictValue ZstdFromDict;
+  // Zstandard blob compressed with the dictionary of the pack it is in.
+  // Readers that predate this variant cannot decode it, so the packer only
+  // writes it when asked to with --trained-dictionaries.
+  3: bytes ZstdFromPackDict;
 }
 
 // One packed entry,  the key being the blobstore key and the data being
@@ -61,6 +65,10 @@ struct PackedFormat {
   // All but the first entry should be ZstdFromDict, to maximize compression.
   // We do not expect significant gains from fewer blobs in the underlying store.
   2: list<PackedEntry> entries;
+  // A Zstandard dictionary trained on the blobs in the pack, which
+  // ZstdFromPackDict entries are compressed with. Unlike using a blob as
+  // the dictionary, this lets entries be decoded without decoding others.
+  3: optional bytes dictionary;
 }
 
 // Discriminated union with the variant forms, for now we handle single
@@ -11,5 +11,5 @@ mod envelope;
 mod pack;
 mod store;
 
-pub use pack::{EmptyPack, Pack, SingleCompressed};
-pub use store::{PackBlob, PackOptions};
+pub use pack::{train_dictionary, EmptyPack, Pack, SingleCompressed};
+pub use store::{PackBlob, PackOptions, ENVELOPE_SUFFIX};
@@ -21,7 +21,7 @@ use packblob_thrift::{
 };
 use std::{
     collections::HashMap,
-    io::{self, Cursor, Write},
+    io::{self, Write},
 };
 use zstd::block::Compressor;
 use zstd::dict::EncoderDictionary;
@@ -65,42 +65,95 @@ impl SingleCompressed {
     }
 }
 
+/// zstd's default maximum dictionary size, which suits blobs of up to a few hundred KiB
+const MAX_TRAINED_DICTIONARY_SIZE: usize = 112_640;
+/// zstd cannot train dictionaries smaller than this
+const MIN_TRAINED_DICTIONARY_SIZE: usize = 256;
+/// Dictionaries trained on fewer blobs than this are rarely worth storing
+const MIN_TRAINING_BLOBS: usize = 8;
+/// The longest chain of deltas `add_blob` creates, so that reading a blob from a pack never
+/// needs more than this many other blobs to be decoded first
+const MAX_DELTA_CHAIN_DEPTH: usize = 8;
+
+/// Trains a zstd dictionary on a group of similar blobs, for use by a pack of
+/// those blobs. Returns None if the blobs are too few or too small to train a
+/// useful dictionary on.
+pub fn train_dictionary<'a>(blobs: impl IntoIterator<Item = &'a BlobstoreBytes>) -> Option<Bytes> {
+    let samples: Vec<&[u8]> = blobs
+        .into_iter()
+        .map(|blob| blob.as_bytes().as_ref())
+        .collect();
+    if samples.len() < MIN_TRAINING_BLOBS {
+        return None;
+    }
+    // zstd recommends dictionaries about 100 times smaller than their samples
+    let total_size: usize = samples.iter().map(|sample| sample.len()).sum();
+    let max_size = (total_size / 100).min(MAX_TRAINED_DICTIONARY_SIZE);
+    if max_size < MIN_TRAINED_DICTIONARY_SIZE {
+        return None;
+    }
+    // Training fails if the samples have too little in common to learn from
+    zstd::dict::from_samples(&samples, max_size)
+        .ok()
+        .map(Bytes::from)
+}
+
 /// An empty pack with no data. Cannot be uploaded, takes a dictionary blob
 #[derive(Debug)]
-pub struct EmptyPack(i32);
+pub struct EmptyPack {
+    zstd_level: i32,
+    dictionary: Option<Bytes>,
+}
+
+/// A dictionary trained for a pack, rather than taken from one of its blobs
+struct PackDictionary {
+    raw: Bytes,
+    prepared: EncoderDictionary<'static>,
+}
 
 /// A pack containing multiple entries, ready to extend or upload
 pub struct Pack {
     zstd_level: i32,
+    pack_dictionary: Option<PackDictionary>,
     dictionaries: HashMap<String, EncoderDictionary<'static>>,
+    /// How many deltas must be decoded to get each blob, 0 if it is not a delta
+    chain_depths: HashMap<String, usize>,
     entries: Vec<PackedEntry>,
 }
 
 impl EmptyPack {
     /// Creates a new EmptyPack
     pub fn new(zstd_level: i32) -> Self {
-        EmptyPack(zstd_level)
+        EmptyPack {
+            zstd_level,
+            dictionary: None,
+        }
+    }
+
+    /// Lets entries of the pack be compressed with a trained dictionary, such
+    /// as one from `train_dictionary`. The dictionary is only stored in the
+    /// pack if an entry uses it.
+    pub fn with_dictionary(mut self, dictionary: Bytes) -> Self {
+        self.dictionary = Some(dictionary);
+        self
     }
 
     /// Adds the first blob to the empty pack
     pub fn add_base_blob(self, key: String, blob: BlobstoreBytes) -> Result<Pack> {
-        let zstd_level = self.0;
-        let bytes = blob.into_bytes();
-
-        let dictionary = EncoderDictionary::copy(&bytes, zstd_level);
-
-        let cursor = Cursor::new(&bytes);
-        let compressed = zstd::encode_all(cursor, zstd_level)?;
-        let data = PackedValue::Single(SingleValue::Zstd(Bytes::from(compressed)));
-
-        let mut dictionaries = HashMap::new();
-        dictionaries.insert(key.clone(), dictionary);
-        let entries = vec![PackedEntry { key, data }];
-        Ok(Pack {
+        let zstd_level = self.zstd_level;
+        let pack_dictionary = self.dictionary.map(|raw| {
+            let prepared = EncoderDictionary::copy(&raw, zstd_level);
+            PackDictionary { raw, prepared }
+        });
+        let mut pack = Pack {
             zstd_level,
-            dictionaries,
-            entries,
-        })
+            pack_dictionary,
+            dictionaries: HashMap::new(),
+            chain_depths: HashMap::new(),
+            entries: Vec::new(),
+        };
+        pack.add_blob(key, blob)?;
+        Ok(pack)
     }
 }
 
@@ -120,28 +173,96 @@ impl Pack {
                 .dictionaries
                 .get(&dict_key)
                 .ok_or_else(|| format_err!("Cannot find dictionary for blob {}", dict_key))?;
+            compress_with_dictionary(&blob, dictionary)?
+        };
+        if self.chain_depths[&dict_key] >= MAX_DELTA_CHAIN_DEPTH {
+            bail!(
+                "Cannot delta {} against {}, the delta chain would be too long",
+                key,
+                dict_key
+            );
+        }
+        let data = PackedValue::ZstdFromDict(ZstdFromDictValue { dict_key, zstd });
+        self.push_entry(key, &blob, data);
+        Ok(())
+    }
 
-            let mut compressed_blob = BytesMut::with_capacity(blob.len());
-            let writer = (&mut compressed_blob).writer();
-            let mut encoder = ZstdEncoder::with_prepared_dictionary(writer, dictionary)?;
+    /// Adds another data blob to a pack, in whichever form is smallest out of
+    /// compressed on its own, compressed with the pack's trained dictionary,
+    /// or delta'd against the first or the previous blob in the pack. Adding
+    /// similar blobs in order, such as successive versions of a file, makes
+    /// the deltas against the previous blob small. Blobs are not delta'd
+    /// against blobs at the end of a long delta chain, so that the chain
+    /// starts again from the first blob instead.
+    pub fn add_blob(&mut self, key: String, blob: BlobstoreBytes) -> Result<()> {
+        if self.dictionaries.contains_key(&key) {
+            bail!("Key {} cannot appear in the same pack twice", key);
+        }
+        let mut best =
+            PackedValue::Single(SingleCompressed::new(self.zstd_level, blob.clone())?.value);
 
-            encoder.write_all(blob.as_bytes())?;
-            encoder.finish()?;
-            compressed_blob.freeze()
-        };
+        if let Some(pack_dictionary) = &self.pack_dictionary {
+            let zstd = compress_with_dictionary(&blob, &pack_dictionary.prepared)?;
+            best = smallest_value(best, PackedValue::ZstdFromPackDict(zstd));
+        }
+
+        let mut dict_keys: Vec<&String> = self
+            .entries
+            .first()
+            .into_iter()
+            .chain(self.entries.last())
+            .map(|entry| &entry.key)
+            .filter(|dict_key| self.chain_depths[*dict_key] < MAX_DELTA_CHAIN_DEPTH)
+            .collect();
+        dict_keys.dedup();
+        for dict_key in dict_keys {
+            let zstd = compress_with_dictionary(&blob, &self.dictionaries[dict_key])?;
+            let delta = PackedValue::ZstdFromDict(ZstdFromDictValue {
+                dict_key: dict_key.clone(),
+                zstd,
+            });
+            best = smallest_value(best, delta);
+        }
+
+        self.push_entry(key, &blob, best);
+        Ok(())
+    }
+
+    fn push_entry(&mut self, key: String, blob: &BlobstoreBytes, data: PackedValue) {
         // This uses `blob` (raw data) to create a dictionary that improves compression
         // at the expense of requiring the decompressor to find blob before it can
         // decompress the resulting blob
         let dictionary = EncoderDictionary::copy(blob.as_bytes(), self.zstd_level);
-        let data = PackedValue::ZstdFromDict(ZstdFromDictValue { dict_key, zstd });
         self.dictionaries.insert(key.clone(), dictionary);
+        let chain_depth = match &data {
+            PackedValue::ZstdFromDict(ZstdFromDictValue { dict_key, .. }) => {
+                self.chain_depths[dict_key] + 1
+            }
+            _ => 0,
+        };
+        self.chain_depths.insert(key.clone(), chain_depth);
         self.entries.push(PackedEntry { key, data });
-        Ok(())
+    }
+
+    /// Returns the trained dictionary if any entry uses it, so it must be stored with the pack
+    fn used_pack_dictionary(&self) -> Option<&Bytes> {
+        let used = self
+            .entries
+            .iter()
+            .any(|entry| matches!(entry.data, PackedValue::ZstdFromPackDict(_)));
+        if used {
+            self.pack_dictionary
+                .as_ref()
+                .map(|dictionary| &dictionary.raw)
+        } else {
+            None
+        }
     }
 
     /// Returns the compressed size of the pack contents, minus framing overheads
     pub fn get_compressed_size(&self) -> usize {
-        self.entries.iter().fold(0, |size, entry| {
+        let dictionary_size = self.used_pack_dictionary().map_or(0, Bytes::len);
+        self.entries.iter().fold(dictionary_size, |size, entry| {
             size + get_entry_compressed_size(entry) + entry.key.len()
         })
     }
@@ -177,6 +298,7 @@ impl Pack {
 
         let pack = PackedFormat {
             key: pack_key.clone(),
+            dictionary: self.used_pack_dictionary().cloned(),
             entries: self.entries,
         };
 
@@ -201,16 +323,52 @@ fn get_value_compressed_size(value: &SingleValue) -> usize {
     }
 }
 
-// Not to be used with a PackedEntry loaded from a blobstore - panics instead of handling errors
-fn get_entry_compressed_size(entry: &PackedEntry) -> usize {
-    match &entry.data {
+// Not to be used with a PackedValue loaded from a blobstore - panics instead of handling errors
+fn get_packed_value_compressed_size(value: &PackedValue) -> usize {
+    match value {
         PackedValue::Single(value) => get_value_compressed_size(value),
-        PackedValue::ZstdFromDict(ZstdFromDictValue { zstd, .. }) => zstd.len(),
+        PackedValue::ZstdFromDict(ZstdFromDictValue { zstd, .. })
+        | PackedValue::ZstdFromPackDict(zstd) => zstd.len(),
         // Can't happen, by construction - this only takes values created by this module
         PackedValue::UnknownField(_) => panic!("Unknown field"),
     }
 }
 
+// Not to be used with a PackedEntry loaded from a blobstore - panics instead of handling errors
+fn get_entry_compressed_size(entry: &PackedEntry) -> usize {
+    get_packed_value_compressed_size(&entry.data)
+}
+
+// Prefers the earlier value if the sizes are equal
+fn smallest_value(best_so_far: PackedValue, new: PackedValue) -> PackedValue {
+    if get_packed_value_compressed_size(&new) < get_packed_value_compressed_size(&best_so_far) {
+        new
+    } else {
+        best_so_far
+    }
+}
+
+fn compress_with_dictionary(
+    blob: &BlobstoreBytes,
+    dictionary: &EncoderDictionary<'static>,
+) -> Result<Bytes> {
+    let mut compressed_blob = BytesMut::with_capacity(blob.len());
+    let writer = (&mut compressed_blob).writer();
+    let mut encoder = ZstdEncoder::with_prepared_dictionary(writer, dictionary)?;
+
+    encoder.write_all(blob.as_bytes())?;
+    encoder.finish()?;
+    Ok(compressed_blob.freeze())
+}
+
+fn decompress_with_dictionary(zstd: Bytes, dictionary: &[u8]) -> Result<BlobstoreBytes> {
+    let mut decoder = ZstdDecoder::with_dictionary(zstd.reader(), dictionary)?;
+    let mut output_bytes = BytesMut::new();
+    let mut writer = (&mut output_bytes).writer();
+    io::copy(&mut decoder, &mut writer)?;
+    Ok(BlobstoreBytes::from_bytes(output_bytes))
+}
+
 // returns (decoded, unique_compressed_size)
 pub(crate) fn decode_independent(v: SingleValue) -> Result<(BlobstoreBytes, u64)> {
     let (compressed_size, decoded) = match v {
@@ -232,13 +390,8 @@ fn decode_zstd_from_dict(
     match dicts.get(&v.dict_key) {
         Some(dict) => {
             let uncompressed_size = v.zstd.len() as u64;
-            let data = v.zstd.reader();
-            let mut decoder = ZstdDecoder::with_dictionary(data, dict.as_bytes())?;
-            let mut output_bytes = BytesMut::new();
-            let mut writer = (&mut output_bytes).writer();
-            io::copy(&mut decoder, &mut writer)?;
-
-            Ok((BlobstoreBytes::from_bytes(output_bytes), uncompressed_size))
+            let decoded = decompress_with_dictionary(v.zstd, dict.as_bytes())?;
+            Ok((decoded, uncompressed_size))
         }
         None => Err(format_err!(
             "Dictionary {} not found for key {}",
@@ -259,6 +412,7 @@ pub(crate) fn decode_pack(
     let PackedFormat {
         key: pack_key,
         entries: pack_entries,
+        dictionary: pack_dictionary,
     } = packed;
 
     let mut entry_map = HashMap::new();
@@ -275,6 +429,7 @@ pub(crate) fn decode_pack(
     let mut unique_compressed_size = 0;
     let mut relevant_compressed_size = 0;
     let mut relevant_uncompressed_size = 0;
+    let mut pack_dictionary_size = None;
     while let Some(next_key) = keys_to_decode.pop() {
         match entry_map.remove(&next_key) {
             None => {
@@ -318,8 +473,28 @@ pub(crate) fn decode_pack(
                     entry_map.insert(next_key, PackedValue::ZstdFromDict(v));
                 }
             }
+            Some(PackedValue::ZstdFromPackDict(v)) => {
+                let dictionary = pack_dictionary.as_ref().ok_or_else(|| {
+                    format_err!(
+                        "Key {} needs the pack dictionary but pack {} has none",
+                        next_key,
+                        pack_key
+                    )
+                })?;
+                let compressed_size = v.len() as u64;
+                let decoded = decompress_with_dictionary(v, dictionary)?;
+                relevant_uncompressed_size += decoded.len() as u64;
+                if next_key == key {
+                    unique_compressed_size += compressed_size;
+                }
+                relevant_compressed_size += compressed_size;
+                // The dictionary is shared by its users, so only count it once
+                pack_dictionary_size = Some(dictionary.len() as u64);
+                decoded_blobs.insert(next_key, decoded);
+            }
         }
     }
+    relevant_compressed_size += pack_dictionary_size.unwrap_or(0);
 
     let decoded = decoded_blobs
         .remove(key)
@@ -368,6 +543,7 @@ mod tests {
     use rand::{Rng, RngCore, SeedableRng};
     use rand_xorshift::XorShiftRng;
     use std::convert::TryInto;
+    use std::io::Cursor;
 
     #[test]
     fn decode_independent_zstd_test() -> Result<()> {
@@ -635,4 +811,173 @@ mod tests {
 
         Ok(())
     }
+
+    fn unpack(blob: BlobstoreBytes) -> Result<PackedFormat> {
+        let envelope: PackEnvelope = blob.try_into()?;
+        if let StorageFormat::Packed(pack) = envelope.0.storage {
+            Ok(pack)
+        } else {
+            bail!("Packing resulted in a single value, not a pack");
+        }
+    }
+
+    #[test]
+    fn pack_add_blob_test() -> Result<()> {
+        let mut rng = XorShiftRng::seed_from_u64(0); // reproducable Rng
+
+        let mut raw_data = vec![];
+        let mut base_version = vec![7u8; 65535];
+        rng.fill_bytes(&mut base_version[0..30000]);
+        raw_data.push(base_version.clone());
+        let mut pack = EmptyPack::new(0).add_base_blob(
+            "0".to_string(),
+            BlobstoreBytes::from_bytes(base_version.clone()),
+        )?;
+
+        // Each version differs more from the base than from the previous version
+        let mut prev_version = base_version;
+        for i in 1..20 {
+            let mut this_version = prev_version;
+            let start = 30000 + i * 1000;
+            rng.fill(&mut this_version[start..start + 1000]);
+            raw_data.push(this_version.clone());
+            prev_version = this_version.clone();
+            pack.add_blob(i.to_string(), BlobstoreBytes::from_bytes(this_version))?;
+        }
+
+        // An unrelated, incompressible blob is best stored as it is
+        let mut unrelated = vec![0u8; 1000];
+        rng.fill_bytes(&mut unrelated);
+        raw_data.push(unrelated.clone());
+        pack.add_blob("20".to_string(), BlobstoreBytes::from_bytes(unrelated))?;
+
+        assert!(pack
+            .add_blob("1".to_string(), BlobstoreBytes::empty())
+            .is_err());
+
+        let (_, _, blob) = pack.into_blobstore_bytes(String::new())?;
+        let packed = unpack(blob)?;
+        assert_eq!(packed.dictionary, None);
+        // Each version is a delta against the previous one, until the chain
+        // gets too long and starts again from the base
+        let mut chain_depths = vec![];
+        for (i, entry) in packed.entries.iter().enumerate() {
+            let chain_depth = match (i, &entry.data) {
+                (0, PackedValue::Single(SingleValue::Zstd(_))) => 0,
+                (20, PackedValue::Single(SingleValue::Raw(_))) => 0,
+                (_, PackedValue::ZstdFromDict(ZstdFromDictValue { dict_key, .. })) => {
+                    let prev_depth = chain_depths[i - 1];
+                    if prev_depth < MAX_DELTA_CHAIN_DEPTH {
+                        assert_eq!(dict_key, &(i - 1).to_string());
+                        prev_depth + 1
+                    } else {
+                        assert_eq!(dict_key, "0");
+                        1
+                    }
+                }
+                _ => bail!("Entry {} was packed unexpectedly", i),
+            };
+            chain_depths.push(chain_depth);
+        }
+        assert_eq!(chain_depths.iter().max(), Some(&MAX_DELTA_CHAIN_DEPTH));
+
+        for (raw_data, i) in raw_data.into_iter().zip(0..21) {
+            let (value, _) = decode_pack(packed.clone(), &i.to_string())?;
+            assert_eq!(value.into_bytes(), Bytes::from(raw_data));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn pack_dictionary_test() -> Result<()> {
+        let mut rng = XorShiftRng::seed_from_u64(0); // reproducable Rng
+
+        // Incompressible data, so that only the dictionary can help
+        let mut dictionary = vec![0u8; 65535];
+        rng.fill_bytes(&mut dictionary);
+        let mut blob = dictionary.clone();
+        rng.fill_bytes(&mut blob[60000..]);
+
+        let pack = EmptyPack::new(0)
+            .with_dictionary(Bytes::from(dictionary.clone()))
+            .add_base_blob("0".to_string(), BlobstoreBytes::from_bytes(blob.clone()))?;
+
+        // The dictionary is part of the size of the pack
+        let compressed_size = pack.get_compressed_size();
+        assert!(compressed_size > dictionary.len());
+        assert!(compressed_size < dictionary.len() + 10000);
+
+        let (_, _, packed_blob) = pack.into_blobstore_bytes(String::new())?;
+        let packed = unpack(packed_blob)?;
+        assert_eq!(packed.dictionary, Some(Bytes::from(dictionary.clone())));
+        assert!(matches!(
+            packed.entries[0].data,
+            PackedValue::ZstdFromPackDict(_)
+        ));
+
+        let (value, size_meta) = decode_pack(packed.clone(), "0")?;
+        assert_eq!(value.into_bytes(), Bytes::from(blob));
+        assert!(size_meta.unique_compressed_size < 10000);
+        let pack_meta = size_meta.pack_meta.unwrap();
+        assert!(pack_meta.relevant_compressed_size > dictionary.len() as u64);
+
+        // Without the dictionary, the entry cannot be decoded
+        let broken = PackedFormat {
+            dictionary: None,
+            ..packed
+        };
+        assert!(decode_pack(broken, "0").is_err());
+
+        // A dictionary that no entry uses is not stored
+        let mut unrelated = vec![0u8; 1000];
+        rng.fill_bytes(&mut unrelated);
+        let pack = EmptyPack::new(0)
+            .with_dictionary(Bytes::from(dictionary))
+            .add_base_blob("0".to_string(), BlobstoreBytes::from_bytes(unrelated))?;
+        assert_eq!(pack.get_compressed_size(), 1000 + 1);
+        let (_, _, packed_blob) = pack.into_blobstore_bytes(String::new())?;
+        assert_eq!(unpack(packed_blob)?.dictionary, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn train_dictionary_test() -> Result<()> {
+        let mut rng = XorShiftRng::seed_from_u64(0); // reproducable Rng
+
+        // Blobs with a lot of structure in common, but little data
+        let blobs: Vec<_> = (0..100)
+            .map(|i| {
+                let mut blob = String::new();
+                for _ in 0..20 {
+                    blob.push_str(&format!(
+                        "{{\"name\": \"file{}\", \"size\": {}, \"owner\": \"user{}\"}}\n",
+                        i,
+                        rng.gen::<u32>(),
+                        rng.gen::<u16>(),
+                    ));
+                }
+                BlobstoreBytes::from_bytes(blob)
+            })
+            .collect();
+
+        assert_eq!(train_dictionary(&blobs[0..2]), None);
+        let dictionary = train_dictionary(&blobs).expect("Dictionary training failed");
+
+        let mut pack = EmptyPack::new(3)
+            .with_dictionary(dictionary)
+            .add_base_blob("0".to_string(), blobs[0].clone())?;
+        for (i, blob) in blobs.iter().enumerate().skip(1) {
+            pack.add_blob(i.to_string(), blob.clone())?;
+        }
+        let (_, _, packed_blob) = pack.into_blobstore_bytes(String::new())?;
+        let packed = unpack(packed_blob)?;
+        for (i, blob) in blobs.into_iter().enumerate() {
+            let (value, _) = decode_pack(packed.clone(), &i.to_string())?;
+            assert_eq!(value, blob);
+        }
+
+        Ok(())
+    }
 }
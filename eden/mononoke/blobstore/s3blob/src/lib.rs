@@ -0,0 +1,772 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+#![deny(warnings)]
+
+//! A blobstore in a bucket of S3, or of any store with an S3-compatible API
+//! such as MinIO or Ceph. Objects are addressed path-style
+//! (`<endpoint>/<bucket>/<key>`).
+
+use std::collections::HashSet;
+use std::future::Future;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use blobstore::{
+    Blobstore, BlobstoreEnumerationData, BlobstoreGetData, BlobstoreIsPresent, BlobstoreKeyParam,
+    BlobstoreKeySource, BlobstoreKeyToken, BlobstoreMetadata, BlobstorePutOps, OverwriteStatus,
+    PutBehaviour,
+};
+use bytes::BytesMut;
+use chrono::DateTime;
+use context::CoreContext;
+use futures::stream::TryStreamExt;
+use hyper::client::HttpConnector;
+use hyper::StatusCode;
+use hyper_openssl::HttpsConnector;
+use mononoke_types::BlobstoreBytes;
+use openssl::ssl::{SslConnector, SslMethod};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use rusoto_core::credential::{ChainProvider, ProfileProvider, ProvideAwsCredentials};
+use rusoto_core::{HttpClient, Region, RusotoError};
+use rusoto_s3::{
+    CopyObjectRequest, GetObjectError, GetObjectRequest, HeadObjectError, HeadObjectRequest,
+    ListObjectsV2Request, PutObjectRequest, S3Client, S3,
+};
+use serde::{Deserialize, Serialize};
+use slog::{info, Logger};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// The number of keys to ask for in each page of an enumeration.
+const LIST_MAX_KEYS: i64 = 1000;
+
+/// How long to wait for a connection to the store.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a request may take in total, including reading the response
+/// body, before it fails.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Characters to escape in the key of the source of a copy, which is sent
+/// in a header. Like a URL path, `/` is left as is.
+const COPY_SOURCE_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b'/');
+
+pub struct S3Blob {
+    client: S3Client,
+    bucket: String,
+    put_behaviour: PutBehaviour,
+    concurrency: Option<Semaphore>,
+    list_max_keys: i64,
+}
+
+/// Where a paged enumeration is up to.
+#[derive(Serialize, Deserialize)]
+struct ListToken {
+    continuation_token: String,
+    end_key: String,
+}
+
+impl S3Blob {
+    /// Open the blobstore in `bucket`. If `endpoint` is empty, this is the
+    /// AWS S3 bucket in `region_name`; otherwise `endpoint` is the URL of
+    /// the store, which is connected to with HTTPS if it has no scheme.
+    ///
+    /// Credentials come from the `AWS_*` environment variables, or from the
+    /// profile named `keychain_group` in the AWS shared credentials file, or
+    /// from the container or instance metadata, in that order.
+    pub async fn new(
+        bucket: String,
+        keychain_group: String,
+        region_name: String,
+        endpoint: String,
+        put_behaviour: PutBehaviour,
+        logger: &Logger,
+        num_concurrent_operations: Option<usize>,
+    ) -> Result<Self> {
+        let mut profile = ProfileProvider::new().context("Failed to find AWS credentials")?;
+        profile.set_profile(keychain_group.as_str());
+        let credentials = ChainProvider::with_profile_provider(profile);
+
+        let blob = Self::with_credentials(
+            bucket,
+            region_name,
+            &endpoint,
+            credentials,
+            put_behaviour,
+            num_concurrent_operations,
+        )?;
+        info!(
+            logger,
+            "Using S3 bucket {} at {:?} with credentials {:?}",
+            blob.bucket,
+            endpoint,
+            keychain_group
+        );
+        Ok(blob)
+    }
+
+    fn with_credentials<P>(
+        bucket: String,
+        region_name: String,
+        endpoint: &str,
+        credentials: P,
+        put_behaviour: PutBehaviour,
+        num_concurrent_operations: Option<usize>,
+    ) -> Result<Self>
+    where
+        P: ProvideAwsCredentials + Send + Sync + 'static,
+    {
+        let concurrency = match num_concurrent_operations {
+            Some(0) => bail!("num_concurrent_operations must be positive"),
+            Some(n) => Some(Semaphore::new(n)),
+            None => None,
+        };
+
+        let region = if endpoint.is_empty() {
+            region_name
+                .parse::<Region>()
+                .with_context(|| format!("Invalid AWS region {:?}", region_name))?
+        } else {
+            Region::Custom {
+                name: region_name,
+                endpoint: endpoint.to_string(),
+            }
+        };
+
+        let mut http = HttpConnector::new();
+        http.enforce_http(false);
+        http.set_connect_timeout(Some(CONNECT_TIMEOUT));
+        let https = HttpsConnector::with_connector(http, SslConnector::builder(SslMethod::tls())?)
+            .context("Failed to create HTTPS connector")?;
+        let client = S3Client::new_with(HttpClient::from_connector(https), credentials, region);
+
+        Ok(Self {
+            client,
+            bucket,
+            put_behaviour,
+            concurrency,
+            list_max_keys: LIST_MAX_KEYS,
+        })
+    }
+
+    async fn permit(&self) -> Result<Option<SemaphorePermit<'_>>> {
+        match &self.concurrency {
+            Some(semaphore) => Ok(Some(semaphore.acquire().await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn head_object(&self, key: &str) -> Result<bool> {
+        let request = HeadObjectRequest {
+            bucket: self.bucket.clone(),
+            key: key.to_string(),
+            ..Default::default()
+        };
+        with_timeout("HeadObject", async {
+            match self.client.head_object(request).await {
+                Ok(_) => Ok(true),
+                Err(RusotoError::Service(HeadObjectError::NoSuchKey(_))) => Ok(false),
+                Err(e) if is_not_found(&e) => Ok(false),
+                Err(e) => Err(e.into()),
+            }
+        })
+        .await
+    }
+
+    async fn put_object(&self, key: &str, value: BlobstoreBytes) -> Result<()> {
+        let value = value.into_bytes();
+        let request = PutObjectRequest {
+            bucket: self.bucket.clone(),
+            key: key.to_string(),
+            content_length: Some(value.len() as i64),
+            body: Some(value.to_vec().into()),
+            ..Default::default()
+        };
+        with_timeout("PutObject", async {
+            self.client.put_object(request).await?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Copy an object onto itself, which updates its last modified time
+    /// without changing its contents.
+    async fn touch_object(&self, key: &str) -> Result<()> {
+        let request = CopyObjectRequest {
+            bucket: self.bucket.clone(),
+            key: key.to_string(),
+            copy_source: format!(
+                "{}/{}",
+                self.bucket,
+                utf8_percent_encode(key, COPY_SOURCE_ENCODE_SET)
+            ),
+            // S3 refuses to copy an object onto itself without changing something
+            metadata_directive: Some("REPLACE".to_string()),
+            ..Default::default()
+        };
+        with_timeout("CopyObject", async {
+            self.client.copy_object(request).await?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// List keys in order, starting after `start_after`, or continuing a
+    /// previous listing. Returns the keys, and the continuation token for
+    /// the next page if there is one.
+    async fn list_objects(
+        &self,
+        start_after: Option<&str>,
+        continuation_token: Option<&str>,
+    ) -> Result<(Vec<String>, Option<String>)> {
+        let request = ListObjectsV2Request {
+            bucket: self.bucket.clone(),
+            start_after: start_after.map(str::to_string),
+            continuation_token: continuation_token.map(str::to_string),
+            max_keys: Some(self.list_max_keys),
+            ..Default::default()
+        };
+        let output = with_timeout("ListObjectsV2", async {
+            Ok(self.client.list_objects_v2(request).await?)
+        })
+        .await?;
+
+        let keys = output
+            .contents
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|object| object.key)
+            .collect();
+        let next_continuation_token = if output.is_truncated == Some(true) {
+            let token = output
+                .next_continuation_token
+                .context("Truncated ListObjectsV2 response without a token")?;
+            Some(token)
+        } else {
+            None
+        };
+        Ok((keys, next_continuation_token))
+    }
+}
+
+/// Run a request, failing it if it takes longer than `REQUEST_TIMEOUT`.
+async fn with_timeout<T>(operation: &str, request: impl Future<Output = Result<T>>) -> Result<T> {
+    tokio::time::timeout(REQUEST_TIMEOUT, request)
+        .await
+        .with_context(|| format!("{} timed out after {:?}", operation, REQUEST_TIMEOUT))?
+}
+
+/// Whether an error is a 404 that isn't described by the response body,
+/// which is the case for all HEAD requests.
+fn is_not_found<E>(e: &RusotoError<E>) -> bool {
+    matches!(e, RusotoError::Unknown(response) if response.status == StatusCode::NOT_FOUND)
+}
+
+impl std::fmt::Display for S3Blob {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "S3Blob[{}]", self.bucket)
+    }
+}
+
+impl std::fmt::Debug for S3Blob {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("S3Blob")
+            .field("bucket", &self.bucket)
+            .field("put_behaviour", &self.put_behaviour)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl BlobstorePutOps for S3Blob {
+    async fn put_explicit<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: String,
+        value: BlobstoreBytes,
+        put_behaviour: PutBehaviour,
+    ) -> Result<OverwriteStatus> {
+        let _permit = self.permit().await?;
+        let status = match put_behaviour {
+            PutBehaviour::Overwrite => {
+                self.put_object(&key, value).await?;
+                OverwriteStatus::NotChecked
+            }
+            // S3 has no conditional puts, so this races with other puts of
+            // the same key, as allowed by `PutBehaviour::IfAbsent`.
+            PutBehaviour::IfAbsent | PutBehaviour::OverwriteAndLog => {
+                if !self.head_object(&key).await? {
+                    self.put_object(&key, value).await?;
+                    OverwriteStatus::New
+                } else if put_behaviour.should_overwrite() {
+                    self.put_object(&key, value).await?;
+                    info!(ctx.logger(), "{}: overwrote existing key {}", self, key);
+                    OverwriteStatus::Overwrote
+                } else {
+                    // Refresh the ctime so that blobstore_gc does not sweep it
+                    self.touch_object(&key).await?;
+                    OverwriteStatus::Prevented
+                }
+            }
+        };
+        Ok(status)
+    }
+
+    async fn put_with_status<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: String,
+        value: BlobstoreBytes,
+    ) -> Result<OverwriteStatus> {
+        self.put_explicit(ctx, key, value, self.put_behaviour).await
+    }
+}
+
+#[async_trait]
+impl Blobstore for S3Blob {
+    async fn get<'a>(
+        &'a self,
+        _ctx: &'a CoreContext,
+        key: &'a str,
+    ) -> Result<Option<BlobstoreGetData>> {
+        let _permit = self.permit().await?;
+        let request = GetObjectRequest {
+            bucket: self.bucket.clone(),
+            key: key.to_string(),
+            ..Default::default()
+        };
+        with_timeout("GetObject", async {
+            let output = match self.client.get_object(request).await {
+                Ok(output) => output,
+                Err(RusotoError::Service(GetObjectError::NoSuchKey(_))) => return Ok(None),
+                Err(e) if is_not_found(&e) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+
+            let ctime = output
+                .last_modified
+                .and_then(|time| DateTime::parse_from_rfc2822(&time).ok())
+                .map(|time| time.timestamp());
+            let mut value = BytesMut::new();
+            if let Some(mut body) = output.body {
+                while let Some(chunk) = body.try_next().await? {
+                    value.extend_from_slice(&chunk);
+                }
+            }
+
+            Ok(Some(BlobstoreGetData::new(
+                BlobstoreMetadata::new(ctime, None),
+                BlobstoreBytes::from_bytes(value.freeze()),
+            )))
+        })
+        .await
+    }
+
+    async fn is_present<'a>(
+        &'a self,
+        _ctx: &'a CoreContext,
+        key: &'a str,
+    ) -> Result<BlobstoreIsPresent> {
+        let _permit = self.permit().await?;
+        Ok(if self.head_object(key).await? {
+            BlobstoreIsPresent::Present
+        } else {
+            BlobstoreIsPresent::Absent
+        })
+    }
+
+    async fn put<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: String,
+        value: BlobstoreBytes,
+    ) -> Result<()> {
+        BlobstorePutOps::put_with_status(self, ctx, key, value).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BlobstoreKeySource for S3Blob {
+    async fn enumerate<'a>(
+        &'a self,
+        _ctx: &'a CoreContext,
+        range: &'a BlobstoreKeyParam,
+    ) -> Result<BlobstoreEnumerationData> {
+        let _permit = self.permit().await?;
+        let mut keys = HashSet::new();
+
+        let ((page, next_continuation_token), end_key) = match range {
+            BlobstoreKeyParam::Start(range) => {
+                // Listings start after a key, but ranges include their first
+                // key, so check for it separately.
+                let begin_key = match range.begin_key.as_str() {
+                    "" => None,
+                    begin_key => Some(begin_key),
+                };
+                if let Some(begin_key) = begin_key {
+                    if (range.end_key.is_empty() || begin_key <= range.end_key.as_str())
+                        && self.head_object(begin_key).await?
+                    {
+                        keys.insert(begin_key.to_string());
+                    }
+                }
+                let page = self.list_objects(begin_key, None).await?;
+                (page, range.end_key.clone())
+            }
+            BlobstoreKeyParam::Continuation(BlobstoreKeyToken::StringToken(token)) => {
+                let token: ListToken =
+                    serde_json::from_str(token).context("Invalid S3Blob enumeration token")?;
+                let page = self
+                    .list_objects(None, Some(&token.continuation_token))
+                    .await?;
+                (page, token.end_key)
+            }
+        };
+
+        let mut past_end = false;
+        for key in page {
+            if !end_key.is_empty() && key > end_key {
+                past_end = true;
+                break;
+            }
+            keys.insert(key);
+        }
+
+        let next_token = match next_continuation_token {
+            Some(continuation_token) if !past_end => {
+                let token = serde_json::to_string(&ListToken {
+                    continuation_token,
+                    end_key,
+                })?;
+                Some(BlobstoreKeyParam::Continuation(
+                    BlobstoreKeyToken::StringToken(token),
+                ))
+            }
+            _ => None,
+        };
+
+        Ok(BlobstoreEnumerationData { keys, next_token })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::BTreeMap;
+    use std::convert::Infallible;
+    use std::env;
+    use std::sync::{Arc, Mutex};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use bytes::Bytes;
+    use fbinit::FacebookInit;
+    use hyper::server::conn::Http;
+    use hyper::service::service_fn;
+    use hyper::{Body, Method, Request, Response};
+    use percent_encoding::percent_decode_str;
+    use rusoto_core::credential::StaticProvider;
+    use tokio::net::TcpListener;
+
+    /// Set to the URL of an S3-compatible store, such as a local MinIO, to
+    /// run the tests against it too. `MONONOKE_TEST_S3_BUCKET` must name a
+    /// bucket in it, and the `AWS_*` environment variables must be set.
+    const TEST_ENDPOINT_ENV: &str = "MONONOKE_TEST_S3_ENDPOINT";
+    const TEST_BUCKET_ENV: &str = "MONONOKE_TEST_S3_BUCKET";
+
+    type Objects = Arc<Mutex<BTreeMap<String, Bytes>>>;
+
+    /// A stand-in for S3, which keeps objects in memory. It handles just
+    /// the requests that the blobstore makes, and checks that they are
+    /// signed, but not that the signatures are right.
+    async fn fake_s3(bucket: &'static str) -> Result<(String, Objects)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let endpoint = format!("http://{}", listener.local_addr()?);
+        let objects = Objects::default();
+        let server_objects = objects.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let objects = server_objects.clone();
+                let svc = service_fn(move |req| {
+                    let objects = objects.clone();
+                    async move {
+                        let response = handle_request(req, bucket, &objects)
+                            .await
+                            .unwrap_or_else(|e| response("400 Bad Request", e.to_string()));
+                        Ok::<_, Infallible>(response)
+                    }
+                });
+                tokio::spawn(Http::new().serve_connection(stream, svc));
+            }
+        });
+        Ok((endpoint, objects))
+    }
+
+    fn response(status: &str, body: impl Into<Body>) -> Response<Body> {
+        let status = status[..3].parse::<StatusCode>().expect("Invalid status");
+        Response::builder()
+            .status(status)
+            .header("last-modified", "Wed, 21 Oct 2015 07:28:00 GMT")
+            .body(body.into())
+            .expect("Invalid response")
+    }
+
+    fn error(status: &str, code: &str) -> Response<Body> {
+        response(status, format!("<Error><Code>{}</Code></Error>", code))
+    }
+
+    fn escape_xml(text: &str) -> String {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    }
+
+    async fn handle_request(
+        req: Request<Body>,
+        bucket: &str,
+        objects: &Objects,
+    ) -> Result<Response<Body>> {
+        let signed = req
+            .headers()
+            .get("authorization")
+            .and_then(|auth| auth.to_str().ok())
+            .map_or(false, |auth| {
+                auth.starts_with("AWS4-HMAC-SHA256 Credential=test/")
+            });
+        if !signed {
+            return Ok(error("403 Forbidden", "AccessDenied"));
+        }
+
+        let copy_source = match req.headers().get("x-amz-copy-source") {
+            Some(source) => Some(
+                percent_decode_str(source.to_str()?)
+                    .decode_utf8()?
+                    .into_owned(),
+            ),
+            None => None,
+        };
+        let method = req.method().clone();
+        let path = percent_decode_str(req.uri().path())
+            .decode_utf8()?
+            .into_owned();
+        let query = req
+            .uri()
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .filter_map(|param| {
+                let pos = param.find('=')?;
+                let value = percent_decode_str(&param[pos + 1..]).decode_utf8().ok()?;
+                Some((param[..pos].to_string(), value.into_owned()))
+            })
+            .collect::<BTreeMap<_, _>>();
+        let body = hyper::body::to_bytes(req.into_body()).await?;
+
+        let mut objects = objects.lock().unwrap();
+        let key = path
+            .strip_prefix(&format!("/{}/", bucket))
+            .map(str::to_string);
+        let response = match (method, key) {
+            (Method::GET, None) if path == format!("/{}", bucket) => {
+                let max_keys = query["max-keys"].parse::<usize>()?;
+                let start_after = query
+                    .get("continuation-token")
+                    .or_else(|| query.get("start-after"))
+                    .cloned()
+                    .unwrap_or_default();
+                let keys = objects
+                    .keys()
+                    .filter(|key| **key > start_after)
+                    .take(max_keys + 1)
+                    .collect::<Vec<_>>();
+                let mut xml = "<ListBucketResult>".to_string();
+                for key in keys.iter().take(max_keys) {
+                    xml.push_str(&format!(
+                        "<Contents><Key>{}</Key></Contents>",
+                        escape_xml(key)
+                    ));
+                }
+                if keys.len() > max_keys {
+                    xml.push_str(&format!(
+                        "<IsTruncated>true</IsTruncated>\
+                         <NextContinuationToken>{}</NextContinuationToken>",
+                        escape_xml(keys[max_keys - 1])
+                    ));
+                } else {
+                    xml.push_str("<IsTruncated>false</IsTruncated>");
+                }
+                xml.push_str("</ListBucketResult>");
+                response("200 OK", xml)
+            }
+            (Method::GET, Some(key)) => match objects.get(&key) {
+                Some(value) => response("200 OK", value.clone()),
+                None => error("404 Not Found", "NoSuchKey"),
+            },
+            (Method::HEAD, Some(key)) if objects.contains_key(&key) => {
+                response("200 OK", Body::empty())
+            }
+            (Method::HEAD, Some(_)) => response("404 Not Found", Body::empty()),
+            (Method::PUT, Some(key)) => match copy_source {
+                Some(source) => match source
+                    .strip_prefix(&format!("{}/", bucket))
+                    .and_then(|source| objects.get(source).cloned())
+                {
+                    Some(value) => {
+                        objects.insert(key, value);
+                        response(
+                            "200 OK",
+                            "<CopyObjectResult>\
+                             <LastModified>2015-10-21T07:28:00.000Z</LastModified>\
+                             </CopyObjectResult>",
+                        )
+                    }
+                    None => error("404 Not Found", "NoSuchKey"),
+                },
+                None => {
+                    objects.insert(key, body);
+                    response("200 OK", Body::empty())
+                }
+            },
+            _ => error("400 Bad Request", "InvalidRequest"),
+        };
+        Ok(response)
+    }
+
+    fn test_blob<P>(endpoint: &str, bucket: &str, credentials: P) -> Result<S3Blob>
+    where
+        P: ProvideAwsCredentials + Send + Sync + 'static,
+    {
+        let mut blob = S3Blob::with_credentials(
+            bucket.to_string(),
+            "us-east-1".to_string(),
+            endpoint,
+            credentials,
+            PutBehaviour::IfAbsent,
+            Some(2),
+        )?;
+        blob.list_max_keys = 2;
+        Ok(blob)
+    }
+
+    async fn check_blobstore(fb: FacebookInit, blob: &S3Blob, prefix: &str) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let key = |name: &str| format!("{}{}", prefix, name);
+        let value = |value: &'static str| BlobstoreBytes::from_bytes(value);
+
+        assert!(blob.get(&ctx, &key("a")).await?.is_none());
+        assert!(!blob.is_present(&ctx, &key("a")).await?.fail_if_unsure()?);
+
+        for (put_behaviour, name, expected) in &[
+            (PutBehaviour::IfAbsent, "a", OverwriteStatus::New),
+            (PutBehaviour::IfAbsent, "a", OverwriteStatus::Prevented),
+            (PutBehaviour::OverwriteAndLog, "b", OverwriteStatus::New),
+            (
+                PutBehaviour::OverwriteAndLog,
+                "b",
+                OverwriteStatus::Overwrote,
+            ),
+            (
+                PutBehaviour::Overwrite,
+                "c d/é",
+                OverwriteStatus::NotChecked,
+            ),
+            (
+                PutBehaviour::Overwrite,
+                "c d/é",
+                OverwriteStatus::NotChecked,
+            ),
+        ] {
+            let status = blob
+                .put_explicit(&ctx, key(name), value(name), *put_behaviour)
+                .await?;
+            assert_eq!(status, *expected, "{} {:?}", name, put_behaviour);
+        }
+        blob.put_explicit(&ctx, key("a"), value("new"), PutBehaviour::IfAbsent)
+            .await?;
+        blob.put(&ctx, key("e"), value("e")).await?;
+
+        let data = blob.get(&ctx, &key("a")).await?.expect("a is missing");
+        assert_eq!(data.as_bytes(), &value("a"));
+        assert!(data.as_meta().ctime().is_some());
+        assert!(blob
+            .is_present(&ctx, &key("c d/é"))
+            .await?
+            .fail_if_unsure()?);
+
+        // Enumerate every key, a page at a time.
+        let mut param = BlobstoreKeyParam::from(key("")..=key("~"));
+        let mut keys = HashSet::new();
+        loop {
+            let data = blob.enumerate(&ctx, &param).await?;
+            keys.extend(data.keys);
+            match data.next_token {
+                Some(next) => param = next,
+                None => break,
+            }
+        }
+        let expected = ["a", "b", "c d/é", "e"]
+            .iter()
+            .map(|name| key(name))
+            .collect::<HashSet<_>>();
+        assert_eq!(keys, expected);
+
+        // Both ends of ranges are inclusive.
+        let data = blob
+            .enumerate(&ctx, &BlobstoreKeyParam::from(key("b")..=key("c d/é")))
+            .await?;
+        assert_eq!(data.keys, hashset(&[key("b"), key("c d/é")]));
+        assert!(data.next_token.is_none());
+
+        let data = blob
+            .enumerate(&ctx, &BlobstoreKeyParam::from(key("bb")..=key("d")))
+            .await?;
+        assert_eq!(data.keys, hashset(&[key("c d/é")]));
+
+        Ok(())
+    }
+
+    fn hashset(keys: &[String]) -> HashSet<String> {
+        keys.iter().cloned().collect()
+    }
+
+    #[fbinit::test]
+    async fn test_fake_s3(fb: FacebookInit) -> Result<()> {
+        let (endpoint, objects) = fake_s3("bucket").await?;
+        let credentials = StaticProvider::new_minimal("test".to_string(), "secret".to_string());
+        let blob = test_blob(&endpoint, "bucket", credentials)?;
+        check_blobstore(fb, &blob, "").await?;
+        assert_eq!(objects.lock().unwrap().get("b"), Some(&Bytes::from("b")));
+
+        let ctx = CoreContext::test_mock(fb);
+        let credentials = StaticProvider::new_minimal("other".to_string(), "secret".to_string());
+        let unsigned = test_blob(&endpoint, "bucket", credentials)?;
+        assert!(unsigned.get(&ctx, "a").await.is_err());
+
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_s3(fb: FacebookInit) -> Result<()> {
+        let (endpoint, bucket) = match (env::var(TEST_ENDPOINT_ENV), env::var(TEST_BUCKET_ENV)) {
+            (Ok(endpoint), Ok(bucket)) => (endpoint, bucket),
+            _ => return Ok(()),
+        };
+        let blob = test_blob(&endpoint, &bucket, ChainProvider::new())?;
+        let prefix = format!(
+            "s3blob_test_{}/",
+            SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos()
+        );
+        check_blobstore(fb, &blob, &prefix).await
+    }
+}
@@ -0,0 +1,29 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+CREATE TABLE data (
+  id VARCHAR(255) NOT NULL,
+  creation_time BIGINT NOT NULL,
+  chunk_id VARCHAR(255) NOT NULL,
+  chunk_count BIGINT NOT NULL,
+  chunking_method BIGINT NOT NULL,
+  PRIMARY KEY (id)
+);
+
+CREATE TABLE chunk (
+  id VARCHAR(255) NOT NULL,
+  creation_time TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  chunk_num BIGINT NOT NULL,
+  value BYTEA NOT NULL,
+  PRIMARY KEY (id, chunk_num)
+);
+
+CREATE TABLE chunk_generation (
+  id VARCHAR(255) NOT NULL,
+  last_seen_generation BIGINT NOT NULL,
+  PRIMARY KEY (id)
+);
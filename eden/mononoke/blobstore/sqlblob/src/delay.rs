@@ -54,7 +54,6 @@ impl BlobDelay {
         }
     }
 
-    #[cfg(fbcode_build)]
     pub fn from_channels(lag_receivers: Vec<watch::Receiver<Duration>>, name: String) -> Self {
         let entity = Some(name);
         Self {
@@ -11,7 +11,7 @@ mod delay;
 #[cfg(fbcode_build)]
 mod facebook;
 #[cfg(not(fbcode_build))]
-mod myadmin_delay_dummy;
+mod myadmin_delay;
 mod store;
 #[cfg(test)]
 mod tests;
@@ -19,13 +19,12 @@ mod tests;
 use crate::delay::BlobDelay;
 #[cfg(fbcode_build)]
 use crate::facebook::myadmin_delay;
-#[cfg(not(fbcode_build))]
-use crate::myadmin_delay_dummy as myadmin_delay;
 use crate::store::{ChunkSqlStore, ChunkingMethod, DataSqlStore};
-use anyhow::{bail, format_err, Error, Result};
+use anyhow::{bail, format_err, Context, Error, Result};
 use async_trait::async_trait;
 use blobstore::{
-    Blobstore, BlobstoreGetData, BlobstoreIsPresent, BlobstoreMetadata, BlobstorePutOps,
+    Blobstore, BlobstoreEnumerationData, BlobstoreGetData, BlobstoreIsPresent, BlobstoreKeyParam,
+    BlobstoreKeyRange, BlobstoreKeySource, BlobstoreKeyToken, BlobstoreMetadata, BlobstorePutOps,
     BlobstoreWithLink, CountedBlobstore, OverwriteStatus, PutBehaviour,
 };
 use bytes::{Bytes, BytesMut};
@@ -35,19 +34,23 @@ use fbinit::FacebookInit;
 use futures::stream::{FuturesOrdered, FuturesUnordered, Stream, TryStreamExt};
 use mononoke_types::{hash::Context as HashContext, BlobstoreBytes};
 use nonzero_ext::nonzero;
+use serde::{Deserialize, Serialize};
 use sql::{rusqlite::Connection as SqliteConnection, Connection};
 use sql_ext::{
+    create_postgres_connections_sharded, create_postgres_connections_unsharded,
     facebook::{
         create_mysql_connections_sharded, create_mysql_connections_unsharded, MysqlOptions,
     },
-    open_sqlite_in_memory, open_sqlite_path, SqlConnections, SqlShardedConnections,
+    open_postgres_test_connections, open_sqlite_in_memory, open_sqlite_path, SqlConnections,
+    SqlShardedConnections,
 };
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     convert::TryInto,
     fmt,
     future::Future,
     num::NonZeroUsize,
+    ops::RangeBounds,
     path::PathBuf,
     sync::Arc,
     time::{Duration, SystemTime},
@@ -59,6 +62,8 @@ use xdb_gc_structs::XdbGc;
 const MAX_KEY_SIZE: usize = 200;
 // MySQL wants multiple chunks, each around 1 MiB, as a tradeoff between query latency and replication lag
 const CHUNK_SIZE: usize = 1024 * 1024;
+// Number of keys to read from a shard in each call to enumerate
+const ENUMERATE_PAGE_SIZE: u64 = 10_000;
 const SQLITE_SHARD_NUM: NonZeroUsize = nonzero!(2_usize);
 const SINGLE_SHARD_NUM: NonZeroUsize = nonzero!(1_usize);
 const GC_GENERATION_PATH: &str = "scm/mononoke/xdb_gc/default";
@@ -77,6 +82,7 @@ pub struct Sqlblob {
     chunk_store: Arc<ChunkSqlStore>,
     put_behaviour: PutBehaviour,
     allow_inline_put: bool,
+    enumerate_page_size: u64,
 }
 
 impl std::fmt::Display for Sqlblob {
@@ -91,6 +97,23 @@ fn get_gc_config_handle(config_store: &ConfigStore) -> Result<ConfigHandle<XdbGc
 
 const DEFAULT_ALLOW_INLINE_PUT: bool = true;
 
+/// What `myadmin_delay` reads replication lag through.
+#[cfg(fbcode_build)]
+fn lag_source(fb: FacebookInit, _mysql_options: &MysqlOptions) -> Result<FacebookInit> {
+    Ok(fb)
+}
+
+/// What `myadmin_delay` reads replication lag through. Outside of fbcode,
+/// lag is read from the databases themselves, with the same connection
+/// settings as the blobstore.
+#[cfg(not(fbcode_build))]
+fn lag_source(
+    fb: FacebookInit,
+    mysql_options: &MysqlOptions,
+) -> Result<sql_ext::facebook::MyAdmin> {
+    mysql_options.myadmin(fb)
+}
+
 // base64 encoding for inline hash has an overhead
 pub const MAX_INLINE_LEN: usize = 255 * 3 / 4;
 
@@ -107,7 +130,7 @@ impl Sqlblob {
         let delay = if readonly {
             BlobDelay::dummy(shard_num)
         } else {
-            myadmin_delay::sharded(fb, shardmap.clone(), shard_num)?
+            myadmin_delay::sharded(lag_source(fb, &mysql_options)?, shardmap.clone(), shard_num)?
         };
         let config_handle = get_gc_config_handle(config_store)?;
         let shard_count = shard_num.clone().get();
@@ -153,6 +176,7 @@ impl Sqlblob {
                 )),
                 put_behaviour,
                 allow_inline_put: DEFAULT_ALLOW_INLINE_PUT,
+                enumerate_page_size: ENUMERATE_PAGE_SIZE,
             },
             shardmap,
         ))
@@ -169,7 +193,7 @@ impl Sqlblob {
         let delay = if readonly {
             BlobDelay::dummy(SINGLE_SHARD_NUM)
         } else {
-            myadmin_delay::single(fb, db_address.clone())?
+            myadmin_delay::single(lag_source(fb, &mysql_options)?, db_address.clone())?
         };
         Self::with_connection_factory(
             delay,
@@ -192,6 +216,82 @@ impl Sqlblob {
         .await
     }
 
+    pub async fn with_postgres(
+        shardmap: String,
+        shard_num: NonZeroUsize,
+        mysql_options: MysqlOptions,
+        readonly: bool,
+        put_behaviour: PutBehaviour,
+        config_store: &ConfigStore,
+    ) -> Result<CountedSqlblob, Error> {
+        let SqlShardedConnections {
+            read_connections,
+            read_master_connections,
+            write_connections,
+        } = create_postgres_connections_sharded(
+            &mysql_options,
+            SQLBLOB_LABEL.into(),
+            shardmap.clone(),
+            0..shard_num.get(),
+            readonly,
+        )?;
+
+        let shard_connections = write_connections
+            .into_iter()
+            .zip(read_connections)
+            .zip(read_master_connections)
+            .map(
+                |((write_connection, read_connection), read_master_connection)| SqlConnections {
+                    write_connection,
+                    read_connection,
+                    read_master_connection,
+                },
+            )
+            .collect::<Vec<_>>();
+
+        Self::with_connection_factory(
+            BlobDelay::dummy(shard_num),
+            shardmap,
+            shard_num,
+            put_behaviour,
+            move |shard_id| {
+                let res = Ok(shard_connections[shard_id].clone());
+                async { res }
+            },
+            config_store,
+            DEFAULT_ALLOW_INLINE_PUT,
+        )
+        .await
+    }
+
+    pub async fn with_postgres_unsharded(
+        db_address: String,
+        mysql_options: MysqlOptions,
+        readonly: bool,
+        put_behaviour: PutBehaviour,
+        config_store: &ConfigStore,
+    ) -> Result<CountedSqlblob, Error> {
+        let connections = create_postgres_connections_unsharded(
+            &mysql_options,
+            SQLBLOB_LABEL.into(),
+            db_address.clone(),
+            readonly,
+        )?;
+        Self::with_connection_factory(
+            BlobDelay::dummy(SINGLE_SHARD_NUM),
+            db_address,
+            SINGLE_SHARD_NUM,
+            put_behaviour,
+            move |_shard_id| {
+                let res = Ok(connections.clone());
+                async { res }
+            },
+            config_store,
+            DEFAULT_ALLOW_INLINE_PUT,
+        )
+        .await
+    }
+
     async fn with_connection_factory<CF, SF>(
         delay: BlobDelay,
         label: String,
@@ -248,11 +348,38 @@ impl Sqlblob {
                 )),
                 put_behaviour,
                 allow_inline_put,
+                enumerate_page_size: ENUMERATE_PAGE_SIZE,
             },
             label,
         ))
     }
 
+    /// Construct a blobstore backed by a fresh schema in the PostgreSQL
+    /// database at `db_address`, for tests. The schema is dropped again once
+    /// the blobstore is.
+    pub fn with_postgres_test_schema(
+        db_address: &str,
+        put_behaviour: PutBehaviour,
+        config_store: &ConfigStore,
+        allow_inline_put: bool,
+    ) -> Result<CountedSqlblob> {
+        let connections = open_postgres_test_connections(
+            db_address,
+            SQLBLOB_LABEL,
+            Self::POSTGRES_CREATION_QUERY,
+        )?;
+        let cons = (0..SQLITE_SHARD_NUM.get())
+            .map(|_| connections.write_connection.clone())
+            .collect();
+        Self::with_test_connections(
+            put_behaviour,
+            cons,
+            config_store,
+            allow_inline_put,
+            "postgres".into(),
+        )
+    }
+
     pub fn with_sqlite_in_memory(
         put_behaviour: PutBehaviour,
         config_store: &ConfigStore,
@@ -309,6 +436,22 @@ impl Sqlblob {
             cons.push(Connection::with_sqlite(constructor(i)?));
         }
 
+        Self::with_test_connections(
+            put_behaviour,
+            cons,
+            config_store,
+            allow_inline_put,
+            "sqlite".into(),
+        )
+    }
+
+    fn with_test_connections(
+        put_behaviour: PutBehaviour,
+        cons: Vec<Connection>,
+        config_store: &ConfigStore,
+        allow_inline_put: bool,
+        label: String,
+    ) -> Result<CountedSqlblob> {
         let cons = Arc::new(cons);
 
         // SQLite is predominately intended for tests, and has less concurrency
@@ -335,13 +478,16 @@ impl Sqlblob {
                 )),
                 put_behaviour,
                 allow_inline_put,
+                enumerate_page_size: ENUMERATE_PAGE_SIZE,
             },
-            "sqlite".into(),
+            label,
         ))
     }
 
     const CREATION_QUERY: &'static str = include_str!("../schema/sqlite-sqlblob.sql");
 
+    const POSTGRES_CREATION_QUERY: &'static str = include_str!("../schema/postgres-sqlblob.sql");
+
     fn counted(self, label: String) -> CountedBlobstore<Self> {
         CountedBlobstore::new(format!("{}.{}", COUNTED_ID, label), self)
     }
@@ -400,6 +546,14 @@ impl Sqlblob {
     }
 }
 
+fn now_secs() -> Result<i64> {
+    let secs = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(offset) => offset.as_secs().try_into(),
+        Err(negative) => negative.duration().as_secs().try_into().map(|v: i64| -v),
+    }?;
+    Ok(secs)
+}
+
 impl fmt::Debug for Sqlblob {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Sqlblob").finish()
@@ -491,7 +645,9 @@ impl BlobstorePutOps for Sqlblob {
         }
 
         if put_behaviour == PutBehaviour::IfAbsent && self.data_store.is_present(&key).await? {
-            // Can short circuit here as key already exists, and is keeping its chunks live
+            // Can short circuit here as key already exists, and is keeping its chunks live.
+            // Refresh its ctime so that blobstore_gc does not sweep it.
+            self.data_store.touch(&key, now_secs()?).await?;
             return Ok(OverwriteStatus::Prevented);
         }
 
@@ -502,12 +658,7 @@ impl BlobstorePutOps for Sqlblob {
         };
 
         let put_fut = async {
-            let ctime = {
-                match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-                    Ok(offset) => offset.as_secs().try_into(),
-                    Err(negative) => negative.duration().as_secs().try_into().map(|v: i64| -v),
-                }
-            }?;
+            let ctime = now_secs()?;
             let (chunk_key, chunk_count) = match chunking_method {
                 ChunkingMethod::ByContentHashBlake2 => {
                     let chunk_key = {
@@ -570,6 +721,7 @@ impl BlobstorePutOps for Sqlblob {
                                     )
                                     .await?;
                             }
+                            self.data_store.touch(&key, now_secs()?).await?;
                             Ok(OverwriteStatus::Prevented)
                         }
                     }
@@ -622,6 +774,88 @@ impl BlobstoreWithLink for Sqlblob {
     }
 }
 
+/// Where a multi-call enumeration of a Sqlblob has got to
+#[derive(Serialize, Deserialize)]
+struct ShardToken {
+    shard_num: usize,
+    range: BlobstoreKeyRange,
+    /// The last key returned from the shard, if any have been
+    #[serde(default)]
+    after: Option<String>,
+}
+
+#[async_trait]
+impl BlobstoreKeySource for Sqlblob {
+    /// Enumerates up to `enumerate_page_size` keys of one shard per call, returning a token for
+    /// the rest of the shard, or for the next shard if there is one
+    async fn enumerate<'a>(
+        &'a self,
+        _ctx: &'a CoreContext,
+        range: &'a BlobstoreKeyParam,
+    ) -> Result<BlobstoreEnumerationData> {
+        let token = match range {
+            BlobstoreKeyParam::Start(range) => ShardToken {
+                shard_num: 0,
+                range: range.clone(),
+                after: None,
+            },
+            BlobstoreKeyParam::Continuation(BlobstoreKeyToken::StringToken(token)) => {
+                serde_json::from_str(token).context("Invalid Sqlblob enumeration token")?
+            }
+        };
+        let ShardToken {
+            shard_num,
+            range,
+            after,
+        } = token;
+        if shard_num >= self.data_store.shard_count() {
+            bail!("Sqlblob enumeration token for unknown shard {}", shard_num);
+        }
+
+        let page = self
+            .data_store
+            .get_keys_page(
+                shard_num,
+                after.as_deref().unwrap_or_default(),
+                self.enumerate_page_size,
+            )
+            .await?;
+        let past_end = match page.last() {
+            Some(last) => !range.end_key.is_empty() && *last > range.end_key,
+            None => true,
+        };
+        let next_token = if page.len() as u64 == self.enumerate_page_size && !past_end {
+            Some(ShardToken {
+                shard_num,
+                after: page.last().cloned(),
+                range: range.clone(),
+            })
+        } else if shard_num + 1 < self.data_store.shard_count() {
+            Some(ShardToken {
+                shard_num: shard_num + 1,
+                range: range.clone(),
+                after: None,
+            })
+        } else {
+            None
+        };
+        let next_token = next_token
+            .map(|token| -> Result<_> {
+                Ok(BlobstoreKeyParam::Continuation(
+                    BlobstoreKeyToken::StringToken(serde_json::to_string(&token)?),
+                ))
+            })
+            .transpose()?;
+
+        let keys: HashSet<String> = page
+            .into_iter()
+            .filter(|key| (&range).contains(key))
+            .collect();
+
+        Ok(BlobstoreEnumerationData { keys, next_token })
+    }
+}
+
 pub fn set_test_generations(
     source: &TestSource,
     put_generation: i64,
@@ -0,0 +1,73 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::num::NonZeroUsize;
+use std::time::Duration;
+
+use anyhow::Result;
+use sql_ext::{facebook::MyAdmin, replication::ReplicaLagMonitor, shard_address};
+use stats::prelude::*;
+use tokio::sync::watch;
+
+use crate::delay::{BlobDelay, MAX_LAG};
+
+const LAG_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+define_stats! {
+    prefix = "mononoke.sqlblob.lag_monitor";
+    lag_read_errors: dynamic_timeseries("{}.lag_read_errors", (entity: String); Rate, Sum),
+}
+
+pub fn sharded(myadmin: MyAdmin, shardmap: String, shard_count: NonZeroUsize) -> Result<BlobDelay> {
+    let lag_receivers = (0..shard_count.get())
+        .map(|shard_id| {
+            let address = shard_address(&shardmap, shard_id)?;
+            Ok(watch_lag(
+                myadmin.single_shard_lag_monitor(address),
+                shardmap.clone(),
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(BlobDelay::from_channels(lag_receivers, shardmap))
+}
+
+pub fn single(myadmin: MyAdmin, shard: String) -> Result<BlobDelay> {
+    let lag_receiver = watch_lag(
+        myadmin.single_shard_lag_monitor(shard.clone()),
+        shard.clone(),
+    );
+    Ok(BlobDelay::from_channels(vec![lag_receiver], shard))
+}
+
+/// Poll replication lag in the background, for as long as anyone is
+/// listening.
+///
+/// Until lag has been read once, it is taken to be `MAX_LAG`, so writes
+/// wait for the first reading. After that, a failure to read lag keeps the
+/// last reading: a monitoring hiccup neither lets writes run ahead of a
+/// lagging replica nor stops them. Failures are counted in
+/// `lag_read_errors`.
+fn watch_lag<M>(monitor: M, entity: String) -> watch::Receiver<Duration>
+where
+    M: ReplicaLagMonitor + 'static,
+{
+    let (sender, receiver) = watch::channel(MAX_LAG);
+    tokio::spawn(async move {
+        let mut last_lag = MAX_LAG;
+        loop {
+            match monitor.get_max_replica_lag().await {
+                Ok(lag) => last_lag = lag.delay,
+                Err(_) => STATS::lag_read_errors.add_value(1, (entity.clone(),)),
+            }
+            if sender.send(last_lag).is_err() {
+                break;
+            }
+            tokio::time::sleep(LAG_POLL_INTERVAL).await;
+        }
+    });
+    receiver
+}
@@ -114,6 +114,12 @@ queries! {
         WHERE id = {id}"
     }
 
+    write TouchData(id: &str, ctime: i64) {
+        none,
+        "UPDATE data SET creation_time = {ctime}
+        WHERE id = {id} AND creation_time < {ctime}"
+    }
+
     write InsertChunk(values: (id: &str, chunk_num: u32, value: &[u8])) {
         insert_or_ignore,
         "{insert_or_ignore} INTO chunk (
@@ -172,6 +178,13 @@ queries! {
         "SELECT id FROM data"
     }
 
+    read GetKeysPage(after: &str, limit: u64) -> (Vec<u8>) {
+        "SELECT id FROM data
+        WHERE id > {after}
+        ORDER BY id
+        LIMIT {limit}"
+    }
+
     read GetGenerationSizes() -> (Option<u64>, u64) {
         "SELECT chunk_generation.last_seen_generation, CAST(SUM(LENGTH(chunk.value)) AS UNSIGNED)
         FROM chunk LEFT JOIN chunk_generation ON chunk.id = chunk_generation.id
@@ -266,6 +279,17 @@ impl DataSqlStore {
         Ok(())
     }
 
+    /// Refresh the ctime of a key that was put again, so that garbage
+    /// collection sees it as recently written.
+    pub(crate) async fn touch(&self, key: &str, ctime: i64) -> Result<(), Error> {
+        let shard_id = self.shard(key);
+
+        self.delay.delay(shard_id).await;
+
+        TouchData::query(&self.write_connection[shard_id], &key, &ctime).await?;
+        Ok(())
+    }
+
     pub(crate) async fn unlink(&self, key: &str) -> Result<(), Error> {
         let shard_id = self.shard(key);
 
@@ -297,6 +321,10 @@ impl DataSqlStore {
         Ok(!rows.is_empty())
     }
 
+    pub(crate) fn shard_count(&self) -> usize {
+        self.shard_count.get()
+    }
+
     pub(crate) fn get_keys_from_shard(
         &self,
         shard_num: usize,
@@ -312,6 +340,21 @@ impl DataSqlStore {
         .try_flatten_stream()
     }
 
+    /// Up to `limit` keys of a shard that sort after `after`, in order.
+    pub(crate) async fn get_keys_page(
+        &self,
+        shard_num: usize,
+        after: &str,
+        limit: u64,
+    ) -> Result<Vec<String>, Error> {
+        let keys =
+            GetKeysPage::query(&self.read_master_connection[shard_num], &after, &limit).await?;
+        Ok(keys
+            .into_iter()
+            .map(|(id,)| String::from_utf8_lossy(&id).to_string())
+            .collect())
+    }
+
     fn shard(&self, key: &str) -> usize {
         let mut hasher = XxHash32::with_seed(0);
         hasher.write(key.as_bytes());
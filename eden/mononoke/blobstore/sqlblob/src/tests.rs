@@ -255,6 +255,68 @@ async fn link(fb: FacebookInit) -> Result<(), Error> {
     .await
 }
 
+#[fbinit::test]
+async fn enumerate(fb: FacebookInit) -> Result<(), Error> {
+    test_chunking_methods(fb, DEFAULT_PUT_BEHAVIOUR, |ctx, bs, _| async move {
+        borrowed!(ctx);
+        let keys: Vec<_> = (0..20)
+            .map(|i| format!("enumerate_test_{:02}", i))
+            .collect();
+        for key in &keys {
+            bs.put(ctx, key.clone(), BlobstoreBytes::from_bytes(key.clone()))
+                .await?;
+        }
+
+        // Collect across all the shards a few keys at a time, only keeping keys in range
+        let mut bs = bs.into_inner();
+        bs.enumerate_page_size = 3;
+        let mut found = HashSet::new();
+        let mut calls = 0;
+        let mut param = BlobstoreKeyParam::from(keys[5].clone()..="enumerate_test_14".to_string());
+        loop {
+            let data = bs.enumerate(ctx, &param).await?;
+            calls += 1;
+            found.extend(data.keys);
+            match data.next_token {
+                Some(next) => param = next,
+                None => break,
+            }
+        }
+        assert!(calls > SQLITE_SHARD_NUM.get(), "Shards are paged");
+        let expected: HashSet<_> = keys[5..15].iter().cloned().collect();
+        assert_eq!(found, expected);
+        Ok(())
+    })
+    .await
+}
+
+#[fbinit::test]
+async fn prevented_put_refreshes_ctime(fb: FacebookInit) -> Result<(), Error> {
+    test_chunking_methods(fb, PutBehaviour::IfAbsent, |ctx, bs, _| async move {
+        borrowed!(ctx);
+        let key = "refresh_test".to_string();
+        bs.put(ctx, key.clone(), BlobstoreBytes::from_bytes("value"))
+            .await?;
+
+        // Make the key look old, as if it had been written long ago
+        let data_store = bs.as_inner().get_data_store();
+        let chunked = data_store.get(&key).await?.expect("key is missing");
+        data_store
+            .put(&key, 0, &chunked.id, chunked.count, chunked.chunking_method)
+            .await?;
+        let ctime = |data: Option<BlobstoreGetData>| data.and_then(|data| data.as_meta().ctime());
+        assert_eq!(ctime(bs.get(ctx, &key).await?), Some(0));
+
+        let status = bs
+            .put_with_status(ctx, key.clone(), BlobstoreBytes::from_bytes("value"))
+            .await?;
+        assert_eq!(status, OverwriteStatus::Prevented);
+        assert!(ctime(bs.get(ctx, &key).await?) > Some(0));
+        Ok(())
+    })
+    .await
+}
+
 #[fbinit::test]
 async fn generations(fb: FacebookInit) -> Result<(), Error> {
     test_chunking_methods(
@@ -13,4 +13,6 @@ pub enum ErrorKind {
     NotFound(String),
     #[error("Error while opening state for blob store")]
     StateOpen,
+    #[error("Blob {0} does not match its checksum, and may be corrupt")]
+    ChecksumMismatch(String),
 }
@@ -382,6 +382,8 @@ pub enum PutBehaviour {
     /// NB due to underlying stores TOCTOU limitations some puts might overwrite when when racing another put.
     /// This is expected, thus Blobstore::put() cannot reveal if the put wrote or not as behaviour other than
     /// logging/metrics should not depend on it.
+    /// Stores that can be garbage collected by `blobstore_gc` refresh the ctime of a key whose put
+    /// was prevented, as whatever made the put may be about to reference the key again.
     IfAbsent,
 }
 
@@ -470,6 +472,12 @@ pub trait BlobstoreKeySource: Blobstore {
     ) -> Result<BlobstoreEnumerationData>;
 }
 
+/// Mixin trait for blobstores that can both enumerate and unlink their keys,
+/// as needed to sweep unreferenced blobs
+pub trait BlobstoreEnumerableWithUnlink: BlobstoreKeySource + BlobstoreWithLink {}
+
+impl<T: BlobstoreKeySource + BlobstoreWithLink> BlobstoreEnumerableWithUnlink for T {}
+
 /// Range of keys.  The range is inclusive (both start and end key are
 /// included in the range), which matches Manifold behaviour.  If the key is
 /// empty then the range is unbounded on that end.
@@ -0,0 +1,14 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+CREATE TABLE bonsai_hg_mapping (
+  repo_id INTEGER NOT NULL,
+  hg_cs_id BYTEA NOT NULL,
+  bcs_id BYTEA NOT NULL,
+  UNIQUE (repo_id, hg_cs_id),
+  PRIMARY KEY (repo_id, bcs_id)
+);
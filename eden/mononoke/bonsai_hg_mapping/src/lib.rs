@@ -271,6 +271,9 @@ impl SqlConstruct for SqlBonsaiHgMappingBuilder {
 
     const CREATION_QUERY: &'static str = include_str!("../schemas/sqlite-bonsai-hg-mapping.sql");
 
+    const POSTGRES_CREATION_QUERY: Option<&'static str> =
+        Some(include_str!("../schemas/postgres-bonsai-hg-mapping.sql"));
+
     fn from_sql_connections(connections: SqlConnections) -> Self {
         Self { connections }
     }
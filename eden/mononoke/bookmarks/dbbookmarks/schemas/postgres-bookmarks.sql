@@ -0,0 +1,35 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+CREATE TABLE bookmarks (
+  repo_id BIGINT NOT NULL,
+  name VARCHAR(512) NOT NULL,
+  changeset_id BYTEA NOT NULL,
+  -- this column is named 'hg_kind' for historical reasons, but applies for non-Mercurial uses (e.g. phase calculations)
+  hg_kind VARCHAR(32) NOT NULL DEFAULT 'pull_default',
+  log_id BIGINT NULL,
+  PRIMARY KEY (repo_id, name),
+  UNIQUE (repo_id, log_id)
+);
+
+CREATE INDEX repo_id_hg_kind ON bookmarks (repo_id, hg_kind);
+
+CREATE TABLE bookmarks_update_log (
+  id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+  repo_id BIGINT NOT NULL,
+  name VARCHAR(512) NOT NULL,
+  from_changeset_id BYTEA,
+  to_changeset_id BYTEA,
+  reason VARCHAR(32) NOT NULL,
+  timestamp BIGINT NOT NULL
+);
+
+CREATE TABLE bundle_replay_data (
+  bookmark_update_log_id BIGINT PRIMARY KEY NOT NULL,
+  bundle_handle VARCHAR(256) NOT NULL,
+  commit_hashes_json TEXT NOT NULL
+);
@@ -21,6 +21,9 @@ impl SqlConstruct for SqlBookmarksBuilder {
 
     const CREATION_QUERY: &'static str = include_str!("../schemas/sqlite-bookmarks.sql");
 
+    const POSTGRES_CREATION_QUERY: Option<&'static str> =
+        Some(include_str!("../schemas/postgres-bookmarks.sql"));
+
     fn from_sql_connections(connections: SqlConnections) -> Self {
         Self { connections }
     }
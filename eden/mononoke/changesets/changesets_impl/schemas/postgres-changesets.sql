@@ -0,0 +1,23 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+CREATE TABLE changesets (
+  id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+  repo_id INTEGER NOT NULL,
+  cs_id BYTEA NOT NULL,
+  gen BIGINT NOT NULL,
+  UNIQUE (repo_id, cs_id)
+);
+
+CREATE INDEX changesets_repo_id_id ON changesets (repo_id, id);
+
+CREATE TABLE csparents (
+  cs_id BIGINT NOT NULL,
+  parent_id BIGINT NOT NULL,
+  seq INTEGER NOT NULL,
+  PRIMARY KEY (cs_id, seq)
+);
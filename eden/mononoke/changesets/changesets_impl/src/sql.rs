@@ -206,6 +206,9 @@ impl SqlConstruct for SqlChangesetsBuilder {
 
     const CREATION_QUERY: &'static str = include_str!("../schemas/sqlite-changesets.sql");
 
+    const POSTGRES_CREATION_QUERY: Option<&'static str> =
+        Some(include_str!("../schemas/postgres-changesets.sql"));
+
     fn from_sql_connections(connections: SqlConnections) -> Self {
         Self { connections }
     }
@@ -51,6 +51,8 @@ pub const TUNABLES_CONFIG: &str = "tunables-config";
 pub const DISABLE_TUNABLES: &str = "disable-tunables";
 pub const SCRIBE_LOGGING_DIRECTORY: &str = "scribe-logging-directory";
 pub const RENDEZVOUS_FREE_CONNECTIONS: &str = "rendezvous-free-connections";
+pub const ACL_CONFIG: &str = "acl-config";
+pub const SCRIBE_CONFIG: &str = "scribe-config";
 
 pub const READ_QPS_ARG: &str = "blobstore-read-qps";
 pub const WRITE_QPS_ARG: &str = "blobstore-write-qps";
@@ -69,6 +71,7 @@ pub const BLOBSTORE_SCRUB_ACTION_ARG: &str = "blobstore-scrub-action";
 pub const BLOBSTORE_SCRUB_GRACE_ARG: &str = "blobstore-scrub-grace";
 pub const BLOBSTORE_SCRUB_WRITE_MOSTLY_MISSING_ARG: &str = "blobstore-scrub-write-mostly-missing";
 pub const BLOBSTORE_SCRUB_QUEUE_PEEK_BOUND_ARG: &str = "blobstore-scrub-queue-peek";
+pub const BLOBSTORE_SCRUB_REENCRYPT_ARG: &str = "blobstore-scrub-reencrypt";
 pub const PUT_MEAN_DELAY_SECS_ARG: &str = "blobstore-put-mean-delay-secs";
 pub const PUT_STDDEV_DELAY_SECS_ARG: &str = "blobstore-put-stddev-delay-secs";
 pub const GET_MEAN_DELAY_SECS_ARG: &str = "blobstore-get-mean-delay-secs";
@@ -590,6 +593,8 @@ impl MononokeAppBuilder {
         }
 
         app = add_megarepo_svc_args(app);
+        app = add_acl_args(app);
+        app = add_scribe_config_args(app);
 
         MononokeClapApp {
             clap: app,
@@ -809,10 +814,16 @@ impl MononokeAppBuilder {
                 scrub_action_on_missing_write_mostly_arg =
                     scrub_action_on_missing_write_mostly_arg.default_value(default.into());
             }
+            let scrub_reencrypt_arg = Arg::with_name(BLOBSTORE_SCRUB_REENCRYPT_ARG)
+                .long(BLOBSTORE_SCRUB_REENCRYPT_ARG)
+                .takes_value(false)
+                .required(false)
+                .help("Re-encrypt blobs in encrypted blobstores that are not encrypted with the active key as they are read");
             app.arg(scrub_action_arg)
                 .arg(scrub_grace_arg)
                 .arg(scrub_action_on_missing_write_mostly_arg)
                 .arg(scrub_queue_peek_bound_arg)
+                .arg(scrub_reencrypt_arg)
         } else {
             app
         }
@@ -1126,3 +1137,27 @@ fn add_megarepo_svc_args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
             .default_value("false"),
     )
 }
+
+fn add_acl_args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    app.arg(
+        Arg::with_name(ACL_CONFIG)
+            .long(ACL_CONFIG)
+            .takes_value(true)
+            .help(
+                "The location of a config with repo and tier ACLs and groups. \
+                 Only used in builds without access to Facebook's ACL services.",
+            ),
+    )
+}
+
+fn add_scribe_config_args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    app.arg(
+        Arg::with_name(SCRIBE_CONFIG)
+            .long(SCRIBE_CONFIG)
+            .takes_value(true)
+            .help(
+                "The location of a config that says where to send Scribe samples. \
+                 Only used in builds without a Scribe daemon.",
+            ),
+    )
+}
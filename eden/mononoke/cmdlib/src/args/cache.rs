@@ -5,6 +5,8 @@
  * GNU General Public License version 2.
  */
 
+#[cfg(not(fbcode_build))]
+use anyhow::Result;
 use clap::{App, Arg, ArgMatches};
 use environment::Caching;
 use fbinit::FacebookInit;
@@ -23,6 +25,7 @@ const CACHELIB_ONLY_BLOBSTORE_NEW: &str = "blobstore-cachelib-only";
 const CACHELIB_SHARDS: &str = "cachelib-shards";
 const CACHELIB_REBALANCING_USE_LRU: &str = "cachelib-rebalancing-use-lru";
 const CACHELIB_REBALANCING_INTERVAL: &str = "cachelib-rebalancing-interval-secs";
+const MEMCACHE_SERVERS: &str = "memcache-servers";
 
 const PHASES_CACHE_SIZE: &str = "phases-cache-size";
 const SEGMENTED_CHANGELOG_CACHE_SIZE: &str = "segmented-changelog-cache-size";
@@ -165,6 +168,18 @@ pub(crate) fn add_cachelib_args<'a, 'b>(
             .takes_value(true)
             .help("number of shards to control concurrent access to a blobstore behind cachelib"),
     )
+    .arg(
+        Arg::with_name(MEMCACHE_SERVERS)
+            .long(MEMCACHE_SERVERS)
+            .takes_value(true)
+            .multiple(true)
+            .use_delimiter(true)
+            .value_name("HOST:PORT")
+            .help(
+                "memcached servers to share the blobstore cache with. \
+                 Only used in builds without access to Facebook's memcache.",
+            ),
+    )
     .args(&cache_args)
 }
 
@@ -264,8 +279,10 @@ pub fn parse_and_init_cachelib(
 
             #[cfg(not(fbcode_build))]
             {
-                let _ = fb;
-                unimplemented!("Initialization of cachelib works only for fbcode builds")
+                init_cachelib_from_settings(fb, settings).unwrap();
+                if let Some(servers) = matches.values_of(MEMCACHE_SERVERS) {
+                    memcache::init_servers(servers).unwrap();
+                }
             }
             #[cfg(fbcode_build)]
             {
@@ -328,3 +345,52 @@ impl Default for CachelibSettings {
         }
     }
 }
+
+/// Without cachelib, the cache is in the process's own memory, so the
+/// settings for shrinking it as the process grows, and for tuning cachelib
+/// itself, are ignored.
+#[cfg(not(fbcode_build))]
+fn init_cachelib_from_settings(fb: FacebookInit, settings: CachelibSettings) -> Result<()> {
+    cachelib::init_cache(fb, cachelib::LruCacheConfig::new(settings.cache_size))?;
+
+    let available_space = cachelib::get_available_space()?;
+    cachelib::get_or_create_pool(
+        "blobstore-presence",
+        settings.presence_cache_size.unwrap_or(available_space / 20),
+    )?;
+    let volatile_pools = [
+        ("changesets", settings.changesets_cache_size, 20),
+        ("filenodes", settings.filenodes_cache_size, 20),
+        (
+            "filenodes_history",
+            settings.filenodes_history_cache_size,
+            20,
+        ),
+        ("bonsai_hg_mapping", settings.idmapping_cache_size, 20),
+        (
+            "bonsai_globalrev_mapping",
+            settings.globalrev_cache_size,
+            100,
+        ),
+        ("bonsai_svnrev_mapping", settings.svnrev_cache_size, 100),
+        ("phases", settings.phases_cache_size, 50),
+        (
+            "segmented_changelog",
+            settings.segmented_changelog_cache_size,
+            20,
+        ),
+    ];
+    for (name, size, default_fraction) in volatile_pools.iter() {
+        cachelib::get_or_create_volatile_pool(
+            name,
+            size.unwrap_or(available_space / default_fraction),
+        )?;
+    }
+    // Blobs get everything that's left.
+    let blob_cache_size = match settings.blob_cache_size {
+        Some(size) => size,
+        None => cachelib::get_available_space()?,
+    };
+    cachelib::get_or_create_pool("blobstore-blobs", blob_cache_size)?;
+    Ok(())
+}
@@ -50,19 +50,20 @@ use super::{
     app::{
         ArgType, MononokeAppData, BLOBSTORE_BYTES_MIN_THROTTLE_ARG, BLOBSTORE_PUT_BEHAVIOUR_ARG,
         BLOBSTORE_SCRUB_ACTION_ARG, BLOBSTORE_SCRUB_GRACE_ARG,
-        BLOBSTORE_SCRUB_QUEUE_PEEK_BOUND_ARG, BLOBSTORE_SCRUB_WRITE_MOSTLY_MISSING_ARG,
-        CACHELIB_ATTEMPT_ZSTD_ARG, CRYPTO_PATH_REGEX_ARG, DISABLE_TUNABLES, ENABLE_MCROUTER,
-        GET_MEAN_DELAY_SECS_ARG, GET_STDDEV_DELAY_SECS_ARG, LOCAL_CONFIGERATOR_PATH_ARG,
-        LOGVIEW_ADDITIONAL_LEVEL_FILTER, LOGVIEW_CATEGORY, LOG_EXCLUDE_TAG, LOG_INCLUDE_TAG,
-        MYSQL_CONN_OPEN_TIMEOUT, MYSQL_MASTER_ONLY, MYSQL_MAX_QUERY_TIME, MYSQL_POOL_AGE_TIMEOUT,
-        MYSQL_POOL_IDLE_TIMEOUT, MYSQL_POOL_LIMIT, MYSQL_POOL_PER_KEY_LIMIT,
-        MYSQL_POOL_THREADS_NUM, MYSQL_SQLBLOB_POOL_AGE_TIMEOUT, MYSQL_SQLBLOB_POOL_IDLE_TIMEOUT,
-        MYSQL_SQLBLOB_POOL_LIMIT, MYSQL_SQLBLOB_POOL_PER_KEY_LIMIT, MYSQL_SQLBLOB_POOL_THREADS_NUM,
-        PUT_MEAN_DELAY_SECS_ARG, PUT_STDDEV_DELAY_SECS_ARG, READ_BURST_BYTES_ARG, READ_BYTES_ARG,
-        READ_CHAOS_ARG, READ_QPS_ARG, RENDEZVOUS_FREE_CONNECTIONS, RUNTIME_THREADS,
-        TUNABLES_CONFIG, WITH_DYNAMIC_OBSERVABILITY, WITH_READONLY_STORAGE_ARG,
-        WITH_TEST_MEGAREPO_CONFIGS_CLIENT, WRITE_BURST_BYTES_ARG, WRITE_BYTES_ARG, WRITE_CHAOS_ARG,
-        WRITE_QPS_ARG, WRITE_ZSTD_ARG, WRITE_ZSTD_LEVEL_ARG,
+        BLOBSTORE_SCRUB_QUEUE_PEEK_BOUND_ARG, BLOBSTORE_SCRUB_REENCRYPT_ARG,
+        BLOBSTORE_SCRUB_WRITE_MOSTLY_MISSING_ARG, CACHELIB_ATTEMPT_ZSTD_ARG, CRYPTO_PATH_REGEX_ARG,
+        DISABLE_TUNABLES, ENABLE_MCROUTER, GET_MEAN_DELAY_SECS_ARG, GET_STDDEV_DELAY_SECS_ARG,
+        LOCAL_CONFIGERATOR_PATH_ARG, LOGVIEW_ADDITIONAL_LEVEL_FILTER, LOGVIEW_CATEGORY,
+        LOG_EXCLUDE_TAG, LOG_INCLUDE_TAG, MYSQL_CONN_OPEN_TIMEOUT, MYSQL_MASTER_ONLY,
+        MYSQL_MAX_QUERY_TIME, MYSQL_POOL_AGE_TIMEOUT, MYSQL_POOL_IDLE_TIMEOUT, MYSQL_POOL_LIMIT,
+        MYSQL_POOL_PER_KEY_LIMIT, MYSQL_POOL_THREADS_NUM, MYSQL_SQLBLOB_POOL_AGE_TIMEOUT,
+        MYSQL_SQLBLOB_POOL_IDLE_TIMEOUT, MYSQL_SQLBLOB_POOL_LIMIT,
+        MYSQL_SQLBLOB_POOL_PER_KEY_LIMIT, MYSQL_SQLBLOB_POOL_THREADS_NUM, PUT_MEAN_DELAY_SECS_ARG,
+        PUT_STDDEV_DELAY_SECS_ARG, READ_BURST_BYTES_ARG, READ_BYTES_ARG, READ_CHAOS_ARG,
+        READ_QPS_ARG, RENDEZVOUS_FREE_CONNECTIONS, RUNTIME_THREADS, TUNABLES_CONFIG,
+        WITH_DYNAMIC_OBSERVABILITY, WITH_READONLY_STORAGE_ARG, WITH_TEST_MEGAREPO_CONFIGS_CLIENT,
+        WRITE_BURST_BYTES_ARG, WRITE_BYTES_ARG, WRITE_CHAOS_ARG, WRITE_QPS_ARG, WRITE_ZSTD_ARG,
+        WRITE_ZSTD_LEVEL_ARG,
     },
     cache::parse_and_init_cachelib,
 };
@@ -117,6 +118,12 @@ impl<'a> MononokeMatches<'a> {
         init_tunables(&matches, &config_store, logger.clone())
             .context("Failed to initialize tunables")?;
 
+        init_acl_provider(&matches, &config_store, &logger)
+            .context("Failed to initialize ACL provider")?;
+
+        init_scribe_sinks(&matches, &config_store, &logger)
+            .context("Failed to initialize Scribe sinks")?;
+
         let mysql_options =
             parse_mysql_options(&matches, &app_data).context("Failed to parse MySQL options")?;
         let blobstore_options = parse_blobstore_options(&matches, &app_data, &arg_types)
@@ -704,7 +711,7 @@ fn parse_blobstore_options(
         if let Some(v) = scrub_queue_peek_bound {
             blobstore_options = blobstore_options.with_scrub_queue_peek_bound(v)
         }
-        blobstore_options
+        blobstore_options.with_reencrypt(matches.is_present(BLOBSTORE_SCRUB_REENCRYPT_ARG))
     } else {
         blobstore_options
     };
@@ -783,6 +790,61 @@ fn init_tunables<'a>(
     init_tunables_worker(logger, config_handle)
 }
 
+#[cfg(fbcode_build)]
+fn init_acl_provider<'a>(
+    _matches: &'a ArgMatches<'a>,
+    _config_store: &'a ConfigStore,
+    _logger: &Logger,
+) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(not(fbcode_build))]
+fn init_acl_provider<'a>(
+    matches: &'a ArgMatches<'a>,
+    config_store: &'a ConfigStore,
+    logger: &Logger,
+) -> Result<()> {
+    let acl_spec = match matches.value_of(super::app::ACL_CONFIG) {
+        Some(acl_spec) => acl_spec,
+        None => {
+            debug!(logger, "No ACL config, all repos and tiers are open");
+            return Ok(());
+        }
+    };
+
+    let path = parse_config_spec_to_path(acl_spec)?;
+    let provider = permission_checker::FileAclProvider::new(config_store, &path)?;
+    permission_checker::set_acl_provider(Arc::new(provider))
+}
+
+#[cfg(fbcode_build)]
+fn init_scribe_sinks<'a>(
+    _matches: &'a ArgMatches<'a>,
+    _config_store: &'a ConfigStore,
+    _logger: &Logger,
+) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(not(fbcode_build))]
+fn init_scribe_sinks<'a>(
+    matches: &'a ArgMatches<'a>,
+    config_store: &'a ConfigStore,
+    logger: &Logger,
+) -> Result<()> {
+    let scribe_spec = match matches.value_of(super::app::SCRIBE_CONFIG) {
+        Some(scribe_spec) => scribe_spec,
+        None => {
+            debug!(logger, "No Scribe config, Scribe samples are dropped");
+            return Ok(());
+        }
+    };
+
+    let path = parse_config_spec_to_path(scribe_spec)?;
+    scribe_ext::init_sinks(logger, config_store, &path)
+}
+
 /// Initialize a new `Runtime` with thread number parsed from the CLI
 fn init_runtime(matches: &ArgMatches<'_>) -> Result<Runtime> {
     let core_threads = matches
@@ -471,7 +471,7 @@ where
     let (reponame, mut config) = get_config_by_repoid(config_store, matches, repo_id)?;
     info!(logger, "using repo \"{}\" repoid {:?}", reponame, repo_id);
     match &config.storage_config.blobstore {
-        BlobConfig::Files { path } | BlobConfig::Sqlite { path } => {
+        BlobConfig::Files { path, .. } | BlobConfig::Sqlite { path } => {
             let create = if create {
                 // Many path repos can share one blobstore, so allow store to exist or create it.
                 CreateStorage::ExistingOrCreate
@@ -37,8 +37,12 @@ pub fn build_subcommand<'a, 'b>() -> App<'a, 'b> {
 }
 
 fn remove_wrapper_blobconfigs(mut blob_config: BlobConfig) -> BlobConfig {
-    // Pack is a wrapper store - remove it
-    while let BlobConfig::Pack { ref blobconfig, .. } = blob_config {
+    // Pack, DiskCache, Encrypted and Checksummed are wrapper stores - remove them
+    while let BlobConfig::Pack { ref blobconfig, .. }
+    | BlobConfig::DiskCache { ref blobconfig, .. }
+    | BlobConfig::Encrypted { ref blobconfig, .. }
+    | BlobConfig::Checksummed { ref blobconfig } = blob_config
+    {
         blob_config = BlobConfig::clone(blobconfig);
     }
     blob_config
@@ -0,0 +1,274 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Sweep phase of mark and sweep garbage collection. The mark phase is
+//! `walker scrub --gc-mark-file`, which records every key reachable from the
+//! roots it walks; this removes keys that were not marked, were last written
+//! before the walks started (less a grace period), and are of a type that the
+//! walks visited.
+//!
+//! Keys that already exist are never rewritten, so a key can be referenced
+//! again after the walks started (e.g. by a commit that re-adds an old file)
+//! without becoming reachable from anything the walks saw. The stores that
+//! can be swept refresh a key's ctime when a put of it is prevented because
+//! it is present, so such a key counts as written since the walks started and
+//! is kept. The grace period covers keys that were written shortly before the
+//! walks started for something that only became reachable later, such as a
+//! push that had uploaded its files but not yet moved its bookmark.
+//!
+//! Only puts are tracked: content that a client reuses without a put because
+//! the server says it has it (e.g. via the LFS batch API, or EdenAPI uploads
+//! that are skipped after a lookup) is not refreshed, and would be swept while
+//! reachable. Each key's ctime is also checked immediately before it is
+//! unlinked, but not atomically with the unlink, so a put that lands in between
+//! is lost. Sweeping therefore refuses to run unless the operator confirms that
+//! neither can happen, i.e. that no LFS server or EdenAPI serves repos using
+//! the storage, and that nothing writes to it while it is swept.
+//!
+//! The records that the LFS server keeps of resumable uploads are never
+//! reachable, so they are swept once last written before the cutoff whatever
+//! the walks visited. Uploads expire after at most two days, well within the
+//! default grace period.
+//!
+//! For sqlblob, this removes the data keys; the chunks are then collected by
+//! the usual generation-based GC, as `sqlblob_gc mark` no longer reaches them.
+
+#![deny(warnings)]
+
+use std::fs::File;
+use std::io::BufReader;
+
+use anyhow::{bail, Context, Result};
+use bytesize::ByteSize;
+use clap::Arg;
+use context::CoreContext;
+use fbinit::FacebookInit;
+use slog::info;
+
+use blobstore_factory::{make_blobstore_enumerable_with_unlink, ReadOnlyStorage};
+use cmdlib::args::{self, MononokeClapApp};
+use metaconfig_types::{BlobConfig, BlobstoreId};
+
+mod sweep;
+
+const ARG_STORAGE_CONFIG_NAME: &str = "storage-config-name";
+const ARG_INNER_ID: &str = "inner-blobstore-id";
+const ARG_MARK_FILE: &str = "mark-file";
+const ARG_GRACE_PERIOD: &str = "grace-period-secs";
+const ARG_DRY_RUN: &str = "dry-run";
+const ARG_SCHEDULED_MAX: &str = "scheduled-max";
+const ARG_CONFIRM_NOT_SERVED: &str = "confirm-not-served";
+
+const DEFAULT_GRACE_PERIOD_SECS: i64 = 7 * 24 * 60 * 60;
+
+fn setup_app<'a, 'b>() -> MononokeClapApp<'a, 'b> {
+    args::MononokeAppBuilder::new("Blobstore GC")
+        .with_advanced_args_hidden()
+        .with_all_repos()
+        .build()
+        .about("Remove blobs that a walk of every repo using the storage found to be unreachable")
+        .arg(
+            Arg::with_name(ARG_STORAGE_CONFIG_NAME)
+                .long(ARG_STORAGE_CONFIG_NAME)
+                .takes_value(true)
+                .required(true)
+                .help("the name of the storage config to GC"),
+        )
+        .arg(
+            Arg::with_name(ARG_INNER_ID)
+                .long(ARG_INNER_ID)
+                .takes_value(true)
+                .required(false)
+                .help("If main blobstore in the storage config is a multiplexed one, use inner blobstore with this id")
+        )
+        .arg(
+            Arg::with_name(ARG_MARK_FILE)
+                .long(ARG_MARK_FILE)
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(true)
+                .help("Marks from walker scrub --gc-mark-file. Give one for every walk of repos that use the storage"),
+        )
+        .arg(
+            Arg::with_name(ARG_GRACE_PERIOD)
+                .long(ARG_GRACE_PERIOD)
+                .takes_value(true)
+                .required(false)
+                .help("Only remove blobs last written this long before the walks started. Default one week."),
+        )
+        .arg(
+            Arg::with_name(ARG_DRY_RUN)
+                .long(ARG_DRY_RUN)
+                .takes_value(false)
+                .required(false)
+                .help("Report what would be removed without removing it"),
+        )
+        .arg(
+            Arg::with_name(ARG_SCHEDULED_MAX)
+                .long(ARG_SCHEDULED_MAX)
+                .takes_value(true)
+                .required(false)
+                .help("Maximum number of parallel keys to check.  Default 100."),
+        )
+        .arg(
+            Arg::with_name(ARG_CONFIRM_NOT_SERVED)
+                .long(ARG_CONFIRM_NOT_SERVED)
+                .takes_value(false)
+                .required(false)
+                .help("Confirm that no LFS server or EdenAPI serves repos that use the storage, and that nothing writes to it while it is swept. Their existence checks reuse content without refreshing it, and puts can race the sweep, so sweeping storage in use loses data. Required unless --dry-run"),
+        )
+}
+
+fn remove_wrapper_blobconfigs(mut blob_config: BlobConfig) -> BlobConfig {
+    // Pack, DiskCache, Encrypted and Checksummed are wrapper stores - remove them
+    while let BlobConfig::Pack { ref blobconfig, .. }
+    | BlobConfig::DiskCache { ref blobconfig, .. }
+    | BlobConfig::Encrypted { ref blobconfig, .. }
+    | BlobConfig::Checksummed { ref blobconfig } = blob_config
+    {
+        blob_config = BlobConfig::clone(blobconfig);
+    }
+    blob_config
+}
+
+fn get_blobconfig(blob_config: BlobConfig, inner_blobstore_id: Option<u64>) -> Result<BlobConfig> {
+    let mut blob_config = remove_wrapper_blobconfigs(blob_config);
+
+    // If the outer store is a mux, find th requested inner store
+    if let Some(inner_blobstore_id) = inner_blobstore_id {
+        blob_config = match blob_config {
+            BlobConfig::Multiplexed { blobstores, .. } => {
+                let required_id = BlobstoreId::new(inner_blobstore_id);
+                blobstores
+                    .into_iter()
+                    .find_map(|(blobstore_id, _, blobstore)| {
+                        if blobstore_id == required_id {
+                            Some(blobstore)
+                        } else {
+                            None
+                        }
+                    })
+                    .with_context(|| {
+                        format!("could not find a blobstore with id {}", inner_blobstore_id)
+                    })?
+            }
+            _ => bail!("inner-blobstore-id can only be supplied for multiplexed blobstores"),
+        }
+    };
+
+    Ok(remove_wrapper_blobconfigs(blob_config))
+}
+
+#[fbinit::main]
+fn main(fb: FacebookInit) -> Result<()> {
+    let matches = setup_app().get_matches(fb)?;
+    let matches = &matches;
+
+    let logger = matches.logger().clone();
+    let config_store = matches.config_store();
+    let runtime = matches.runtime();
+
+    let inner_blobstore_id = matches
+        .value_of(ARG_INNER_ID)
+        .map(str::parse::<u64>)
+        .transpose()?;
+    let grace_period = matches
+        .value_of(ARG_GRACE_PERIOD)
+        .map_or(Ok(DEFAULT_GRACE_PERIOD_SECS), str::parse::<i64>)?;
+    let dry_run = matches.is_present(ARG_DRY_RUN);
+    if !dry_run && !matches.is_present(ARG_CONFIRM_NOT_SERVED) {
+        bail!(
+            "Sweeping storage that is in use loses data, use --{} to confirm that it is not",
+            ARG_CONFIRM_NOT_SERVED
+        );
+    }
+    let scheduled_max = matches
+        .value_of(ARG_SCHEDULED_MAX)
+        .map_or(Ok(100), str::parse::<usize>)?;
+
+    let mut marks = sweep::Marks::default();
+    for path in matches.values_of(ARG_MARK_FILE).into_iter().flatten() {
+        let file = File::open(path).with_context(|| format!("Failed to open {}", path))?;
+        marks
+            .add_file(BufReader::new(file))
+            .with_context(|| format!("Failed to read marks from {}", path))?;
+    }
+    let run_start = marks.run_start().context("No marks")?;
+    let options = sweep::SweepOptions {
+        cutoff: run_start - grace_period,
+        dry_run,
+        scheduled_max,
+    };
+
+    let blobstore_config = {
+        let storage_config = args::load_storage_configs(config_store, &matches)
+            .context("Could not read storage configs")?
+            .storage
+            .remove(
+                matches
+                    .value_of(ARG_STORAGE_CONFIG_NAME)
+                    .context("No storage config name")?,
+            )
+            .context("Requested storage config not found")?;
+        storage_config.blobstore
+    };
+    let blobstore_config = get_blobconfig(blobstore_config, inner_blobstore_id)?;
+
+    let blobstore_options = matches.blobstore_options();
+
+    runtime.block_on(async move {
+        let blobstore = make_blobstore_enumerable_with_unlink(
+            fb,
+            blobstore_config,
+            ReadOnlyStorage(dry_run),
+            &blobstore_options,
+            &logger,
+            &config_store,
+        )
+        .await?;
+        let ctx = CoreContext::new_for_bulk_processing(fb, logger.clone());
+
+        info!(
+            logger,
+            "Sweeping {} for unmarked keys last written before {}", blobstore, options.cutoff
+        );
+        let stats = sweep::sweep(&ctx, blobstore.as_ref(), &marks, &options).await?;
+
+        info!(
+            logger,
+            "Enumerated {} keys: {} marked, {} of types not walked, {} too new, {} gone",
+            stats.enumerated,
+            stats.marked,
+            stats.not_covered,
+            stats.too_new,
+            stats.vanished,
+        );
+        let verb = if dry_run { "Would remove" } else { "Removed" };
+        let (mut total_keys, mut total_bytes) = (0, 0);
+        for (key_type, (keys, bytes)) in &stats.swept_by_type {
+            info!(
+                logger,
+                "{} {} keys of type {} ({})",
+                verb,
+                keys,
+                key_type,
+                ByteSize::b(*bytes).to_string_as(true)
+            );
+            total_keys += keys;
+            total_bytes += bytes;
+        }
+        info!(
+            logger,
+            "{} {} keys in total ({})",
+            verb,
+            total_keys,
+            ByteSize::b(total_bytes).to_string_as(true)
+        );
+        Ok(())
+    })
+}
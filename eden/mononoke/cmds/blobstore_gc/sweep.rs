@@ -0,0 +1,343 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::collections::{BTreeMap, HashSet};
+use std::io::BufRead;
+
+use anyhow::{bail, Context, Result};
+use blobstore::{BlobstoreEnumerableWithUnlink, BlobstoreKeyParam};
+use context::CoreContext;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use packblob::ENVELOPE_SUFFIX;
+
+// Line prefixes in the walker's --gc-mark-file
+const RUN_START: &str = "run_start";
+const KEY: &str = "key";
+const COMPLETE: &str = "complete";
+
+// Keys that record the progress of resumable LFS uploads contain this
+const LFS_UPLOAD: &str = ".lfs_upload.";
+
+/// The part of a key before its last component, e.g. `repo0000.content.blake2`
+/// for a file content key.
+fn key_type(key: &str) -> Option<&str> {
+    lfs_upload_type(key).or_else(|| key.rsplit_once('.').map(|(key_type, _)| key_type))
+}
+
+/// The type of a resumable LFS upload record, e.g. `repo0000.lfs_upload`.
+/// These are never reachable, and expire long before the grace period, so
+/// they are always swept.
+fn lfs_upload_type(key: &str) -> Option<&str> {
+    key.find(LFS_UPLOAD)
+        .map(|pos| &key[..pos + LFS_UPLOAD.len() - 1])
+}
+
+// Keys stored via packblob have a suffix that the walker does not see
+fn logical_key(key: &str) -> &str {
+    key.strip_suffix(ENVELOPE_SUFFIX).unwrap_or(key)
+}
+
+/// The keys that walks found to be reachable
+#[derive(Default)]
+pub struct Marks {
+    run_start: Option<i64>,
+    keys: HashSet<String>,
+    key_types: HashSet<String>,
+}
+
+impl Marks {
+    /// Adds the keys from a walker mark file, which must be from walks that
+    /// completed, as otherwise reachable keys could be missing.
+    pub fn add_file(&mut self, file: impl BufRead) -> Result<()> {
+        let mut run_start: Option<i64> = None;
+        let mut complete = false;
+        for (line_num, line) in file.lines().enumerate() {
+            let line = line?;
+            match line.split_once('\t') {
+                Some((KEY, key)) => self.add_key(key),
+                Some((RUN_START, secs)) => {
+                    let secs = secs
+                        .parse()
+                        .with_context(|| format!("Invalid walk start on line {}", line_num + 1))?;
+                    run_start = Some(run_start.map_or(secs, |t| t.min(secs)));
+                }
+                None if line == COMPLETE => complete = true,
+                _ => bail!("Invalid mark on line {}", line_num + 1),
+            }
+        }
+        if !complete {
+            bail!("Marks are from walks that did not complete");
+        }
+        let run_start = run_start.context("Marks have no walk start time")?;
+        self.run_start = Some(self.run_start.map_or(run_start, |t| t.min(run_start)));
+        Ok(())
+    }
+
+    fn add_key(&mut self, key: &str) {
+        if let Some(key_type) = key_type(key) {
+            if !self.key_types.contains(key_type) {
+                self.key_types.insert(key_type.to_string());
+            }
+        }
+        self.keys.insert(key.to_string());
+    }
+
+    /// When the earliest walk started. Keys written after that might be
+    /// reachable from something the walks did not see.
+    pub fn run_start(&self) -> Option<i64> {
+        self.run_start
+    }
+
+    fn is_marked(&self, key: &str) -> bool {
+        self.keys.contains(key)
+    }
+
+    // Only keys of types that the walks visited can be known to be
+    // unreachable, e.g. if no node type was walked that loads a key of the
+    // type, then none of its keys are marked.
+    fn is_covered(&self, key: &str) -> bool {
+        lfs_upload_type(key).is_some()
+            || matches!(key_type(key), Some(key_type) if self.key_types.contains(key_type))
+    }
+}
+
+pub struct SweepOptions {
+    /// Only keys last written before this time (in seconds since the epoch) are swept
+    pub cutoff: i64,
+    pub dry_run: bool,
+    pub scheduled_max: usize,
+}
+
+/// What happened to the keys in the blobstore
+#[derive(Debug, Default, PartialEq)]
+pub struct SweepStats {
+    pub enumerated: u64,
+    pub marked: u64,
+    /// Of a type that the walks did not visit
+    pub not_covered: u64,
+    /// Unmarked, but written after the cutoff or of unknown age
+    pub too_new: u64,
+    /// Unmarked, but gone before the sweep could check it
+    pub vanished: u64,
+    /// Number of keys and bytes swept (or that would be swept in a dry run) per key type
+    pub swept_by_type: BTreeMap<String, (u64, u64)>,
+}
+
+enum Outcome {
+    Swept(u64),
+    TooNew,
+    Vanished,
+}
+
+async fn sweep_key<B>(
+    ctx: &CoreContext,
+    blobstore: &B,
+    key: String,
+    options: &SweepOptions,
+) -> Result<(String, Outcome)>
+where
+    B: BlobstoreEnumerableWithUnlink + ?Sized,
+{
+    // Check the age right before unlinking, as the key can have been
+    // rewritten or put again since it was enumerated
+    let outcome = match blobstore.get(ctx, &key).await? {
+        None => Outcome::Vanished,
+        Some(value) => match value.as_meta().ctime() {
+            Some(ctime) if ctime < options.cutoff => {
+                if !options.dry_run {
+                    blobstore.unlink(ctx, &key).await?;
+                }
+                Outcome::Swept(value.as_bytes().len() as u64)
+            }
+            _ => Outcome::TooNew,
+        },
+    };
+    Ok((key, outcome))
+}
+
+/// Unlinks the keys in the blobstore that are not marked, are of a type
+/// that the walks visited, and were last written before the cutoff.
+pub async fn sweep<B>(
+    ctx: &CoreContext,
+    blobstore: &B,
+    marks: &Marks,
+    options: &SweepOptions,
+) -> Result<SweepStats>
+where
+    B: BlobstoreEnumerableWithUnlink + ?Sized,
+{
+    let mut stats = SweepStats::default();
+    let mut param = BlobstoreKeyParam::from(..);
+    loop {
+        let data = blobstore.enumerate(ctx, &param).await?;
+
+        let mut candidates = vec![];
+        for key in data.keys {
+            stats.enumerated += 1;
+            let logical = logical_key(&key);
+            if marks.is_marked(logical) {
+                stats.marked += 1;
+            } else if !marks.is_covered(logical) {
+                stats.not_covered += 1;
+            } else {
+                candidates.push(key);
+            }
+        }
+
+        let outcomes: Vec<_> = stream::iter(candidates)
+            .map(|key| sweep_key(ctx, blobstore, key, options))
+            .buffer_unordered(options.scheduled_max)
+            .try_collect()
+            .await?;
+        for (key, outcome) in outcomes {
+            match outcome {
+                Outcome::Swept(size) => {
+                    let key_type = key_type(logical_key(&key)).unwrap_or_default();
+                    let swept = stats.swept_by_type.entry(key_type.to_string()).or_default();
+                    swept.0 += 1;
+                    swept.1 += size;
+                }
+                Outcome::TooNew => stats.too_new += 1,
+                Outcome::Vanished => stats.vanished += 1,
+            }
+        }
+
+        match data.next_token {
+            Some(next) => param = next,
+            None => break,
+        }
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use blobstore::{Blobstore, PutBehaviour};
+    use fbinit::FacebookInit;
+    use mononoke_types::{BlobstoreBytes, Timestamp};
+    use sqlblob::{get_test_config_store, Sqlblob};
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn test_read_marks() -> Result<()> {
+        let mut marks = Marks::default();
+        marks.add_file(
+            "run_start\t200\nkey\trepo0000.content.blake2.aa\nrun_start\t100\ncomplete\n"
+                .as_bytes(),
+        )?;
+        marks
+            .add_file("run_start\t150\nkey\trepo0001.changeset.blake2.bb\ncomplete\n".as_bytes())?;
+        assert_eq!(marks.run_start(), Some(100));
+        assert!(marks.is_marked("repo0000.content.blake2.aa"));
+        assert!(!marks.is_marked("repo0000.content.blake2.cc"));
+        assert!(marks.is_covered("repo0000.content.blake2.cc"));
+        assert!(marks.is_covered("repo0001.changeset.blake2.cc"));
+        assert!(!marks.is_covered("repo0000.changeset.blake2.cc"));
+        assert!(!marks.is_covered("repo0000"));
+        assert!(marks.is_covered("repo0002.lfs_upload.sha256.ee.9.4.1.0.part.0"));
+
+        // A walk that did not finish
+        let mut marks = Marks::default();
+        assert!(marks
+            .add_file("run_start\t200\nkey\trepo0000.content.blake2.aa\n".as_bytes())
+            .is_err());
+        Ok(())
+    }
+
+    #[fbinit::test]
+    fn test_sweep(fb: FacebookInit) -> Result<()> {
+        let runtime = Runtime::new()?;
+        runtime.block_on(async move {
+            let ctx = CoreContext::test_mock(fb);
+            let (_test_source, config_store) = get_test_config_store();
+            let blobstore =
+                Sqlblob::with_sqlite_in_memory(PutBehaviour::Overwrite, &config_store, true)?;
+            for key in &[
+                "repo0000.content.blake2.aa",
+                "repo0000.content.blake2.bb.pack",
+                "repo0000.content.blake2.cc",
+                "repo0000.changeset.blake2.dd",
+                "repo0000.skiplist_4hg",
+                "repo0000.lfs_upload.sha256.ee.9.4.1.0.part.0",
+            ] {
+                blobstore
+                    .put(&ctx, key.to_string(), BlobstoreBytes::from_bytes("value"))
+                    .await?;
+            }
+
+            let mut marks = Marks::default();
+            marks.add_file(
+                "run_start\t100\nkey\trepo0000.content.blake2.aa\ncomplete\n".as_bytes(),
+            )?;
+
+            // Everything was written after the cutoff
+            let options = SweepOptions {
+                cutoff: 100,
+                dry_run: false,
+                scheduled_max: 10,
+            };
+            let stats = sweep(&ctx, &blobstore, &marks, &options).await?;
+            assert_eq!(
+                stats,
+                SweepStats {
+                    enumerated: 6,
+                    marked: 1,
+                    not_covered: 2,
+                    too_new: 3,
+                    ..Default::default()
+                }
+            );
+
+            // A dry run reports what would be swept without unlinking it
+            let options = SweepOptions {
+                cutoff: Timestamp::now().timestamp_seconds() + 10,
+                dry_run: true,
+                scheduled_max: 10,
+            };
+            let stats = sweep(&ctx, &blobstore, &marks, &options).await?;
+            let expected_swept: BTreeMap<_, _> = vec![
+                ("repo0000.content.blake2".to_string(), (2, 10)),
+                ("repo0000.lfs_upload".to_string(), (1, 5)),
+            ]
+            .into_iter()
+            .collect();
+            assert_eq!(stats.swept_by_type, expected_swept);
+            assert!(blobstore
+                .is_present(&ctx, "repo0000.content.blake2.cc")
+                .await?
+                .assume_not_found_if_unsure());
+
+            let options = SweepOptions {
+                dry_run: false,
+                ..options
+            };
+            let stats = sweep(&ctx, &blobstore, &marks, &options).await?;
+            assert_eq!(stats.swept_by_type, expected_swept);
+            for (key, present) in &[
+                ("repo0000.content.blake2.aa", true),
+                ("repo0000.content.blake2.bb.pack", false),
+                ("repo0000.content.blake2.cc", false),
+                ("repo0000.changeset.blake2.dd", true),
+                ("repo0000.skiplist_4hg", true),
+                ("repo0000.lfs_upload.sha256.ee.9.4.1.0.part.0", false),
+            ] {
+                assert_eq!(
+                    blobstore
+                        .is_present(&ctx, key)
+                        .await?
+                        .assume_not_found_if_unsure(),
+                    *present,
+                    "{}",
+                    key
+                );
+            }
+            Ok(())
+        })
+    }
+}
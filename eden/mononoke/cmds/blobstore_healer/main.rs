@@ -34,8 +34,6 @@ use metaconfig_types::{BlobConfig, DatabaseConfig, StorageConfig};
 use mononoke_types::DateTime;
 use slog::{info, o};
 use sql_construct::SqlConstructFromDatabaseConfig;
-#[cfg(fbcode_build)]
-use sql_ext::facebook::MyAdmin;
 use sql_ext::{
     facebook::MysqlOptions,
     replication::{NoReplicaLagMonitor, ReplicaLagMonitor, WaitForReplicationConfig},
@@ -146,16 +144,8 @@ async fn maybe_schedule_healer_for_storage(
     let lag_monitor: Box<dyn ReplicaLagMonitor> = match queue_db {
         DatabaseConfig::Local(_) => Box::new(NoReplicaLagMonitor()),
         DatabaseConfig::Remote(remote) => {
-            #[cfg(fbcode_build)]
-            {
-                let myadmin = MyAdmin::new(fb)?;
-                Box::new(myadmin.single_shard_lag_monitor(remote.db_address))
-            }
-            #[cfg(not(fbcode_build))]
-            {
-                let _ = remote;
-                unimplemented!("Remote DB is not yet implemented for non fbcode builds");
-            }
+            let myadmin = mysql_options.myadmin(fb)?;
+            Box::new(myadmin.single_shard_lag_monitor(remote.db_address))
         }
     };
 
@@ -291,7 +281,12 @@ fn main(fb: FacebookInit) -> Result<()> {
     let config_store = matches.config_store();
     let mysql_options = matches.mysql_options();
     let readonly_storage = matches.readonly_storage();
-    let blobstore_options = matches.blobstore_options();
+    // The stores being healed may hold corrupt copies of the blobs that are
+    // put to them, which checksummed stores should replace
+    let blobstore_options = matches
+        .blobstore_options()
+        .clone()
+        .with_checksum_repair(true);
     let storage_config = args::load_storage_configs(config_store, &matches)?
         .storage
         .remove(storage_id)
@@ -334,7 +329,7 @@ fn main(fb: FacebookInit) -> Result<()> {
         mysql_options,
         source_blobstore_key.map(|s| s.to_string()),
         *readonly_storage,
-        blobstore_options,
+        &blobstore_options,
         iter_limit,
         healing_min_age,
         config_store,
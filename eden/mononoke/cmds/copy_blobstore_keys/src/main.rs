@@ -233,7 +233,7 @@ async fn open_repo<'a>(
     }
     info!(logger, "using repo \"{}\" repoid {:?}", reponame, repo_id);
     match &config.storage_config.blobstore {
-        BlobConfig::Files { path } | BlobConfig::Sqlite { path } => {
+        BlobConfig::Files { path, .. } | BlobConfig::Sqlite { path } => {
             setup_repo_dir(path, CreateStorage::ExistingOnly)?;
         }
         _ => {}
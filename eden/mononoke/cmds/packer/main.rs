@@ -8,25 +8,39 @@
 #![deny(warnings)]
 
 use anyhow::{bail, Context, Result};
+use blobstore::BlobstoreWithLink;
 use blobstore_factory::make_packblob;
-use borrowed::borrowed;
 use clap::Arg;
 use cmdlib::args::{self, MononokeClapApp};
 use context::CoreContext;
 use fbinit::FacebookInit;
-use futures::stream::{self, TryStreamExt};
+use futures::stream::{self, StreamExt};
 use metaconfig_types::{BlobConfig, BlobstoreId};
-use std::io::{self, BufRead};
+use packblob::PackBlob;
+use slog::{info, warn, Logger};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::time::Duration;
 
 mod pack_utils;
+mod walker_log;
 
 const ARG_ZSTD_LEVEL: &str = "zstd-level";
 const ARG_INNER_ID: &str = "inner-blobstore-id";
 const ARG_DRY_RUN: &str = "dry-run";
 const ARG_SCHEDULED_MAX: &str = "scheduled-max";
+const ARG_WALKER_PACK_LOG: &str = "walker-pack-log";
+const ARG_MAX_PACK_KEYS: &str = "max-pack-keys";
+const ARG_REPACK_PERIOD: &str = "repack-period-secs";
+const ARG_TRAINED_DICTIONARIES: &str = "trained-dictionaries";
+const ARG_QUICK_PACK_BASES: &str = "quick-pack-bases";
 
 const PACK_PREFIX: &str = "multiblob-";
 
+// How many packed keys the periodic mode remembers before it starts over
+const MAX_REMEMBERED_KEYS: usize = 1_000_000;
+
 fn setup_app<'a, 'b>() -> MononokeClapApp<'a, 'b> {
     args::MononokeAppBuilder::new("Packer")
         .with_advanced_args_hidden()
@@ -61,6 +75,43 @@ fn setup_app<'a, 'b>() -> MononokeClapApp<'a, 'b> {
                 .required(false)
                 .help("Maximum number of parallel packs to work on. Default 10"),
         )
+        .arg(
+            Arg::with_name(ARG_WALKER_PACK_LOG)
+                .long(ARG_WALKER_PACK_LOG)
+                .takes_value(true)
+                .required(false)
+                .help("Instead of reading blob names from stdin, pack the blobs in this walker pack info log (from --pack-log-scuba-file) that are not yet packed, grouping similar blobs such as versions of the same file")
+        )
+        .arg(
+            Arg::with_name(ARG_MAX_PACK_KEYS)
+                .long(ARG_MAX_PACK_KEYS)
+                .takes_value(true)
+                .required(false)
+                .requires(ARG_WALKER_PACK_LOG)
+                .help("Maximum number of blobs to put in one pack when grouping blobs from a walker pack info log. Default 20"),
+        )
+        .arg(
+            Arg::with_name(ARG_REPACK_PERIOD)
+                .long(ARG_REPACK_PERIOD)
+                .takes_value(true)
+                .required(false)
+                .requires(ARG_WALKER_PACK_LOG)
+                .help("Keep running, and every this many seconds read the walker pack info log again and pack the blobs in it that are not yet packed, so that packing keeps up with a repo that is in use. The walker should be rewriting the log periodically"),
+        )
+        .arg(
+            Arg::with_name(ARG_TRAINED_DICTIONARIES)
+                .long(ARG_TRAINED_DICTIONARIES)
+                .takes_value(false)
+                .required(false)
+                .help("Let packs carry a Zstd dictionary trained on their blobs. Only use this once everything that reads the blobstore can decode such packs"),
+        )
+        .arg(
+            Arg::with_name(ARG_QUICK_PACK_BASES)
+                .long(ARG_QUICK_PACK_BASES)
+                .takes_value(false)
+                .required(false)
+                .help("Only try the first and the largest blob of each group as the base of its pack, rather than every blob, which takes time quadratic in the size of the group. Always the case with --walker-pack-log"),
+        )
 }
 
 fn get_blobconfig(
@@ -130,11 +181,38 @@ fn main(fb: FacebookInit) -> Result<()> {
     let max_parallelism = matches
         .value_of(ARG_SCHEDULED_MAX)
         .map_or(Ok(10), str::parse::<usize>)?;
+    let repack_period = matches
+        .value_of(ARG_REPACK_PERIOD)
+        .map(str::parse::<u64>)
+        .transpose()?
+        .map(Duration::from_secs);
+    let max_pack_keys = matches
+        .value_of(ARG_MAX_PACK_KEYS)
+        .map_or(Ok(20), str::parse::<usize>)?;
+    let walker_pack_log = matches.value_of(ARG_WALKER_PACK_LOG);
+    let repack = Repack {
+        zstd_level,
+        trained_dictionaries: matches.is_present(ARG_TRAINED_DICTIONARIES),
+        every_base: !matches.is_present(ARG_QUICK_PACK_BASES) && walker_pack_log.is_none(),
+        repo_prefix,
+        max_parallelism,
+        dry_run,
+    };
+
+    let pack_groups: Vec<Vec<String>> = match walker_pack_log {
+        Some(path) => read_walker_pack_log(path, &repack.repo_prefix, inner_id, max_pack_keys)?,
+        None => {
+            let input_lines: Vec<String> = io::stdin()
+                .lock()
+                .lines()
+                .collect::<Result<_, io::Error>>()?;
+            input_lines
+                .split(String::is_empty)
+                .map(<[String]>::to_vec)
+                .collect()
+        }
+    };
 
-    let input_lines: Vec<String> = io::stdin()
-        .lock()
-        .lines()
-        .collect::<Result<_, io::Error>>()?;
     runtime.block_on(async move {
         let blobstore = make_packblob(
             fb,
@@ -145,23 +223,156 @@ fn main(fb: FacebookInit) -> Result<()> {
             &config_store,
         )
         .await?;
-        stream::iter(input_lines.split(String::is_empty).map(Result::Ok))
-            .try_for_each_concurrent(max_parallelism, |pack_keys| {
-                borrowed!(ctx, repo_prefix, blobstore);
-                async move {
-                    let pack_keys: Vec<&str> = pack_keys.iter().map(|i| i.as_ref()).collect();
-                    pack_utils::repack_keys(
-                        &ctx,
-                        &blobstore,
-                        PACK_PREFIX,
-                        zstd_level,
-                        &repo_prefix,
-                        &pack_keys,
-                        dry_run,
-                    )
-                    .await
-                }
-            })
-            .await
+        let (sizes, repacked) = repack
+            .repack_groups(&ctx, &logger, &blobstore, &pack_groups)
+            .await;
+        repack.log_sizes(&logger, "", sizes);
+
+        let (period, path) = match (repack_period, walker_pack_log) {
+            (Some(period), Some(path)) => (period, path),
+            _ if repacked.len() < pack_groups.len() => bail!(
+                "Failed to repack {} of {} groups",
+                pack_groups.len() - repacked.len(),
+                pack_groups.len()
+            ),
+            _ => return Ok(()),
+        };
+        // The log only shows which blobs were packed when the walker next
+        // runs, so remember what was packed in the meantime.
+        let mut packed = HashSet::new();
+        repack.remember_packed(&logger, &mut packed, repacked);
+        let mut total = sizes;
+        loop {
+            tokio::time::sleep(period).await;
+            let pack_groups =
+                match read_walker_pack_log(path, &repack.repo_prefix, inner_id, max_pack_keys) {
+                    Ok(pack_groups) => pack_groups,
+                    Err(e) => {
+                        warn!(logger, "Failed to read walker pack info log: {:?}", e);
+                        continue;
+                    }
+                };
+            let pack_groups: Vec<Vec<String>> = pack_groups
+                .into_iter()
+                .map(|group| {
+                    group
+                        .into_iter()
+                        .filter(|key| !packed.contains(key))
+                        .collect::<Vec<_>>()
+                })
+                .filter(|group| group.len() > 1)
+                .collect();
+
+            let (sizes, repacked) = repack
+                .repack_groups(&ctx, &logger, &blobstore, &pack_groups)
+                .await;
+            repack.remember_packed(&logger, &mut packed, repacked);
+            total = total + sizes;
+            repack.log_sizes(&logger, "", sizes);
+            repack.log_sizes(&logger, " in total", total);
+        }
     })
 }
+
+fn read_walker_pack_log(
+    path: &str,
+    repo_prefix: &str,
+    inner_id: Option<u64>,
+    max_pack_keys: usize,
+) -> Result<Vec<Vec<String>>> {
+    let log = File::open(path).with_context(|| format!("Failed to open {}", path))?;
+    walker_log::read_pack_groups(BufReader::new(log), repo_prefix, inner_id, max_pack_keys)
+}
+
+/// How to repack groups of blobs
+struct Repack {
+    zstd_level: i32,
+    trained_dictionaries: bool,
+    every_base: bool,
+    repo_prefix: String,
+    max_parallelism: usize,
+    dry_run: bool,
+}
+
+impl Repack {
+    /// Repacks each group on its own, so that a group that fails doesn't hold
+    /// back the others. Returns the sizes and the groups that were repacked,
+    /// and logs the groups that failed.
+    async fn repack_groups<'a, T: BlobstoreWithLink>(
+        &self,
+        ctx: &CoreContext,
+        logger: &Logger,
+        blobstore: &PackBlob<T>,
+        pack_groups: &'a [Vec<String>],
+    ) -> (pack_utils::RepackSizes, Vec<&'a [String]>) {
+        let results: Vec<_> = stream::iter(pack_groups)
+            .map(|pack_keys| async move {
+                let keys: Vec<&str> = pack_keys.iter().map(|i| i.as_ref()).collect();
+                let result = pack_utils::repack_keys(
+                    ctx,
+                    blobstore,
+                    PACK_PREFIX,
+                    self.zstd_level,
+                    self.trained_dictionaries,
+                    self.every_base,
+                    &self.repo_prefix,
+                    &keys,
+                    self.dry_run,
+                )
+                .await;
+                (pack_keys.as_slice(), result)
+            })
+            .buffer_unordered(self.max_parallelism)
+            .collect()
+            .await;
+
+        let mut total = pack_utils::RepackSizes::default();
+        let mut repacked = vec![];
+        for (pack_keys, result) in results {
+            match result {
+                Ok(sizes) => {
+                    total = total + sizes;
+                    repacked.push(pack_keys);
+                }
+                Err(e) => warn!(logger, "Failed to repack {:?}: {:?}", pack_keys, e),
+            }
+        }
+        (total, repacked)
+    }
+
+    /// Remembers the keys that were packed. Once too many are remembered, they
+    /// are forgotten, and packed again if the log still lists them.
+    fn remember_packed(
+        &self,
+        logger: &Logger,
+        packed: &mut HashSet<String>,
+        repacked: Vec<&[String]>,
+    ) {
+        if self.dry_run {
+            return;
+        }
+        let keys: usize = repacked.iter().map(|group| group.len()).sum();
+        if packed.len() + keys > MAX_REMEMBERED_KEYS {
+            info!(logger, "Forgetting {} packed keys", packed.len());
+            packed.clear();
+        }
+        packed.extend(repacked.into_iter().flatten().cloned());
+    }
+
+    fn log_sizes(&self, logger: &Logger, scope: &str, sizes: pack_utils::RepackSizes) {
+        info!(
+            logger,
+            "{} {} blobs{} from {} bytes to {} bytes, saving {} bytes",
+            if self.dry_run {
+                "Would repack"
+            } else {
+                "Repacked"
+            },
+            sizes.keys,
+            scope,
+            sizes.old_size,
+            sizes.new_size,
+            sizes.saved_bytes(),
+        );
+    }
+}
@@ -7,23 +7,60 @@
 
 use anyhow::{anyhow, Error, Result};
 use blobstore::{Blobstore, BlobstoreBytes, BlobstoreWithLink};
+use bytes::Bytes;
 use context::CoreContext;
+use futures::future::try_join_all;
 use futures::stream::{FuturesUnordered, TryStreamExt};
-use packblob::{EmptyPack, Pack, PackBlob, SingleCompressed};
+use packblob::{train_dictionary, EmptyPack, Pack, PackBlob, SingleCompressed};
+use std::ops::Add;
 use tokio::task::spawn_blocking;
 
 type BlobsWithKeys = Vec<(String, BlobstoreBytes)>;
 
-// Tries to pack with the first blob from `blobs` as the dictionary for the other blobs
-fn try_pack(zstd_level: i32, blobs: Vec<(String, BlobstoreBytes)>) -> Result<Pack> {
-    let empty_pack = EmptyPack::new(zstd_level);
+/// How much space some keys took up in the blobstore before and after repacking
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RepackSizes {
+    pub keys: usize,
+    pub old_size: u64,
+    pub new_size: u64,
+}
+
+impl RepackSizes {
+    pub fn saved_bytes(&self) -> i64 {
+        self.old_size as i64 - self.new_size as i64
+    }
+}
+
+impl Add for RepackSizes {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            keys: self.keys + other.keys,
+            old_size: self.old_size + other.old_size,
+            new_size: self.new_size + other.new_size,
+        }
+    }
+}
+
+// Tries to pack with the first blob from `blobs` as the base, letting the pack
+// choose how to store each of the other blobs
+fn try_pack(
+    zstd_level: i32,
+    dictionary: Option<Bytes>,
+    blobs: Vec<(String, BlobstoreBytes)>,
+) -> Result<Pack> {
+    let mut empty_pack = EmptyPack::new(zstd_level);
+    if let Some(dictionary) = dictionary {
+        empty_pack = empty_pack.with_dictionary(dictionary);
+    }
 
     let mut blobs = blobs.into_iter();
 
-    let (dict_key, dict_blob) = blobs.next().ok_or_else(|| anyhow!("No blobs to pack"))?;
-    let mut pack = empty_pack.add_base_blob(dict_key.clone(), dict_blob)?;
+    let (base_key, base_blob) = blobs.next().ok_or_else(|| anyhow!("No blobs to pack"))?;
+    let mut pack = empty_pack.add_base_blob(base_key, base_blob)?;
     for (key, blob) in blobs {
-        pack.add_delta_blob(dict_key.clone(), key, blob)?;
+        pack.add_blob(key, blob)?;
     }
 
     Ok(pack)
@@ -37,14 +74,55 @@ fn is_better_pack(best_so_far: Option<&Pack>, new: &Pack) -> bool {
     }
 }
 
-async fn find_best_pack(mut blobs: BlobsWithKeys, zstd_level: i32) -> Result<Option<Pack>> {
+async fn find_best_pack(
+    mut blobs: BlobsWithKeys,
+    zstd_level: i32,
+    trained_dictionaries: bool,
+    every_base: bool,
+) -> Result<Option<Pack>> {
+    // Packs with a trained dictionary are not always smaller, as the dictionary is
+    // stored in the pack too, so try with and without one
+    let dictionaries: Vec<Option<Bytes>> = if trained_dictionaries {
+        let blobs = blobs.clone();
+        let dictionary =
+            spawn_blocking(move || train_dictionary(blobs.iter().map(|(_, blob)| blob))).await?;
+        std::iter::once(None).chain(dictionary.map(Some)).collect()
+    } else {
+        vec![None]
+    };
+
+    let mut orders = vec![];
+    if every_base {
+        // Try every blob as the base, which takes time quadratic in the number of blobs
+        for _ in 0..blobs.len() {
+            orders.push(blobs.clone());
+            blobs.rotate_left(1);
+        }
+    } else {
+        // The blobs come in the order they are most likely to delta well in, such as
+        // successive versions of a file, so use the first one as the base. The largest
+        // blob often has the most in common with the others, so try it as the base too.
+        let largest = (0..blobs.len()).max_by_key(|i| blobs[*i].1.len());
+        if let Some(largest) = largest.filter(|largest| *largest != 0) {
+            let mut largest_first = blobs.clone();
+            let base = largest_first.remove(largest);
+            largest_first.insert(0, base);
+            orders.push(largest_first);
+        }
+        orders.push(blobs);
+    }
+
     let build_packs = FuturesUnordered::new();
-    for _ in 0..blobs.len() {
-        build_packs.push({
-            let blobs = blobs.clone();
-            async { tokio::task::spawn_blocking(move || try_pack(zstd_level, blobs)).await? }
-        });
-        blobs.rotate_left(1);
+    for blobs in orders {
+        for dictionary in dictionaries.iter().cloned() {
+            build_packs.push({
+                let blobs = blobs.clone();
+                async move {
+                    tokio::task::spawn_blocking(move || try_pack(zstd_level, dictionary, blobs))
+                        .await?
+                }
+            });
+        }
     }
 
     build_packs
@@ -58,41 +136,58 @@ async fn find_best_pack(mut blobs: BlobsWithKeys, zstd_level: i32) -> Result<Opt
         .await
 }
 
+// Returns the blobs in the same order as `keys`, and how much space they
+// currently take up in the blobstore
 async fn fetch_blobs<T: BlobstoreWithLink>(
     ctx: &CoreContext,
     blobstore: &PackBlob<T>,
     repo_prefix: &str,
     keys: &[&str],
-) -> Result<BlobsWithKeys> {
-    let blob_fetches: FuturesUnordered<_> = keys
-        .iter()
-        .map(|key| async move {
-            let blob = blobstore
-                .get(ctx, key)
-                .await?
-                .ok_or_else(|| anyhow!("Blob {} not in store", key))?
-                .into_bytes();
-            let pack_key = key
-                .strip_prefix(repo_prefix)
-                .ok_or_else(|| anyhow!("Could not strip {} from {}", repo_prefix, key))?;
-            Result::<_>::Ok((pack_key.to_string(), blob))
-        })
+) -> Result<(BlobsWithKeys, u64)> {
+    let fetched: Vec<_> = try_join_all(keys.iter().map(|key| async move {
+        let data = blobstore
+            .get(ctx, key)
+            .await?
+            .ok_or_else(|| anyhow!("Blob {} not in store", key))?;
+        // Blobs that are already packed only count what they add to their pack
+        let stored_size = data.as_meta().sizes().map_or_else(
+            || data.as_bytes().len() as u64,
+            |sizes| sizes.unique_compressed_size,
+        );
+        let pack_key = key
+            .strip_prefix(repo_prefix)
+            .ok_or_else(|| anyhow!("Could not strip {} from {}", repo_prefix, key))?;
+        Result::<_>::Ok((pack_key.to_string(), data.into_bytes(), stored_size))
+    }))
+    .await?;
+    let stored_size = fetched.iter().map(|(_, _, size)| size).sum();
+    let blobs = fetched
+        .into_iter()
+        .map(|(key, blob, _)| (key, blob))
         .collect();
-
-    blob_fetches.try_collect().await
+    Ok((blobs, stored_size))
 }
 
-/// Given a list of keys to repack, convert them to a single pack
+/// Given a list of keys to repack, convert them to a single pack, or to single
+/// compressed blobs if that is smaller. Returns the space the keys took up
+/// before and after.
+///
+/// Packs only carry a trained dictionary if `trained_dictionaries` is set, as
+/// readers that predate them cannot decode such packs. Every key is tried as the
+/// base of the pack if `every_base` is set, otherwise only the first and the
+/// largest.
 pub async fn repack_keys<T: BlobstoreWithLink>(
     ctx: &CoreContext,
     blobstore: &PackBlob<T>,
     pack_prefix: &str,
     zstd_level: i32,
+    trained_dictionaries: bool,
+    every_base: bool,
     repo_prefix: &str,
     keys: &[&str],
     dry_run: bool,
-) -> Result<()> {
-    let blobs = fetch_blobs(ctx, blobstore, repo_prefix, keys).await?;
+) -> Result<RepackSizes> {
+    let (blobs, old_size) = fetch_blobs(ctx, blobstore, repo_prefix, keys).await?;
     let compression_futs: FuturesUnordered<_> = blobs
         .clone()
         .into_iter()
@@ -104,7 +199,7 @@ pub async fn repack_keys<T: BlobstoreWithLink>(
 
     let single_compressed: Vec<_> = compression_futs.try_collect().await?;
     let pack = if keys.len() > 1 {
-        find_best_pack(blobs, zstd_level).await?
+        find_best_pack(blobs, zstd_level, trained_dictionaries, every_base).await?
     } else {
         None
     };
@@ -112,14 +207,19 @@ pub async fn repack_keys<T: BlobstoreWithLink>(
     let single_compressed_size = single_compressed
         .iter()
         .fold(0usize, |size, (_, item)| size + item.get_compressed_size());
+    let pack = pack.filter(|pack| pack.get_compressed_size() < single_compressed_size);
+    let new_size = pack
+        .as_ref()
+        .map_or(single_compressed_size, Pack::get_compressed_size);
+
     if !dry_run {
         match pack {
-            Some(pack) if pack.get_compressed_size() < single_compressed_size => {
+            Some(pack) => {
                 blobstore
                     .put_packed(ctx, pack, repo_prefix.to_string(), pack_prefix.to_string())
                     .await?;
             }
-            Some(_) | None => {
+            None => {
                 let put_futs: FuturesUnordered<_> = single_compressed
                     .into_iter()
                     .map(|(key, value)| {
@@ -131,5 +231,9 @@ pub async fn repack_keys<T: BlobstoreWithLink>(
             }
         }
     }
-    Ok(())
+    Ok(RepackSizes {
+        keys: keys.len(),
+        old_size,
+        new_size: new_size as u64,
+    })
 }
@@ -0,0 +1,158 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::io::BufRead;
+
+/// The parts of a walker pack info log sample (see the walker's
+/// `--pack-log-scuba-file`) that are needed to choose what to pack.
+#[derive(Deserialize)]
+struct PackInfoSample {
+    int: PackInfoInts,
+    normal: PackInfoNormals,
+}
+
+#[derive(Deserialize)]
+struct PackInfoInts {
+    blobstore_id: Option<u64>,
+    similarity_key: Option<i64>,
+    mtime: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct PackInfoNormals {
+    blobstore_key: String,
+    node_type: String,
+    pack_key: Option<String>,
+}
+
+/// Reads a walker pack info log and groups the keys that are not yet packed
+/// into packs of up to `max_pack_keys` keys. Keys are grouped if they have the
+/// same node type and similarity key, e.g. versions of the same file, and are
+/// ordered by mtime within the group so that each version follows the one it
+/// is most likely to be similar to. Keys with no similarity key, and groups of
+/// one key, are left alone.
+pub fn read_pack_groups(
+    log: impl BufRead,
+    repo_prefix: &str,
+    blobstore_id: Option<u64>,
+    max_pack_keys: usize,
+) -> Result<Vec<Vec<String>>> {
+    let mut seen = HashSet::new();
+    let mut groups = BTreeMap::new();
+    for (line_num, line) in log.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let sample: PackInfoSample = serde_json::from_str(&line)
+            .with_context(|| format!("Invalid walker pack info on line {}", line_num + 1))?;
+        let PackInfoSample { int, normal } = sample;
+
+        // When the walker ran against a multiplex, only consider the store being packed
+        if blobstore_id.is_some() && int.blobstore_id.is_some() && int.blobstore_id != blobstore_id
+        {
+            continue;
+        }
+        if normal.pack_key.is_some() || !normal.blobstore_key.starts_with(repo_prefix) {
+            continue;
+        }
+        let similarity_key = match int.similarity_key {
+            Some(similarity_key) => similarity_key,
+            None => continue,
+        };
+        if !seen.insert(normal.blobstore_key.clone()) {
+            continue;
+        }
+        groups
+            .entry((normal.node_type, similarity_key))
+            .or_insert_with(Vec::new)
+            .push((int.mtime, normal.blobstore_key));
+    }
+
+    let mut packs = vec![];
+    for (_, mut group) in groups {
+        group.sort_unstable();
+        for chunk in group.chunks(max_pack_keys.max(2)) {
+            if chunk.len() > 1 {
+                packs.push(chunk.iter().map(|(_, key)| key.clone()).collect());
+            }
+        }
+    }
+    Ok(packs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample(
+        key: &str,
+        similarity_key: Option<i64>,
+        mtime: u64,
+        pack_key: Option<&str>,
+    ) -> String {
+        serde_json::json!({
+            "int": {
+                "blobstore_id": 1,
+                "similarity_key": similarity_key,
+                "mtime": mtime,
+                "uncompressed_size": 100,
+            },
+            "normal": {
+                "blobstore_key": key,
+                "node_type": "FileContent",
+                "pack_key": pack_key,
+                "repo": "repo",
+            },
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn test_read_pack_groups() -> Result<()> {
+        let log = [
+            sample("repo0000.content.c", Some(1), 3, None),
+            sample("repo0000.content.a", Some(1), 1, None),
+            sample("repo0000.content.b", Some(1), 2, None),
+            // Seen twice, e.g. from different changesets
+            sample("repo0000.content.b", Some(1), 2, None),
+            sample("repo0000.content.d", Some(1), 4, None),
+            // Already packed
+            sample("repo0000.content.e", Some(1), 5, Some("multiblob-x.pack")),
+            // Another file, but only one version of it
+            sample("repo0000.content.f", Some(-2), 1, None),
+            // Nothing to group by
+            sample("repo0000.content.g", None, 1, None),
+            sample("repo0000.content.h", None, 1, None),
+            // Another repo
+            sample("repo0001.content.i", Some(1), 1, None),
+        ]
+        .join("\n");
+
+        let groups = read_pack_groups(log.as_bytes(), "repo0000.", Some(1), 3)?;
+        assert_eq!(
+            groups,
+            vec![vec![
+                "repo0000.content.a".to_string(),
+                "repo0000.content.b".to_string(),
+                "repo0000.content.c".to_string(),
+            ]]
+        );
+
+        let groups = read_pack_groups(log.as_bytes(), "repo0000.", None, 10)?;
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].len(), 4);
+
+        // Samples from other stores in a multiplex are ignored
+        let groups = read_pack_groups(log.as_bytes(), "repo0000.", Some(2), 10)?;
+        assert!(groups.is_empty());
+        Ok(())
+    }
+}
@@ -21,7 +21,7 @@ use blobstore_sync_queue::{
 };
 use cmdlib::args;
 use context::CoreContext;
-use fileblob::Fileblob;
+use fileblob::{Fileblob, Layout};
 use manifoldblob::ManifoldBlob;
 use metaconfig_types::{
     BlobConfig, BlobstoreId, MetadataDatabaseConfig, MultiplexId, RemoteDatabaseConfig,
@@ -204,7 +204,7 @@ fn parse_args(fb: FacebookInit) -> Result<Config, Error> {
         StorageConfig {
             metadata:
                 MetadataDatabaseConfig::Remote(RemoteMetadataDatabaseConfig {
-                    primary: RemoteDatabaseConfig { db_address },
+                    primary: RemoteDatabaseConfig { db_address, .. },
                     ..
                 }),
             blobstore:
@@ -387,8 +387,17 @@ fn make_key_source(
             );
             Ok(res)
         }
-        BlobConfig::Files { path } => {
-            let res = Arc::new(Fileblob::create(path, DEFAULT_PUT_BEHAVIOUR)?);
+        BlobConfig::Files { path, fanout } => {
+            let layout = if *fanout {
+                Layout::FanOut
+            } else {
+                Layout::Flat
+            };
+            let res = Arc::new(Fileblob::create_with_layout(
+                path,
+                DEFAULT_PUT_BEHAVIOUR,
+                layout,
+            )?);
             Ok(res)
         }
         _ => Err(format_err!("Unsupported Blobstore type")),
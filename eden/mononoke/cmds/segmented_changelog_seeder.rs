@@ -26,7 +26,6 @@ use fbinit::FacebookInit;
 use metaconfig_types::MetadataDatabaseConfig;
 use segmented_changelog::types::IdMapVersion;
 use segmented_changelog::{SegmentedChangelogSeeder, SegmentedChangelogSqlConnections};
-use sql_ext::facebook::MyAdmin;
 use sql_ext::replication::{NoReplicaLagMonitor, ReplicaLagMonitor};
 
 const ARG_PREFETCHED_COMMITS_PATH: &str = "prefetched-commits-path";
@@ -103,7 +102,9 @@ async fn run<'a>(ctx: CoreContext, matches: &'a MononokeMatches<'a>) -> Result<(
     let replica_lag_monitor: Arc<dyn ReplicaLagMonitor> = match db_address {
         None => Arc::new(NoReplicaLagMonitor()),
         Some(address) => {
-            let my_admin = MyAdmin::new(ctx.fb).context("building myadmin client")?;
+            let my_admin = mysql_options
+                .myadmin(ctx.fb)
+                .context("building myadmin client")?;
             Arc::new(my_admin.single_shard_lag_monitor(address))
         }
     };
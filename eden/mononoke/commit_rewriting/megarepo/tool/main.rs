@@ -40,8 +40,6 @@ use mononoke_types::{MPath, RepositoryId};
 use movers::get_small_to_large_mover;
 use regex::Regex;
 use slog::{info, warn};
-#[cfg(fbcode_build)]
-use sql_ext::facebook::MyAdmin;
 use sql_ext::replication::{NoReplicaLagMonitor, ReplicaLagMonitor, WaitForReplicationConfig};
 use std::num::NonZeroU64;
 use std::sync::Arc;
@@ -1019,16 +1017,11 @@ async fn process_stream_and_wait_for_replication<'a>(
     let replica_lag_monitor: Arc<dyn ReplicaLagMonitor> = match db_address {
         None => Arc::new(NoReplicaLagMonitor()),
         Some(address) => {
-            #[cfg(fbcode_build)]
-            {
-                let my_admin = MyAdmin::new(ctx.fb).context("building myadmin client")?;
-                Arc::new(my_admin.single_shard_lag_monitor(address))
-            }
-            #[cfg(not(fbcode_build))]
-            {
-                let _address = address;
-                Arc::new(NoReplicaLagMonitor())
-            }
+            let my_admin = matches
+                .mysql_options()
+                .myadmin(ctx.fb)
+                .context("building myadmin client")?;
+            Arc::new(my_admin.single_shard_lag_monitor(address))
         }
     };
 
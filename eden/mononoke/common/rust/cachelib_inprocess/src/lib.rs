@@ -0,0 +1,400 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! An in-process cache with the same interface as the cachelib bindings,
+//! for builds without cachelib. The cache is split into named pools with a
+//! fixed size in bytes, and each pool into shards, each of which is an LRU
+//! list with TinyLFU admission (see `shard`).
+//!
+//! Items live on the heap of this process, so unlike cachelib, the cache
+//! does not shrink when the process grows, and it does not survive
+//! restarts.
+
+#![deny(warnings)]
+
+mod shard;
+mod sketch;
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hasher;
+use std::mem::{align_of, size_of};
+use std::slice;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail, Result};
+use bytes::{Buf, Bytes};
+use fbinit::FacebookInit;
+use once_cell::sync::Lazy;
+
+use crate::shard::{Insert, Shard};
+
+pub use abomonation::Abomonation;
+
+/// Larger items are never cached.
+const MAX_ITEM_SIZE: usize = 4 * 1024 * 1024;
+/// Pools are split into shards of at least this size...
+const MIN_SHARD_SIZE: usize = 64 * 1024 * 1024;
+/// ... and into at most this many shards.
+const MAX_SHARDS: usize = 64;
+
+/// The configuration of the whole cache.
+#[derive(Clone, Debug)]
+pub struct LruCacheConfig {
+    size: usize,
+}
+
+impl LruCacheConfig {
+    /// A cache of `size` bytes, which is divided between the pools.
+    pub fn new(size: usize) -> Self {
+        Self { size }
+    }
+}
+
+struct Cache {
+    available: usize,
+    pools: HashMap<String, Arc<Pool>>,
+}
+
+static CACHE: Lazy<Mutex<Option<Cache>>> = Lazy::new(|| Mutex::new(None));
+
+/// Initialize the cache. This must be done once, before any pool is created.
+pub fn init_cache(_fb: FacebookInit, config: LruCacheConfig) -> Result<()> {
+    let mut cache = CACHE.lock().expect("lock poisoned");
+    if cache.is_some() {
+        bail!("Cache is already initialized");
+    }
+    *cache = Some(Cache {
+        available: config.size,
+        pools: HashMap::new(),
+    });
+    Ok(())
+}
+
+fn with_cache<T>(f: impl FnOnce(&mut Cache) -> Result<T>) -> Result<T> {
+    let mut cache = CACHE.lock().expect("lock poisoned");
+    match cache.as_mut() {
+        Some(cache) => f(cache),
+        None => Err(anyhow!("Cache is not initialized")),
+    }
+}
+
+/// The space that is not yet allocated to a pool, in bytes.
+pub fn get_available_space() -> Result<usize> {
+    with_cache(|cache| Ok(cache.available))
+}
+
+fn get_or_create(name: &str, size: usize) -> Result<Arc<Pool>> {
+    with_cache(|cache| {
+        if let Some(pool) = cache.pools.get(name) {
+            return Ok(pool.clone());
+        }
+        if size == 0 {
+            bail!("Cache pool {} must have a size", name);
+        }
+        if size > cache.available {
+            bail!(
+                "Not enough space for cache pool {}: {} bytes requested, {} available",
+                name,
+                size,
+                cache.available
+            );
+        }
+        let pool = Arc::new(Pool::new(name.to_string(), size));
+        cache.available -= size;
+        cache.pools.insert(name.to_string(), pool.clone());
+        Ok(pool)
+    })
+}
+
+fn get(name: &str) -> Option<Arc<Pool>> {
+    with_cache(|cache| Ok(cache.pools.get(name).cloned()))
+        .ok()
+        .flatten()
+}
+
+/// Get the pool with this name, or create it with `size` bytes of the
+/// available space.
+pub fn get_or_create_pool(name: &str, size: usize) -> Result<LruCachePool> {
+    Ok(LruCachePool {
+        pool: get_or_create(name, size)?,
+    })
+}
+
+/// As `get_or_create_pool`. There are no non-volatile items in this cache.
+pub fn get_or_create_volatile_pool(name: &str, size: usize) -> Result<VolatileLruCachePool> {
+    Ok(VolatileLruCachePool {
+        pool: get_or_create(name, size)?,
+    })
+}
+
+pub fn get_pool(name: &str) -> Option<LruCachePool> {
+    get(name).map(|pool| LruCachePool { pool })
+}
+
+pub fn get_volatile_pool(name: &str) -> Result<Option<VolatileLruCachePool>> {
+    Ok(get(name).map(|pool| VolatileLruCachePool { pool }))
+}
+
+/// The statistics of every pool, named `cachelib.<pool>.<statistic>`.
+pub fn get_counters() -> BTreeMap<String, i64> {
+    let pools = with_cache(|cache| Ok(cache.pools.values().cloned().collect::<Vec<_>>()))
+        .unwrap_or_default();
+    let mut counters = BTreeMap::new();
+    for pool in pools {
+        for (name, value) in pool.counters() {
+            counters.insert(format!("cachelib.{}.{}", pool.name, name), value);
+        }
+    }
+    counters
+}
+
+#[derive(Default)]
+struct Stats {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    sets: AtomicU64,
+    rejected_sets: AtomicU64,
+    evictions: AtomicU64,
+}
+
+struct Pool {
+    name: String,
+    capacity: usize,
+    shards: Vec<Mutex<Shard>>,
+    stats: Stats,
+}
+
+impl Pool {
+    fn new(name: String, capacity: usize) -> Self {
+        let num_shards = (capacity / MIN_SHARD_SIZE).clamp(1, MAX_SHARDS);
+        let shards = (0..num_shards)
+            .map(|_| Mutex::new(Shard::new(capacity / num_shards)))
+            .collect();
+        Self {
+            name,
+            capacity,
+            shards,
+            stats: Stats::default(),
+        }
+    }
+
+    fn shard(&self, key: &[u8]) -> (&Mutex<Shard>, u64) {
+        let mut hasher = DefaultHasher::new();
+        hasher.write(key);
+        let hash = hasher.finish();
+        // The low bits pick the counters in the shard's sketch, so use the
+        // high bits to pick the shard.
+        let shard = ((hash >> 32) as usize) % self.shards.len();
+        (&self.shards[shard], hash)
+    }
+
+    fn get(&self, key: &[u8]) -> Option<Bytes> {
+        let (shard, hash) = self.shard(key);
+        let value = shard.lock().expect("lock poisoned").get(key, hash);
+        let counter = match value {
+            Some(_) => &self.stats.hits,
+            None => &self.stats.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        value
+    }
+
+    fn set(&self, key: &[u8], mut value: impl Buf, replace: bool) -> bool {
+        if value.remaining() > MAX_ITEM_SIZE {
+            self.stats.rejected_sets.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        let value = value.copy_to_bytes(value.remaining());
+        let (shard, hash) = self.shard(key);
+        let insert = shard
+            .lock()
+            .expect("lock poisoned")
+            .insert(key, value, hash, replace);
+        match insert {
+            Insert::Inserted { evicted } => {
+                self.stats.sets.fetch_add(1, Ordering::Relaxed);
+                self.stats.evictions.fetch_add(evicted, Ordering::Relaxed);
+                true
+            }
+            Insert::Exists => false,
+            Insert::Rejected => {
+                self.stats.rejected_sets.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+        }
+    }
+
+    fn remove(&self, key: &[u8]) {
+        let (shard, _) = self.shard(key);
+        shard.lock().expect("lock poisoned").remove(key);
+    }
+
+    fn counters(&self) -> Vec<(&'static str, i64)> {
+        let (mut items, mut size) = (0, 0);
+        for shard in &self.shards {
+            let shard = shard.lock().expect("lock poisoned");
+            items += shard.len();
+            size += shard.size();
+        }
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed) as i64;
+        vec![
+            ("hits", load(&self.stats.hits)),
+            ("misses", load(&self.stats.misses)),
+            ("sets", load(&self.stats.sets)),
+            ("rejected_sets", load(&self.stats.rejected_sets)),
+            ("evictions", load(&self.stats.evictions)),
+            ("items", items as i64),
+            ("size_bytes", size as i64),
+            ("capacity_bytes", self.capacity as i64),
+        ]
+    }
+}
+
+macro_rules! impl_pool {
+    ($name:ident) => {
+        /// A handle to a cache pool.
+        #[derive(Clone)]
+        pub struct $name {
+            pool: Arc<Pool>,
+        }
+
+        impl $name {
+            pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Bytes>> {
+                Ok(self.pool.get(key.as_ref()))
+            }
+
+            /// Add an item if there is no item with this key. Returns
+            /// whether the item was added: items can also be left out of
+            /// the cache if they are too large, or accessed too rarely.
+            pub fn set<K: AsRef<[u8]>, V: Buf>(&self, key: K, value: V) -> Result<bool> {
+                Ok(self.pool.set(key.as_ref(), value, false))
+            }
+
+            /// As `set`, but replaces any existing item.
+            pub fn set_or_replace<K: AsRef<[u8]>, V: Buf>(&self, key: K, value: V) -> Result<bool> {
+                Ok(self.pool.set(key.as_ref(), value, true))
+            }
+
+            pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Result<()> {
+                self.pool.remove(key.as_ref());
+                Ok(())
+            }
+
+            pub fn get_pool_name(&self) -> &str {
+                &self.pool.name
+            }
+        }
+    };
+}
+
+impl_pool!(LruCachePool);
+impl_pool!(VolatileLruCachePool);
+
+/// A unit of storage for decoding items, aligned for any type.
+#[derive(Clone, Copy)]
+#[repr(C, align(16))]
+struct Aligned([u8; 16]);
+
+/// Get an item that was added with `set_cached`.
+#[allow(clippy::ptr_arg)]
+pub fn get_cached<T>(pool: &VolatileLruCachePool, key: &String) -> Result<Option<T>>
+where
+    T: Abomonation + Clone + Send + 'static,
+{
+    assert!(align_of::<T>() <= align_of::<Aligned>());
+
+    let bytes = match pool.get(key)? {
+        Some(bytes) => bytes,
+        None => return Ok(None),
+    };
+    // Decoding reads the item in place, so it needs a mutable copy that is
+    // aligned for `T`, which a `Vec<u8>` isn't guaranteed to be.
+    let mut storage = vec![Aligned([0; 16]); bytes.len() / size_of::<Aligned>() + 1];
+    // SAFETY: the storage is at least `bytes.len()` bytes long, and any bit
+    // pattern is a valid `u8`.
+    let buf = unsafe { slice::from_raw_parts_mut(storage.as_mut_ptr() as *mut u8, bytes.len()) };
+    buf.copy_from_slice(&bytes);
+    // This is safe as long as each key is only used for one type, which is
+    // already needed with cachelib.
+    match unsafe { abomonation::decode::<T>(buf) } {
+        Some((value, [])) => Ok(Some(value.clone())),
+        _ => Err(anyhow!("Invalid cache item for {}", key)),
+    }
+}
+
+/// Add an item, encoded with abomonation, if there is no item with this key.
+#[allow(clippy::ptr_arg)]
+pub fn set_cached<T>(pool: &VolatileLruCachePool, key: &String, value: &T) -> Result<bool>
+where
+    T: Abomonation + Clone + Send + 'static,
+{
+    let mut buf = Vec::new();
+    unsafe { abomonation::encode(value, &mut buf)? };
+    pool.set(key, Bytes::from(buf))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use abomonation_derive::Abomonation;
+
+    #[derive(Abomonation, Clone, Debug, PartialEq)]
+    struct Entry {
+        id: u64,
+        name: String,
+    }
+
+    // There is only one cache per process, so all the tests share it.
+    #[fbinit::test]
+    fn test_pools(fb: FacebookInit) -> Result<()> {
+        assert!(get_available_space().is_err());
+        init_cache(fb, LruCacheConfig::new(10 * 1024 * 1024))?;
+        assert!(init_cache(fb, LruCacheConfig::new(1)).is_err());
+
+        let pool = get_or_create_pool("test", 1024 * 1024)?;
+        assert_eq!(get_available_space()?, 9 * 1024 * 1024);
+        assert!(get_or_create_pool("too_large", 10 * 1024 * 1024).is_err());
+        assert_eq!(get_or_create_pool("test", 5)?.get_pool_name(), "test");
+        assert_eq!(get_available_space()?, 9 * 1024 * 1024);
+        assert!(get_pool("missing").is_none());
+
+        assert_eq!(pool.get("key")?, None);
+        assert!(pool.set("key", Bytes::from("value"))?);
+        assert!(!pool.set("key", Bytes::from("other"))?);
+        assert_eq!(pool.get("key")?, Some(Bytes::from("value")));
+        assert!(pool.set_or_replace("key", &b"other"[..])?);
+        assert_eq!(
+            get_pool("test").unwrap().get("key")?,
+            Some(Bytes::from("other"))
+        );
+        pool.remove("key")?;
+        assert_eq!(pool.get("key")?, None);
+        assert!(!pool.set("large", vec![0u8; MAX_ITEM_SIZE + 1].as_slice())?);
+
+        let counters = get_counters();
+        assert_eq!(counters["cachelib.test.hits"], 2);
+        assert_eq!(counters["cachelib.test.misses"], 2);
+        assert_eq!(counters["cachelib.test.sets"], 2);
+        assert_eq!(counters["cachelib.test.rejected_sets"], 1);
+        assert_eq!(counters["cachelib.test.items"], 0);
+        assert_eq!(counters["cachelib.test.capacity_bytes"], 1024 * 1024);
+
+        let pool = get_or_create_volatile_pool("abomonation", 1024 * 1024)?;
+        let key = "entry".to_string();
+        let entry = Entry {
+            id: 1,
+            name: "one".to_string(),
+        };
+        assert_eq!(get_cached::<Entry>(&pool, &key)?, None);
+        assert!(set_cached(&pool, &key, &entry)?);
+        assert_eq!(get_cached(&pool, &key)?, Some(entry));
+        Ok(())
+    }
+}
@@ -0,0 +1,258 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! One shard of a pool: an LRU list of items bounded by their total size,
+//! which only lets a new item in if doing so evicts an item that has been
+//! accessed less often recently (TinyLFU).
+
+use std::collections::HashMap;
+
+use bytes::Bytes;
+
+use crate::sketch::FrequencySketch;
+
+/// The memory used by an item, on top of its key and value.
+pub const ITEM_OVERHEAD: usize = 64;
+
+/// The sketch is sized for shards of items of about this size.
+const EXPECTED_ITEM_SIZE: usize = 1024;
+const MAX_SKETCH_ITEMS: usize = 1 << 20;
+
+struct Node {
+    key: Bytes,
+    value: Bytes,
+    hash: u64,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+impl Node {
+    fn size(&self) -> usize {
+        self.key.len() + self.value.len() + ITEM_OVERHEAD
+    }
+}
+
+pub enum Insert {
+    /// The item was inserted, evicting this many items.
+    Inserted { evicted: u64 },
+    /// There already is an item with this key.
+    Exists,
+    /// The item is too large, or is accessed less often than the items it
+    /// would evict.
+    Rejected,
+}
+
+pub struct Shard {
+    map: HashMap<Bytes, usize>,
+    nodes: Vec<Node>,
+    free: Vec<usize>,
+    /// The most recently used item.
+    head: Option<usize>,
+    /// The least recently used item, which is evicted first.
+    tail: Option<usize>,
+    size: usize,
+    capacity: usize,
+    sketch: FrequencySketch,
+}
+
+impl Shard {
+    pub fn new(capacity: usize) -> Self {
+        let sketch_items = (capacity / EXPECTED_ITEM_SIZE).min(MAX_SKETCH_ITEMS);
+        Self {
+            map: HashMap::new(),
+            nodes: Vec::new(),
+            free: Vec::new(),
+            head: None,
+            tail: None,
+            size: 0,
+            capacity,
+            sketch: FrequencySketch::new(sketch_items),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn get(&mut self, key: &[u8], hash: u64) -> Option<Bytes> {
+        self.sketch.increment(hash);
+        let index = *self.map.get(key)?;
+        self.unlink(index);
+        self.push_front(index);
+        Some(self.nodes[index].value.clone())
+    }
+
+    pub fn insert(&mut self, key: &[u8], value: Bytes, hash: u64, replace: bool) -> Insert {
+        self.sketch.increment(hash);
+        if let Some(&index) = self.map.get(key) {
+            if !replace {
+                return Insert::Exists;
+            }
+            self.remove_index(index);
+        }
+
+        let size = key.len() + value.len() + ITEM_OVERHEAD;
+        if size > self.capacity {
+            return Insert::Rejected;
+        }
+        if self.size + size > self.capacity {
+            if let Some(victim) = self.tail {
+                let frequency = self.sketch.estimate(hash);
+                if frequency < self.sketch.estimate(self.nodes[victim].hash) {
+                    return Insert::Rejected;
+                }
+            }
+        }
+
+        let mut evicted = 0;
+        while self.size + size > self.capacity {
+            match self.tail {
+                Some(victim) => {
+                    self.remove_index(victim);
+                    evicted += 1;
+                }
+                None => break,
+            }
+        }
+
+        let key = Bytes::copy_from_slice(key);
+        let node = Node {
+            key: key.clone(),
+            value,
+            hash,
+            prev: None,
+            next: None,
+        };
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+        self.size += size;
+        self.map.insert(key, index);
+        self.push_front(index);
+        Insert::Inserted { evicted }
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> bool {
+        match self.map.get(key) {
+            Some(&index) => {
+                self.remove_index(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn remove_index(&mut self, index: usize) {
+        self.unlink(index);
+        let node = &mut self.nodes[index];
+        self.size -= node.size();
+        self.map.remove(&node.key);
+        // Don't hold on to the memory of free nodes.
+        node.key = Bytes::new();
+        node.value = Bytes::new();
+        self.free.push(index);
+    }
+
+    fn unlink(&mut self, index: usize) {
+        let (prev, next) = (self.nodes[index].prev, self.nodes[index].next);
+        match prev {
+            Some(prev) => self.nodes[prev].next = next,
+            None => self.head = next,
+        }
+        match next {
+            Some(next) => self.nodes[next].prev = prev,
+            None => self.tail = prev,
+        }
+        self.nodes[index].prev = None;
+        self.nodes[index].next = None;
+    }
+
+    fn push_front(&mut self, index: usize) {
+        self.nodes[index].next = self.head;
+        match self.head {
+            Some(head) => self.nodes[head].prev = Some(index),
+            None => self.tail = Some(index),
+        }
+        self.head = Some(index);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn insert(shard: &mut Shard, key: u64, replace: bool) -> Insert {
+        let value = Bytes::from(vec![key as u8; 100 - ITEM_OVERHEAD - 8]);
+        shard.insert(&key.to_be_bytes(), value, key, replace)
+    }
+
+    fn get(shard: &mut Shard, key: u64) -> Option<Bytes> {
+        shard.get(&key.to_be_bytes(), key)
+    }
+
+    #[test]
+    fn test_lru() {
+        // Room for three items of 100 bytes.
+        let mut shard = Shard::new(300);
+        for key in 0..3 {
+            assert!(matches!(
+                insert(&mut shard, key, false),
+                Insert::Inserted { evicted: 0 }
+            ));
+        }
+        assert!(matches!(insert(&mut shard, 0, false), Insert::Exists));
+        assert_eq!((shard.len(), shard.size()), (3, 300));
+
+        // Make 0 the most recently used, so that 1 is evicted instead.
+        assert_eq!(get(&mut shard, 0), Some(Bytes::from(vec![0; 28])));
+        assert!(matches!(
+            insert(&mut shard, 3, false),
+            Insert::Inserted { evicted: 1 }
+        ));
+        assert_eq!(get(&mut shard, 1), None);
+        assert!(get(&mut shard, 0).is_some());
+        assert!(get(&mut shard, 3).is_some());
+
+        // 0 is now the least recently used, but it has been accessed three
+        // times, so a key that has been seen once doesn't replace it.
+        assert!(get(&mut shard, 2).is_some());
+        assert!(matches!(insert(&mut shard, 4, false), Insert::Rejected));
+        // Until it's been seen more.
+        get(&mut shard, 4);
+        get(&mut shard, 4);
+        assert!(matches!(
+            insert(&mut shard, 4, false),
+            Insert::Inserted { evicted: 1 }
+        ));
+        assert_eq!(get(&mut shard, 0), None);
+
+        assert!(matches!(
+            insert(&mut shard, 4, true),
+            Insert::Inserted { evicted: 0 }
+        ));
+        assert!(shard.remove(&4u64.to_be_bytes()));
+        assert!(!shard.remove(&4u64.to_be_bytes()));
+        assert_eq!((shard.len(), shard.size()), (2, 200));
+
+        let too_large = Bytes::from(vec![0; 300]);
+        assert!(matches!(
+            shard.insert(b"large", too_large, 5, false),
+            Insert::Rejected
+        ));
+    }
+}
@@ -0,0 +1,102 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! An approximate count of recent accesses to each key, for TinyLFU
+//! admission: a count-min sketch of small counters, which are all halved
+//! every so often so that old accesses count for less.
+
+const ROWS: usize = 4;
+const MAX_COUNT: u8 = 15;
+/// Counters are halved after this many accesses per counter in a row.
+const SAMPLE_FACTOR: usize = 10;
+
+const SEEDS: [u64; ROWS] = [
+    0x9e37_79b9_7f4a_7c15,
+    0xc2b2_ae3d_27d4_eb4f,
+    0x1656_67b1_9e37_79f9,
+    0x27d4_eb2f_1656_67c5,
+];
+
+pub struct FrequencySketch {
+    counters: Vec<u8>,
+    width: usize,
+    additions: usize,
+    sample_size: usize,
+}
+
+impl FrequencySketch {
+    /// A sketch with room for about `items` distinct keys.
+    pub fn new(items: usize) -> Self {
+        let width = items.max(16).next_power_of_two();
+        Self {
+            counters: vec![0; width * ROWS],
+            width,
+            additions: 0,
+            sample_size: width * SAMPLE_FACTOR,
+        }
+    }
+
+    fn index(&self, hash: u64, row: usize) -> usize {
+        let mut h = hash.wrapping_add(SEEDS[row]);
+        h = (h ^ (h >> 33)).wrapping_mul(0xff51_afd7_ed55_8ccd);
+        h ^= h >> 33;
+        row * self.width + (h as usize & (self.width - 1))
+    }
+
+    pub fn increment(&mut self, hash: u64) {
+        for row in 0..ROWS {
+            let index = self.index(hash, row);
+            if self.counters[index] < MAX_COUNT {
+                self.counters[index] += 1;
+            }
+        }
+        self.additions += 1;
+        if self.additions >= self.sample_size {
+            for counter in self.counters.iter_mut() {
+                *counter /= 2;
+            }
+            self.additions /= 2;
+        }
+    }
+
+    pub fn estimate(&self, hash: u64) -> u8 {
+        (0..ROWS)
+            .map(|row| self.counters[self.index(hash, row)])
+            .min()
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sketch() {
+        let mut sketch = FrequencySketch::new(16);
+        for _ in 0..5 {
+            sketch.increment(1);
+        }
+        sketch.increment(2);
+        assert_eq!(sketch.estimate(1), 5);
+        assert_eq!(sketch.estimate(2), 1);
+        assert_eq!(sketch.estimate(3), 0);
+
+        // Counts saturate.
+        for _ in 0..100 {
+            sketch.increment(1);
+        }
+        assert_eq!(sketch.estimate(1), MAX_COUNT);
+
+        // Reaching the sample size of 160 accesses halves all counts.
+        for _ in 0..54 {
+            sketch.increment(1);
+        }
+        assert_eq!(sketch.estimate(1), MAX_COUNT / 2);
+        assert_eq!(sketch.estimate(2), 0);
+    }
+}
@@ -0,0 +1,148 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! A connection to a memcached server, speaking the text protocol described
+//! in https://github.com/memcached/memcached/blob/master/doc/protocol.txt
+
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::Bytes;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream};
+use tokio::net::TcpStream;
+
+/// How to store a value.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StoreCommand {
+    /// Store the value whether or not the key has a value.
+    Set,
+    /// Only store the value if the key has no value.
+    Add,
+}
+
+impl StoreCommand {
+    fn name(self) -> &'static str {
+        match self {
+            StoreCommand::Set => "set",
+            StoreCommand::Add => "add",
+        }
+    }
+}
+
+pub struct Connection {
+    stream: BufStream<TcpStream>,
+}
+
+impl Connection {
+    pub async fn connect(addr: &str) -> Result<Self> {
+        let stream = TcpStream::connect(addr)
+            .await
+            .with_context(|| format!("Failed to connect to memcached at {}", addr))?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream: BufStream::new(stream),
+        })
+    }
+
+    pub async fn get(&mut self, key: &str) -> Result<Option<Bytes>> {
+        self.send(&[format!("get {}\r\n", key).as_bytes()]).await?;
+
+        let line = self.read_line().await?;
+        if line == "END" {
+            return Ok(None);
+        }
+        // VALUE <key> <flags> <bytes>
+        let parts = line.split(' ').collect::<Vec<_>>();
+        let len = match parts.as_slice() {
+            ["VALUE", value_key, _flags, len] if *value_key == key => len
+                .parse::<usize>()
+                .with_context(|| format!("Invalid memcached response: {:?}", line))?,
+            _ => return Err(response_error(&line)),
+        };
+
+        let mut value = vec![0; len + 2];
+        self.stream.read_exact(&mut value).await?;
+        if !value.ends_with(b"\r\n") {
+            bail!("Invalid memcached response: value is not terminated");
+        }
+        value.truncate(len);
+
+        let line = self.read_line().await?;
+        if line != "END" {
+            return Err(response_error(&line));
+        }
+        Ok(Some(Bytes::from(value)))
+    }
+
+    /// Store a value, which expires `exptime` seconds from now (or at that
+    /// Unix time, if it's more than 30 days), or never if it's 0. Returns
+    /// whether the value was stored.
+    pub async fn store(
+        &mut self,
+        command: StoreCommand,
+        key: &str,
+        value: &[u8],
+        exptime: u64,
+    ) -> Result<bool> {
+        let header = format!(
+            "{} {} 0 {} {}\r\n",
+            command.name(),
+            key,
+            exptime,
+            value.len()
+        );
+        self.send(&[header.as_bytes(), value, b"\r\n"]).await?;
+
+        let line = self.read_line().await?;
+        match line.as_str() {
+            "STORED" => Ok(true),
+            "NOT_STORED" => Ok(false),
+            _ => Err(response_error(&line)),
+        }
+    }
+
+    /// Delete a value. Returns whether there was one.
+    pub async fn delete(&mut self, key: &str) -> Result<bool> {
+        self.send(&[format!("delete {}\r\n", key).as_bytes()])
+            .await?;
+
+        let line = self.read_line().await?;
+        match line.as_str() {
+            "DELETED" => Ok(true),
+            "NOT_FOUND" => Ok(false),
+            _ => Err(response_error(&line)),
+        }
+    }
+
+    async fn send(&mut self, parts: &[&[u8]]) -> Result<()> {
+        for part in parts {
+            self.stream.write_all(part).await?;
+        }
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    async fn read_line(&mut self) -> Result<String> {
+        let mut line = String::new();
+        if self.stream.read_line(&mut line).await? == 0 {
+            bail!("memcached closed the connection");
+        }
+        if !line.ends_with("\r\n") {
+            bail!("Invalid memcached response: {:?}", line);
+        }
+        line.truncate(line.len() - 2);
+        Ok(line)
+    }
+}
+
+/// The error for an unexpected response line, which is either one of the
+/// protocol's errors, or something that isn't memcached.
+fn response_error(line: &str) -> anyhow::Error {
+    if line == "ERROR" || line.starts_with("CLIENT_ERROR") || line.starts_with("SERVER_ERROR") {
+        anyhow!("memcached returned an error: {}", line)
+    } else {
+        anyhow!("Unexpected memcached response: {:?}", line)
+    }
+}
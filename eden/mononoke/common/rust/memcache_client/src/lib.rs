@@ -0,0 +1,607 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! A memcached client with the same interface as the memcache bindings, for
+//! builds without them. It talks to the memcached servers set with
+//! `init_servers` (`--memcache-servers` in cmdlib), so that Mononoke servers
+//! can share a cache. Each key lives on one server, which
+//! is picked by rendezvous hashing, so that all clients with the same list
+//! agree on it, and only the keys of a server that is removed from the list
+//! move.
+//!
+//! Without servers, the client is a cache that is always empty: gets miss,
+//! and writes succeed, but are forgotten.
+//!
+//! The interface uses old-style futures, whose errors are `()`: a request
+//! that fails, or times out, is best treated as a miss.
+
+#![deny(warnings)]
+
+mod connection;
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use fbinit::FacebookInit;
+use futures::future::{BoxFuture, FutureExt, TryFutureExt};
+use futures_old::Future;
+use once_cell::sync::OnceCell;
+use sha2::{Digest, Sha256};
+
+use crate::connection::{Connection, StoreCommand};
+
+/// The largest value that is worth setting: memcached's default limit for
+/// items is 1MiB, including the key and the item header.
+pub const MEMCACHE_VALUE_MAX_SIZE: usize = 1024000;
+
+/// The longest key memcached accepts.
+const MAX_KEY_LEN: usize = 250;
+/// memcached treats expiry times longer than this as Unix times.
+const MAX_RELATIVE_EXPTIME: u64 = 30 * 24 * 60 * 60;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+const REQUEST_TIMEOUT: Duration = Duration::from_millis(500);
+/// After failing to connect to a server, requests for its keys fail without
+/// trying it again for this long.
+const DOWN_TIME: Duration = Duration::from_secs(5);
+/// Idle connections kept open to each server.
+const MAX_IDLE_CONNECTIONS: usize = 32;
+
+static SERVERS: OnceCell<Arc<Vec<Server>>> = OnceCell::new();
+
+/// Set the memcached servers (`host:port`) that clients created from now on
+/// talk to. This can only be done once per process. Clients created without
+/// it have no servers.
+pub fn init_servers<'a>(addrs: impl IntoIterator<Item = &'a str>) -> Result<()> {
+    let servers = addrs
+        .into_iter()
+        .map(str::trim)
+        .filter(|addr| !addr.is_empty())
+        .map(Server::new)
+        .collect();
+    SERVERS
+        .set(Arc::new(servers))
+        .map_err(|_| anyhow!("memcache servers are already initialized"))
+}
+
+struct Server {
+    addr: String,
+    idle: Mutex<Vec<Connection>>,
+    down_until: Mutex<Option<Instant>>,
+}
+
+impl Server {
+    fn new(addr: &str) -> Self {
+        Self {
+            addr: addr.to_string(),
+            idle: Mutex::new(Vec::new()),
+            down_until: Mutex::new(None),
+        }
+    }
+
+    async fn connection(&self) -> Result<Connection> {
+        if let Some(conn) = self.idle.lock().expect("lock poisoned").pop() {
+            return Ok(conn);
+        }
+
+        if self.is_down() {
+            return Err(anyhow!("memcached at {} is down", self.addr));
+        }
+        let conn = tokio::time::timeout(CONNECT_TIMEOUT, Connection::connect(&self.addr))
+            .await
+            .map_err(|_| anyhow!("Timed out connecting to memcached at {}", self.addr))
+            .and_then(|conn| conn);
+        if conn.is_err() {
+            *self.down_until.lock().expect("lock poisoned") = Some(Instant::now() + DOWN_TIME);
+        }
+        conn
+    }
+
+    fn is_down(&self) -> bool {
+        let mut down_until = self.down_until.lock().expect("lock poisoned");
+        match *down_until {
+            Some(until) if Instant::now() < until => true,
+            _ => {
+                *down_until = None;
+                false
+            }
+        }
+    }
+
+    /// Run a request on a connection to this server. The connection is only
+    /// reused if the request succeeds, as otherwise it may be in the middle
+    /// of a response.
+    async fn request<T>(
+        &self,
+        key: &str,
+        request: impl for<'a> FnOnce(&'a mut Connection, &'a str) -> BoxFuture<'a, Result<T>>,
+    ) -> Result<T> {
+        let mut conn = self.connection().await?;
+        let res = tokio::time::timeout(REQUEST_TIMEOUT, request(&mut conn, key))
+            .await
+            .map_err(|_| anyhow!("Request to memcached at {} timed out", self.addr))
+            .and_then(|res| res)?;
+        let mut idle = self.idle.lock().expect("lock poisoned");
+        if idle.len() < MAX_IDLE_CONNECTIONS {
+            idle.push(conn);
+        }
+        Ok(res)
+    }
+}
+
+/// A stable hash, as every client must pick the same server: 64-bit FNV-1a,
+/// with a final mix so that all of its bits depend on every byte.
+fn stable_hash(parts: &[&[u8]]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for part in parts {
+        for byte in part.iter() {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+        // Separate the parts, so that ("ab", "c") and ("a", "bc") differ.
+        hash ^= 0xff;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+/// The server for a key: the one with the highest hash of its address and
+/// the key.
+fn pick_server<'a>(servers: &'a [Server], key: &str) -> Option<&'a Server> {
+    servers
+        .iter()
+        .max_by_key(|server| stable_hash(&[server.addr.as_bytes(), key.as_bytes()]))
+}
+
+/// Keys that memcached wouldn't accept - too long, or containing spaces or
+/// control characters - are replaced with their hash.
+fn normalize_key(key: &[u8]) -> String {
+    let valid = key.len() <= MAX_KEY_LEN && key.iter().all(|b| *b > b' ' && *b != 0x7f);
+    match std::str::from_utf8(key) {
+        Ok(key) if valid => key.to_string(),
+        _ => format!("sha256.{}", hex::encode(Sha256::digest(key))),
+    }
+}
+
+fn exptime(ttl: Duration) -> u64 {
+    // 0 means never expire, so round short TTLs up.
+    let secs = ttl.as_secs().max(1);
+    if secs <= MAX_RELATIVE_EXPTIME {
+        secs
+    } else {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        now.as_secs() + secs
+    }
+}
+
+/// A value to set.
+pub struct MemcacheSetType(Bytes);
+
+impl From<Bytes> for MemcacheSetType {
+    fn from(value: Bytes) -> Self {
+        MemcacheSetType(value)
+    }
+}
+
+impl From<Vec<u8>> for MemcacheSetType {
+    fn from(value: Vec<u8>) -> Self {
+        MemcacheSetType(Bytes::from(value))
+    }
+}
+
+impl From<String> for MemcacheSetType {
+    fn from(value: String) -> Self {
+        MemcacheSetType(Bytes::from(value))
+    }
+}
+
+impl From<&'static str> for MemcacheSetType {
+    fn from(value: &'static str) -> Self {
+        MemcacheSetType(Bytes::from(value))
+    }
+}
+
+#[derive(Clone)]
+pub struct MemcacheClient {
+    servers: Arc<Vec<Server>>,
+}
+
+impl fmt::Debug for MemcacheClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let addrs = self
+            .servers
+            .iter()
+            .map(|server| server.addr.as_str())
+            .collect::<Vec<_>>();
+        f.debug_struct("MemcacheClient")
+            .field("servers", &addrs)
+            .finish()
+    }
+}
+
+impl MemcacheClient {
+    pub fn new(_fb: FacebookInit) -> Result<Self> {
+        Ok(Self {
+            servers: SERVERS.get().cloned().unwrap_or_default(),
+        })
+    }
+
+    /// Run a request on the server for `key`, or return `default` if there
+    /// are no servers.
+    fn request<T, K>(
+        &self,
+        key: K,
+        default: T,
+        request: impl for<'a> FnOnce(&'a mut Connection, &'a str) -> BoxFuture<'a, Result<T>>
+            + Send
+            + 'static,
+    ) -> impl Future<Item = T, Error = ()>
+    where
+        T: Send + 'static,
+        K: AsRef<[u8]>,
+    {
+        let servers = self.servers.clone();
+        let key = normalize_key(key.as_ref());
+        async move {
+            match pick_server(&servers, &key) {
+                Some(server) => server.request(&key, request).await,
+                None => Ok(default),
+            }
+        }
+        .boxed()
+        .map_err(|_| ())
+        .compat()
+    }
+
+    pub fn get<K>(&self, key: K) -> impl Future<Item = Option<Bytes>, Error = ()>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.request(key, None, |conn, key| conn.get(key).boxed())
+    }
+
+    fn store<K, V>(
+        &self,
+        command: StoreCommand,
+        key: K,
+        value: V,
+        exptime: u64,
+    ) -> impl Future<Item = bool, Error = ()>
+    where
+        K: AsRef<[u8]>,
+        MemcacheSetType: From<V>,
+    {
+        let MemcacheSetType(value) = MemcacheSetType::from(value);
+        // Without servers, values are stored and immediately forgotten.
+        self.request(key, true, move |conn, key| {
+            async move { conn.store(command, key, &value, exptime).await }.boxed()
+        })
+    }
+
+    pub fn set<K, V>(&self, key: K, value: V) -> impl Future<Item = (), Error = ()>
+    where
+        K: AsRef<[u8]>,
+        MemcacheSetType: From<V>,
+    {
+        self.store(StoreCommand::Set, key, value, 0).map(|_| ())
+    }
+
+    pub fn set_with_ttl<K, V>(
+        &self,
+        key: K,
+        value: V,
+        ttl: Duration,
+    ) -> impl Future<Item = (), Error = ()>
+    where
+        K: AsRef<[u8]>,
+        MemcacheSetType: From<V>,
+    {
+        self.store(StoreCommand::Set, key, value, exptime(ttl))
+            .map(|_| ())
+    }
+
+    /// Set a value if the key has none. Returns whether it was set.
+    pub fn add<K, V>(&self, key: K, value: V) -> impl Future<Item = bool, Error = ()>
+    where
+        K: AsRef<[u8]>,
+        MemcacheSetType: From<V>,
+    {
+        self.store(StoreCommand::Add, key, value, 0)
+    }
+
+    pub fn add_with_ttl<K, V>(
+        &self,
+        key: K,
+        value: V,
+        ttl: Duration,
+    ) -> impl Future<Item = bool, Error = ()>
+    where
+        K: AsRef<[u8]>,
+        MemcacheSetType: From<V>,
+    {
+        self.store(StoreCommand::Add, key, value, exptime(ttl))
+    }
+
+    pub fn del<K>(&self, key: K) -> impl Future<Item = (), Error = ()>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.request(key, false, |conn, key| conn.delete(key).boxed())
+            .map(|_| ())
+    }
+}
+
+/// Generates versioned keys: changing the code version or the site version
+/// invalidates every key that was generated before.
+#[derive(Clone, Debug)]
+pub struct KeyGen {
+    prefix: String,
+    code_version: u32,
+    site_version: u32,
+}
+
+impl KeyGen {
+    pub fn new(prefix: impl Into<String>, code_version: u32, site_version: u32) -> Self {
+        Self {
+            prefix: prefix.into(),
+            code_version,
+            site_version,
+        }
+    }
+
+    pub fn key(&self, key: impl AsRef<str>) -> String {
+        format!(
+            "{}.{}.{}.{}",
+            self.prefix,
+            self.code_version,
+            self.site_version,
+            key.as_ref()
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::env;
+
+    use fbinit::FacebookInit;
+    use futures::compat::Future01CompatExt;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{TcpListener, TcpStream};
+
+    type Store = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+    /// Serve the parts of the text protocol that the client uses, ignoring
+    /// expiry times.
+    async fn serve_fake_memcached(stream: TcpStream, store: Store) -> Result<()> {
+        let mut stream = BufReader::new(stream);
+        loop {
+            let mut line = String::new();
+            if stream.read_line(&mut line).await? == 0 {
+                return Ok(());
+            }
+            let parts = line.trim_end().split(' ').collect::<Vec<_>>();
+            let response = match parts.as_slice() {
+                ["get", key] => match store.lock().unwrap().get(*key) {
+                    Some(value) => {
+                        let mut response =
+                            format!("VALUE {} 0 {}\r\n", key, value.len()).into_bytes();
+                        response.extend(value);
+                        response.extend(b"\r\nEND\r\n");
+                        response
+                    }
+                    None => b"END\r\n".to_vec(),
+                },
+                [command, key, _flags, _exptime, len] => {
+                    let mut value = vec![0; len.parse::<usize>()? + 2];
+                    stream.read_exact(&mut value).await?;
+                    value.truncate(value.len() - 2);
+                    let mut store = store.lock().unwrap();
+                    if *command == "add" && store.contains_key(*key) {
+                        b"NOT_STORED\r\n".to_vec()
+                    } else {
+                        store.insert(key.to_string(), value);
+                        b"STORED\r\n".to_vec()
+                    }
+                }
+                ["delete", key] => match store.lock().unwrap().remove(*key) {
+                    Some(_) => b"DELETED\r\n".to_vec(),
+                    None => b"NOT_FOUND\r\n".to_vec(),
+                },
+                _ => b"ERROR\r\n".to_vec(),
+            };
+            stream.get_mut().write_all(&response).await?;
+        }
+    }
+
+    async fn start_fake_memcached() -> Result<(String, Store)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?.to_string();
+        let store = Store::default();
+        let server_store = store.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve_fake_memcached(stream, server_store.clone()));
+            }
+        });
+        Ok((addr, store))
+    }
+
+    fn client(addrs: &[&str]) -> MemcacheClient {
+        MemcacheClient {
+            servers: Arc::new(addrs.iter().map(|addr| Server::new(addr)).collect()),
+        }
+    }
+
+    async fn check_client(client: &MemcacheClient, prefix: &str) -> Result<(), ()> {
+        let key = |key: &str| format!("{}{}", prefix, key);
+        assert_eq!(client.get(key("missing")).compat().await?, None);
+        client
+            .set(key("key"), Bytes::from("value"))
+            .compat()
+            .await?;
+        assert_eq!(
+            client.get(key("key")).compat().await?,
+            Some(Bytes::from("value"))
+        );
+        assert!(!client.add(key("key"), "other").compat().await?);
+        client
+            .set_with_ttl(
+                key("key"),
+                b"new value\r\n".to_vec(),
+                Duration::from_secs(60),
+            )
+            .compat()
+            .await?;
+        assert_eq!(
+            client.get(key("key")).compat().await?,
+            Some(Bytes::from("new value\r\n"))
+        );
+        client.del(key("key")).compat().await?;
+        assert_eq!(client.get(key("key")).compat().await?, None);
+        assert!(
+            client
+                .add_with_ttl(key("key"), "added".to_string(), Duration::from_secs(60))
+                .compat()
+                .await?
+        );
+
+        // Keys that memcached would reject are hashed.
+        let long_key = key(&"long key ".repeat(100));
+        client.set(long_key.as_str(), "long").compat().await?;
+        assert_eq!(
+            client.get(long_key.as_str()).compat().await?,
+            Some(Bytes::from("long"))
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fake_memcached() -> Result<()> {
+        let (addr1, store1) = start_fake_memcached().await?;
+        let (addr2, store2) = start_fake_memcached().await?;
+        let client = client(&[&addr1, &addr2]);
+        check_client(&client, "")
+            .await
+            .map_err(|()| anyhow!("Request failed"))?;
+
+        // Each key is stored on one server.
+        let (store1, store2) = (store1.lock().unwrap(), store2.lock().unwrap());
+        assert_eq!(store1.len() + store2.len(), 2);
+        assert!(store1
+            .keys()
+            .chain(store2.keys())
+            .all(|key| key == "key" || (key.starts_with("sha256.") && !key.contains(' '))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_no_servers() {
+        let client = client(&[]);
+        assert_eq!(client.get("key").compat().await, Ok(None));
+        assert_eq!(client.set("key", "value").compat().await, Ok(()));
+        assert_eq!(client.add("key", "value").compat().await, Ok(true));
+        assert_eq!(client.get("key").compat().await, Ok(None));
+    }
+
+    // This is the only test that sets the servers of the process.
+    #[fbinit::test]
+    fn test_init_servers(fb: FacebookInit) -> Result<()> {
+        assert!(MemcacheClient::new(fb)?.servers.is_empty());
+        init_servers(vec!["a:11211", " b:11211", ""])?;
+        assert!(init_servers(vec!["c:11211"]).is_err());
+        let addrs = MemcacheClient::new(fb)?
+            .servers
+            .iter()
+            .map(|server| server.addr.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(addrs, vec!["a:11211", "b:11211"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_server_down() -> Result<()> {
+        // Nothing listens on a port that was just released.
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await?
+            .local_addr()?
+            .to_string();
+        let client = client(&[&addr]);
+        assert_eq!(client.get("key").compat().await, Err(()));
+        assert!(client.servers[0].down_until.lock().unwrap().is_some());
+        assert_eq!(client.set("key", "value").compat().await, Err(()));
+        Ok(())
+    }
+
+    /// Set `MONONOKE_TEST_MEMCACHED` to the address of a memcached server to
+    /// test against it.
+    #[tokio::test]
+    async fn test_memcached() -> Result<()> {
+        let addr = match env::var("MONONOKE_TEST_MEMCACHED") {
+            Ok(addr) => addr,
+            Err(_) => return Ok(()),
+        };
+        let prefix = format!(
+            "mononoke.test.{}.",
+            SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos()
+        );
+        check_client(&client(&[&addr]), &prefix)
+            .await
+            .map_err(|()| anyhow!("Request failed"))
+    }
+
+    #[test]
+    fn test_pick_server() {
+        let servers = ["a:11211", "b:11211", "c:11211"]
+            .iter()
+            .map(|addr| Server::new(addr))
+            .collect::<Vec<_>>();
+        let keys = (0..300).map(|i| format!("key{}", i)).collect::<Vec<_>>();
+        let picked = keys
+            .iter()
+            .map(|key| pick_server(&servers, key).unwrap().addr.clone())
+            .collect::<Vec<_>>();
+        for addr in ["a:11211", "b:11211", "c:11211"].iter() {
+            let count = picked.iter().filter(|picked| picked == addr).count();
+            assert!(count > 50, "{} has {} keys", addr, count);
+        }
+
+        // Removing a server only moves its keys.
+        let remaining = &servers[1..];
+        for (key, picked) in keys.iter().zip(picked.iter()) {
+            if picked != "a:11211" {
+                assert_eq!(&pick_server(remaining, key).unwrap().addr, picked);
+            }
+        }
+    }
+
+    #[test]
+    fn test_keys() {
+        assert_eq!(
+            KeyGen::new("scm.mononoke.changesets", 2, 3).key("repo1.abc"),
+            "scm.mononoke.changesets.2.3.repo1.abc"
+        );
+        assert_eq!(normalize_key(b"a.b:c"), "a.b:c");
+        for key in [&b"a b"[..], b"a\nb", b"\xff", &[b'a'; 251]].iter() {
+            let normalized = normalize_key(key);
+            assert!(normalized.starts_with("sha256."), "{:?}", key);
+            assert_eq!(normalized.len(), 7 + 64);
+        }
+    }
+}
@@ -5,13 +5,22 @@
  * GNU General Public License version 2.
  */
 
+#[cfg(not(fbcode_build))]
+mod mysql_pool;
 #[cfg(not(fbcode_build))]
 mod oss;
+mod postgres;
 pub mod replication;
+mod shard;
 mod sqlite;
 
 use sql::Transaction;
 
+pub use postgres::{
+    create_postgres_connections_sharded, create_postgres_connections_unsharded,
+    open_postgres_test_connections, PostgresLagMonitor, PostgresTimeouts,
+};
+pub use shard::{shard_address, SHARD_PLACEHOLDER};
 pub use sql::{SqlConnections, SqlShardedConnections};
 pub use sqlite::{open_existing_sqlite_path, open_sqlite_in_memory, open_sqlite_path};
 
@@ -26,6 +35,10 @@ pub mod facebook {
     mod r#impl;
 
     use std::fmt::{self, Debug};
+    use std::time::Duration;
+
+    use anyhow::Result;
+    use fbinit::FacebookInit;
 
     #[cfg(fbcode_build)]
     pub use r#impl::{
@@ -51,13 +64,35 @@ pub mod facebook {
 
     impl MysqlOptions {
         pub fn per_key_limit(&self) -> Option<usize> {
+            Some(self.pool_config.per_key_limit as usize)
+        }
+
+        pub fn conn_open_timeout(&self) -> Duration {
+            #[cfg(not(fbcode_build))]
+            {
+                Duration::from_millis(self.pool_config.conn_open_timeout_ms)
+            }
+            #[cfg(fbcode_build)]
+            {
+                Duration::from_millis(self.pool_config.conn_open_timeout)
+            }
+        }
+
+        pub fn query_time_limit(&self) -> Duration {
+            self.pool_config.query_time_limit
+        }
+
+        /// A MyAdmin client for monitoring the replication lag of databases
+        /// that are connected to with these options.
+        pub fn myadmin(&self, fb: FacebookInit) -> Result<MyAdmin> {
             #[cfg(not(fbcode_build))]
             {
-                None
+                let _ = fb;
+                Ok(MyAdmin::with_mysql_options(self))
             }
             #[cfg(fbcode_build)]
             {
-                Some(self.pool_config.per_key_limit as usize)
+                MyAdmin::new(fb)
             }
         }
     }
@@ -0,0 +1,666 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Translation of MySQL queries into PostgreSQL.
+//!
+//! Queries are rendered by the `sql` crate with their values already inlined
+//! as MySQL literals. This rewrites the handful of MySQL-isms that Mononoke
+//! queries use:
+//!
+//! - `INSERT IGNORE` becomes `INSERT ... ON CONFLICT DO NOTHING`.
+//! - `REPLACE INTO` becomes an upsert on the first unique key of the table
+//!   that is fully covered by the inserted columns.
+//! - Index hints (`FORCE INDEX (...)`) are dropped.
+//! - `CAST(... AS UNSIGNED)` casts to `BIGINT`.
+//! - Backtick-quoted identifiers are double-quoted.
+//! - String literals lose their backslash escapes, and values for `bytea`
+//!   columns are written as hex `bytea` literals.
+//! - Inserts into tables with a generated id return that id, so that callers
+//!   get a `last_insert_id`.
+//!
+//! Whether a value is bound for a `bytea` column is worked out from the
+//! column list of inserts, and from the column a value is compared with or
+//! assigned to. This needs to know the columns and keys of the tables a
+//! query touches, which are described by `TableInfo`.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use anyhow::{bail, Result};
+
+/// What a query needs to know about a table.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TableInfo {
+    /// All columns, in table order.
+    pub columns: Vec<String>,
+    /// Columns of type `bytea`.
+    pub binary_columns: HashSet<String>,
+    /// Unique keys of the table, primary key first.
+    pub unique_keys: Vec<Vec<String>>,
+    /// Column filled in from a sequence, if any.
+    pub generated_column: Option<String>,
+}
+
+/// A query translated into PostgreSQL.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Translated {
+    pub sql: String,
+    /// Whether the query returns the generated id of each inserted row.
+    pub returns_id: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Space(String),
+    Word(String),
+    QuotedIdent(String),
+    Str(Vec<u8>),
+    Hex(Vec<u8>),
+    Number(String),
+    Punct(char),
+}
+
+impl Token {
+    fn is_word(&self, word: &str) -> bool {
+        match self {
+            Token::Word(w) => w.eq_ignore_ascii_case(word),
+            _ => false,
+        }
+    }
+
+    fn ident(&self) -> Option<&str> {
+        match self {
+            Token::Word(w) | Token::QuotedIdent(w) => Some(w),
+            _ => None,
+        }
+    }
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>> {
+    let bytes = query.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let c = bytes[i];
+        let start = i;
+
+        if c.is_ascii_whitespace() {
+            while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            tokens.push(Token::Space(query[start..i].to_string()));
+        } else if c == b'0'
+            && bytes.get(i + 1).map_or(false, |c| *c == b'x' || *c == b'X')
+            && bytes.get(i + 2).map_or(false, u8::is_ascii_hexdigit)
+        {
+            i += 2;
+            while i < bytes.len() && bytes[i].is_ascii_hexdigit() {
+                i += 1;
+            }
+            tokens.push(Token::Hex(decode_hex(&query[start + 2..i])?));
+        } else if c.is_ascii_digit() {
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'.') {
+                i += 1;
+            }
+            tokens.push(Token::Number(query[start..i].to_string()));
+        } else if c.is_ascii_alphabetic() || c == b'_' || c >= 0x80 {
+            while i < bytes.len()
+                && (bytes[i].is_ascii_alphanumeric()
+                    || bytes[i] == b'_'
+                    || bytes[i] == b'$'
+                    || bytes[i] >= 0x80)
+            {
+                i += 1;
+            }
+            tokens.push(Token::Word(query[start..i].to_string()));
+        } else if c == b'`' {
+            let end = match query[i + 1..].find('`') {
+                Some(end) => i + 1 + end,
+                None => bail!("Unterminated identifier in query: {}", query),
+            };
+            tokens.push(Token::QuotedIdent(query[i + 1..end].to_string()));
+            i = end + 1;
+        } else if c == b'\'' || c == b'"' {
+            let (value, end) = parse_string(bytes, i)?;
+            tokens.push(Token::Str(value));
+            i = end;
+        } else {
+            let c = query[i..].chars().next().expect("not at end of query");
+            tokens.push(Token::Punct(c));
+            i += c.len_utf8();
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Parse a MySQL string literal starting at `start`, returning its value and
+/// the position just past it.
+fn parse_string(bytes: &[u8], start: usize) -> Result<(Vec<u8>, usize)> {
+    let quote = bytes[start];
+    let mut value = Vec::new();
+    let mut i = start + 1;
+
+    while i < bytes.len() {
+        let c = bytes[i];
+        if c == b'\\' {
+            let escaped = match bytes.get(i + 1) {
+                Some(escaped) => *escaped,
+                None => break,
+            };
+            value.push(match escaped {
+                b'0' => 0,
+                b'n' => b'\n',
+                b'r' => b'\r',
+                b't' => b'\t',
+                b'b' => 8,
+                b'Z' => 0x1a,
+                other => other,
+            });
+            i += 2;
+        } else if c == quote {
+            if bytes.get(i + 1) == Some(&quote) {
+                value.push(quote);
+                i += 2;
+            } else {
+                return Ok((value, i + 1));
+            }
+        } else {
+            value.push(c);
+            i += 1;
+        }
+    }
+
+    bail!("Unterminated string in query")
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>> {
+    // MySQL pads odd-length hex literals on the left.
+    let padded;
+    let hex = if hex.len() % 2 == 1 {
+        padded = format!("0{}", hex);
+        &padded
+    } else {
+        hex
+    };
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| Ok(u8::from_str_radix(&hex[i..i + 2], 16)?))
+        .collect()
+}
+
+fn binary_literal(value: &[u8]) -> String {
+    let mut literal = String::with_capacity(value.len() * 2 + 12);
+    literal.push_str("'\\x");
+    for byte in value {
+        write!(literal, "{:02x}", byte).expect("writing to a String cannot fail");
+    }
+    literal.push_str("'::bytea");
+    literal
+}
+
+fn text_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum OnConflict {
+    None,
+    Ignore,
+    Replace,
+}
+
+/// Names of the tables a query reads from or writes to.
+pub fn referenced_tables(query: &str) -> Result<Vec<String>> {
+    let tokens = tokenize(query)?;
+    Ok(tables_in(&tokens))
+}
+
+fn tables_in(tokens: &[Token]) -> Vec<String> {
+    let mut tables = Vec::new();
+    let mut words = tokens.iter().filter(|t| !matches!(t, Token::Space(_)));
+
+    while let Some(token) = words.next() {
+        if ["FROM", "JOIN", "INTO", "UPDATE"]
+            .iter()
+            .any(|kw| token.is_word(kw))
+        {
+            if let Some(table) = words.next().and_then(Token::ident) {
+                if !tables.iter().any(|t| t == table) {
+                    tables.push(table.to_string());
+                }
+            }
+        }
+    }
+
+    tables
+}
+
+struct Translator<'a> {
+    tokens: Vec<Token>,
+    tables: Vec<&'a TableInfo>,
+    target: Option<&'a TableInfo>,
+}
+
+impl<'a> Translator<'a> {
+    fn is_binary(&self, column: &str) -> bool {
+        self.tables
+            .iter()
+            .any(|table| table.binary_columns.contains(column))
+    }
+
+    /// Index of the previous non-space token before `i`.
+    fn prev(&self, i: usize) -> Option<usize> {
+        (0..i)
+            .rev()
+            .find(|j| !matches!(self.tokens[*j], Token::Space(_)))
+    }
+
+    /// Index of the next non-space token after `i`.
+    fn next(&self, i: usize) -> Option<usize> {
+        (i + 1..self.tokens.len()).find(|j| !matches!(self.tokens[*j], Token::Space(_)))
+    }
+
+    /// The column that the value at `i` is compared with or assigned to, as
+    /// in `column = value` or `column >= value`.
+    fn compared_column(&self, i: usize) -> Option<&str> {
+        let mut j = self.prev(i)?;
+        let mut saw_operator = false;
+        while let Token::Punct(c) = self.tokens[j] {
+            if !"=<>!".contains(c) {
+                return None;
+            }
+            saw_operator = true;
+            j = self.prev(j)?;
+        }
+        if saw_operator {
+            self.tokens[j].ident()
+        } else {
+            None
+        }
+    }
+
+    /// The column before `IN` for an `IN (...)` list opening at `i`.
+    fn in_list_column(&self, i: usize) -> Option<&str> {
+        let j = self.prev(i)?;
+        if !self.tokens[j].is_word("IN") {
+            return None;
+        }
+        let mut j = self.prev(j)?;
+        if self.tokens[j].is_word("NOT") {
+            j = self.prev(j)?;
+        }
+        self.tokens[j].ident()
+    }
+
+    fn translate(mut self) -> Result<Translated> {
+        let first = (0..self.tokens.len())
+            .find(|i| !matches!(self.tokens[*i], Token::Space(_)))
+            .unwrap_or(0);
+
+        let mut on_conflict = OnConflict::None;
+        let is_insert = match self.tokens.get(first) {
+            Some(t) if t.is_word("INSERT") => {
+                if let Some(next) = self.next(first) {
+                    if self.tokens[next].is_word("IGNORE") {
+                        on_conflict = OnConflict::Ignore;
+                        self.tokens.drain(first + 1..=next);
+                    }
+                }
+                true
+            }
+            Some(t) if t.is_word("REPLACE") => {
+                on_conflict = OnConflict::Replace;
+                self.tokens[first] = Token::Word("INSERT".to_string());
+                true
+            }
+            _ => false,
+        };
+
+        let insert_columns = if is_insert {
+            self.insert_columns()
+        } else {
+            Vec::new()
+        };
+
+        let mut out = String::new();
+        // Column of each value inside an INSERT ... VALUES list.
+        let mut values_depth = None;
+        let mut tuple_index = 0;
+        // Columns of IN lists we are inside of, with their paren depth.
+        let mut in_lists: Vec<(usize, Option<String>)> = Vec::new();
+        let mut depth = 0;
+        let mut i = 0;
+
+        while i < self.tokens.len() {
+            match &self.tokens[i] {
+                Token::Space(s) => out.push_str(s),
+                Token::Word(w) if is_index_hint(w) => {
+                    if let Some(end) = self.index_hint_end(i) {
+                        i = end + 1;
+                        if out.ends_with(char::is_whitespace)
+                            && matches!(self.tokens.get(i), Some(Token::Space(_)))
+                        {
+                            i += 1;
+                        }
+                        continue;
+                    }
+                    out.push_str(w);
+                }
+                Token::Word(w) if w.eq_ignore_ascii_case("VALUES") && is_insert => {
+                    values_depth = Some(depth);
+                    out.push_str(w);
+                }
+                Token::Word(w)
+                    if (w.eq_ignore_ascii_case("UNSIGNED") || w.eq_ignore_ascii_case("SIGNED"))
+                        && self.prev(i).map_or(false, |j| self.tokens[j].is_word("AS")) =>
+                {
+                    out.push_str("BIGINT");
+                    if let Some(next) = self.next(i) {
+                        if self.tokens[next].is_word("INTEGER") {
+                            i = next + 1;
+                            continue;
+                        }
+                    }
+                }
+                Token::Word(w) => out.push_str(w),
+                Token::QuotedIdent(ident) => out.push_str(&quote_ident(ident)),
+                Token::Number(n) => out.push_str(n),
+                Token::Punct(c) => {
+                    match c {
+                        '(' => {
+                            if values_depth == Some(depth) {
+                                tuple_index = 0;
+                            }
+                            depth += 1;
+                            let column = self.in_list_column(i).map(str::to_string);
+                            if column.is_some() {
+                                in_lists.push((depth, column));
+                            }
+                        }
+                        ')' => {
+                            if in_lists.last().map_or(false, |(d, _)| *d == depth) {
+                                in_lists.pop();
+                            }
+                            depth = depth.saturating_sub(1);
+                        }
+                        ',' if values_depth.map_or(false, |d| depth == d + 1) => {
+                            tuple_index += 1;
+                        }
+                        ';' if depth == 0 => {
+                            // Trailing semicolons would end up before the
+                            // conflict clause.
+                            i += 1;
+                            continue;
+                        }
+                        _ => {}
+                    }
+                    out.push(*c);
+                }
+                Token::Str(value) | Token::Hex(value) => {
+                    let column = if values_depth.map_or(false, |d| depth == d + 1) {
+                        insert_columns.get(tuple_index).map(String::as_str)
+                    } else if let Some((_, column)) = in_lists.last().filter(|(d, _)| *d == depth) {
+                        column.as_deref()
+                    } else {
+                        self.compared_column(i)
+                    };
+
+                    let binary = column.map_or(false, |c| self.is_binary(c));
+                    let is_hex = matches!(self.tokens[i], Token::Hex(_));
+                    match std::str::from_utf8(value) {
+                        Ok(text) if !binary && !is_hex && !text.contains('\0') => {
+                            out.push_str(&text_literal(text))
+                        }
+                        _ => out.push_str(&binary_literal(value)),
+                    }
+                }
+            }
+            i += 1;
+        }
+
+        let out = out.trim_end().to_string();
+        let mut translated = Translated {
+            sql: out,
+            returns_id: false,
+        };
+
+        match on_conflict {
+            OnConflict::None => {}
+            OnConflict::Ignore => translated.sql.push_str(" ON CONFLICT DO NOTHING"),
+            OnConflict::Replace => {
+                if let Some(clause) = self.upsert_clause(&insert_columns) {
+                    translated.sql.push(' ');
+                    translated.sql.push_str(&clause);
+                }
+            }
+        }
+
+        if is_insert {
+            if let Some(column) = self.target.and_then(|t| t.generated_column.as_ref()) {
+                translated.sql.push_str(" RETURNING ");
+                translated.sql.push_str(&quote_ident(column));
+                translated.returns_id = true;
+            }
+        }
+
+        Ok(translated)
+    }
+
+    /// Columns that an insert provides values for: either its explicit
+    /// column list, or all columns of the table.
+    fn insert_columns(&self) -> Vec<String> {
+        let into = match self.tokens.iter().position(|t| t.is_word("INTO")) {
+            Some(into) => into,
+            None => return Vec::new(),
+        };
+        let open = self.next(into).and_then(|table| self.next(table));
+        match open.map(|open| (open, &self.tokens[open])) {
+            Some((open, Token::Punct('('))) => self.tokens[open + 1..]
+                .iter()
+                .take_while(|t| **t != Token::Punct(')'))
+                .filter_map(Token::ident)
+                .map(str::to_string)
+                .collect(),
+            _ => self
+                .target
+                .map(|table| table.columns.clone())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// If the word at `i` starts an index hint, the index of its closing
+    /// parenthesis.
+    fn index_hint_end(&self, i: usize) -> Option<usize> {
+        let index = self.next(i)?;
+        if !(self.tokens[index].is_word("INDEX") || self.tokens[index].is_word("KEY")) {
+            return None;
+        }
+        let open = self.next(index)?;
+        if self.tokens[open] != Token::Punct('(') {
+            return None;
+        }
+        (open..self.tokens.len()).find(|j| self.tokens[*j] == Token::Punct(')'))
+    }
+
+    /// `ON CONFLICT` clause that makes an insert replace existing rows, like
+    /// MySQL's `REPLACE INTO`.
+    fn upsert_clause(&self, columns: &[String]) -> Option<String> {
+        let target = self.target?;
+        let key = target
+            .unique_keys
+            .iter()
+            .find(|key| key.iter().all(|k| columns.contains(k)))?;
+
+        let updates: Vec<String> = columns
+            .iter()
+            .filter(|c| !key.contains(c))
+            .map(|c| format!("{} = EXCLUDED.{}", quote_ident(c), quote_ident(c)))
+            .collect();
+
+        let key = key
+            .iter()
+            .map(|k| quote_ident(k))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        Some(if updates.is_empty() {
+            format!("ON CONFLICT ({}) DO NOTHING", key)
+        } else {
+            format!("ON CONFLICT ({}) DO UPDATE SET {}", key, updates.join(", "))
+        })
+    }
+}
+
+fn is_index_hint(word: &str) -> bool {
+    ["FORCE", "USE", "IGNORE"]
+        .iter()
+        .any(|hint| word.eq_ignore_ascii_case(hint))
+}
+
+/// Translate a MySQL query into PostgreSQL. `tables` describes the tables
+/// that the query references, as returned by `referenced_tables`. Tables
+/// missing from it are assumed to have no binary columns and no keys.
+pub fn translate(query: &str, tables: &HashMap<String, TableInfo>) -> Result<Translated> {
+    let tokens = tokenize(query)?;
+    let names = tables_in(&tokens);
+
+    let target = tokens
+        .iter()
+        .filter(|t| !matches!(t, Token::Space(_)))
+        .take(3)
+        .any(|t| t.is_word("INSERT") || t.is_word("REPLACE"))
+        .then(|| names.first().and_then(|name| tables.get(name)))
+        .flatten();
+
+    Translator {
+        tables: names.iter().filter_map(|name| tables.get(name)).collect(),
+        target,
+        tokens,
+    }
+    .translate()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tables() -> HashMap<String, TableInfo> {
+        let mut tables = HashMap::new();
+        tables.insert(
+            "changesets".to_string(),
+            TableInfo {
+                columns: vec!["id".into(), "repo_id".into(), "cs_id".into(), "gen".into()],
+                binary_columns: vec!["cs_id".to_string()].into_iter().collect(),
+                unique_keys: vec![vec!["id".into()], vec!["repo_id".into(), "cs_id".into()]],
+                generated_column: Some("id".into()),
+            },
+        );
+        tables.insert(
+            "mutable_counters".to_string(),
+            TableInfo {
+                columns: vec!["repo_id".into(), "name".into(), "value".into()],
+                binary_columns: HashSet::new(),
+                unique_keys: vec![vec!["repo_id".into(), "name".into()]],
+                generated_column: None,
+            },
+        );
+        tables.insert(
+            "chunk".to_string(),
+            TableInfo {
+                columns: vec!["id".into(), "chunk_num".into(), "value".into()],
+                binary_columns: vec!["value".to_string()].into_iter().collect(),
+                unique_keys: vec![vec!["id".into(), "chunk_num".into()]],
+                generated_column: None,
+            },
+        );
+        tables
+    }
+
+    fn translate_sql(query: &str) -> String {
+        translate(query, &tables()).unwrap().sql
+    }
+
+    #[test]
+    fn test_insert_ignore() {
+        let translated = translate(
+            "INSERT IGNORE INTO changesets (repo_id, cs_id, gen) VALUES (1, 0xABCD, 2), (1, 'ab\\\\', 3)",
+            &tables(),
+        )
+        .unwrap();
+        assert_eq!(
+            translated.sql,
+            "INSERT INTO changesets (repo_id, cs_id, gen) VALUES (1, '\\xabcd'::bytea, 2), \
+             (1, '\\x61625c'::bytea, 3) ON CONFLICT DO NOTHING RETURNING \"id\""
+        );
+        assert!(translated.returns_id);
+    }
+
+    #[test]
+    fn test_replace() {
+        assert_eq!(
+            translate_sql(
+                "REPLACE INTO mutable_counters (repo_id, name, value) VALUES (1, 'it\\'s', 5)"
+            ),
+            "INSERT INTO mutable_counters (repo_id, name, value) VALUES (1, 'it''s', 5) \
+             ON CONFLICT (\"repo_id\", \"name\") DO UPDATE SET \"value\" = EXCLUDED.\"value\""
+        );
+    }
+
+    #[test]
+    fn test_binary_columns() {
+        // Text that happens to be valid UTF-8 still goes into bytea columns
+        // as bytes, without its backslashes being interpreted.
+        assert_eq!(
+            translate_sql("INSERT IGNORE INTO chunk VALUES ('a', 0, 'C:\\\\x')"),
+            "INSERT INTO chunk VALUES ('a', 0, '\\x433a5c78'::bytea) ON CONFLICT DO NOTHING"
+        );
+        assert_eq!(
+            translate_sql(
+                "SELECT id FROM changesets WHERE repo_id = 1 AND cs_id IN ('ab', 0x01) AND gen >= 3"
+            ),
+            "SELECT id FROM changesets WHERE repo_id = 1 AND cs_id IN ('\\x6162'::bytea, \
+             '\\x01'::bytea) AND gen >= 3"
+        );
+        assert_eq!(
+            translate_sql("SELECT value FROM mutable_counters WHERE name = 'a\\0b'"),
+            "SELECT value FROM mutable_counters WHERE name = '\\x610062'::bytea"
+        );
+    }
+
+    #[test]
+    fn test_hints_and_casts() {
+        assert_eq!(
+            translate_sql(
+                "SELECT cs_id FROM changesets FORCE INDEX(repo_id_id) WHERE repo_id = 1 LIMIT 10"
+            ),
+            "SELECT cs_id FROM changesets WHERE repo_id = 1 LIMIT 10"
+        );
+        assert_eq!(
+            translate_sql("SELECT CAST(SUM(LENGTH(chunk.value)) AS UNSIGNED) FROM `chunk`"),
+            "SELECT CAST(SUM(LENGTH(chunk.value)) AS BIGINT) FROM \"chunk\""
+        );
+    }
+
+    #[test]
+    fn test_referenced_tables() {
+        assert_eq!(
+            referenced_tables(
+                "SELECT a FROM changesets cs0 INNER JOIN csparents ON x = y \
+                 UNION SELECT b FROM changesets"
+            )
+            .unwrap(),
+            vec!["changesets".to_string(), "csparents".to_string()]
+        );
+    }
+}
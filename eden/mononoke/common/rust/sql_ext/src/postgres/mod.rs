@@ -0,0 +1,751 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! PostgreSQL connections.
+//!
+//! The `sql` crate only knows how to talk to SQLite and MySQL, so Postgres
+//! databases are handed to it as MySQL connections: queries are translated
+//! into PostgreSQL by `dialect` before they are sent, and result rows are
+//! converted back into MySQL rows. Database addresses are libpq connection
+//! URLs, e.g. `postgresql://mononoke@db.example.com:5432/metadata`.
+//!
+//! `PostgresLagMonitor` reports how far the standbys of a primary are behind
+//! it, as seen in the primary's `pg_stat_replication` view.
+//!
+//! `open_postgres_test_connections` connects to a fresh schema in a given
+//! database, so that tests can be run against Postgres. The schema is
+//! dropped once the connections are.
+
+mod dialect;
+
+use std::collections::HashMap;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Error, Result};
+use async_trait::async_trait;
+use futures::future::{BoxFuture, FutureExt};
+use mysql_common::constants::ColumnType;
+use mysql_common::packets::Column;
+use mysql_common::row::{new_row, Row};
+use mysql_common::value::Value;
+use once_cell::sync::Lazy;
+use sql::sql_common::deprecated_mysql::{
+    BoxMysqlConnection, BoxMysqlTransaction, MysqlConnection, MysqlTransaction,
+};
+use sql::sql_common::WriteResult;
+use sql::Connection;
+use stats::prelude::*;
+use tokio::sync::{OnceCell, OwnedSemaphorePermit, Semaphore};
+use tokio::time::timeout;
+use tokio_postgres::types::Type;
+use tokio_postgres::{Client, Config, NoTls, Row as PgRow};
+use vec1::Vec1;
+
+use crate::facebook::MysqlOptions;
+use crate::replication::{ReplicaLag, ReplicaLagMonitor};
+use crate::shard::shard_address;
+use crate::{SqlConnections, SqlShardedConnections};
+
+use self::dialect::{referenced_tables, translate, TableInfo};
+
+const DEFAULT_POOL_SIZE: usize = 100;
+const LAG_MONITOR_POOL_SIZE: usize = 2;
+
+/// Test schemas are named `mononoke_test_<pid>_<counter>_<nanos>`.
+const TEST_SCHEMA_PREFIX: &str = "mononoke_test_";
+
+define_stats! {
+    prefix = "mononoke.sql.postgres";
+    queries: dynamic_timeseries("{}.queries", (label: String); Rate, Sum),
+    query_errors: dynamic_timeseries("{}.query_errors", (label: String); Rate, Sum),
+    query_timeouts: dynamic_timeseries("{}.query_timeouts", (label: String); Rate, Sum),
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct PoolKey {
+    address: String,
+    readonly: bool,
+}
+
+/// Pools are shared by every database in the process with the same address.
+static POOLS: Lazy<Mutex<HashMap<PoolKey, PostgresPool>>> = Lazy::new(Default::default);
+
+/// Columns and keys of tables, looked up in the catalog on first use.
+const TABLE_COLUMNS_QUERY: &str = "
+    SELECT a.attname::text,
+           a.atttypid = 'bytea'::regtype::oid,
+           a.attidentity <> '' OR COALESCE(pg_get_expr(d.adbin, d.adrelid), '') LIKE 'nextval(%'
+    FROM pg_attribute a
+    LEFT JOIN pg_attrdef d ON d.adrelid = a.attrelid AND d.adnum = a.attnum
+    WHERE a.attrelid = to_regclass($1::text) AND a.attnum > 0 AND NOT a.attisdropped
+    ORDER BY a.attnum";
+
+const TABLE_KEYS_QUERY: &str = "
+    SELECT ARRAY(
+        SELECT a.attname::text
+        FROM unnest(i.indkey::int2[]) WITH ORDINALITY AS k(attnum, n)
+        JOIN pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = k.attnum
+        ORDER BY k.n
+    )
+    FROM pg_index i
+    WHERE i.indrelid = to_regclass($1::text) AND i.indisunique
+    ORDER BY i.indisprimary DESC, i.indexrelid";
+
+/// Replay lag of each standby. The lag is NULL when a standby has caught up
+/// and nothing has been written since.
+const REPLICA_LAG_QUERY: &str = "
+    SELECT COALESCE(application_name, ''),
+           COALESCE(client_addr::text, ''),
+           COALESCE(EXTRACT(EPOCH FROM replay_lag), 0)::float8
+    FROM pg_stat_replication";
+
+/// Timeouts of a pool. They come from the MySQL pool config, so that both
+/// kinds of database are tuned by the same options.
+#[derive(Copy, Clone, Debug)]
+pub struct PostgresTimeouts {
+    pub conn_open_timeout: Duration,
+    pub query_time_limit: Duration,
+}
+
+impl Default for PostgresTimeouts {
+    fn default() -> Self {
+        Self {
+            conn_open_timeout: Duration::from_secs(3),
+            query_time_limit: Duration::from_secs(10),
+        }
+    }
+}
+
+impl PostgresTimeouts {
+    fn from_options(mysql_options: &MysqlOptions) -> Self {
+        Self {
+            conn_open_timeout: mysql_options.conn_open_timeout(),
+            query_time_limit: mysql_options.query_time_limit(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct PostgresPool {
+    inner: Arc<PoolInner>,
+}
+
+struct PoolInner {
+    config: Config,
+    timeouts: PostgresTimeouts,
+    /// Statements run once, on the first connection, before anything else.
+    setup: Option<String>,
+    setup_done: OnceCell<()>,
+    idle: Mutex<Vec<Client>>,
+    permits: Arc<Semaphore>,
+    tables: Mutex<HashMap<String, TableInfo>>,
+    /// Schema to drop when the pool goes away, for test schemas.
+    drop_schema: Option<String>,
+}
+
+impl Drop for PoolInner {
+    fn drop(&mut self) {
+        if let Some(schema) = self.drop_schema.take() {
+            // This may run on a runtime that is about to shut down, so use
+            // one of our own and wait for it, rather than leave the schema
+            // behind.
+            let config = self.config.clone();
+            let _ = std::thread::spawn(move || {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()?;
+                runtime.block_on(drop_test_schema(config, schema))
+            })
+            .join();
+        }
+    }
+}
+
+async fn drop_test_schema(config: Config, schema: String) -> Result<()> {
+    let (client, connection) = config.connect(NoTls).await?;
+    tokio::spawn(connection);
+    client
+        .batch_execute(&format!("DROP SCHEMA IF EXISTS {} CASCADE", schema))
+        .await?;
+    Ok(())
+}
+
+impl PostgresPool {
+    fn new(
+        config: Config,
+        timeouts: PostgresTimeouts,
+        size: usize,
+        setup: Option<String>,
+        drop_schema: Option<String>,
+    ) -> Self {
+        Self {
+            inner: Arc::new(PoolInner {
+                config,
+                timeouts,
+                setup,
+                setup_done: OnceCell::new(),
+                idle: Mutex::new(Vec::new()),
+                permits: Arc::new(Semaphore::new(size.max(1))),
+                tables: Mutex::new(HashMap::new()),
+                drop_schema,
+            }),
+        }
+    }
+
+    /// Get the pool for `address`, creating it on first use. As for MySQL,
+    /// the size and timeouts of the first caller are the ones that stick.
+    fn shared(
+        address: &str,
+        readonly: bool,
+        size: usize,
+        timeouts: PostgresTimeouts,
+    ) -> Result<Self> {
+        let key = PoolKey {
+            address: address.to_string(),
+            readonly,
+        };
+        let mut pools = POOLS.lock().expect("poisoned lock");
+        if let Some(pool) = pools.get(&key) {
+            return Ok(pool.clone());
+        }
+
+        let config = connection_config(address, readonly, None, timeouts)?;
+        let pool = Self::new(config, timeouts, size, None, None);
+        pools.insert(key, pool.clone());
+        Ok(pool)
+    }
+
+    async fn get(&self) -> Result<PooledClient> {
+        let permit = self.inner.permits.clone().acquire_owned().await?;
+
+        let idle = {
+            let mut idle = self.inner.idle.lock().expect("poisoned lock");
+            std::iter::from_fn(|| idle.pop()).find(|client| !client.is_closed())
+        };
+        let client = match idle {
+            Some(client) => client,
+            None => self.connect().await?,
+        };
+
+        let client = PooledClient {
+            client: Some(client),
+            pool: self.inner.clone(),
+            in_transaction: false,
+            _permit: permit,
+        };
+
+        if let Some(setup) = &self.inner.setup {
+            self.inner
+                .setup_done
+                .get_or_try_init(|| client.client().batch_execute(setup))
+                .await
+                .context("While setting up Postgres database")?;
+        }
+
+        Ok(client)
+    }
+
+    async fn connect(&self) -> Result<Client> {
+        let (client, connection) = timeout(
+            self.inner.timeouts.conn_open_timeout,
+            self.inner.config.connect(NoTls),
+        )
+        .await
+        .map_err(|_| anyhow!("Timed out opening Postgres connection"))?
+        .context("Failed to open Postgres connection")?;
+        tokio::spawn(async move {
+            // Errors show up in the client as a closed connection.
+            let _ = connection.await;
+        });
+        Ok(client)
+    }
+
+    async fn table_infos(
+        &self,
+        client: &Client,
+        tables: Vec<String>,
+    ) -> Result<HashMap<String, TableInfo>> {
+        let mut infos = HashMap::new();
+        for table in tables {
+            let cached = self
+                .inner
+                .tables
+                .lock()
+                .expect("poisoned lock")
+                .get(&table)
+                .cloned();
+            let info = match cached {
+                Some(info) => info,
+                None => {
+                    let info = load_table_info(client, &table).await?;
+                    self.inner
+                        .tables
+                        .lock()
+                        .expect("poisoned lock")
+                        .insert(table.clone(), info.clone());
+                    info
+                }
+            };
+            infos.insert(table, info);
+        }
+        Ok(infos)
+    }
+}
+
+fn connection_config(
+    address: &str,
+    readonly: bool,
+    schema: Option<&str>,
+    timeouts: PostgresTimeouts,
+) -> Result<Config> {
+    let mut config: Config = address
+        .parse()
+        .with_context(|| format!("Invalid Postgres address {:?}", address))?;
+
+    let mut options = config.get_options().unwrap_or_default().to_string();
+    if readonly {
+        options.push_str(" -c default_transaction_read_only=on");
+    }
+    if let Some(schema) = schema {
+        options.push_str(&format!(" -c search_path={}", schema));
+    }
+    config.options(options.trim());
+    config.connect_timeout(timeouts.conn_open_timeout);
+    Ok(config)
+}
+
+async fn load_table_info(client: &Client, table: &str) -> Result<TableInfo> {
+    let mut info = TableInfo::default();
+
+    for row in client.query(TABLE_COLUMNS_QUERY, &[&table]).await? {
+        let name: String = row.try_get(0)?;
+        if row.try_get(1)? {
+            info.binary_columns.insert(name.clone());
+        }
+        if row.try_get(2)? {
+            info.generated_column = Some(name.clone());
+        }
+        info.columns.push(name);
+    }
+
+    for row in client.query(TABLE_KEYS_QUERY, &[&table]).await? {
+        info.unique_keys.push(row.try_get(0)?);
+    }
+
+    Ok(info)
+}
+
+/// A connection checked out of a pool. It goes back to the pool when
+/// dropped, unless it is in the middle of a transaction, in which case it is
+/// closed so that the transaction is rolled back.
+struct PooledClient {
+    client: Option<Client>,
+    pool: Arc<PoolInner>,
+    in_transaction: bool,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl PooledClient {
+    fn client(&self) -> &Client {
+        self.client.as_ref().expect("client is only taken on drop")
+    }
+}
+
+impl Drop for PooledClient {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take() {
+            if !self.in_transaction && !client.is_closed() {
+                self.pool.idle.lock().expect("poisoned lock").push(client);
+            }
+        }
+    }
+}
+
+fn convert_row(row: &PgRow) -> Result<Row> {
+    let mut values = Vec::with_capacity(row.len());
+    let mut columns = Vec::with_capacity(row.len());
+
+    for (i, column) in row.columns().iter().enumerate() {
+        let ty = column.type_();
+        let (value, column_type) = if *ty == Type::INT2 {
+            let v: Option<i16> = row.try_get(i)?;
+            (
+                v.map(|v| Value::Int(v.into())),
+                ColumnType::MYSQL_TYPE_SHORT,
+            )
+        } else if *ty == Type::INT4 {
+            let v: Option<i32> = row.try_get(i)?;
+            (v.map(|v| Value::Int(v.into())), ColumnType::MYSQL_TYPE_LONG)
+        } else if *ty == Type::INT8 {
+            let v: Option<i64> = row.try_get(i)?;
+            (v.map(Value::Int), ColumnType::MYSQL_TYPE_LONGLONG)
+        } else if *ty == Type::BOOL {
+            let v: Option<bool> = row.try_get(i)?;
+            (v.map(|v| Value::Int(v.into())), ColumnType::MYSQL_TYPE_TINY)
+        } else if *ty == Type::FLOAT4 {
+            let v: Option<f32> = row.try_get(i)?;
+            (v.map(Value::Float), ColumnType::MYSQL_TYPE_FLOAT)
+        } else if *ty == Type::FLOAT8 {
+            let v: Option<f64> = row.try_get(i)?;
+            (v.map(Value::Double), ColumnType::MYSQL_TYPE_DOUBLE)
+        } else if *ty == Type::BYTEA {
+            let v: Option<Vec<u8>> = row.try_get(i)?;
+            (v.map(Value::Bytes), ColumnType::MYSQL_TYPE_BLOB)
+        } else if [Type::TEXT, Type::VARCHAR, Type::BPCHAR, Type::NAME].contains(ty) {
+            let v: Option<String> = row.try_get(i)?;
+            (
+                v.map(|v| Value::Bytes(v.into_bytes())),
+                ColumnType::MYSQL_TYPE_VAR_STRING,
+            )
+        } else {
+            return Err(anyhow!(
+                "Unsupported Postgres type {} for column {}",
+                ty,
+                column.name()
+            ));
+        };
+
+        values.push(value.unwrap_or(Value::NULL));
+        columns.push(Column::new(column_type).with_name(column.name().as_bytes()));
+    }
+
+    Ok(new_row(values, columns.into()))
+}
+
+async fn run_query<T, Fut>(label: &str, limit: Duration, query: Fut) -> Result<T>
+where
+    Fut: std::future::Future<Output = Result<T, tokio_postgres::Error>>,
+{
+    STATS::queries.add_value(1, (label.to_string(),));
+    match timeout(limit, query).await {
+        Ok(Ok(res)) => Ok(res),
+        Ok(Err(e)) => {
+            STATS::query_errors.add_value(1, (label.to_string(),));
+            Err(Error::from(e).context(format!("Postgres query failed for {}", label)))
+        }
+        Err(_) => {
+            STATS::query_timeouts.add_value(1, (label.to_string(),));
+            Err(anyhow!(
+                "Postgres query timed out after {:?} for {}",
+                limit,
+                label
+            ))
+        }
+    }
+}
+
+async fn read(
+    pool: &PostgresPool,
+    client: &Client,
+    label: &str,
+    query: String,
+) -> Result<Vec<Row>> {
+    let tables = pool.table_infos(client, referenced_tables(&query)?).await?;
+    let translated = translate(&query, &tables)?;
+    let limit = pool.inner.timeouts.query_time_limit;
+    let rows = run_query(label, limit, client.query(translated.sql.as_str(), &[])).await?;
+    rows.iter().map(convert_row).collect()
+}
+
+async fn write(
+    pool: &PostgresPool,
+    client: &Client,
+    label: &str,
+    query: String,
+) -> Result<WriteResult> {
+    let tables = pool.table_infos(client, referenced_tables(&query)?).await?;
+    let translated = translate(&query, &tables)?;
+    let limit = pool.inner.timeouts.query_time_limit;
+
+    if translated.returns_id {
+        let rows = run_query(label, limit, client.query(translated.sql.as_str(), &[])).await?;
+        // Like MySQL, report the id of the first row inserted.
+        let last_insert_id = match rows.first() {
+            Some(row) => Some(row.try_get::<_, i64>(0)? as u64),
+            None => None,
+        };
+        Ok(WriteResult::new(last_insert_id, rows.len() as u64))
+    } else {
+        let affected_rows =
+            run_query(label, limit, client.execute(translated.sql.as_str(), &[])).await?;
+        Ok(WriteResult::new(None, affected_rows))
+    }
+}
+
+#[derive(Clone)]
+struct PostgresConnection {
+    pool: PostgresPool,
+    label: String,
+}
+
+impl MysqlConnection for PostgresConnection {
+    fn read_query(&self, query: String) -> BoxFuture<'static, Result<Vec<Row>>> {
+        let this = self.clone();
+        async move {
+            let client = this.pool.get().await?;
+            read(&this.pool, client.client(), &this.label, query).await
+        }
+        .boxed()
+    }
+
+    fn write_query(&self, query: String) -> BoxFuture<'static, Result<WriteResult>> {
+        let this = self.clone();
+        async move {
+            let client = this.pool.get().await?;
+            write(&this.pool, client.client(), &this.label, query).await
+        }
+        .boxed()
+    }
+
+    fn transaction(&self) -> BoxFuture<'static, Result<BoxMysqlTransaction>> {
+        let this = self.clone();
+        async move {
+            let mut client = this.pool.get().await?;
+            run_query(
+                &this.label,
+                this.pool.inner.timeouts.query_time_limit,
+                client.client().batch_execute("BEGIN"),
+            )
+            .await?;
+            client.in_transaction = true;
+            let transaction: BoxMysqlTransaction = Box::new(PostgresTransaction {
+                client: Arc::new(tokio::sync::Mutex::new(client)),
+                pool: this.pool,
+                label: this.label,
+            });
+            Ok(transaction)
+        }
+        .boxed()
+    }
+
+    fn box_clone(&self) -> BoxMysqlConnection {
+        Box::new(self.clone())
+    }
+}
+
+struct PostgresTransaction {
+    client: Arc<tokio::sync::Mutex<PooledClient>>,
+    pool: PostgresPool,
+    label: String,
+}
+
+impl PostgresTransaction {
+    fn finish(self: Box<Self>, statement: &'static str) -> BoxFuture<'static, Result<()>> {
+        async move {
+            let mut client = self.client.lock().await;
+            run_query(
+                &self.label,
+                self.pool.inner.timeouts.query_time_limit,
+                client.client().batch_execute(statement),
+            )
+            .await?;
+            client.in_transaction = false;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+impl MysqlTransaction for PostgresTransaction {
+    fn read_query(&self, query: String) -> BoxFuture<'static, Result<Vec<Row>>> {
+        let client = self.client.clone();
+        let pool = self.pool.clone();
+        let label = self.label.clone();
+        async move { read(&pool, client.lock().await.client(), &label, query).await }.boxed()
+    }
+
+    fn write_query(&self, query: String) -> BoxFuture<'static, Result<WriteResult>> {
+        let client = self.client.clone();
+        let pool = self.pool.clone();
+        let label = self.label.clone();
+        async move { write(&pool, client.lock().await.client(), &label, query).await }.boxed()
+    }
+
+    fn commit(self: Box<Self>) -> BoxFuture<'static, Result<()>> {
+        self.finish("COMMIT")
+    }
+
+    fn rollback(self: Box<Self>) -> BoxFuture<'static, Result<()>> {
+        self.finish("ROLLBACK")
+    }
+}
+
+fn pool_connection(pool: PostgresPool, label: String) -> Connection {
+    let connection: BoxMysqlConnection = Box::new(PostgresConnection { pool, label });
+    Connection::DeprecatedMysql(connection)
+}
+
+fn create_connections(
+    mysql_options: &MysqlOptions,
+    label: String,
+    address: &str,
+    readonly: bool,
+) -> Result<SqlConnections> {
+    let size = mysql_options.per_key_limit().unwrap_or(DEFAULT_POOL_SIZE);
+    let timeouts = PostgresTimeouts::from_options(mysql_options);
+
+    let write_connection = pool_connection(
+        PostgresPool::shared(address, readonly, size, timeouts)?,
+        label.clone(),
+    );
+    let read_connection =
+        pool_connection(PostgresPool::shared(address, true, size, timeouts)?, label);
+
+    Ok(SqlConnections {
+        write_connection,
+        read_master_connection: read_connection.clone(),
+        read_connection,
+    })
+}
+
+/// Connect to the Postgres database at `db_address`. The size of the
+/// connection pool is taken from `mysql_options`.
+pub fn create_postgres_connections_unsharded(
+    mysql_options: &MysqlOptions,
+    label: String,
+    db_address: String,
+    readonly: bool,
+) -> Result<SqlConnections> {
+    create_connections(mysql_options, label, &db_address, readonly)
+}
+
+/// Connect to each shard of a sharded Postgres database. `shardmap` is a
+/// Postgres URL in which `{shard}` is replaced by the shard id.
+pub fn create_postgres_connections_sharded<S>(
+    mysql_options: &MysqlOptions,
+    label: String,
+    shardmap: String,
+    shards: S,
+    readonly: bool,
+) -> Result<SqlShardedConnections>
+where
+    S: IntoIterator<Item = usize>,
+{
+    let shard_connections = shards
+        .into_iter()
+        .map(|shard_id| {
+            let address = shard_address(&shardmap, shard_id)?;
+            create_connections(mysql_options, label.clone(), &address, readonly)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let shard_connections = Vec1::try_from_vec(shard_connections)
+        .map_err(|_| anyhow!("No shards given for sharded database {}", shardmap))?;
+
+    Ok(SqlShardedConnections::from(shard_connections))
+}
+
+/// Create a new, empty schema in the Postgres database at `db_address`, set
+/// it up with `creation_query` and connect to it. This is for tests.
+///
+/// Each call gets its own schema, so tests don't see each other's data. The
+/// schema is dropped when the last of the connections is. Schemas left
+/// behind by tests that didn't get to drop theirs are dropped once they are
+/// a day old.
+pub fn open_postgres_test_connections(
+    db_address: &str,
+    label: &str,
+    creation_query: &str,
+) -> Result<SqlConnections> {
+    static SCHEMA_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let schema = format!(
+        "{}{}_{}_{}",
+        TEST_SCHEMA_PREFIX,
+        process::id(),
+        SCHEMA_COUNTER.fetch_add(1, Ordering::Relaxed),
+        nanos
+    );
+
+    let timeouts = PostgresTimeouts::default();
+    let config = connection_config(db_address, false, Some(&schema), timeouts)?;
+    let setup = format!(
+        "{}\nCREATE SCHEMA {};\n{}",
+        drop_stale_test_schemas(nanos),
+        schema,
+        creation_query
+    );
+    let pool = PostgresPool::new(
+        config,
+        timeouts,
+        DEFAULT_POOL_SIZE,
+        Some(setup),
+        Some(schema),
+    );
+
+    Ok(SqlConnections::new_single(pool_connection(
+        pool,
+        label.to_string(),
+    )))
+}
+
+/// A statement that drops test schemas created more than a day before
+/// `now_nanos`.
+fn drop_stale_test_schemas(now_nanos: u128) -> String {
+    const DAY_NANOS: u128 = 24 * 60 * 60 * 1_000_000_000;
+    format!(
+        "DO $$
+         DECLARE s text;
+         BEGIN
+             FOR s IN
+                 SELECT nspname FROM pg_namespace
+                 WHERE nspname LIKE '{prefix}%'
+                   AND split_part(nspname, '_', 5)::numeric < {cutoff}
+             LOOP
+                 EXECUTE format('DROP SCHEMA IF EXISTS %I CASCADE', s);
+             END LOOP;
+         END $$;",
+        prefix = TEST_SCHEMA_PREFIX.replace('_', "\\_"),
+        cutoff = now_nanos.saturating_sub(DAY_NANOS),
+    )
+}
+
+/// Monitors replication lag of the standbys of the Postgres primary at an
+/// address. The user needs the `pg_monitor` role to see all standbys.
+pub struct PostgresLagMonitor {
+    pool: PostgresPool,
+}
+
+impl PostgresLagMonitor {
+    pub fn new(db_address: &str, timeouts: PostgresTimeouts) -> Result<Self> {
+        Ok(Self {
+            pool: PostgresPool::shared(db_address, true, LAG_MONITOR_POOL_SIZE, timeouts)?,
+        })
+    }
+}
+
+#[async_trait]
+impl ReplicaLagMonitor for PostgresLagMonitor {
+    async fn get_replica_lag(&self) -> Result<Vec<ReplicaLag>> {
+        let client = self.pool.get().await?;
+        let rows = run_query(
+            "replica_lag",
+            self.pool.inner.timeouts.query_time_limit,
+            client.client().query(REPLICA_LAG_QUERY, &[]),
+        )
+        .await?;
+
+        rows.iter()
+            .map(|row| {
+                let name: String = row.try_get(0)?;
+                let address: String = row.try_get(1)?;
+                let lag: f64 = row.try_get(2)?;
+                Ok(ReplicaLag::new(
+                    Duration::from_secs_f64(lag.max(0.0)),
+                    Some(format!("Standby {} ({})", name, address)),
+                ))
+            })
+            .collect()
+    }
+}
@@ -124,6 +124,18 @@ impl<'a> WaitForReplicationConfig<'a> {
         self.logger = Some(logger);
         self
     }
+
+    /// Wait until the lag of every replica is below `max_lag`.
+    pub fn with_max_replication_lag_allowed(mut self, max_lag: Duration) -> Self {
+        self.max_replication_lag_allowed = max_lag;
+        self
+    }
+
+    /// How long to wait between checks of replication lag.
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
 }
 
 #[cfg(test)]
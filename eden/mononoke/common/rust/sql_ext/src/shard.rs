@@ -0,0 +1,38 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use anyhow::{bail, Result};
+
+/// Placeholder in a shard map address that is replaced by the shard id.
+pub const SHARD_PLACEHOLDER: &str = "{shard}";
+
+/// Expand a shard map address into the address of a single shard.
+pub fn shard_address(shardmap: &str, shard_id: usize) -> Result<String> {
+    if !shardmap.contains(SHARD_PLACEHOLDER) {
+        bail!(
+            "Sharded database address {:?} must contain {} to be replaced by the shard id",
+            shardmap,
+            SHARD_PLACEHOLDER
+        );
+    }
+    Ok(shardmap.replace(SHARD_PLACEHOLDER, &shard_id.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_shard_address() -> Result<()> {
+        assert_eq!(
+            shard_address("mysql://db.example.com/filenodes_{shard}", 3)?,
+            "mysql://db.example.com/filenodes_3"
+        );
+        assert!(shard_address("mysql://db.example.com/filenodes", 3).is_err());
+        Ok(())
+    }
+}
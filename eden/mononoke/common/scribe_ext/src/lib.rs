@@ -20,9 +20,11 @@ use anyhow as _; // oss uses anyhow
 
 #[cfg(not(fbcode_build))]
 mod oss;
+#[cfg(not(fbcode_build))]
+mod sink;
 
 #[cfg(not(fbcode_build))]
-pub use oss::ScribeClientImplementation;
+pub use oss::{init_sinks, ScribeClientImplementation};
 #[cfg(fbcode_build)]
 pub use scuba::ScribeClientImplementation;
 
@@ -54,24 +56,10 @@ impl Scribe {
         use Scribe::*;
 
         match self {
-            Client(client) => {
-                #[cfg(fbcode_build)]
-                let res = client.offer(category, sample);
-
-                #[cfg(not(fbcode_build))]
-                let res = {
-                    let _ = client;
-                    Ok(())
-                };
-
-                res
-            }
+            Client(client) => client.offer(category, sample),
             LogToFile(dir_path) => {
                 let dir_path = dir_path.lock().unwrap();
-                let is_valid_category = category
-                    .chars()
-                    .all(|c| char::is_alphanumeric(c) || c == '-' || c == '_');
-                if !is_valid_category {
+                if !is_valid_category(category) {
                     return Err(anyhow!("invalid category: {}", category));
                 }
                 let filename = dir_path.join(category);
@@ -85,3 +73,11 @@ impl Scribe {
         }
     }
 }
+
+/// Categories are used as file names, so only allow characters that are safe
+/// in them.
+fn is_valid_category(category: &str) -> bool {
+    category
+        .chars()
+        .all(|c| char::is_alphanumeric(c) || c == '-' || c == '_')
+}
@@ -5,17 +5,48 @@
  * GNU General Public License version 2.
  */
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
+use cached_config::ConfigStore;
 use fbinit::FacebookInit;
+use once_cell::sync::OnceCell;
+use slog::Logger;
 
-pub struct ScribeClientImplementation {}
+use crate::sink::{ScribeConfig, Sinks};
+
+/// The sinks of this process. Until `init_sinks` is called, samples are
+/// dropped.
+static SINKS: OnceCell<Sinks> = OnceCell::new();
+
+fn sinks() -> &'static Sinks {
+    SINKS.get_or_init(Sinks::empty)
+}
+
+/// Set up the sinks for this process from the `ScribeConfig` at `path` in
+/// `config_store`. This can only be done once, and should happen before any
+/// samples are offered.
+pub fn init_sinks(logger: &Logger, config_store: &ConfigStore, path: &str) -> Result<()> {
+    let config = config_store
+        .get_config_handle::<ScribeConfig>(path.to_string())
+        .with_context(|| format!("Failed to load Scribe config from {}", path))?
+        .get();
+    let sinks = Sinks::from_config(logger, (*config).clone())?;
+    SINKS
+        .set(sinks)
+        .map_err(|_| anyhow!("Scribe sinks have already been set up"))
+}
+
+/// A handle to the sinks for this process. Sinks are set up once, so every
+/// client shares the same files and connections.
+pub struct ScribeClientImplementation {
+    sinks: &'static Sinks,
+}
 
 impl ScribeClientImplementation {
     pub fn new(_fb: FacebookInit) -> Self {
-        Self {}
+        Self { sinks: sinks() }
     }
 
-    pub fn offer(&self, _category: &str, _sample: &str) -> Result<()> {
-        Ok(())
+    pub fn offer(&self, category: &str, sample: &str) -> Result<()> {
+        self.sinks.offer(category, sample)
     }
 }
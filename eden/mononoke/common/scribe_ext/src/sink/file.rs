@@ -0,0 +1,137 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use super::json_line;
+use super::queue::Transport;
+
+struct OpenFile {
+    file: File,
+    len: u64,
+}
+
+/// Appends samples to a JSON-lines file per category. Files are written by
+/// the sink's background thread, never by whoever offers the sample.
+pub struct FileTransport {
+    directory: PathBuf,
+    max_bytes: Option<u64>,
+    max_files: usize,
+    files: HashMap<String, OpenFile>,
+}
+
+impl FileTransport {
+    pub fn new(directory: PathBuf, max_bytes: Option<u64>, max_files: usize) -> Result<Self> {
+        fs::create_dir_all(&directory)
+            .with_context(|| format!("Failed to create {}", directory.display()))?;
+        Ok(Self {
+            directory,
+            max_bytes,
+            max_files,
+            files: HashMap::new(),
+        })
+    }
+
+    fn path(&self, category: &str) -> PathBuf {
+        self.directory.join(format!("{}.jsonl", category))
+    }
+
+    fn open(path: &Path) -> Result<OpenFile> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        let len = file.metadata()?.len();
+        Ok(OpenFile { file, len })
+    }
+
+    /// Shift `path.1` to `path.2` and so on, dropping the oldest file, then
+    /// move `path` to `path.1`.
+    fn rotate(&self, path: &Path) -> Result<()> {
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}", path.display(), n));
+        if self.max_files == 0 {
+            return Ok(fs::remove_file(path)?);
+        }
+        for n in (1..self.max_files).rev() {
+            match fs::rename(rotated(n), rotated(n + 1)) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(fs::rename(path, rotated(1))?)
+    }
+
+    fn write(&mut self, category: &str, sample: &str) -> Result<()> {
+        let mut line = json_line(sample).into_owned();
+        line.push('\n');
+
+        let path = self.path(category);
+        if !self.files.contains_key(category) {
+            self.files.insert(category.to_string(), Self::open(&path)?);
+        }
+        if let Some(max_bytes) = self.max_bytes {
+            let len = self.files[category].len;
+            if len > 0 && len + line.len() as u64 > max_bytes {
+                self.files.remove(category);
+                self.rotate(&path)
+                    .with_context(|| format!("Failed to rotate {}", path.display()))?;
+                self.files.insert(category.to_string(), Self::open(&path)?);
+            }
+        }
+
+        let open_file = self.files.get_mut(category).expect("file was just opened");
+        if let Err(e) = open_file.file.write_all(line.as_bytes()) {
+            // Reopen the file next time rather than keep writing to a
+            // handle that failed.
+            self.files.remove(category);
+            return Err(e.into());
+        }
+        open_file.len += line.len() as u64;
+        Ok(())
+    }
+}
+
+impl Transport for FileTransport {
+    fn send(&mut self, batch: &[(String, String)]) -> Result<()> {
+        batch
+            .iter()
+            .try_for_each(|(category, sample)| self.write(category, sample))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_rotation() -> Result<()> {
+        let dir = TempDir::new("scribe_file_sink")?;
+        let mut transport = FileTransport::new(dir.path().to_path_buf(), Some(20), 2)?;
+
+        for i in 0..5 {
+            transport.send(&[
+                ("cat".to_string(), format!(r#"{{"n": {}}}"#, i)),
+                ("other".to_string(), "{}".to_string()),
+            ])?;
+        }
+
+        let read = |name: &str| fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("cat.jsonl"), "{\"n\": 4}\n");
+        assert_eq!(read("cat.jsonl.1"), "{\"n\": 2}\n{\"n\": 3}\n");
+        assert_eq!(read("cat.jsonl.2"), "{\"n\": 0}\n{\"n\": 1}\n");
+        assert_eq!(read("other.jsonl"), "{}\n".repeat(5));
+        assert!(!dir.path().join("cat.jsonl.3").exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::io::{BufWriter, Write};
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+
+use super::json_line;
+use super::queue::Transport;
+
+enum Address {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+/// Sends each sample as a `<category>\t<sample>\n` line over a stream
+/// socket.
+pub struct LineTransport {
+    address: Address,
+    stream: Option<BufWriter<Box<dyn Write + Send>>>,
+}
+
+impl LineTransport {
+    pub fn tcp(address: String) -> Self {
+        Self {
+            address: Address::Tcp(address),
+            stream: None,
+        }
+    }
+
+    pub fn unix(path: PathBuf) -> Self {
+        Self {
+            address: Address::Unix(path),
+            stream: None,
+        }
+    }
+
+    fn connect(&self) -> Result<Box<dyn Write + Send>> {
+        Ok(match &self.address {
+            Address::Tcp(address) => {
+                let stream = TcpStream::connect(address)
+                    .with_context(|| format!("Failed to connect to {}", address))?;
+                stream.set_nodelay(true)?;
+                Box::new(stream)
+            }
+            Address::Unix(path) => Box::new(
+                UnixStream::connect(path)
+                    .with_context(|| format!("Failed to connect to {}", path.display()))?,
+            ),
+        })
+    }
+}
+
+impl Transport for LineTransport {
+    fn send(&mut self, batch: &[(String, String)]) -> Result<()> {
+        if self.stream.is_none() {
+            self.stream = Some(BufWriter::new(self.connect()?));
+        }
+        let stream = self.stream.as_mut().expect("stream was just connected");
+        let res = batch
+            .iter()
+            .try_for_each(|(category, sample)| {
+                writeln!(stream, "{}\t{}", category, json_line(sample))
+            })
+            .and_then(|()| stream.flush());
+        if res.is_err() {
+            self.stream = None;
+        }
+        Ok(res?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sink::queue::QueuedSink;
+    use crate::sink::Sink;
+    use slog::{o, Discard, Logger};
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::os::unix::net::UnixListener;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_tcp() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?.to_string();
+        let logger = Logger::root(Discard, o!());
+        let sink = QueuedSink::spawn(logger, address.clone(), LineTransport::tcp(address))?;
+        sink.offer("cat", r#"{"a": 1}"#)?;
+        sink.offer("dog", "{\n}")?;
+
+        let (stream, _) = listener.accept()?;
+        let lines = BufReader::new(stream)
+            .lines()
+            .take(2)
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(lines, vec!["cat\t{\"a\": 1}", "dog\t{}"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unix() -> Result<()> {
+        let dir = TempDir::new("scribe_line_sink")?;
+        let path = dir.path().join("socket");
+        let listener = UnixListener::bind(&path)?;
+        let logger = Logger::root(Discard, o!());
+        let sink = QueuedSink::spawn(logger, "unix".to_string(), LineTransport::unix(path))?;
+        sink.offer("cat", "{}")?;
+
+        let (stream, _) = listener.accept()?;
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line)?;
+        assert_eq!(line, "cat\t{}\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,221 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Destinations for Scribe samples in builds without a Scribe daemon.
+//!
+//! Each category can be sent to its own sink, and categories that aren't
+//! listed go to the default sink, if there is one. Samples for categories
+//! with no sink are dropped.
+
+mod file;
+mod line;
+mod queue;
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+use slog::Logger;
+
+use self::file::FileTransport;
+use self::line::LineTransport;
+use self::queue::QueuedSink;
+use crate::is_valid_category;
+
+pub trait Sink: Send + Sync {
+    fn offer(&self, category: &str, sample: &str) -> Result<()>;
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ScribeConfig {
+    /// Sink for categories that aren't listed in `categories`.
+    #[serde(default)]
+    pub default: Option<SinkConfig>,
+    #[serde(default)]
+    pub categories: HashMap<String, SinkConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum SinkConfig {
+    /// Drop all samples.
+    Discard,
+    /// Append samples to `<directory>/<category>.jsonl`, one per line. If
+    /// `max_bytes` is set, a file that would grow beyond it is first renamed
+    /// to `<category>.jsonl.1` (and older files to `.2`, `.3` and so on), and
+    /// at most `max_files` old files are kept.
+    File {
+        directory: PathBuf,
+        #[serde(default)]
+        max_bytes: Option<u64>,
+        #[serde(default = "default_max_files")]
+        max_files: usize,
+    },
+    /// Write `<category>\t<sample>\n` lines to a TCP socket.
+    Tcp { address: String },
+    /// Write `<category>\t<sample>\n` lines to a Unix domain socket.
+    Unix { path: PathBuf },
+}
+
+fn default_max_files() -> usize {
+    5
+}
+
+impl SinkConfig {
+    fn build(self, logger: &Logger) -> Result<Option<Arc<dyn Sink>>> {
+        let sink = match self {
+            SinkConfig::Discard => return Ok(None),
+            SinkConfig::File {
+                directory,
+                max_bytes,
+                max_files,
+            } => {
+                let name = format!("file:{}", directory.display());
+                let transport = FileTransport::new(directory, max_bytes, max_files)?;
+                QueuedSink::spawn(logger.clone(), name, transport)?
+            }
+            SinkConfig::Tcp { address } => {
+                let name = format!("tcp:{}", address);
+                let transport = LineTransport::tcp(address);
+                QueuedSink::spawn(logger.clone(), name, transport)?
+            }
+            SinkConfig::Unix { path } => {
+                let name = format!("unix:{}", path.display());
+                let transport = LineTransport::unix(path);
+                QueuedSink::spawn(logger.clone(), name, transport)?
+            }
+        };
+        Ok(Some(Arc::new(sink)))
+    }
+}
+
+/// The sinks for every category.
+pub struct Sinks {
+    default: Option<Arc<dyn Sink>>,
+    categories: HashMap<String, Option<Arc<dyn Sink>>>,
+}
+
+impl Sinks {
+    pub fn empty() -> Self {
+        Self {
+            default: None,
+            categories: HashMap::new(),
+        }
+    }
+
+    pub fn from_config(logger: &Logger, config: ScribeConfig) -> Result<Self> {
+        let default = match config.default {
+            Some(sink) => sink
+                .build(logger)
+                .context("Failed to create default Scribe sink")?,
+            None => None,
+        };
+        let categories = config
+            .categories
+            .into_iter()
+            .map(|(category, sink)| {
+                let sink = sink
+                    .build(logger)
+                    .with_context(|| format!("Failed to create Scribe sink for {}", category))?;
+                Ok((category, sink))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            default,
+            categories,
+        })
+    }
+
+    pub fn offer(&self, category: &str, sample: &str) -> Result<()> {
+        if !is_valid_category(category) {
+            return Err(anyhow!("invalid category: {}", category));
+        }
+        let sink = match self.categories.get(category) {
+            Some(sink) => sink,
+            None => &self.default,
+        };
+        match sink {
+            Some(sink) => sink.offer(category, sample),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Samples are usually JSON already, but the sinks write one sample per
+/// line, so make sure that they really are JSON and fit on one line.
+fn json_line(sample: &str) -> Cow<'_, str> {
+    if !sample.contains('\n') && serde_json::from_str::<IgnoredAny>(sample).is_ok() {
+        return Cow::Borrowed(sample);
+    }
+    match serde_json::from_str::<serde_json::Value>(sample) {
+        Ok(value) => Cow::Owned(value.to_string()),
+        Err(_) => Cow::Owned(serde_json::Value::from(sample).to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() -> Result<()> {
+        let config: ScribeConfig = serde_json::from_str(
+            r#"{
+                "default": {"type": "file", "directory": "/var/log/mononoke", "max_bytes": 1000},
+                "categories": {
+                    "mononoke_commits": {"type": "unix", "path": "/run/scribe.sock"},
+                    "mononoke_bookmarks": {"type": "tcp", "address": "localhost:1234"},
+                    "noisy": {"type": "discard"}
+                }
+            }"#,
+        )?;
+        assert_eq!(
+            config.default,
+            Some(SinkConfig::File {
+                directory: PathBuf::from("/var/log/mononoke"),
+                max_bytes: Some(1000),
+                max_files: 5,
+            })
+        );
+        assert_eq!(
+            config.categories["mononoke_commits"],
+            SinkConfig::Unix {
+                path: PathBuf::from("/run/scribe.sock"),
+            }
+        );
+        assert_eq!(
+            config.categories["mononoke_bookmarks"],
+            SinkConfig::Tcp {
+                address: "localhost:1234".to_string()
+            }
+        );
+        assert_eq!(config.categories["noisy"], SinkConfig::Discard);
+
+        assert!(serde_json::from_str::<ScribeConfig>(r#"{"default": {"type": "ftp"}}"#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_line() {
+        assert_eq!(json_line(r#"{"a": 1}"#), r#"{"a": 1}"#);
+        assert_eq!(json_line("{\n  \"a\": 1\n}"), r#"{"a":1}"#);
+        assert_eq!(json_line("not json\n"), r#""not json\n""#);
+    }
+
+    #[test]
+    fn test_invalid_category() {
+        let sinks = Sinks::empty();
+        assert!(sinks.offer("cat", "{}").is_ok());
+        assert!(sinks.offer("../cat", "{}").is_err());
+        assert!(sinks.offer("cat\tdog", "{}").is_err());
+    }
+}
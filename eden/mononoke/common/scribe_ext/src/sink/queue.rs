@@ -0,0 +1,108 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use slog::{warn, Logger};
+use stats::prelude::*;
+
+use super::Sink;
+
+/// Samples waiting to be sent, per sink. Like Scribe, offering a sample
+/// fails rather than blocks if the destination can't keep up.
+const QUEUE_SIZE: usize = 10_000;
+
+/// Maximum number of samples to send at once.
+const MAX_BATCH: usize = 500;
+
+/// How long to wait after failing to send before trying again.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+define_stats! {
+    prefix = "mononoke.scribe";
+    queue_full: timeseries(Rate, Sum),
+    send_failures: timeseries(Rate, Sum),
+    samples_lost: timeseries(Rate, Sum),
+}
+
+/// A connection to somewhere that samples can be sent.
+pub trait Transport: Send + 'static {
+    /// Send `(category, sample)` pairs. If this fails, the transport should
+    /// reconnect the next time it is called.
+    fn send(&mut self, batch: &[(String, String)]) -> Result<()>;
+}
+
+/// A sink that sends samples from a background thread, so that slow or
+/// unavailable destinations don't hold up the caller.
+pub struct QueuedSink {
+    name: String,
+    sender: SyncSender<(String, String)>,
+}
+
+impl QueuedSink {
+    pub fn spawn(logger: Logger, name: String, transport: impl Transport) -> Result<Self> {
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_SIZE);
+        thread::Builder::new()
+            .name(format!("scribe {}", name))
+            .spawn({
+                let name = name.clone();
+                move || run(logger, name, transport, receiver)
+            })?;
+        Ok(Self { name, sender })
+    }
+}
+
+impl Sink for QueuedSink {
+    fn offer(&self, category: &str, sample: &str) -> Result<()> {
+        match self
+            .sender
+            .try_send((category.to_string(), sample.to_string()))
+        {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                STATS::queue_full.add_value(1);
+                Err(anyhow!("Scribe queue for {} is full", self.name))
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                Err(anyhow!("Scribe sender for {} has stopped", self.name))
+            }
+        }
+    }
+}
+
+fn run(
+    logger: Logger,
+    name: String,
+    mut transport: impl Transport,
+    receiver: Receiver<(String, String)>,
+) {
+    let mut batch = Vec::with_capacity(MAX_BATCH);
+    while let Ok(first) = receiver.recv() {
+        batch.push(first);
+        batch.extend(receiver.try_iter().take(MAX_BATCH - 1));
+
+        // Retry once straight away, since the connection may have gone
+        // away while the sink was idle.
+        let res = transport.send(&batch).or_else(|_| transport.send(&batch));
+        if let Err(e) = res {
+            STATS::send_failures.add_value(1);
+            STATS::samples_lost.add_value(batch.len() as i64);
+            warn!(
+                logger,
+                "Failed to send {} Scribe samples to {}: {:?}",
+                batch.len(),
+                name,
+                e
+            );
+            thread::sleep(RETRY_DELAY);
+        }
+        batch.clear();
+    }
+}
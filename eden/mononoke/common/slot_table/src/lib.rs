@@ -0,0 +1,326 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! A fixed-size, open-addressed hash table of slots made only of atomics.
+//!
+//! Because slots hold nothing but atomics, the table can either be an
+//! ordinary heap allocation (visible only to this process) or a
+//! memory-mapped file that several processes on the same host map at once.
+//! All updates are lock-free, so a process that dies half-way through an
+//! update cannot wedge the others. This is what the local rate limiters and
+//! time window counters keep their counters in.
+
+use std::fs::OpenOptions;
+use std::mem::{align_of, size_of};
+use std::path::Path;
+use std::slice;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::{bail, Context, Error};
+use memmap::MmapMut;
+
+/// How many slots we probe before giving up on finding room for a key.
+const MAX_PROBES: usize = 64;
+
+const EMPTY_KEY: u64 = 0;
+
+/// Marks a slot that is being (re)initialized for a new key. The key is only
+/// published once the slot's data is in place, so nobody can see a key
+/// paired with the previous occupant's data.
+const RESERVED_KEY: u64 = u64::MAX;
+
+/// How many times we re-read a reserved slot waiting for its key to be
+/// published before treating it as taken by some other key.
+const RESERVED_SPINS: usize = 100;
+
+/// Data that can be kept in a `SlotTable`.
+///
+/// # Safety
+///
+/// Implementors must be `#[repr(C)]` and made only of atomics (or arrays of
+/// them), so that any bit pattern, including all zeroes, is a valid value,
+/// and so that it can be modified concurrently by other processes.
+pub unsafe trait SlotData: Sync {}
+
+#[repr(C)]
+struct Slot<T> {
+    /// Hashed key, `EMPTY_KEY` if the slot is free, or `RESERVED_KEY` while
+    /// it is being initialized.
+    key: AtomicU64,
+    data: T,
+}
+
+#[repr(C)]
+struct Header {
+    magic: AtomicU64,
+    slots: AtomicU64,
+}
+
+enum Storage<T> {
+    Local(Box<[Slot<T>]>),
+    Shared(MmapMut),
+}
+
+/// A table of `T`s indexed by 64 bit keys, either private to this process
+/// or shared with other processes through a memory-mapped file.
+pub struct SlotTable<T> {
+    storage: Storage<T>,
+    num_slots: usize,
+}
+
+impl<T: SlotData> SlotTable<T> {
+    /// Create a table that is only visible to this process.
+    pub fn local(num_slots: usize) -> Self {
+        let slots = (0..num_slots)
+            // SAFETY: `SlotData` is valid for any bit pattern, and so is the
+            // key.
+            .map(|_| unsafe { std::mem::zeroed() })
+            .collect();
+
+        Self {
+            storage: Storage::Local(slots),
+            num_slots,
+        }
+    }
+
+    /// Create (or open) a table backed by the file at `path`. Every process
+    /// that opens the same path shares the same slots. The file should be on
+    /// a local filesystem, ideally a tmpfs such as `/dev/shm`.
+    ///
+    /// `magic` identifies the kind of data in the file, so that we never map
+    /// a file that holds something else.
+    pub fn shared(path: &Path, num_slots: usize, magic: u64) -> Result<Self, Error> {
+        // The mapping is page-aligned, and slots follow the header.
+        assert!(align_of::<Slot<T>>() <= size_of::<Header>());
+        let len = size_of::<Header>() + num_slots * size_of::<Slot<T>>();
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .with_context(|| format!("While opening slot table {}", path.display()))?;
+
+        let existing_len = file.metadata()?.len();
+        if existing_len == 0 {
+            // New files are zero-filled, which is an empty table.
+            file.set_len(len as u64)?;
+        } else if existing_len != len as u64 {
+            bail!(
+                "Slot table {} has size {}, expected {}",
+                path.display(),
+                existing_len,
+                len
+            );
+        }
+
+        // SAFETY: the mapping is only ever accessed through atomics, so
+        // concurrent modification by other processes is well-defined.
+        let mmap = unsafe { MmapMut::map_mut(&file)? };
+
+        let table = Self {
+            storage: Storage::Shared(mmap),
+            num_slots,
+        };
+
+        let header = table.header().expect("shared tables have a header");
+        match header
+            .magic
+            .compare_exchange(0, magic, Ordering::SeqCst, Ordering::SeqCst)
+        {
+            Ok(_) => header.slots.store(num_slots as u64, Ordering::SeqCst),
+            Err(actual) if actual == magic => {}
+            Err(other) => bail!(
+                "{} holds other data than expected (magic {:#x}, expected {:#x})",
+                path.display(),
+                other,
+                magic
+            ),
+        }
+
+        Ok(table)
+    }
+
+    fn header(&self) -> Option<&Header> {
+        match &self.storage {
+            Storage::Local(_) => None,
+            // SAFETY: the mapping is page-aligned and at least as long as
+            // the header, which is made only of atomics.
+            Storage::Shared(mmap) => Some(unsafe { &*(mmap.as_ptr() as *const Header) }),
+        }
+    }
+
+    fn slots(&self) -> &[Slot<T>] {
+        match &self.storage {
+            Storage::Local(slots) => &slots[..],
+            // SAFETY: the file was sized to hold the header followed by
+            // `num_slots` slots, which are valid for any bit pattern
+            // (including the zeroed file) as they are made only of atomics.
+            Storage::Shared(mmap) => unsafe {
+                let first = mmap.as_ptr().add(size_of::<Header>()) as *const Slot<T>;
+                slice::from_raw_parts(first, self.num_slots)
+            },
+        }
+    }
+
+    /// Find the data for `key`, claiming a free or idle slot if the key
+    /// isn't in the table yet. Returns `None` if the table is full, in which
+    /// case callers should fail open.
+    ///
+    /// `is_idle` tells whether the data of another key can be thrown away
+    /// because it is indistinguishable from fresh data, e.g. a counter whose
+    /// counts have all expired. `init` resets the data of a slot that is
+    /// claimed for `key`.
+    ///
+    /// Claiming a slot first swaps its key for `RESERVED_KEY`, then calls
+    /// `init`, and only then publishes the real key. A process that dies
+    /// between the two leaves the slot reserved forever, which costs one slot
+    /// of the table but never hands out half-initialized data.
+    pub fn find_slot(
+        &self,
+        key: u64,
+        is_idle: impl Fn(&T) -> bool,
+        init: impl FnOnce(&T),
+    ) -> Option<&T> {
+        let key = match key {
+            EMPTY_KEY => 1,
+            RESERVED_KEY => RESERVED_KEY - 1,
+            key => key,
+        };
+        let slots = self.slots();
+        let start = (key % slots.len() as u64) as usize;
+
+        for probe in 0..MAX_PROBES.min(slots.len()) {
+            let slot = &slots[(start + probe) % slots.len()];
+            let current = Self::published_key(slot);
+
+            if current == key {
+                return Some(&slot.data);
+            }
+
+            let reusable = current == EMPTY_KEY || (current != RESERVED_KEY && is_idle(&slot.data));
+
+            if reusable
+                && slot
+                    .key
+                    .compare_exchange(current, RESERVED_KEY, Ordering::AcqRel, Ordering::Acquire)
+                    .is_ok()
+            {
+                init(&slot.data);
+                slot.key.store(key, Ordering::Release);
+                return Some(&slot.data);
+            }
+
+            // Somebody else may have just claimed this slot for our key.
+            if Self::published_key(slot) == key {
+                return Some(&slot.data);
+            }
+        }
+
+        None
+    }
+
+    /// Read the key of a slot, giving a concurrent claim a moment to publish
+    /// its key. Returns `RESERVED_KEY` if it doesn't.
+    fn published_key(slot: &Slot<T>) -> u64 {
+        for _ in 0..RESERVED_SPINS {
+            let key = slot.key.load(Ordering::Acquire);
+            if key != RESERVED_KEY {
+                return key;
+            }
+            std::hint::spin_loop();
+        }
+        RESERVED_KEY
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[repr(C)]
+    struct Counter(AtomicU64);
+
+    unsafe impl SlotData for Counter {}
+
+    const TEST_MAGIC: u64 = 0x6d6f_6e6f_7374_0001;
+
+    fn bump(table: &SlotTable<Counter>, key: u64) -> Option<u64> {
+        let counter = table.find_slot(
+            key,
+            |counter| counter.0.load(Ordering::Acquire) == 0,
+            |counter| counter.0.store(0, Ordering::Release),
+        )?;
+        Some(counter.0.fetch_add(1, Ordering::AcqRel) + 1)
+    }
+
+    #[test]
+    fn test_find_slot() {
+        let table = SlotTable::local(16);
+
+        assert_eq!(bump(&table, 1), Some(1));
+        assert_eq!(bump(&table, 1), Some(2));
+        assert_eq!(bump(&table, 2), Some(1));
+        assert_eq!(bump(&table, 1), Some(3));
+    }
+
+    #[test]
+    fn test_table_full() {
+        let table = SlotTable::local(2);
+
+        assert_eq!(bump(&table, 1), Some(1));
+        assert_eq!(bump(&table, 2), Some(1));
+        assert_eq!(bump(&table, 3), None);
+    }
+
+    #[test]
+    fn test_idle_slots_are_reused() {
+        let table = SlotTable::local(1);
+
+        assert_eq!(bump(&table, 1), Some(1));
+        table.slots()[0].data.0.store(0, Ordering::SeqCst);
+        assert_eq!(bump(&table, 2), Some(1));
+        assert_eq!(table.slots()[0].key.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_reserved_slot() {
+        let table = SlotTable::local(2);
+
+        // A slot left reserved by a claim that never finished is skipped,
+        // even though its (zeroed) data looks idle.
+        table.slots()[0].key.store(RESERVED_KEY, Ordering::SeqCst);
+        assert_eq!(bump(&table, 2), Some(1));
+        assert_eq!(table.slots()[0].key.load(Ordering::SeqCst), RESERVED_KEY);
+        assert_eq!(table.slots()[1].key.load(Ordering::SeqCst), 2);
+
+        // Keys that collide with the sentinels still get a slot of their own.
+        let table = SlotTable::local(4);
+        assert_eq!(bump(&table, RESERVED_KEY), Some(1));
+        assert_eq!(bump(&table, EMPTY_KEY), Some(1));
+    }
+
+    #[test]
+    fn test_shared() -> Result<(), Error> {
+        let dir = tempdir::TempDir::new("slot_table")?;
+        let path = dir.path().join("table");
+
+        let first = SlotTable::shared(&path, 64, TEST_MAGIC)?;
+        let second = SlotTable::shared(&path, 64, TEST_MAGIC)?;
+
+        assert_eq!(bump(&first, 42), Some(1));
+        assert_eq!(bump(&second, 42), Some(2));
+        assert_eq!(bump(&first, 42), Some(3));
+
+        // Opening with a different geometry or data is refused.
+        assert!(SlotTable::<Counter>::shared(&path, 128, TEST_MAGIC).is_err());
+        assert!(SlotTable::<Counter>::shared(&path, 64, TEST_MAGIC + 1).is_err());
+
+        Ok(())
+    }
+}
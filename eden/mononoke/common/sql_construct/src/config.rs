@@ -9,13 +9,55 @@ use anyhow::{anyhow, Context, Result};
 use fbinit::FacebookInit;
 use metaconfig_types::{
     DatabaseConfig, LocalDatabaseConfig, MetadataDatabaseConfig, RemoteDatabaseConfig,
-    RemoteMetadataDatabaseConfig, ShardableRemoteDatabaseConfig,
+    RemoteDatabaseType, RemoteMetadataDatabaseConfig, ShardableRemoteDatabaseConfig,
+    ShardedRemoteDatabaseConfig,
 };
 use sql_ext::facebook::MysqlOptions;
 
 use crate::construct::SqlConstruct;
 use crate::facebook::{FbSqlConstruct, FbSqlShardedConstruct};
 
+/// Construct from a remote database, using the client for its type.
+fn with_remote_database<T: FbSqlConstruct>(
+    fb: FacebookInit,
+    config: &RemoteDatabaseConfig,
+    mysql_options: &MysqlOptions,
+    readonly: bool,
+) -> Result<T> {
+    match config.db_type {
+        RemoteDatabaseType::Mysql => {
+            T::with_mysql(fb, config.db_address.clone(), mysql_options, readonly)
+        }
+        RemoteDatabaseType::Postgres => {
+            T::with_postgres(config.db_address.clone(), mysql_options, readonly)
+        }
+    }
+}
+
+/// Construct from a sharded remote database, using the client for its type.
+fn with_sharded_remote_database<T: FbSqlShardedConstruct>(
+    fb: FacebookInit,
+    config: &ShardedRemoteDatabaseConfig,
+    mysql_options: &MysqlOptions,
+    readonly: bool,
+) -> Result<T> {
+    match config.db_type {
+        RemoteDatabaseType::Mysql => T::with_sharded_mysql(
+            fb,
+            config.shard_map.clone(),
+            config.shard_num.get(),
+            mysql_options,
+            readonly,
+        ),
+        RemoteDatabaseType::Postgres => T::with_sharded_postgres(
+            config.shard_map.clone(),
+            config.shard_num.get(),
+            mysql_options,
+            readonly,
+        ),
+    }
+}
+
 /// Trait that allows construction from database config.
 pub trait SqlConstructFromDatabaseConfig: FbSqlConstruct + SqlConstruct {
     fn with_database_config(
@@ -29,7 +71,7 @@ pub trait SqlConstructFromDatabaseConfig: FbSqlConstruct + SqlConstruct {
                 Self::with_sqlite_path(path.join("sqlite_dbs"), readonly)
             }
             DatabaseConfig::Remote(config) => {
-                Self::with_mysql(fb, config.db_address.clone(), mysql_options, readonly)
+                with_remote_database(fb, config, mysql_options, readonly)
             }
         }
         .with_context(|| {
@@ -58,7 +100,7 @@ pub trait SqlConstructFromMetadataDatabaseConfig: FbSqlConstruct + SqlConstruct
             MetadataDatabaseConfig::Remote(remote) => {
                 let config = Self::remote_database_config(remote)
                     .ok_or_else(|| anyhow!("no configuration available"))?;
-                Self::with_mysql(fb, config.db_address.clone(), mysql_options, readonly)
+                with_remote_database(fb, config, mysql_options, readonly)
             }
         }
     }
@@ -91,15 +133,11 @@ pub trait SqlShardableConstructFromMetadataDatabaseConfig:
                     .ok_or_else(|| anyhow!("no configuration available"))?;
                 match config {
                     ShardableRemoteDatabaseConfig::Unsharded(config) => {
-                        Self::with_mysql(fb, config.db_address.clone(), mysql_options, readonly)
+                        with_remote_database(fb, config, mysql_options, readonly)
+                    }
+                    ShardableRemoteDatabaseConfig::Sharded(config) => {
+                        with_sharded_remote_database(fb, config, mysql_options, readonly)
                     }
-                    ShardableRemoteDatabaseConfig::Sharded(config) => Self::with_sharded_mysql(
-                        fb,
-                        config.shard_map.clone(),
-                        config.shard_num.get(),
-                        mysql_options,
-                        readonly,
-                    ),
                 }
             }
         }
@@ -7,11 +7,13 @@
 
 use std::path::Path;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use sql::Connection;
+use sql_ext::facebook::MysqlOptions;
 use sql_ext::{
-    open_existing_sqlite_path, open_sqlite_in_memory, open_sqlite_path, SqlConnections,
-    SqlShardedConnections,
+    create_postgres_connections_sharded, create_postgres_connections_unsharded,
+    open_existing_sqlite_path, open_postgres_test_connections, open_sqlite_in_memory,
+    open_sqlite_path, SqlConnections, SqlShardedConnections,
 };
 
 /// Construct a SQL data manager backed by a database
@@ -25,6 +27,10 @@ pub trait SqlConstruct: Sized + Send + Sync + 'static {
     /// Query used to create an empty instance of the database
     const CREATION_QUERY: &'static str;
 
+    /// Query used to create an empty instance of the database on PostgreSQL,
+    /// if this data manager supports it
+    const POSTGRES_CREATION_QUERY: Option<&'static str> = None;
+
     /// Construct an instance from SqlConnections
     ///
     /// This function may be called in an async context and must not block.
@@ -56,6 +62,37 @@ pub trait SqlConstruct: Sized + Send + Sync + 'static {
         };
         Ok(Self::from_sql_connections(connections))
     }
+
+    /// Construct an instance from a PostgreSQL database
+    ///
+    /// The database address is a PostgreSQL URL, e.g.
+    /// `postgresql://mononoke@db.example.com:5432/metadata`. The database
+    /// must already have been set up with `POSTGRES_CREATION_QUERY`.
+    fn with_postgres(
+        db_address: String,
+        mysql_options: &MysqlOptions,
+        readonly: bool,
+    ) -> Result<Self> {
+        let connections = create_postgres_connections_unsharded(
+            mysql_options,
+            Self::LABEL.to_string(),
+            db_address,
+            readonly,
+        )?;
+        Ok(Self::from_sql_connections(connections))
+    }
+
+    /// Construct an instance from a fresh schema in the PostgreSQL database
+    /// at `db_address`, for tests
+    ///
+    /// The schema is set up with `POSTGRES_CREATION_QUERY`, and dropped
+    /// again once the instance is.
+    fn with_postgres_test_schema(db_address: &str) -> Result<Self> {
+        let creation_query = Self::POSTGRES_CREATION_QUERY
+            .ok_or_else(|| anyhow!("{} doesn't support PostgreSQL", Self::LABEL))?;
+        let connections = open_postgres_test_connections(db_address, Self::LABEL, creation_query)?;
+        Ok(Self::from_sql_connections(connections))
+    }
 }
 
 /// Construct a SQL data manager backed by a sharded database
@@ -69,8 +106,32 @@ pub trait SqlShardedConstruct: Sized + Send + Sync + 'static {
     /// Query used to create an empty instance of a shard
     const CREATION_QUERY: &'static str;
 
+    /// Query used to create an empty instance of a shard on PostgreSQL, if
+    /// this data manager supports it
+    const POSTGRES_CREATION_QUERY: Option<&'static str> = None;
+
     /// Construct an instance from a vector of SqlConnections, one for each shard
     ///
     /// This function may be called in an async context and must not block.
     fn from_sql_shard_connections(shard_connections: SqlShardedConnections) -> Self;
+
+    /// Construct an instance from a sharded PostgreSQL database
+    ///
+    /// The shard map is a PostgreSQL URL in which `{shard}` is replaced by
+    /// each shard id from `0` to `shard_num - 1`.
+    fn with_sharded_postgres(
+        shardmap: String,
+        shard_num: usize,
+        mysql_options: &MysqlOptions,
+        readonly: bool,
+    ) -> Result<Self> {
+        let shard_connections = create_postgres_connections_sharded(
+            mysql_options,
+            Self::LABEL.to_string(),
+            shardmap,
+            0..shard_num,
+            readonly,
+        )?;
+        Ok(Self::from_sql_shard_connections(shard_connections))
+    }
 }
@@ -7,35 +7,57 @@
 
 use anyhow::Result;
 use fbinit::FacebookInit;
-use sql_ext::facebook::MysqlOptions;
+use sql_ext::facebook::{
+    create_mysql_connections_sharded, create_mysql_connections_unsharded, MysqlOptions,
+};
 
 use crate::{SqlConstruct, SqlShardedConstruct};
 
-macro_rules! fb_unimplemented {
-    () => {
-        unimplemented!("This is implemented only for fbcode_build!")
-    };
-}
-
-/// Construct a SQL data manager backed by Facebook infrastructure
+/// Construct a SQL data manager backed by a MySQL or MariaDB server.
+///
+/// Outside of fbcode, the database address is a MySQL URL, e.g.
+/// `mysql://mononoke@db.example.com:3306/metadata`.
 pub trait FbSqlConstruct: SqlConstruct + Sized + Send + Sync + 'static {
-    fn with_mysql<'a>(_: FacebookInit, _: String, _: &'a MysqlOptions, _: bool) -> Result<Self> {
-        fb_unimplemented!()
+    fn with_mysql<'a>(
+        fb: FacebookInit,
+        db_address: String,
+        mysql_options: &'a MysqlOptions,
+        readonly: bool,
+    ) -> Result<Self> {
+        let connections = create_mysql_connections_unsharded(
+            fb,
+            mysql_options.clone(),
+            Self::LABEL.to_string(),
+            db_address,
+            readonly,
+        )?;
+        Ok(Self::from_sql_connections(connections))
     }
 }
 
 impl<T: SqlConstruct> FbSqlConstruct for T {}
 
-/// Construct a sharded SQL data manager backed by Facebook infrastructure
+/// Construct a sharded SQL data manager backed by MySQL or MariaDB servers.
+///
+/// The shard map is a MySQL URL in which `{shard}` is replaced by each shard
+/// id from `0` to `shard_num - 1`.
 pub trait FbSqlShardedConstruct: SqlShardedConstruct + Sized + Send + Sync + 'static {
     fn with_sharded_mysql(
-        _: FacebookInit,
-        _: String,
-        _: usize,
-        _: &MysqlOptions,
-        _: bool,
+        fb: FacebookInit,
+        shardmap: String,
+        shard_num: usize,
+        mysql_options: &MysqlOptions,
+        readonly: bool,
     ) -> Result<Self> {
-        fb_unimplemented!()
+        let shard_connections = create_mysql_connections_sharded(
+            fb,
+            mysql_options.clone(),
+            Self::LABEL.to_string(),
+            shardmap,
+            0..shard_num,
+            readonly,
+        )?;
+        Ok(Self::from_sql_shard_connections(shard_connections))
     }
 }
 
@@ -40,7 +40,7 @@ use futures::{
     Future, Stream, TryFutureExt, TryStreamExt,
 };
 use futures_stats::TimedTryFutureExt;
-use git_types::{TreeHandle, TreeMapping};
+use git_types::{GitCommitMapping, MappedGitCommitId, TreeHandle, TreeMapping};
 use lazy_static::lazy_static;
 use lock_ext::LockExt;
 use mercurial_derived_data::MappedHgChangesetId;
@@ -71,6 +71,7 @@ pub const POSSIBLE_DERIVED_TYPES: &[&str] = &[
     FilenodesOnlyPublic::NAME,
     RootSkeletonManifestId::NAME,
     TreeHandle::NAME,
+    MappedGitCommitId::NAME,
 ];
 
 lazy_static! {
@@ -85,6 +86,8 @@ lazy_static! {
         let deleted_mf = RootDeletedManifestId::NAME;
         let filenodes = FilenodesOnlyPublic::NAME;
         let skeleton_mf = RootSkeletonManifestId::NAME;
+        let git_trees = TreeHandle::NAME;
+        let git_commits = MappedGitCommitId::NAME;
 
         let mut dag = HashMap::new();
 
@@ -97,6 +100,8 @@ lazy_static! {
         dag.insert(fsnodes, vec![]);
         dag.insert(deleted_mf, vec![unodes]);
         dag.insert(skeleton_mf, vec![]);
+        dag.insert(git_trees, vec![]);
+        dag.insert(git_commits, vec![git_trees]);
 
         dag
     };
@@ -724,6 +729,14 @@ fn derived_data_utils_impl(
                 repo.clone(),
             )))
         }
+        MappedGitCommitId::NAME => {
+            let mapping = GitCommitMapping::new(blobstore, repo, config);
+            Ok(Arc::new(DerivedUtilsFromMapping::new(
+                fb,
+                mapping,
+                repo.clone(),
+            )))
+        }
         name => Err(format_err!("Unsupported derived data type: {}", name)),
     }
 }
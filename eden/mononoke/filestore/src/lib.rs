@@ -36,6 +36,7 @@ mod prepare;
 mod rechunk;
 mod streamhash;
 
+pub use errors::{ErrorKind, InvalidHash};
 pub use fetch::Range;
 pub use fetch_key::{Alias, AliasBlob, FetchKey};
 pub use rechunk::{force_rechunk, rechunk};
@@ -275,6 +276,37 @@ pub async fn fetch_range_with_size<'a, B: Blobstore + Clone + 'a>(
     }
 }
 
+/// Fetch a file's contents, from which one or more ranges of the file can then be streamed using
+/// stream_range. This returns success with the file's ContentId and contents if the file exists,
+/// and success with None if it does not.
+pub async fn fetch_contents<B: Blobstore>(
+    blobstore: &B,
+    ctx: &CoreContext,
+    key: &FetchKey,
+) -> Result<Option<(ContentId, FileContents)>, Error> {
+    let content_id = match key.load(ctx, blobstore).await {
+        Ok(content_id) => content_id,
+        Err(LoadableError::Missing(_)) => return Ok(None),
+        Err(LoadableError::Error(err)) => return Err(err),
+    };
+
+    match content_id.load(ctx, blobstore).await {
+        Ok(contents) => Ok(Some((content_id, contents))),
+        Err(LoadableError::Missing(_)) => Ok(None),
+        Err(LoadableError::Error(err)) => Err(err),
+    }
+}
+
+/// Stream a range of a file, given its contents as returned by fetch_contents.
+pub fn stream_range<'a, B: Blobstore + Clone + 'a>(
+    blobstore: B,
+    ctx: impl Borrow<CoreContext> + Clone + Send + Sync + 'a,
+    contents: FileContents,
+    range: Range,
+) -> Result<impl Stream<Item = Result<Bytes, Error>> + 'a, Error> {
+    fetch::stream_file_bytes(blobstore, ctx, contents, range)
+}
+
 /// This function has the same functionality as fetch_with_size, but doesn't return the file size.
 pub async fn fetch<'a, B: Blobstore + Clone + 'a>(
     blobstore: B,
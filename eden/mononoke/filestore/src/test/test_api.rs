@@ -598,6 +598,61 @@ async fn filestore_get_range(fb: FacebookInit) -> Result<()> {
     Ok(())
 }
 
+#[fbinit::test]
+async fn filestore_fetch_contents_stream_ranges(fb: FacebookInit) -> Result<()> {
+    let req = request(HELLO_WORLD);
+    let content_id = canonical(HELLO_WORLD);
+    let small = FilestoreConfig {
+        chunk_size: Some(5),
+        concurrency: 1,
+    };
+
+    let blob = memblob::Memblob::default();
+    let ctx = CoreContext::test_mock(fb);
+    borrowed!(ctx, blob, req);
+
+    assert!(
+        filestore::fetch_contents(blob, ctx, &FetchKey::Canonical(content_id))
+            .await?
+            .is_none()
+    );
+
+    filestore::store(
+        blob,
+        small,
+        ctx,
+        req,
+        stream::once(future::ready(Ok(Bytes::from(HELLO_WORLD)))),
+    )
+    .await?;
+
+    let (fetched_id, contents) = filestore::fetch_contents(
+        blob,
+        ctx,
+        &FetchKey::Aliased(Alias::Sha256(*HELLO_WORLD_SHA256)),
+    )
+    .await?
+    .ok_or_else(|| Error::msg("Object does not exist"))?;
+    assert_eq!(fetched_id, content_id);
+    assert_eq!(contents.size(), HELLO_WORLD_LENGTH);
+
+    for (start, end) in &[(0, 11), (3, 8), (7, 11)] {
+        let range = filestore::Range::range_inclusive(*start, *end)?;
+        let bytes = filestore::stream_range(blob, ctx, contents.clone(), range)?
+            .try_fold(BytesMut::new(), |mut buff, chunk| async move {
+                buff.extend_from_slice(&chunk);
+                Result::<_, Error>::Ok(buff)
+            })
+            .await?;
+        assert_eq!(
+            bytes.freeze(),
+            Bytes::from(&HELLO_WORLD[*start as usize..=*end as usize])
+        );
+    }
+
+    Ok(())
+}
+
 #[fbinit::test]
 async fn filestore_get_invalid_range(fb: FacebookInit) -> Result<()> {
     let req = request(HELLO_WORLD);
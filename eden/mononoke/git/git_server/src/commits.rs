@@ -0,0 +1,190 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{anyhow, Error};
+use blobrepo::BlobRepo;
+use blobstore::Loadable;
+use context::CoreContext;
+use derived_data::{BonsaiDerived, BonsaiDerivedMapping, BonsaiDerivedOld};
+use futures::future;
+use git_types::{Commit, CommitBuilder, GitCommitMapping, MappedGitCommitId, TreeHandle};
+use mononoke_types::{hash::GitSha1, BonsaiChangeset, ChangesetId};
+
+use crate::errors::ErrorKind;
+
+/// How many changesets to look up at once.
+const LOOKUP_BATCH_SIZE: usize = 1000;
+
+/// Maps changesets to the Git commits that represent them, for the duration of a request.
+///
+/// The Git hash of a changeset is its `git_commits` derived data. Commit objects aren't stored, so
+/// they get rebuilt from the changeset when they are sent.
+pub struct GitCommits {
+    ctx: CoreContext,
+    repo: BlobRepo,
+    mapping: GitCommitMapping,
+    sha1s: HashMap<ChangesetId, GitSha1>,
+}
+
+impl GitCommits {
+    pub fn new(ctx: CoreContext, repo: BlobRepo) -> Result<Self, Error> {
+        let mapping = MappedGitCommitId::default_mapping(&ctx, &repo)?;
+        Ok(Self {
+            ctx,
+            repo,
+            mapping,
+            sha1s: HashMap::new(),
+        })
+    }
+
+    /// The Git hash of a changeset. The changeset must have been resolved already.
+    pub fn sha1(&self, cs_id: ChangesetId) -> Result<GitSha1, Error> {
+        self.sha1s
+            .get(&cs_id)
+            .copied()
+            .ok_or_else(|| anyhow!("Changeset {} has not been resolved to a Git commit", cs_id))
+    }
+
+    /// Find the changesets for Git hashes. Hashes that don't correspond to a commit in this
+    /// repository are left out.
+    pub async fn changesets(
+        &mut self,
+        sha1s: Vec<GitSha1>,
+    ) -> Result<HashMap<GitSha1, ChangesetId>, Error> {
+        let mut changesets = HashMap::new();
+
+        for chunk in sha1s.chunks(LOOKUP_BATCH_SIZE) {
+            let found = self
+                .mapping
+                .get_changesets(&self.ctx, chunk.to_vec())
+                .await?;
+
+            for (sha1, cs_id) in found {
+                self.sha1s.insert(cs_id, sha1);
+                changesets.insert(sha1, cs_id);
+            }
+        }
+
+        Ok(changesets)
+    }
+
+    /// Make sure that all of those changesets have a Git hash, along with their parents, which
+    /// their commits refer to. Changesets that don't have one yet get their Git commit derived.
+    pub async fn resolve(&mut self, cs_ids: Vec<ChangesetId>) -> Result<(), Error> {
+        let cs_ids: Vec<_> = cs_ids
+            .into_iter()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+
+        let mut parents = HashMap::new();
+        for chunk in cs_ids.chunks(LOOKUP_BATCH_SIZE) {
+            let entries = self
+                .repo
+                .get_changesets_object()
+                .get_many(self.ctx.clone(), chunk.to_vec())
+                .await?;
+            if entries.len() != chunk.len() {
+                return Err(anyhow!("Some changesets are missing: {:?}", chunk));
+            }
+            parents.extend(
+                entries
+                    .into_iter()
+                    .map(|entry| (entry.cs_id, entry.parents)),
+            );
+        }
+
+        let mut unknown: Vec<_> = parents
+            .iter()
+            .flat_map(|(cs_id, parents)| std::iter::once(cs_id).chain(parents))
+            .copied()
+            .filter(|cs_id| !self.sha1s.contains_key(cs_id))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        self.lookup(&unknown).await?;
+        unknown.retain(|cs_id| !self.sha1s.contains_key(cs_id));
+
+        if unknown.is_empty() {
+            return Ok(());
+        }
+
+        // Deriving a commit derives all of its ancestors first, so we only derive the changesets
+        // that no other unknown changeset will cover, rather than have each of them race to
+        // derive their own ancestors.
+        let covered: HashSet<_> = unknown
+            .iter()
+            .filter_map(|cs_id| parents.get(cs_id))
+            .flatten()
+            .copied()
+            .collect();
+        future::try_join_all(
+            unknown
+                .iter()
+                .filter(|cs_id| !covered.contains(cs_id))
+                .map(|cs_id| MappedGitCommitId::derive(&self.ctx, &self.repo, *cs_id)),
+        )
+        .await?;
+
+        self.lookup(&unknown).await?;
+        match unknown.iter().find(|cs_id| !self.sha1s.contains_key(cs_id)) {
+            Some(cs_id) => Err(anyhow!("Git commit for {} was not derived", cs_id)),
+            None => Ok(()),
+        }
+    }
+
+    /// Generate the Git commit for a changeset, along with its tree. The changeset and its
+    /// parents must have been resolved already.
+    pub async fn commit(&self, cs_id: ChangesetId) -> Result<(Commit, TreeHandle), Error> {
+        let expected = self.sha1(cs_id)?;
+        let (bonsai, tree) = load(&self.ctx, &self.repo, cs_id).await?;
+        let commit = self.build(&bonsai, tree)?;
+
+        // Derived hashes are those of the commits we build, but sending a commit with a different
+        // hash than what we advertised would corrupt the client's repository, so make sure.
+        let actual = commit.oid().sha1();
+        if actual != expected {
+            return Err(ErrorKind::CommitMismatch(cs_id, expected, actual).into());
+        }
+
+        Ok((commit, tree))
+    }
+
+    fn build(&self, bonsai: &BonsaiChangeset, tree: TreeHandle) -> Result<Commit, Error> {
+        let parents = bonsai
+            .parents()
+            .map(|p| self.sha1(p))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(CommitBuilder::new(bonsai, tree, parents).into())
+    }
+
+    async fn lookup(&mut self, cs_ids: &[ChangesetId]) -> Result<(), Error> {
+        for chunk in cs_ids.chunks(LOOKUP_BATCH_SIZE) {
+            let derived = self.mapping.get(&self.ctx, chunk.to_vec()).await?;
+            for (cs_id, commit) in derived {
+                self.sha1s.insert(cs_id, commit.oid());
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn load(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    cs_id: ChangesetId,
+) -> Result<(BonsaiChangeset, TreeHandle), Error> {
+    let (bonsai, tree) = future::try_join(
+        async { Ok::<_, Error>(cs_id.load(ctx, repo.blobstore()).await?) },
+        async { Ok::<_, Error>(TreeHandle::derive(ctx, repo, cs_id).await?) },
+    )
+    .await?;
+    Ok((bonsai, tree))
+}
@@ -0,0 +1,38 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use gotham_derive::StateData;
+use mononoke_api::Mononoke;
+
+/// The Git server's global state, which is shared by all requests.
+#[derive(Clone, StateData)]
+pub struct ServerContext {
+    mononoke: Arc<Mononoke>,
+    will_exit: Arc<AtomicBool>,
+}
+
+impl ServerContext {
+    pub fn new(mononoke: Mononoke, will_exit: Arc<AtomicBool>) -> Self {
+        Self {
+            mononoke: Arc::new(mononoke),
+            will_exit,
+        }
+    }
+
+    pub fn will_exit(&self) -> bool {
+        self.will_exit.load(Ordering::Relaxed)
+    }
+
+    pub fn mononoke(&self) -> &Mononoke {
+        &self.mononoke
+    }
+}
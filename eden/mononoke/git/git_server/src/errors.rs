@@ -0,0 +1,53 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use mononoke_types::{hash::GitSha1, ChangesetId};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ErrorKind {
+    #[error("Repository does not exist: {0}")]
+    RepoDoesNotExist(String),
+    #[error("Failed to load repository: {0}")]
+    RepoLoadFailed(String),
+    #[error("Only version 2 of the Git protocol is supported")]
+    UnsupportedProtocolVersion,
+    #[error("Unsupported service: {0}")]
+    UnsupportedService(String),
+    #[error("Client cancelled the request")]
+    ClientCancelled,
+    #[error("Invalid Content-Length")]
+    InvalidContentLength,
+    #[error("Invalid gzip request body")]
+    InvalidGzipBody,
+    #[error("Request body exceeds the maximum size ({0} bytes)")]
+    RequestBodyTooLarge(u64),
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+    #[error("Unknown command: {0}")]
+    UnknownCommand(String),
+    #[error("Unsupported argument to {0}: {1}")]
+    UnsupportedArgument(&'static str, String),
+    #[error("Object not found: {0}")]
+    ObjectNotFound(GitSha1),
+    #[error("Git commit generated for {0} is {2}, but it is mapped to {1}")]
+    CommitMismatch(ChangesetId, GitSha1, GitSha1),
+}
+
+impl ErrorKind {
+    /// Whether this error is a problem with what the client asked for. Those errors are reported
+    /// through the Git protocol, so that the client can show them to the user.
+    pub fn is_client_error(&self) -> bool {
+        matches!(
+            self,
+            Self::InvalidRequest(..)
+                | Self::UnknownCommand(..)
+                | Self::UnsupportedArgument(..)
+                | Self::ObjectNotFound(..)
+        )
+    }
+}
@@ -0,0 +1,293 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! The routes of Git's smart HTTP protocol, as described in Documentation/technical/http-protocol.txt.
+//! Only the upload-pack service is available, using version 2 of the protocol.
+
+use std::io::Read;
+use std::pin::Pin;
+
+use anyhow::{Context, Error};
+use blobrepo::BlobRepo;
+use bytes::{Bytes, BytesMut};
+use context::CoreContext;
+use flate2::read::GzDecoder;
+use futures::{future::Either, FutureExt, TryStreamExt};
+use gotham::{
+    handler::HandlerFuture,
+    middleware::state::StateMiddleware,
+    pipeline::{new_pipeline, single::single_pipeline},
+    router::{
+        builder::{build_router as gotham_build_router, DefineSingleRoute, DrawRoutes},
+        Router,
+    },
+    state::{FromState, State},
+};
+use gotham_derive::{StateData, StaticResponseExtender};
+use gotham_ext::{
+    error::{ErrorFormatter, HttpError},
+    response::{
+        build_response, BytesBody, ResponseStream, ResponseTryStreamExt, StreamBody,
+        TryIntoResponse,
+    },
+};
+use http::header::{HeaderMap, CONTENT_ENCODING, CONTENT_LENGTH};
+use hyper::Body;
+use mime::Mime;
+use mononoke_api::MononokeError;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+
+use crate::context::ServerContext;
+use crate::errors::ErrorKind;
+use crate::middleware::RequestContext;
+use crate::pktline::PacketWriter;
+use crate::protocol::{capability_advertisement, parse_request, Command};
+use crate::upload_pack;
+
+const UPLOAD_PACK_SERVICE: &str = "git-upload-pack";
+const GIT_PROTOCOL_HEADER: &str = "Git-Protocol";
+
+/// Largest request body we accept. Requests list the objects that the client wants and has, and
+/// that is far less than this even for large repositories.
+const MAX_REQUEST_BODY_SIZE: u64 = 64 * 1024 * 1024;
+
+static ADVERTISEMENT_MIME: Lazy<Mime> = Lazy::new(|| {
+    "application/x-git-upload-pack-advertisement"
+        .parse()
+        .unwrap()
+});
+static RESULT_MIME: Lazy<Mime> =
+    Lazy::new(|| "application/x-git-upload-pack-result".parse().unwrap());
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+struct RepoParams {
+    repository: String,
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+struct InfoRefsQueryString {
+    service: String,
+}
+
+struct TextErrorFormatter;
+
+impl ErrorFormatter for TextErrorFormatter {
+    type Body = String;
+
+    fn format(&self, error: &Error, _state: &State) -> Result<(Self::Body, Mime), Error> {
+        Ok((format!("{:#}\n", error), mime::TEXT_PLAIN))
+    }
+}
+
+fn health_handler(state: State) -> (State, &'static str) {
+    if ServerContext::borrow_from(&state).will_exit() {
+        (state, "EXITING")
+    } else {
+        (state, "I_AM_ALIVE")
+    }
+}
+
+fn info_refs_handler(mut state: State) -> Pin<Box<HandlerFuture>> {
+    async move {
+        let res = info_refs(&mut state).await;
+        build_response(res, state, &TextErrorFormatter)
+    }
+    .boxed()
+}
+
+fn upload_pack_handler(mut state: State) -> Pin<Box<HandlerFuture>> {
+    async move {
+        let res = upload_pack(&mut state).await;
+        build_response(res, state, &TextErrorFormatter)
+    }
+    .boxed()
+}
+
+fn into_http_error(error: MononokeError, repository: &str) -> HttpError {
+    use MononokeError::*;
+    (match error {
+        InvalidRequest(_) | HookFailure(_) => HttpError::e400,
+        PermissionDenied { .. } | ServicePermissionDenied { .. } | ServiceRestricted { .. } => {
+            HttpError::e403
+        }
+        NotAvailable { .. } => HttpError::e503,
+        InternalError(_) => HttpError::e500,
+    })(Error::from(error).context(ErrorKind::RepoLoadFailed(repository.to_string())))
+}
+
+/// Clients can refer to repositories with or without the `.git` suffix.
+async fn get_repo(
+    sctx: &ServerContext,
+    ctx: &CoreContext,
+    repository: &str,
+) -> Result<BlobRepo, HttpError> {
+    let name = repository.strip_suffix(".git").unwrap_or(repository);
+
+    let repo = sctx
+        .mononoke()
+        .repo(ctx.clone(), name)
+        .await
+        .map_err(|e| into_http_error(e, name))?
+        .ok_or_else(|| HttpError::e404(ErrorKind::RepoDoesNotExist(name.to_string())))?;
+
+    Ok(repo.blob_repo().clone())
+}
+
+/// Clients ask for version 2 of the protocol through the Git-Protocol header. Without it, they
+/// expect the original protocol, which we don't support.
+fn check_protocol_version(state: &State) -> Result<(), HttpError> {
+    let is_v2 = HeaderMap::borrow_from(state)
+        .get_all(GIT_PROTOCOL_HEADER)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(':'))
+        .any(|param| param.trim() == "version=2");
+
+    if is_v2 {
+        Ok(())
+    } else {
+        Err(HttpError::e400(ErrorKind::UnsupportedProtocolVersion))
+    }
+}
+
+async fn info_refs(state: &mut State) -> Result<BytesBody<Bytes>, HttpError> {
+    let params = RepoParams::take_from(state);
+    let query = InfoRefsQueryString::take_from(state);
+
+    if query.service != UPLOAD_PACK_SERVICE {
+        return Err(HttpError::e403(ErrorKind::UnsupportedService(
+            query.service,
+        )));
+    }
+    check_protocol_version(state)?;
+
+    let sctx = ServerContext::borrow_from(state).clone();
+    let ctx = RequestContext::borrow_from(state).ctx.clone();
+    get_repo(&sctx, &ctx, &params.repository).await?;
+
+    Ok(BytesBody::new(
+        capability_advertisement(),
+        ADVERTISEMENT_MIME.clone(),
+    ))
+}
+
+/// Read the request body, which clients compress with gzip when it is large. Bodies that are
+/// larger than MAX_REQUEST_BODY_SIZE, either as sent or once decompressed, are rejected.
+async fn get_request_body(state: &mut State) -> Result<Bytes, HttpError> {
+    let mut body = Body::take_from(state);
+    let headers = HeaderMap::try_borrow_from(state);
+    let is_gzip = headers
+        .and_then(|headers| headers.get(CONTENT_ENCODING))
+        .map_or(false, |encoding| encoding == "gzip");
+
+    let too_large = || HttpError::e413(ErrorKind::RequestBodyTooLarge(MAX_REQUEST_BODY_SIZE));
+
+    if let Some(content_length) = headers.and_then(|headers| headers.get(CONTENT_LENGTH)) {
+        let content_length: u64 = content_length
+            .to_str()
+            .ok()
+            .and_then(|content_length| content_length.parse().ok())
+            .ok_or(ErrorKind::InvalidContentLength)
+            .map_err(HttpError::e400)?;
+        if content_length > MAX_REQUEST_BODY_SIZE {
+            return Err(too_large());
+        }
+    }
+
+    let mut bytes = BytesMut::new();
+    while let Some(chunk) = body
+        .try_next()
+        .await
+        .context(ErrorKind::ClientCancelled)
+        .map_err(HttpError::e400)?
+    {
+        if (bytes.len() + chunk.len()) as u64 > MAX_REQUEST_BODY_SIZE {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    if !is_gzip {
+        return Ok(bytes.freeze());
+    }
+
+    let mut decoded = Vec::new();
+    GzDecoder::new(&bytes[..])
+        .take(MAX_REQUEST_BODY_SIZE + 1)
+        .read_to_end(&mut decoded)
+        .context(ErrorKind::InvalidGzipBody)
+        .map_err(HttpError::e400)?;
+    if decoded.len() as u64 > MAX_REQUEST_BODY_SIZE {
+        return Err(too_large());
+    }
+    Ok(Bytes::from(decoded))
+}
+
+async fn upload_pack(state: &mut State) -> Result<impl TryIntoResponse, HttpError> {
+    let params = RepoParams::take_from(state);
+    check_protocol_version(state)?;
+
+    let sctx = ServerContext::borrow_from(state).clone();
+    let ctx = RequestContext::borrow_from(state).ctx.clone();
+    let repo = get_repo(&sctx, &ctx, &params.repository).await?;
+    let body = get_request_body(state).await?;
+
+    let res: Result<_, Error> = async {
+        match parse_request(&body)? {
+            None => Ok(Either::Left(Bytes::new())),
+            Some(Command::LsRefs(args)) => upload_pack::ls_refs(ctx, repo, args)
+                .await
+                .map(Either::Left),
+            Some(Command::Fetch(args)) => {
+                upload_pack::fetch(ctx, repo, args).await.map(Either::Right)
+            }
+        }
+    }
+    .await;
+
+    match res {
+        Ok(Either::Left(bytes)) => Ok(Either::Left(BytesBody::new(bytes, RESULT_MIME.clone()))),
+        Ok(Either::Right(stream)) => {
+            let stream = ResponseStream::new(stream).end_on_err();
+            Ok(Either::Right(StreamBody::new(stream, RESULT_MIME.clone())))
+        }
+        Err(error) => {
+            // Errors in what the client asked for are reported through the protocol, which lets
+            // Git show them to the user.
+            match error.downcast_ref::<ErrorKind>() {
+                Some(kind) if kind.is_client_error() => {
+                    let mut writer = PacketWriter::new();
+                    writer.line(format!("ERR {}", kind));
+                    Ok(Either::Left(BytesBody::new(
+                        writer.into_bytes(),
+                        RESULT_MIME.clone(),
+                    )))
+                }
+                _ => Err(HttpError::e500(error)),
+            }
+        }
+    }
+}
+
+pub fn build_router(ctx: ServerContext) -> Router {
+    let pipeline = new_pipeline().add(StateMiddleware::new(ctx)).build();
+    let (chain, pipelines) = single_pipeline(pipeline);
+
+    gotham_build_router(chain, pipelines, |route| {
+        route.get("/health_check").to(health_handler);
+        route
+            .get("/:repository/info/refs")
+            .with_path_extractor::<RepoParams>()
+            .with_query_string_extractor::<InfoRefsQueryString>()
+            .to(info_refs_handler);
+        route
+            .post("/:repository/git-upload-pack")
+            .with_path_extractor::<RepoParams>()
+            .to(upload_pack_handler);
+    })
+}
@@ -0,0 +1,64 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::sync::Arc;
+
+use context::{CoreContext, SessionContainer};
+use fbinit::FacebookInit;
+use gotham::state::{request_id, FromState, State};
+use gotham_derive::StateData;
+use gotham_ext::middleware::{ClientIdentity, Middleware};
+use hyper::{Body, Response};
+use scuba_ext::MononokeScubaSampleBuilder;
+use slog::{o, Logger};
+use sshrelay::Metadata;
+
+#[derive(StateData, Clone)]
+pub struct RequestContext {
+    pub ctx: CoreContext,
+}
+
+/// Sets up a CoreContext for each request, carrying the identity of the client.
+#[derive(Clone)]
+pub struct RequestContextMiddleware {
+    fb: FacebookInit,
+    logger: Logger,
+    scuba: Arc<MononokeScubaSampleBuilder>,
+}
+
+impl RequestContextMiddleware {
+    pub fn new(fb: FacebookInit, logger: Logger, scuba: MononokeScubaSampleBuilder) -> Self {
+        Self {
+            fb,
+            logger,
+            scuba: Arc::new(scuba),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for RequestContextMiddleware {
+    async fn inbound(&self, state: &mut State) -> Option<Response<Body>> {
+        let identities = ClientIdentity::borrow_from(state)
+            .identities()
+            .clone()
+            .unwrap_or_default();
+
+        let metadata = Arc::new(Metadata::default().set_identities(identities));
+        let session = SessionContainer::builder(self.fb)
+            .metadata(metadata)
+            .build();
+
+        let request_id = request_id(state);
+        let logger = self.logger.new(o!("request_id" => request_id.to_string()));
+        let ctx = session.new_context(logger, (*self.scuba).clone());
+
+        state.put(RequestContext { ctx });
+
+        None
+    }
+}
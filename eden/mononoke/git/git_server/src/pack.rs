@@ -0,0 +1,145 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Generation of Git packfiles, as described in Documentation/technical/pack-format.txt. Objects
+//! aren't deltified against each other: each one is stored whole, compressed with zlib. That
+//! makes packs larger than what Git would send, but lets us produce them as a stream.
+
+use std::convert::TryFrom;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Error};
+use bytes::Bytes;
+use digest::Digest;
+use flate2::{write::ZlibEncoder, Compression};
+use futures::{
+    future,
+    stream::{self, Stream, StreamExt, TryStreamExt},
+};
+use git_types::ObjectKind;
+use sha1::Sha1;
+
+const PACK_VERSION: u32 = 2;
+
+fn object_type(kind: ObjectKind) -> u8 {
+    match kind {
+        ObjectKind::Commit => 1,
+        ObjectKind::Tree => 2,
+        ObjectKind::Blob => 3,
+    }
+}
+
+/// Encode an object as a pack entry: a header with its type and size, followed by its contents
+/// compressed with zlib.
+pub fn encode_entry(kind: ObjectKind, object: &[u8]) -> Result<Bytes, Error> {
+    let mut entry = Vec::with_capacity(object.len() / 2 + 16);
+
+    // The size is a variable-length integer, of which the first byte only holds 4 bits, as it
+    // also holds the object type.
+    let mut size = object.len() as u64;
+    let mut byte = (object_type(kind) << 4) | (size & 0x0f) as u8;
+    size >>= 4;
+    while size > 0 {
+        entry.push(byte | 0x80);
+        byte = (size & 0x7f) as u8;
+        size >>= 7;
+    }
+    entry.push(byte);
+
+    let mut encoder = ZlibEncoder::new(entry, Compression::default());
+    encoder.write_all(object)?;
+    Ok(Bytes::from(encoder.finish()?))
+}
+
+/// Assemble a packfile from the encoded entries of `count` objects (see `encode_entry`). This adds
+/// the header and the trailing checksum around the entries.
+pub fn pack_stream<S>(
+    count: usize,
+    entries: S,
+) -> Result<impl Stream<Item = Result<Bytes, Error>>, Error>
+where
+    S: Stream<Item = Result<Bytes, Error>>,
+{
+    let count = u32::try_from(count).context("Too many objects for a single pack")?;
+
+    let mut header = Vec::with_capacity(12);
+    header.extend_from_slice(b"PACK");
+    header.extend_from_slice(&PACK_VERSION.to_be_bytes());
+    header.extend_from_slice(&count.to_be_bytes());
+
+    let hasher = Arc::new(Mutex::new(Sha1::new()));
+
+    let body = stream::once(future::ready(Ok(Bytes::from(header))))
+        .chain(entries)
+        .inspect_ok({
+            let hasher = hasher.clone();
+            move |bytes| hasher.lock().expect("lock poisoned").input(bytes)
+        });
+
+    // This only gets polled once all of the body has been sent, at which point the hash is
+    // complete.
+    let trailer = stream::once(async move {
+        let hasher = std::mem::take(&mut *hasher.lock().expect("lock poisoned"));
+        Ok(Bytes::copy_from_slice(hasher.result().as_slice()))
+    });
+
+    Ok(body.chain(trailer))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use git2::{Oid, Repository};
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    async fn test_pack() -> Result<(), Error> {
+        let blob = b"hello, world\n";
+        let tree = {
+            let mut tree = b"100644 hello\0".to_vec();
+            tree.extend_from_slice(ObjectKind::Blob.create_oid(blob).as_ref());
+            tree
+        };
+        // Make sure that we have objects that need more than one byte of size.
+        let big = vec![b'x'; 100_000];
+
+        let objects = vec![
+            (ObjectKind::Blob, blob.to_vec()),
+            (ObjectKind::Tree, tree),
+            (ObjectKind::Blob, big),
+        ];
+
+        let entries = objects
+            .iter()
+            .map(|(kind, object)| encode_entry(*kind, object))
+            .collect::<Vec<_>>();
+
+        let pack = pack_stream(objects.len(), stream::iter(entries))?
+            .try_fold(Vec::new(), |mut pack, bytes| async move {
+                pack.extend_from_slice(&bytes);
+                Result::<_, Error>::Ok(pack)
+            })
+            .await?;
+
+        // Index the pack with libgit2, which validates it.
+        let tmp_dir = TempDir::new("git_server_test")?;
+        let git = Repository::init_bare(tmp_dir.path())?;
+        let odb = git.odb()?;
+        let mut writer = odb.packwriter()?;
+        writer.write_all(&pack)?;
+        writer.commit()?;
+
+        for (kind, object) in &objects {
+            let oid = Oid::from_bytes(kind.create_oid(object).as_ref())?;
+            assert_eq!(odb.read(oid)?.data(), &object[..]);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,224 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Git's pkt-line framing, as described in Documentation/technical/protocol-common.txt. Every
+//! packet is prefixed with its length (including the prefix itself) as 4 hex digits. Lengths 0
+//! and 1 are special packets that delimit sections of a message.
+
+use anyhow::{anyhow, Context, Error};
+use bytes::{BufMut, Bytes, BytesMut};
+
+/// The largest amount of data a single pkt-line can carry.
+pub const MAX_DATA_LEN: usize = 65516;
+
+const FLUSH_PKT: &[u8] = b"0000";
+const DELIM_PKT: &[u8] = b"0001";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Packet<'a> {
+    /// Marks the end of a message.
+    Flush,
+    /// Separates sections of a message.
+    Delim,
+    Data(&'a [u8]),
+}
+
+impl<'a> Packet<'a> {
+    /// The contents of a data packet as a line of text, without its trailing newline.
+    pub fn as_line(&self) -> Result<Option<&'a str>, Error> {
+        match self {
+            Self::Data(data) => {
+                let line = std::str::from_utf8(data).context("Invalid pkt-line")?;
+                Ok(Some(line.strip_suffix('\n').unwrap_or(line)))
+            }
+            Self::Flush | Self::Delim => Ok(None),
+        }
+    }
+}
+
+/// Accumulates pkt-lines into a buffer.
+#[derive(Default)]
+pub struct PacketWriter {
+    buf: BytesMut,
+}
+
+impl PacketWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn data(&mut self, data: impl AsRef<[u8]>) -> &mut Self {
+        let data = data.as_ref();
+        debug_assert!(data.len() <= MAX_DATA_LEN);
+
+        self.buf.reserve(data.len() + 4);
+        self.buf.put(format!("{:04x}", data.len() + 4).as_bytes());
+        self.buf.put(data);
+        self
+    }
+
+    /// Write a line of text, adding the trailing newline that Git expects.
+    pub fn line(&mut self, line: impl AsRef<str>) -> &mut Self {
+        self.data(format!("{}\n", line.as_ref()))
+    }
+
+    pub fn flush(&mut self) -> &mut Self {
+        self.buf.put(FLUSH_PKT);
+        self
+    }
+
+    pub fn delim(&mut self) -> &mut Self {
+        self.buf.put(DELIM_PKT);
+        self
+    }
+
+    /// Write data to a sideband channel (1 for data, 2 for progress, 3 for errors), splitting it
+    /// into as many packets as needed.
+    pub fn sideband(&mut self, band: u8, data: &[u8]) -> &mut Self {
+        for chunk in data.chunks(MAX_DATA_LEN - 1) {
+            self.buf.reserve(chunk.len() + 5);
+            self.buf.put(format!("{:04x}", chunk.len() + 5).as_bytes());
+            self.buf.put_u8(band);
+            self.buf.put(chunk);
+        }
+        self
+    }
+
+    pub fn into_bytes(self) -> Bytes {
+        self.buf.freeze()
+    }
+}
+
+/// Reads pkt-lines out of a buffer.
+pub struct PacketReader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> PacketReader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    fn read_packet(&mut self) -> Result<Packet<'a>, Error> {
+        if self.buf.len() < 4 {
+            return Err(anyhow!("Truncated pkt-line header"));
+        }
+
+        let (len, rest) = self.buf.split_at(4);
+        let len = std::str::from_utf8(len)
+            .ok()
+            .and_then(|len| usize::from_str_radix(len, 16).ok())
+            .with_context(|| format!("Invalid pkt-line length: {:?}", len))?;
+
+        let (packet, rest) = match len {
+            0 => (Packet::Flush, rest),
+            1 => (Packet::Delim, rest),
+            2 | 3 => return Err(anyhow!("Unsupported pkt-line length: {}", len)),
+            len => {
+                let len = len - 4;
+                if rest.len() < len {
+                    return Err(anyhow!("Truncated pkt-line"));
+                }
+                let (data, rest) = rest.split_at(len);
+                (Packet::Data(data), rest)
+            }
+        };
+
+        self.buf = rest;
+        Ok(packet)
+    }
+}
+
+impl<'a> Iterator for PacketReader<'a> {
+    type Item = Result<Packet<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buf.is_empty() {
+            return None;
+        }
+
+        let res = self.read_packet();
+        if res.is_err() {
+            // Don't try to read anything past an invalid packet.
+            self.buf = &[];
+        }
+        Some(res)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write() {
+        let mut writer = PacketWriter::new();
+        writer.line("version 2").delim().data(b"a").flush();
+        assert_eq!(
+            writer.into_bytes(),
+            Bytes::from_static(b"000eversion 2\n000100050000")
+        );
+    }
+
+    #[test]
+    fn test_sideband() {
+        let data = vec![b'x'; MAX_DATA_LEN + 10];
+        let mut writer = PacketWriter::new();
+        writer.sideband(1, &data);
+        let bytes = writer.into_bytes();
+
+        let packets = PacketReader::new(&bytes)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(packets.len(), 2);
+        for packet in &packets {
+            match packet {
+                Packet::Data(data) => assert_eq!(data[0], 1),
+                _ => panic!("Unexpected packet: {:?}", packet),
+            }
+        }
+
+        let total: usize = packets
+            .iter()
+            .map(|p| match p {
+                Packet::Data(data) => data.len() - 1,
+                _ => 0,
+            })
+            .sum();
+        assert_eq!(total, data.len());
+    }
+
+    #[test]
+    fn test_read() -> Result<(), Error> {
+        let buf = b"0015command=ls-refs\n00010009peel\n0000";
+        let packets = PacketReader::new(buf).collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            packets,
+            vec![
+                Packet::Data(b"command=ls-refs\n"),
+                Packet::Delim,
+                Packet::Data(b"peel\n"),
+                Packet::Flush,
+            ]
+        );
+        assert_eq!(packets[0].as_line()?, Some("command=ls-refs"));
+        assert_eq!(packets[1].as_line()?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_invalid() {
+        assert!(PacketReader::new(b"00").next().unwrap().is_err());
+        assert!(PacketReader::new(b"zzzz").next().unwrap().is_err());
+        assert!(PacketReader::new(b"0002").next().unwrap().is_err());
+        assert!(PacketReader::new(b"0009pee").next().unwrap().is_err());
+
+        let mut reader = PacketReader::new(b"0009pee");
+        assert!(reader.next().unwrap().is_err());
+        assert!(reader.next().is_none());
+    }
+}
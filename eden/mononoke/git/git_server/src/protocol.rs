@@ -0,0 +1,324 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Parsing of Git protocol v2 requests, as described in Documentation/technical/protocol-v2.txt.
+//! A request is made of a command, a list of capabilities and a list of arguments to the command.
+
+use std::str::FromStr;
+
+use anyhow::Error;
+use bytes::Bytes;
+use mononoke_types::hash::GitSha1;
+
+use crate::errors::ErrorKind;
+use crate::pktline::{Packet, PacketReader, PacketWriter};
+
+pub const AGENT: &str = "mononoke-git-server";
+
+/// The capabilities we advertise to clients, in response to the initial request for
+/// `info/refs`. Clients only send arguments to commands that we advertised support for.
+pub fn capability_advertisement() -> Bytes {
+    let mut writer = PacketWriter::new();
+    writer
+        .line("# service=git-upload-pack")
+        .flush()
+        .line("version 2")
+        .line(format!("agent={}", AGENT))
+        .line("ls-refs")
+        .line("fetch=shallow filter")
+        .line("object-format=sha1")
+        .flush();
+    writer.into_bytes()
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LsRefsArgs {
+    /// Report the target of symbolic refs.
+    pub symrefs: bool,
+    /// Report the objects that annotated tags point to. We don't serve tags, so this is a no-op.
+    pub peel: bool,
+    /// Only report refs starting with one of those prefixes. Empty means all refs.
+    pub ref_prefixes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FetchArgs {
+    pub wants: Vec<GitSha1>,
+    pub haves: Vec<GitSha1>,
+    /// The client is done negotiating, and wants the pack regardless of common commits.
+    pub done: bool,
+    /// Commits that are shallow in the client's repository.
+    pub shallows: Vec<GitSha1>,
+    /// Only send this many commits of history from the wants.
+    pub deepen: Option<u64>,
+    /// Omit all blobs from the pack (`filter blob:none`), for partial clones.
+    pub filter_blobs: bool,
+    /// Don't send progress messages.
+    pub no_progress: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    LsRefs(LsRefsArgs),
+    Fetch(FetchArgs),
+}
+
+fn invalid_request(msg: impl Into<String>) -> Error {
+    ErrorKind::InvalidRequest(msg.into()).into()
+}
+
+fn parse_sha1(value: &str) -> Result<GitSha1, Error> {
+    GitSha1::from_str(value).map_err(|_| invalid_request(format!("Invalid object id: {}", value)))
+}
+
+/// Parse a request. Clients may send a request that only has a flush packet when they close the
+/// connection, in which case there is no command.
+pub fn parse_request(body: &[u8]) -> Result<Option<Command>, Error> {
+    let mut packets = PacketReader::new(body);
+
+    let command = match packets.next().transpose()? {
+        None | Some(Packet::Flush) => return Ok(None),
+        Some(packet) => packet
+            .as_line()?
+            .and_then(|line| line.strip_prefix("command="))
+            .ok_or_else(|| invalid_request("Expected a command"))?,
+    };
+
+    // Capabilities come first, and are separated from arguments by a delimiter packet. A request
+    // without arguments can end right after its capabilities.
+    let mut has_args = false;
+    for packet in &mut packets {
+        let packet = packet?;
+        match packet.as_line()? {
+            Some(capability) => {
+                if let Some(format) = capability.strip_prefix("object-format=") {
+                    if format != "sha1" {
+                        return Err(invalid_request(format!(
+                            "Unsupported object format: {}",
+                            format
+                        )));
+                    }
+                }
+            }
+            None => {
+                has_args = packet == Packet::Delim;
+                break;
+            }
+        }
+    }
+
+    let mut args = Vec::new();
+    if has_args {
+        for packet in &mut packets {
+            match packet?.as_line()? {
+                Some(arg) => args.push(arg),
+                None => break,
+            }
+        }
+    }
+
+    match command {
+        "ls-refs" => parse_ls_refs(args).map(|args| Some(Command::LsRefs(args))),
+        "fetch" => parse_fetch(args).map(|args| Some(Command::Fetch(args))),
+        command => Err(ErrorKind::UnknownCommand(command.to_string()).into()),
+    }
+}
+
+fn parse_ls_refs(args: Vec<&str>) -> Result<LsRefsArgs, Error> {
+    let mut ls_refs = LsRefsArgs::default();
+
+    for arg in args {
+        match arg {
+            "symrefs" => ls_refs.symrefs = true,
+            "peel" => ls_refs.peel = true,
+            arg => match arg.strip_prefix("ref-prefix ") {
+                Some(prefix) => ls_refs.ref_prefixes.push(prefix.to_string()),
+                None => {
+                    return Err(ErrorKind::UnsupportedArgument("ls-refs", arg.to_string()).into());
+                }
+            },
+        }
+    }
+
+    Ok(ls_refs)
+}
+
+fn parse_fetch(args: Vec<&str>) -> Result<FetchArgs, Error> {
+    let mut fetch = FetchArgs::default();
+
+    for arg in args {
+        let (name, value) = match arg.find(' ') {
+            Some(idx) => (&arg[..idx], Some(&arg[idx + 1..])),
+            None => (arg, None),
+        };
+
+        match (name, value) {
+            ("want", Some(value)) => fetch.wants.push(parse_sha1(value)?),
+            ("have", Some(value)) => fetch.haves.push(parse_sha1(value)?),
+            ("shallow", Some(value)) => fetch.shallows.push(parse_sha1(value)?),
+            ("done", None) => fetch.done = true,
+            ("deepen", Some(value)) => {
+                let depth = value
+                    .parse()
+                    .ok()
+                    .filter(|depth| *depth > 0)
+                    .ok_or_else(|| invalid_request(format!("Invalid depth: {}", value)))?;
+                fetch.deepen = Some(depth);
+            }
+            ("filter", Some("blob:none")) => fetch.filter_blobs = true,
+            ("no-progress", None) => fetch.no_progress = true,
+            // We always send full objects without deltas, and never send tags, so those options
+            // don't change anything for us.
+            ("thin-pack", None) | ("include-tag", None) | ("ofs-delta", None) => {}
+            _ => return Err(ErrorKind::UnsupportedArgument("fetch", arg.to_string()).into()),
+        }
+    }
+
+    Ok(fetch)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn request(command: &str, capabilities: &[&str], args: &[&str]) -> Bytes {
+        let mut writer = PacketWriter::new();
+        writer.line(format!("command={}", command));
+        for capability in capabilities {
+            writer.line(capability);
+        }
+        writer.delim();
+        for arg in args {
+            writer.line(arg);
+        }
+        writer.flush();
+        writer.into_bytes()
+    }
+
+    fn sha1(c: char) -> String {
+        std::iter::repeat(c).take(40).collect()
+    }
+
+    #[test]
+    fn test_parse_ls_refs() -> Result<(), Error> {
+        let body = request(
+            "ls-refs",
+            &["agent=git/2.39.0", "object-format=sha1"],
+            &[
+                "peel",
+                "symrefs",
+                "ref-prefix HEAD",
+                "ref-prefix refs/heads/",
+            ],
+        );
+        assert_eq!(
+            parse_request(&body)?,
+            Some(Command::LsRefs(LsRefsArgs {
+                symrefs: true,
+                peel: true,
+                ref_prefixes: vec!["HEAD".to_string(), "refs/heads/".to_string()],
+            }))
+        );
+
+        // Arguments are optional.
+        let mut writer = PacketWriter::new();
+        writer.line("command=ls-refs").flush();
+        assert_eq!(
+            parse_request(&writer.into_bytes())?,
+            Some(Command::LsRefs(LsRefsArgs::default()))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_fetch() -> Result<(), Error> {
+        let body = request(
+            "fetch",
+            &["agent=git/2.39.0"],
+            &[
+                "thin-pack",
+                "ofs-delta",
+                "no-progress",
+                &format!("want {}", sha1('a')),
+                &format!("want {}", sha1('b')),
+                &format!("have {}", sha1('c')),
+                &format!("shallow {}", sha1('d')),
+                "deepen 3",
+                "filter blob:none",
+                "done",
+            ],
+        );
+        assert_eq!(
+            parse_request(&body)?,
+            Some(Command::Fetch(FetchArgs {
+                wants: vec![sha1('a').parse()?, sha1('b').parse()?],
+                haves: vec![sha1('c').parse()?],
+                done: true,
+                shallows: vec![sha1('d').parse()?],
+                deepen: Some(3),
+                filter_blobs: true,
+                no_progress: true,
+            }))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_invalid() -> Result<(), Error> {
+        assert_eq!(parse_request(b"0000")?, None);
+        assert_eq!(parse_request(b"")?, None);
+
+        let is_client_error = |body: &[u8]| match parse_request(body) {
+            Err(e) => e
+                .downcast_ref::<ErrorKind>()
+                .map_or(false, ErrorKind::is_client_error),
+            Ok(_) => false,
+        };
+
+        assert!(is_client_error(&request("push", &[], &[])));
+        assert!(is_client_error(&request(
+            "fetch",
+            &[],
+            &["deepen-since 1000"]
+        )));
+        assert!(is_client_error(&request("fetch", &[], &["filter tree:0"])));
+        assert!(is_client_error(&request("fetch", &[], &["want abc"])));
+        assert!(is_client_error(&request("fetch", &[], &["deepen 0"])));
+        assert!(is_client_error(&request("ls-refs", &[], &["unborn"])));
+        assert!(is_client_error(&request(
+            "ls-refs",
+            &["object-format=sha256"],
+            &[]
+        )));
+        assert!(parse_request(b"0009pee").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_capability_advertisement() -> Result<(), Error> {
+        let advertisement = capability_advertisement();
+        let lines = PacketReader::new(&advertisement)
+            .map(|packet| Ok(packet?.as_line()?))
+            .collect::<Result<Vec<_>, Error>>()?;
+        assert_eq!(
+            lines,
+            vec![
+                Some("# service=git-upload-pack"),
+                None,
+                Some("version 2"),
+                Some("agent=mononoke-git-server"),
+                Some("ls-refs"),
+                Some("fetch=shallow filter"),
+                Some("object-format=sha1"),
+                None,
+            ]
+        );
+        Ok(())
+    }
+}
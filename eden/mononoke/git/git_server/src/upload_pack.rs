@@ -0,0 +1,866 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! The commands of the upload-pack service, which serves fetches and clones.
+
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Error};
+use blobrepo::BlobRepo;
+use blobstore::Loadable;
+use bytes::Bytes;
+use changesets::ChangesetEntry;
+use context::CoreContext;
+use derived_data::BonsaiDerived;
+use filestore::{self, Alias, FetchKey};
+use futures::{
+    channel::mpsc,
+    future,
+    stream::{self, Stream, StreamExt, TryStreamExt},
+};
+use git_types::{ObjectKind, TreeHandle, Treeish};
+use manifest::{find_intersection_of_diffs, Entry};
+use mononoke_types::{hash::GitSha1, ChangesetId};
+
+use crate::commits::GitCommits;
+use crate::errors::ErrorKind;
+use crate::pack::{encode_entry, pack_stream};
+use crate::pktline::PacketWriter;
+use crate::protocol::{FetchArgs, LsRefsArgs};
+
+/// Bookmarks that HEAD can point to, in order of preference.
+const HEAD_CANDIDATES: &[&str] = &["master", "main"];
+
+/// How many commits to list objects for concurrently.
+const DIFF_CONCURRENCY: usize = 20;
+
+/// How many objects to fetch concurrently while sending the pack.
+const FETCH_CONCURRENCY: usize = 100;
+
+/// How many commits to list objects for between progress messages.
+const PROGRESS_INTERVAL: usize = 1000;
+
+const SIDEBAND_DATA: u8 = 1;
+const SIDEBAND_PROGRESS: u8 = 2;
+const SIDEBAND_ERROR: u8 = 3;
+
+/// List refs. Publishing bookmarks are served as branches, and HEAD points to the main branch.
+pub async fn ls_refs(ctx: CoreContext, repo: BlobRepo, args: LsRefsArgs) -> Result<Bytes, Error> {
+    let bookmarks: Vec<_> = repo
+        .get_bonsai_publishing_bookmarks_maybe_stale(ctx.clone())
+        .try_collect()
+        .await?;
+
+    let mut refs: Vec<_> = bookmarks
+        .into_iter()
+        .map(|(bookmark, cs_id)| (format!("refs/heads/{}", bookmark.name()), cs_id))
+        .collect();
+    refs.sort();
+
+    let head = HEAD_CANDIDATES.iter().find_map(|candidate| {
+        let name = format!("refs/heads/{}", candidate);
+        refs.iter()
+            .find(|(ref_name, _)| *ref_name == name)
+            .map(|(ref_name, cs_id)| (ref_name.clone(), *cs_id))
+    });
+
+    let matches = |name: &str| {
+        args.ref_prefixes.is_empty()
+            || args
+                .ref_prefixes
+                .iter()
+                .any(|prefix| name.starts_with(prefix.as_str()))
+    };
+
+    let mut lines: Vec<(String, ChangesetId, Option<String>)> = Vec::new();
+    if let Some((target, cs_id)) = head {
+        if matches("HEAD") {
+            lines.push(("HEAD".to_string(), cs_id, Some(target)));
+        }
+    }
+    lines.extend(
+        refs.into_iter()
+            .filter(|(name, _)| matches(name))
+            .map(|(name, cs_id)| (name, cs_id, None)),
+    );
+
+    let mut commits = GitCommits::new(ctx, repo)?;
+    commits
+        .resolve(lines.iter().map(|(_, cs_id, _)| *cs_id).collect())
+        .await?;
+
+    let mut writer = PacketWriter::new();
+    for (name, cs_id, target) in lines {
+        let sha1 = commits.sha1(cs_id)?;
+        match target {
+            Some(target) if args.symrefs => {
+                writer.line(format!("{} {} symref-target:{}", sha1, name, target))
+            }
+            _ => writer.line(format!("{} {}", sha1, name)),
+        };
+    }
+    writer.flush();
+
+    Ok(writer.into_bytes())
+}
+
+/// How a commit was reached while walking history.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Mark {
+    /// Distance from the closest want, counting the want itself as 1, if this commit is
+    /// reachable from a want.
+    depth: Option<u64>,
+    /// The client already has this commit.
+    uninteresting: bool,
+}
+
+impl Mark {
+    fn merge(self, other: Mark) -> Mark {
+        let depth = match (self.depth, other.depth) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        Mark {
+            depth,
+            uninteresting: self.uninteresting || other.uninteresting,
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct WalkResult {
+    /// The commits to send, children first.
+    commits: Vec<ChangesetId>,
+    /// Commits at the boundary of a shallow fetch, which the client won't get the parents of.
+    shallow: Vec<ChangesetId>,
+    /// Commits that were shallow in the client, but whose parents are now being sent.
+    unshallow: Vec<ChangesetId>,
+}
+
+/// Walks history from the wants, in generation order. Visiting commits in decreasing generation
+/// order means that all of a commit's descendants have been visited before it, so its mark is
+/// final once it's at the top of the queue.
+struct Walk<'a> {
+    ctx: &'a CoreContext,
+    repo: &'a BlobRepo,
+    client_shallow: &'a HashSet<ChangesetId>,
+    deepen: Option<u64>,
+    queue: BinaryHeap<(u64, ChangesetId)>,
+    entries: HashMap<ChangesetId, ChangesetEntry>,
+    marks: HashMap<ChangesetId, Mark>,
+    /// How many queued commits still need to be visited. Once only uninteresting commits remain
+    /// in the queue, walking further wouldn't find anything to send.
+    active: usize,
+    result: WalkResult,
+}
+
+impl<'a> Walk<'a> {
+    fn is_active(&self, cs_id: &ChangesetId, mark: &Mark) -> bool {
+        !mark.uninteresting || self.client_shallow.contains(cs_id)
+    }
+
+    async fn push(&mut self, commits: Vec<(ChangesetId, Mark)>) -> Result<(), Error> {
+        let missing: Vec<_> = commits
+            .iter()
+            .map(|(cs_id, _)| *cs_id)
+            .filter(|cs_id| !self.entries.contains_key(cs_id))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+
+        if !missing.is_empty() {
+            let entries = self
+                .repo
+                .get_changesets_object()
+                .get_many(self.ctx.clone(), missing)
+                .await?;
+            for entry in entries {
+                self.entries.insert(entry.cs_id, entry);
+            }
+        }
+
+        for (cs_id, mark) in commits {
+            let gen = self
+                .entries
+                .get(&cs_id)
+                .ok_or_else(|| anyhow!("Changeset is missing: {}", cs_id))?
+                .gen;
+
+            let (was_active, mark) = match self.marks.get(&cs_id) {
+                Some(existing) => (self.is_active(&cs_id, existing), existing.merge(mark)),
+                None => {
+                    self.queue.push((gen, cs_id));
+                    (false, mark)
+                }
+            };
+            let is_active = self.is_active(&cs_id, &mark);
+            self.marks.insert(cs_id, mark);
+
+            if is_active && !was_active {
+                self.active += 1;
+            } else if was_active && !is_active {
+                self.active -= 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn run(mut self) -> Result<WalkResult, Error> {
+        while self.active > 0 {
+            let (_, cs_id) = match self.queue.pop() {
+                Some(top) => top,
+                None => break,
+            };
+            let mark = self.marks[&cs_id];
+            if self.is_active(&cs_id, &mark) {
+                self.active -= 1;
+            }
+            let parents = self.entries[&cs_id].parents.clone();
+
+            // The client has shallow commits, but not their parents. We only go past them when
+            // the client asked for more history.
+            if self.client_shallow.contains(&cs_id) {
+                if let (Some(deepen), Some(depth)) = (self.deepen, mark.depth) {
+                    if depth < deepen {
+                        self.result.unshallow.push(cs_id);
+                        let parent_mark = Mark {
+                            depth: Some(depth + 1),
+                            uninteresting: false,
+                        };
+                        self.push(parents.into_iter().map(|p| (p, parent_mark)).collect())
+                            .await?;
+                    }
+                }
+                continue;
+            }
+
+            if mark.uninteresting {
+                let parent_mark = Mark {
+                    depth: None,
+                    uninteresting: true,
+                };
+                self.push(parents.into_iter().map(|p| (p, parent_mark)).collect())
+                    .await?;
+                continue;
+            }
+
+            self.result.commits.push(cs_id);
+
+            if let (Some(deepen), Some(depth)) = (self.deepen, mark.depth) {
+                if depth >= deepen {
+                    if !parents.is_empty() {
+                        self.result.shallow.push(cs_id);
+                    }
+                    continue;
+                }
+            }
+
+            let parent_mark = Mark {
+                depth: mark.depth.map(|depth| depth + 1),
+                uninteresting: false,
+            };
+            self.push(parents.into_iter().map(|p| (p, parent_mark)).collect())
+                .await?;
+        }
+
+        Ok(self.result)
+    }
+}
+
+async fn walk(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    wants: Vec<ChangesetId>,
+    common: Vec<ChangesetId>,
+    client_shallow: &HashSet<ChangesetId>,
+    deepen: Option<u64>,
+) -> Result<WalkResult, Error> {
+    let mut walk = Walk {
+        ctx,
+        repo,
+        client_shallow,
+        deepen,
+        queue: BinaryHeap::new(),
+        entries: HashMap::new(),
+        marks: HashMap::new(),
+        active: 0,
+        result: WalkResult::default(),
+    };
+
+    let want_mark = Mark {
+        depth: Some(1),
+        uninteresting: false,
+    };
+    let common_mark = Mark {
+        depth: None,
+        uninteresting: true,
+    };
+    walk.push(wants.into_iter().map(|cs_id| (cs_id, want_mark)).collect())
+        .await?;
+    walk.push(
+        common
+            .into_iter()
+            .map(|cs_id| (cs_id, common_mark))
+            .collect(),
+    )
+    .await?;
+
+    walk.run().await
+}
+
+/// Progress messages for the client, which Git shows to the user, so that a fetch doesn't look
+/// stuck while we work out what to send.
+struct Progress {
+    sender: Option<mpsc::UnboundedSender<Bytes>>,
+}
+
+impl Progress {
+    fn report(&self, message: String) {
+        if let Some(sender) = &self.sender {
+            // This only fails if the response was dropped, in which case nobody is listening.
+            let _ = sender.unbounded_send(sideband(SIDEBAND_PROGRESS, message.as_bytes()));
+        }
+    }
+}
+
+enum Object {
+    Commit(ChangesetId),
+    Tree(TreeHandle),
+    Blob(GitSha1),
+}
+
+/// List the objects that the client needs for those commits: the commits themselves, and the
+/// trees and blobs that they introduce compared to their parents. The client either has the
+/// parents' objects already, or is getting them as part of the same pack.
+async fn list_objects(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    walk: &WalkResult,
+    filter_blobs: bool,
+    progress: &Progress,
+) -> Result<Vec<Object>, Error> {
+    let shallow: HashSet<_> = walk.shallow.iter().collect();
+    let blobstore = repo.get_blobstore();
+
+    let mut diffs = stream::iter(walk.commits.iter().copied())
+        .map(|cs_id| {
+            let blobstore = blobstore.clone();
+            let is_shallow = shallow.contains(&cs_id);
+            async move {
+                let tree = TreeHandle::derive(ctx, repo, cs_id).await?;
+
+                // Shallow commits are sent without their parents, so they need their full tree.
+                let parent_trees = if is_shallow {
+                    vec![]
+                } else {
+                    let parents = repo
+                        .changeset_fetcher()
+                        .get_parents(ctx.clone(), cs_id)
+                        .await?;
+                    future::try_join_all(
+                        parents
+                            .into_iter()
+                            .map(|p| TreeHandle::derive(ctx, repo, p)),
+                    )
+                    .await?
+                };
+
+                let entries =
+                    find_intersection_of_diffs(ctx.clone(), blobstore, tree, parent_trees)
+                        .try_collect::<Vec<_>>()
+                        .await?;
+
+                Result::<_, Error>::Ok((cs_id, entries))
+            }
+        })
+        .buffered(DIFF_CONCURRENCY);
+
+    let mut objects = Vec::new();
+    let mut seen = HashSet::new();
+    let mut listed = 0;
+    while let Some((cs_id, entries)) = diffs.try_next().await? {
+        objects.push(Object::Commit(cs_id));
+
+        listed += 1;
+        if listed % PROGRESS_INTERVAL == 0 {
+            progress.report(format!("Enumerating objects: {}\r", objects.len()));
+        }
+
+        for (_path, entry) in entries {
+            match entry {
+                Entry::Tree(tree) => {
+                    if seen.insert(tree.oid().sha1()) {
+                        objects.push(Object::Tree(tree));
+                    }
+                }
+                Entry::Leaf(blob) => {
+                    if !filter_blobs && seen.insert(blob.oid().sha1()) {
+                        objects.push(Object::Blob(blob.oid().sha1()));
+                    }
+                }
+            }
+        }
+    }
+
+    progress.report(format!("Enumerating objects: {}, done.\n", objects.len()));
+
+    Ok(objects)
+}
+
+async fn encode_object(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    commits: &GitCommits,
+    object: Object,
+) -> Result<Bytes, Error> {
+    match object {
+        Object::Commit(cs_id) => {
+            let (commit, _) = commits.commit(cs_id).await?;
+            encode_entry(ObjectKind::Commit, commit.object())
+        }
+        Object::Tree(handle) => {
+            let tree = handle.load(ctx, repo.blobstore()).await?;
+            let mut object = Vec::new();
+            tree.write_serialized_object(&mut object)?;
+            encode_entry(ObjectKind::Tree, &object)
+        }
+        Object::Blob(sha1) => {
+            let key = FetchKey::Aliased(Alias::GitSha1(sha1));
+            let object = filestore::fetch_concat(repo.blobstore(), ctx, key).await?;
+            encode_entry(ObjectKind::Blob, &object)
+        }
+    }
+}
+
+fn sideband(band: u8, data: &[u8]) -> Bytes {
+    let mut writer = PacketWriter::new();
+    writer.sideband(band, data);
+    writer.into_bytes()
+}
+
+/// Serve a fetch. When the client is still negotiating, this only acknowledges the commits that
+/// we have in common. Otherwise, the response ends with a pack of the objects the client needs,
+/// which is streamed as it gets generated. The pack header has the number of objects in it, so
+/// they all have to be listed first, and we send progress messages until then.
+pub async fn fetch(
+    ctx: CoreContext,
+    repo: BlobRepo,
+    args: FetchArgs,
+) -> Result<impl Stream<Item = Result<Bytes, Error>>, Error> {
+    let mut commits = GitCommits::new(ctx.clone(), repo.clone())?;
+
+    // Wants are usually commits that we advertised, but clients with a partial clone also fetch
+    // individual blobs as they need them.
+    let want_commits = commits.changesets(args.wants.clone()).await?;
+    let mut want_blobs = Vec::new();
+    for want in &args.wants {
+        if !want_commits.contains_key(want) {
+            let key = FetchKey::Aliased(Alias::GitSha1(*want));
+            match filestore::get_metadata(repo.blobstore(), &ctx, &key).await? {
+                Some(_) => want_blobs.push(*want),
+                None => return Err(ErrorKind::ObjectNotFound(*want).into()),
+            }
+        }
+    }
+
+    let common = commits.changesets(args.haves.clone()).await?;
+
+    let mut writer = PacketWriter::new();
+
+    if !args.done {
+        writer.line("acknowledgments");
+        if common.is_empty() {
+            writer.line("NAK").flush();
+            return Ok(stream::once(future::ready(Ok(writer.into_bytes()))).left_stream());
+        }
+        for have in &args.haves {
+            if common.contains_key(have) {
+                writer.line(format!("ACK {}", have));
+            }
+        }
+        writer.line("ready").delim();
+    }
+
+    let client_shallow: HashSet<_> = commits
+        .changesets(args.shallows.clone())
+        .await?
+        .into_iter()
+        .map(|(_, cs_id)| cs_id)
+        .collect();
+
+    let walk = walk(
+        &ctx,
+        &repo,
+        want_commits.values().copied().collect(),
+        common.values().copied().collect(),
+        &client_shallow,
+        args.deepen,
+    )
+    .await?;
+
+    commits.resolve(walk.commits.clone()).await?;
+
+    if args.deepen.is_some() || !args.shallows.is_empty() {
+        writer.line("shallow-info");
+        for cs_id in &walk.shallow {
+            writer.line(format!("shallow {}", commits.sha1(*cs_id)?));
+        }
+        for cs_id in &walk.unshallow {
+            writer.line(format!("unshallow {}", commits.sha1(*cs_id)?));
+        }
+        writer.delim();
+    }
+
+    writer.line("packfile");
+    let header = writer.into_bytes();
+
+    let (sender, receiver) = mpsc::unbounded();
+    let progress = Progress {
+        sender: if args.no_progress { None } else { Some(sender) },
+    };
+
+    let filter_blobs = args.filter_blobs;
+    let pack = stream::once(async move {
+        let mut objects = list_objects(&ctx, &repo, &walk, filter_blobs, &progress).await?;
+        objects.extend(want_blobs.into_iter().map(Object::Blob));
+        // This ends the progress messages.
+        drop(progress);
+
+        let count = objects.len();
+        let commits = Arc::new(commits);
+        let entries = stream::iter(objects)
+            .map(move |object| {
+                let ctx = ctx.clone();
+                let repo = repo.clone();
+                let commits = commits.clone();
+                async move { encode_object(&ctx, &repo, &commits, object).await }
+            })
+            .buffered(FETCH_CONCURRENCY);
+
+        Result::<_, Error>::Ok(
+            pack_stream(count, entries)?.map_ok(|bytes| sideband(SIDEBAND_DATA, &bytes)),
+        )
+    })
+    .try_flatten();
+
+    // Progress messages and the pack go on different sidebands, so they can be interleaved.
+    let pack = stream::select(receiver.map(Ok), pack);
+
+    let mut flush = PacketWriter::new();
+    flush.flush();
+
+    // Once we've started sending the pack, the only way to report an error to the client is
+    // through the error sideband. The error is still passed on afterwards, which ends the
+    // response and lets it get logged.
+    let response = stream::once(future::ready(Ok(header)))
+        .chain(pack)
+        .chain(stream::once(future::ready(Ok(flush.into_bytes()))))
+        .map(|res| match res {
+            Ok(bytes) => stream::iter(vec![Ok(bytes)]),
+            Err(err) => {
+                let message = format!("{:#}\n", err);
+                stream::iter(vec![
+                    Ok(sideband(SIDEBAND_ERROR, message.as_bytes())),
+                    Err(err),
+                ])
+            }
+        })
+        .flatten();
+
+    Ok(response.right_stream())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Write;
+
+    use fbinit::FacebookInit;
+    use git2::{Oid, Repository};
+    use tempdir::TempDir;
+    use tests_utils::{bookmark, CreateCommitContext};
+
+    use crate::pktline::{Packet, PacketReader};
+
+    async fn collect(stream: impl Stream<Item = Result<Bytes, Error>>) -> Result<Vec<u8>, Error> {
+        stream
+            .try_fold(Vec::new(), |mut body, bytes| async move {
+                body.extend_from_slice(&bytes);
+                Ok(body)
+            })
+            .await
+    }
+
+    /// Split a fetch response into its lines of text, its progress messages, and the pack that
+    /// it contains.
+    fn parse_fetch_response(body: &[u8]) -> Result<(Vec<String>, String, Vec<u8>), Error> {
+        let mut lines = Vec::new();
+        let mut progress = String::new();
+        let mut pack = Vec::new();
+        let mut in_pack = false;
+
+        for packet in PacketReader::new(body) {
+            match packet? {
+                Packet::Data(data) if in_pack => match data[0] {
+                    SIDEBAND_DATA => pack.extend_from_slice(&data[1..]),
+                    SIDEBAND_PROGRESS => progress.push_str(std::str::from_utf8(&data[1..])?),
+                    band => return Err(anyhow!("Unexpected sideband {}", band)),
+                },
+                packet => {
+                    if let Some(line) = packet.as_line()? {
+                        in_pack = line == "packfile";
+                        lines.push(line.to_string());
+                    }
+                }
+            }
+        }
+
+        Ok((lines, progress, pack))
+    }
+
+    /// Index a pack into a bare Git repository, which checks that it's valid.
+    fn index_pack(pack: &[u8]) -> Result<(TempDir, Repository), Error> {
+        let tmp_dir = TempDir::new("git_server_test")?;
+        let git = Repository::init_bare(tmp_dir.path())?;
+        {
+            let odb = git.odb()?;
+            let mut writer = odb.packwriter()?;
+            writer.write_all(pack)?;
+            writer.commit()?;
+        }
+        Ok((tmp_dir, git))
+    }
+
+    fn count_objects(git: &Repository) -> Result<usize, Error> {
+        let mut count = 0;
+        git.odb()?.foreach(|_| {
+            count += 1;
+            true
+        })?;
+        Ok(count)
+    }
+
+    async fn sha1_for(
+        ctx: &CoreContext,
+        repo: &BlobRepo,
+        cs_id: ChangesetId,
+    ) -> Result<GitSha1, Error> {
+        repo.bonsai_git_mapping()
+            .get_git_sha1_from_bonsai(ctx, cs_id)
+            .await?
+            .ok_or_else(|| anyhow!("No Git hash for {}", cs_id))
+    }
+
+    async fn fetch_args(
+        ctx: &CoreContext,
+        repo: &BlobRepo,
+        wants: &[ChangesetId],
+    ) -> Result<FetchArgs, Error> {
+        let mut args = FetchArgs {
+            done: true,
+            ..Default::default()
+        };
+        for want in wants {
+            args.wants.push(sha1_for(ctx, repo, *want).await?);
+        }
+        Ok(args)
+    }
+
+    #[fbinit::test]
+    async fn test_ls_refs(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo: BlobRepo = test_repo_factory::build_empty()?;
+
+        let root = CreateCommitContext::new_root(&ctx, &repo)
+            .add_file("a", "a")
+            .commit()
+            .await?;
+        let child = CreateCommitContext::new(&ctx, &repo, vec![root])
+            .add_file("b", "b")
+            .commit()
+            .await?;
+        bookmark(&ctx, &repo, "master")
+            .create_publishing(child)
+            .await?;
+        bookmark(&ctx, &repo, "release")
+            .create_publishing(root)
+            .await?;
+        // Scratch bookmarks aren't served.
+        bookmark(&ctx, &repo, "scratch")
+            .create_scratch(root)
+            .await?;
+
+        let args = LsRefsArgs {
+            symrefs: true,
+            ..Default::default()
+        };
+        let body = ls_refs(ctx.clone(), repo.clone(), args).await?;
+
+        // Serving refs records the Git hashes of the commits, and of their ancestors.
+        let root_sha1 = sha1_for(&ctx, &repo, root).await?;
+        let child_sha1 = sha1_for(&ctx, &repo, child).await?;
+
+        let lines = PacketReader::new(&body)
+            .map(|packet| Ok(packet?.as_line()?.map(ToString::to_string)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        assert_eq!(
+            lines,
+            vec![
+                Some(format!(
+                    "{} HEAD symref-target:refs/heads/master",
+                    child_sha1
+                )),
+                Some(format!("{} refs/heads/master", child_sha1)),
+                Some(format!("{} refs/heads/release", root_sha1)),
+                None,
+            ]
+        );
+
+        let args = LsRefsArgs {
+            ref_prefixes: vec!["refs/heads/rel".to_string()],
+            ..Default::default()
+        };
+        let body = ls_refs(ctx.clone(), repo.clone(), args).await?;
+        let mut expected = PacketWriter::new();
+        expected
+            .line(format!("{} refs/heads/release", root_sha1))
+            .flush();
+        assert_eq!(body, expected.into_bytes());
+
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_fetch(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo: BlobRepo = test_repo_factory::build_empty()?;
+
+        let root = CreateCommitContext::new_root(&ctx, &repo)
+            .add_file("a", "a")
+            .add_file("dir/b", "b")
+            .commit()
+            .await?;
+        let left = CreateCommitContext::new(&ctx, &repo, vec![root])
+            .add_file("dir/c", "c")
+            .commit()
+            .await?;
+        let right = CreateCommitContext::new(&ctx, &repo, vec![root])
+            .add_file("d", "d")
+            .commit()
+            .await?;
+        let merge = CreateCommitContext::new(&ctx, &repo, vec![left, right])
+            .add_file("e", "e")
+            .commit()
+            .await?;
+
+        let mut commits = GitCommits::new(ctx.clone(), repo.clone())?;
+        commits.resolve(vec![merge]).await?;
+
+        // A full clone.
+        let args = fetch_args(&ctx, &repo, &[merge]).await?;
+        let body = collect(fetch(ctx.clone(), repo.clone(), args).await?).await?;
+        let (lines, progress, pack) = parse_fetch_response(&body)?;
+        assert_eq!(lines, vec!["packfile".to_string()]);
+        assert_eq!(progress, "Enumerating objects: 15, done.\n");
+
+        let (_tmp_dir, git) = index_pack(&pack)?;
+        let merge_sha1 = sha1_for(&ctx, &repo, merge).await?;
+        let merge_commit = git.find_commit(Oid::from_bytes(merge_sha1.as_ref())?)?;
+        assert_eq!(merge_commit.parent_count(), 2);
+
+        // Every commit, tree and blob is in the pack.
+        let mut revwalk = git.revwalk()?;
+        revwalk.push(merge_commit.id())?;
+        let mut count = 0;
+        for oid in revwalk {
+            let commit = git.find_commit(oid?)?;
+            commit
+                .tree()?
+                .walk(git2::TreeWalkMode::PreOrder, |_, entry| {
+                    assert!(entry.to_object(&git).is_ok());
+                    git2::TreeWalkResult::Ok
+                })?;
+            count += 1;
+        }
+        assert_eq!(count, 4);
+
+        // An incremental fetch, from a client that has the left side.
+        let mut args = fetch_args(&ctx, &repo, &[merge]).await?;
+        args.haves.push(sha1_for(&ctx, &repo, left).await?);
+        args.done = false;
+        args.no_progress = true;
+        let body = collect(fetch(ctx.clone(), repo.clone(), args).await?).await?;
+        let (lines, progress, pack) = parse_fetch_response(&body)?;
+        assert_eq!(progress, "");
+        assert_eq!(
+            lines,
+            vec![
+                "acknowledgments".to_string(),
+                format!("ACK {}", sha1_for(&ctx, &repo, left).await?),
+                "ready".to_string(),
+                "packfile".to_string(),
+            ]
+        );
+        // The merge and the right side, with their new root trees and blobs. The directory in the
+        // merge is the same as in the left side.
+        let (_tmp_dir, git) = index_pack(&pack)?;
+        assert_eq!(count_objects(&git)?, 6);
+        assert!(git
+            .find_commit(Oid::from_bytes(merge_sha1.as_ref())?)
+            .is_ok());
+        let left_sha1 = sha1_for(&ctx, &repo, left).await?;
+        assert!(git
+            .find_commit(Oid::from_bytes(left_sha1.as_ref())?)
+            .is_err());
+
+        // A shallow clone, without blobs.
+        let mut args = fetch_args(&ctx, &repo, &[merge]).await?;
+        args.deepen = Some(1);
+        args.filter_blobs = true;
+        let body = collect(fetch(ctx.clone(), repo.clone(), args).await?).await?;
+        let (lines, _, pack) = parse_fetch_response(&body)?;
+        assert_eq!(
+            lines,
+            vec![
+                "shallow-info".to_string(),
+                format!("shallow {}", merge_sha1),
+                "packfile".to_string(),
+            ]
+        );
+        let (_tmp_dir, git) = index_pack(&pack)?;
+        assert_eq!(count_objects(&git)?, 3);
+
+        // Deepening that clone.
+        let mut args = fetch_args(&ctx, &repo, &[merge]).await?;
+        args.haves.push(merge_sha1);
+        args.shallows.push(merge_sha1);
+        args.deepen = Some(2);
+        let body = collect(fetch(ctx.clone(), repo.clone(), args).await?).await?;
+        let (mut lines, _, pack) = parse_fetch_response(&body)?;
+        // Both sides are at the same depth, so they can come in any order.
+        lines[1..3].sort();
+        let mut shallow = vec![
+            format!("shallow {}", left_sha1),
+            format!("shallow {}", sha1_for(&ctx, &repo, right).await?),
+        ];
+        shallow.sort();
+        assert_eq!(lines[0], "shallow-info");
+        assert_eq!(lines[1..3], shallow[..]);
+        assert_eq!(
+            lines[3..],
+            [format!("unshallow {}", merge_sha1), "packfile".to_string()]
+        );
+        // Both sides, with their full trees: 2 commits, 4 trees and 4 blobs.
+        let (_tmp_dir, git) = index_pack(&pack)?;
+        assert_eq!(count_objects(&git)?, 10);
+
+        Ok(())
+    }
+}
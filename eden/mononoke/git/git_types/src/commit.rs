@@ -0,0 +1,277 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::io::{self, Write};
+
+use mononoke_types::{
+    hash::{GitSha1, RichGitSha1},
+    BonsaiChangeset, DateTime,
+};
+
+use crate::{ObjectKind, TreeHandle};
+
+/// A Git commit object. This is the serialized object along with its Git hash, since that's what
+/// we need to send it to a client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commit {
+    oid: RichGitSha1,
+    object: Vec<u8>,
+}
+
+impl Commit {
+    pub fn oid(&self) -> &RichGitSha1 {
+        &self.oid
+    }
+
+    /// The serialized object, without the Git object header.
+    pub fn object(&self) -> &[u8] {
+        &self.object
+    }
+
+    pub fn into_object(self) -> Vec<u8> {
+        self.object
+    }
+}
+
+/// Builds the Git commit for a Bonsai changeset. Git commits refer to their tree and their parents
+/// by hash, so those have to be known before the commit can be built.
+#[derive(Debug, Clone)]
+pub struct CommitBuilder {
+    tree: TreeHandle,
+    parents: Vec<GitSha1>,
+    author: String,
+    author_date: DateTime,
+    committer: String,
+    committer_date: DateTime,
+    message: String,
+}
+
+impl CommitBuilder {
+    /// Create a builder for a changeset, given its Git tree and the Git hashes of its parents (in
+    /// the same order as the changeset's parents).
+    pub fn new(bonsai: &BonsaiChangeset, tree: TreeHandle, parents: Vec<GitSha1>) -> Self {
+        // Changesets that don't come from Git might not have a committer. Git requires one, so
+        // we use the author instead, which is what Git itself does by default.
+        let committer = bonsai.committer().unwrap_or_else(|| bonsai.author());
+        let committer_date = bonsai
+            .committer_date()
+            .unwrap_or_else(|| bonsai.author_date());
+
+        Self {
+            tree,
+            parents,
+            author: format_identity(bonsai.author()),
+            author_date: *bonsai.author_date(),
+            committer: format_identity(committer),
+            committer_date: *committer_date,
+            message: bonsai.message().to_string(),
+        }
+    }
+
+    pub fn write_serialized_object(&self, writer: &mut impl Write) -> Result<(), io::Error> {
+        writeln!(writer, "tree {}", self.tree.oid())?;
+        for parent in &self.parents {
+            writeln!(writer, "parent {}", parent)?;
+        }
+        writeln!(
+            writer,
+            "author {} {}",
+            self.author,
+            format_date(&self.author_date)
+        )?;
+        writeln!(
+            writer,
+            "committer {} {}",
+            self.committer,
+            format_date(&self.committer_date)
+        )?;
+        writeln!(writer)?;
+        writer.write_all(self.message.as_bytes())?;
+
+        Ok(())
+    }
+}
+
+impl From<CommitBuilder> for Commit {
+    fn from(builder: CommitBuilder) -> Self {
+        let mut object = Vec::new();
+        builder
+            .write_serialized_object(&mut object)
+            .expect("Writes to Vec cannot fail");
+
+        let oid = ObjectKind::Commit.create_oid(&object);
+
+        Self { oid, object }
+    }
+}
+
+/// Format an identity the way Git expects in commits, i.e. `Name <email>`. Changesets that don't
+/// come from Git can have any author, e.g. without an email, so identities that aren't in that
+/// form are rebuilt from their name and email (which is empty if there is none). Characters that
+/// would end the name, the email or the header are dropped, as Git does.
+fn format_identity(identity: &str) -> String {
+    let is_valid = |s: &str| !s.contains(&['<', '>', '\n', '\0'][..]);
+    if let Some((name, email)) = identity
+        .strip_suffix('>')
+        .and_then(|identity| identity.split_once(" <"))
+    {
+        if is_valid(name) && is_valid(email) {
+            return identity.to_string();
+        }
+    }
+
+    let (name, email) = match identity.split_once('<') {
+        Some((name, rest)) => (name, rest.split_once('>').map_or(rest, |(email, _)| email)),
+        None => (identity, ""),
+    };
+    let clean = |s: &str| {
+        s.chars()
+            .filter(|c| !matches!(c, '<' | '>' | '\n' | '\0'))
+            .collect::<String>()
+            .trim()
+            .to_string()
+    };
+    format!("{} <{}>", clean(name), clean(email))
+}
+
+/// Format a date the way Git does in commits, e.g. `1609459200 +0100`. Mononoke stores timezone
+/// offsets as seconds west of UTC, whereas Git uses an offset east of UTC.
+fn format_date(date: &DateTime) -> String {
+    let offset = -date.tz_offset_secs() / 60;
+    let sign = if offset < 0 { '-' } else { '+' };
+    let offset = offset.abs();
+
+    format!(
+        "{} {}{:02}{:02}",
+        date.timestamp_secs(),
+        sign,
+        offset / 60,
+        offset % 60
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Error;
+    use git2::{Oid, Repository, Signature, Time};
+    use mononoke_types::BonsaiChangesetMut;
+    use sorted_vector_map::SortedVectorMap;
+    use tempdir::TempDir;
+
+    use crate::{Tree, TreeBuilder};
+
+    fn bonsai(committer: Option<(&str, DateTime)>) -> Result<BonsaiChangeset, Error> {
+        BonsaiChangesetMut {
+            parents: vec![],
+            author: "Jane <jane@example.com>".to_string(),
+            author_date: DateTime::from_timestamp(1000, -3600)?,
+            committer: committer.map(|(c, _)| c.to_string()),
+            committer_date: committer.map(|(_, d)| d),
+            message: "Some commit\n\nWith a description\n".to_string(),
+            extra: SortedVectorMap::new(),
+            file_changes: SortedVectorMap::new(),
+            is_snapshot: false,
+        }
+        .freeze()
+    }
+
+    #[test]
+    fn test_commit_matches_git() -> Result<(), Error> {
+        let tmp_dir = TempDir::new("git_types_test")?;
+        let git = Repository::init(tmp_dir.path())?;
+        let git_tree = git.find_tree(git.treebuilder(None)?.write()?)?;
+
+        let tree: Tree = TreeBuilder::default().into();
+        let author = Signature::new("Jane", "jane@example.com", &Time::new(1000, 60))?;
+        let committer = Signature::new("John", "john@example.com", &Time::new(2000, -360))?;
+
+        let root: Commit = CommitBuilder::new(&bonsai(None)?, *tree.handle(), vec![]).into();
+        let git_root = git.commit(
+            None,
+            &author,
+            &author,
+            "Some commit\n\nWith a description\n",
+            &git_tree,
+            &[],
+        )?;
+        assert_eq!(Oid::from_bytes(root.oid().as_ref())?, git_root);
+
+        let child: Commit = CommitBuilder::new(
+            &bonsai(Some((
+                "John <john@example.com>",
+                DateTime::from_timestamp(2000, 21600)?,
+            )))?,
+            *tree.handle(),
+            vec![root.oid().sha1()],
+        )
+        .into();
+        let git_child = git.commit(
+            None,
+            &author,
+            &committer,
+            "Some commit\n\nWith a description\n",
+            &git_tree,
+            &[&git.find_commit(git_root)?],
+        )?;
+        assert_eq!(Oid::from_bytes(child.oid().as_ref())?, git_child);
+
+        // The object is exactly what Git stored.
+        let odb = git.odb()?;
+        assert_eq!(child.object(), odb.read(git_child)?.data());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_identity() {
+        assert_eq!(
+            format_identity("Jane <jane@example.com>"),
+            "Jane <jane@example.com>"
+        );
+        assert_eq!(format_identity("Jane Doe <>"), "Jane Doe <>");
+        assert_eq!(format_identity("jane"), "jane <>");
+        assert_eq!(
+            format_identity("Jane<jane@example.com>"),
+            "Jane <jane@example.com>"
+        );
+        assert_eq!(
+            format_identity("Jane <jane@example.com> (work)"),
+            "Jane <jane@example.com>"
+        );
+        assert_eq!(
+            format_identity("Jane <jane@example.com>\nparent 0000"),
+            "Jane <jane@example.com>"
+        );
+        assert_eq!(
+            format_identity("Jane\n <jane@example.com\n>"),
+            "Jane <jane@example.com>"
+        );
+        assert_eq!(format_identity("<jane@example.com>"), " <jane@example.com>");
+    }
+
+    #[test]
+    fn test_format_date() -> Result<(), Error> {
+        assert_eq!(
+            format_date(&DateTime::from_timestamp(1000, 0)?),
+            "1000 +0000"
+        );
+        assert_eq!(
+            format_date(&DateTime::from_timestamp(1000, -3600)?),
+            "1000 +0100"
+        );
+        assert_eq!(
+            format_date(&DateTime::from_timestamp(1000, -19800)?),
+            "1000 +0530"
+        );
+        assert_eq!(
+            format_date(&DateTime::from_timestamp(1000, 21600)?),
+            "1000 -0600"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,478 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use anyhow::{bail, Error};
+use async_trait::async_trait;
+use blobrepo::BlobRepo;
+use blobstore::{Blobstore, BlobstoreBytes};
+use bonsai_git_mapping::{
+    AddGitMappingErrorKind, BonsaiGitMapping, BonsaiGitMappingArc, BonsaiGitMappingEntry,
+    BonsaisOrGitShas,
+};
+use context::CoreContext;
+use derived_data::{
+    BonsaiDerivable, BonsaiDerived, BonsaiDerivedMapping, BonsaiDerivedOld, DeriveError,
+    DerivedDataTypesConfig,
+};
+use futures::stream::{FuturesUnordered, TryStreamExt};
+use mononoke_types::{hash::GitSha1, BonsaiChangeset, ChangesetId};
+
+use crate::{Commit, CommitBuilder, TreeHandle};
+
+/// The Git hash of the commit for a changeset.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct MappedGitCommitId(GitSha1);
+
+impl MappedGitCommitId {
+    pub fn oid(&self) -> GitSha1 {
+        self.0
+    }
+}
+
+/// Git hashes of changesets are stored in the blobstore, and recorded in the bonsai_git_mapping so
+/// that changesets can be found by their Git hash. The bonsai_git_mapping also has the hashes that
+/// hg-git recorded when commits were imported from Git, which aren't necessarily those of the
+/// commits we build, so it can't tell which changesets have been derived.
+#[derive(Clone)]
+pub struct GitCommitMapping {
+    blobstore: Arc<dyn Blobstore>,
+    bonsai_git_mapping: Arc<dyn BonsaiGitMapping>,
+}
+
+impl GitCommitMapping {
+    pub fn new(
+        blobstore: Arc<dyn Blobstore>,
+        repo: &impl BonsaiGitMappingArc,
+        _config: &DerivedDataTypesConfig,
+    ) -> Self {
+        Self {
+            blobstore,
+            bonsai_git_mapping: repo.bonsai_git_mapping_arc(),
+        }
+    }
+
+    fn commit_key(&self, cs_id: ChangesetId) -> String {
+        format!("git.derived_commit.{}", cs_id)
+    }
+
+    fn changeset_key(&self, git_sha1: GitSha1) -> String {
+        format!("git.derived_commit_changeset.{}", git_sha1)
+    }
+
+    async fn fetch_commit(
+        &self,
+        ctx: &CoreContext,
+        cs_id: ChangesetId,
+    ) -> Result<Option<(ChangesetId, MappedGitCommitId)>, Error> {
+        match self.blobstore.get(ctx, &self.commit_key(cs_id)).await? {
+            Some(bytes) => {
+                let git_sha1 = GitSha1::from_bytes(bytes.as_raw_bytes())?;
+                Ok(Some((cs_id, MappedGitCommitId(git_sha1))))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn fetch_changeset(
+        &self,
+        ctx: &CoreContext,
+        git_sha1: GitSha1,
+    ) -> Result<Option<(GitSha1, ChangesetId)>, Error> {
+        match self
+            .blobstore
+            .get(ctx, &self.changeset_key(git_sha1))
+            .await?
+        {
+            Some(bytes) => {
+                let cs_id = ChangesetId::from_bytes(bytes.as_raw_bytes())?;
+                Ok(Some((git_sha1, cs_id)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Find the changesets whose Git commits have these hashes. Hashes that aren't the commit of
+    /// any derived changeset are left out.
+    pub async fn get_changesets(
+        &self,
+        ctx: &CoreContext,
+        git_sha1s: Vec<GitSha1>,
+    ) -> Result<HashMap<GitSha1, ChangesetId>, Error> {
+        let candidates = self
+            .bonsai_git_mapping
+            .get(ctx, BonsaisOrGitShas::GitSha1(git_sha1s.clone()))
+            .await?
+            .into_iter()
+            .map(|entry| (entry.git_sha1, entry.bcs_id))
+            .collect();
+        let mut changesets = self.derived_changesets(ctx, candidates).await?;
+
+        // Commits that couldn't be recorded in the bonsai_git_mapping have their changeset in the
+        // blobstore instead.
+        let candidates = git_sha1s
+            .into_iter()
+            .filter(|git_sha1| !changesets.contains_key(git_sha1))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .map(|git_sha1| self.fetch_changeset(ctx, git_sha1))
+            .collect::<FuturesUnordered<_>>()
+            .try_filter_map(|maybe_changeset| async move { Ok(maybe_changeset) })
+            .try_collect()
+            .await?;
+        changesets.extend(self.derived_changesets(ctx, candidates).await?);
+
+        Ok(changesets)
+    }
+
+    /// Keep the changesets whose derived Git commit is the one they are mapped to.
+    async fn derived_changesets(
+        &self,
+        ctx: &CoreContext,
+        candidates: Vec<(GitSha1, ChangesetId)>,
+    ) -> Result<HashMap<GitSha1, ChangesetId>, Error> {
+        let derived = self
+            .get(ctx, candidates.iter().map(|(_, cs_id)| *cs_id).collect())
+            .await?;
+
+        Ok(candidates
+            .into_iter()
+            .filter(|(git_sha1, cs_id)| {
+                derived.get(cs_id).map(MappedGitCommitId::oid) == Some(*git_sha1)
+            })
+            .collect())
+    }
+}
+
+#[async_trait]
+impl BonsaiDerivedMapping for GitCommitMapping {
+    type Value = MappedGitCommitId;
+
+    async fn get(
+        &self,
+        ctx: &CoreContext,
+        csids: Vec<ChangesetId>,
+    ) -> Result<HashMap<ChangesetId, Self::Value>, Error> {
+        csids
+            .into_iter()
+            .map(|cs_id| self.fetch_commit(ctx, cs_id))
+            .collect::<FuturesUnordered<_>>()
+            .try_filter_map(|maybe_commit| async move { Ok(maybe_commit) })
+            .try_collect()
+            .await
+    }
+
+    async fn put(
+        &self,
+        ctx: &CoreContext,
+        csid: ChangesetId,
+        id: &Self::Value,
+    ) -> Result<(), Error> {
+        // The changeset might already be mapped to the hash hg-git recorded, and another changeset
+        // might already be mapped to this hash, since changesets that only differ in what Git
+        // doesn't keep (e.g. extras) have the same commit. Either way, the changeset is stored in
+        // the blobstore instead.
+        let entry = BonsaiGitMappingEntry::new(id.0, csid);
+        match self.bonsai_git_mapping.bulk_add(ctx, &[entry]).await {
+            Ok(()) => {}
+            Err(AddGitMappingErrorKind::Conflict(..)) => {
+                let bytes = BlobstoreBytes::from_bytes(csid.as_ref().to_vec());
+                self.blobstore
+                    .put(ctx, self.changeset_key(id.0), bytes)
+                    .await?;
+            }
+            Err(AddGitMappingErrorKind::InternalError(e)) => return Err(e),
+        }
+
+        let bytes = BlobstoreBytes::from_bytes(id.0.as_ref().to_vec());
+        self.blobstore.put(ctx, self.commit_key(csid), bytes).await
+    }
+
+    fn options(&self) {}
+}
+
+#[async_trait]
+impl BonsaiDerivable for MappedGitCommitId {
+    const NAME: &'static str = "git_commits";
+
+    type Options = ();
+
+    async fn derive_from_parents_impl(
+        ctx: CoreContext,
+        repo: BlobRepo,
+        bonsai: BonsaiChangeset,
+        parents: Vec<Self>,
+        _options: &Self::Options,
+    ) -> Result<Self, Error> {
+        if bonsai.is_snapshot() {
+            bail!("Can't derive Git commit for snapshot")
+        }
+
+        // Commits converted from Git by hg-git record their original hash in their extras, but
+        // commit objects aren't stored, so the Git server rebuilds them from their changeset. The
+        // original hash is only what we build when the conversion kept everything (e.g. not for
+        // signed commits), so we always use the hash of the commit we build, which is one we can
+        // serve.
+        let tree = TreeHandle::derive(&ctx, &repo, bonsai.get_changeset_id()).await?;
+        let parents = parents.into_iter().map(|p| p.0).collect();
+        let commit: Commit = CommitBuilder::new(&bonsai, tree, parents).into();
+
+        Ok(MappedGitCommitId(commit.oid().sha1()))
+    }
+}
+
+#[async_trait]
+impl BonsaiDerivedOld for MappedGitCommitId {
+    type DefaultMapping = GitCommitMapping;
+
+    fn default_mapping(
+        _ctx: &CoreContext,
+        repo: &BlobRepo,
+    ) -> Result<Self::DefaultMapping, DeriveError> {
+        let config = derived_data::enabled_type_config(repo, Self::NAME)?;
+        Ok(GitCommitMapping::new(
+            repo.blobstore().boxed(),
+            repo,
+            config,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::format_err;
+    use blobstore::Loadable;
+    use bonsai_git_mapping::{CONVERT_REVISION_EXTRA, HGGIT_SOURCE_EXTRA};
+    use fbinit::FacebookInit;
+    use git2::{ObjectType, Oid, Repository};
+    use maplit::hashmap;
+    use std::collections::HashSet;
+    use std::convert::TryInto;
+    use std::str::FromStr;
+    use tempdir::TempDir;
+    use tests_utils::CreateCommitContext;
+
+    /// Derive the Git commit for the fixture's master bookmark, and check that every commit in
+    /// its history is the one Git produces from the same tree, parents and metadata.
+    async fn run_commit_derivation_for_fixture(
+        fb: FacebookInit,
+        repo: BlobRepo,
+    ) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+
+        let bcs_id = repo
+            .get_bonsai_bookmark(ctx.clone(), &("master".try_into()?))
+            .await?
+            .ok_or(format_err!("no master"))?;
+
+        let derived = MappedGitCommitId::derive(&ctx, &repo, bcs_id).await?;
+        assert_eq!(
+            repo.bonsai_git_mapping()
+                .get_git_sha1_from_bonsai(&ctx, bcs_id)
+                .await?,
+            Some(derived.oid())
+        );
+
+        let tmp_dir = TempDir::new("git_types_test")?;
+        let git = Repository::init_bare(tmp_dir.path())?;
+        let odb = git.odb()?;
+
+        let mut seen = HashSet::new();
+        let mut to_check = vec![bcs_id];
+        while let Some(cs_id) = to_check.pop() {
+            if !seen.insert(cs_id) {
+                continue;
+            }
+
+            let bonsai = cs_id.load(&ctx, repo.blobstore()).await?;
+            let tree = TreeHandle::derive(&ctx, &repo, cs_id).await?;
+            let oid = MappedGitCommitId::derive(&ctx, &repo, cs_id).await?.oid();
+
+            let mut parents = Vec::new();
+            for parent in bonsai.parents() {
+                parents.push(MappedGitCommitId::derive(&ctx, &repo, parent).await?.oid());
+                to_check.push(parent);
+            }
+
+            // The tree doesn't need to be present for Git to hash the commit, so this only
+            // writes the commit object and checks that Git agrees on its hash.
+            let commit: Commit = CommitBuilder::new(&bonsai, tree, parents).into();
+            let git_oid = odb.write(ObjectType::Commit, commit.object())?;
+            assert_eq!(git_oid, Oid::from_bytes(oid.as_ref())?);
+
+            let git_commit = git.find_commit(git_oid)?;
+            assert_eq!(git_commit.tree_id(), Oid::from_bytes(tree.oid().as_ref())?);
+            assert_eq!(git_commit.parent_count(), bonsai.parents().count());
+        }
+
+        tmp_dir.close()?;
+
+        Ok(())
+    }
+
+    macro_rules! impl_test {
+        ($fixture:ident) => {
+            #[fbinit::test]
+            fn $fixture(fb: FacebookInit) -> Result<(), Error> {
+                let runtime = tokio::runtime::Runtime::new()?;
+                runtime.block_on(async move {
+                    let repo = fixtures::$fixture::getrepo(fb).await;
+                    run_commit_derivation_for_fixture(fb, repo).await
+                })
+            }
+        };
+    }
+
+    impl_test!(linear);
+    impl_test!(branch_even);
+    impl_test!(merge_even);
+    impl_test!(merge_uneven);
+    impl_test!(many_diamonds);
+
+    #[fbinit::test]
+    async fn test_hggit_extras(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = fixtures::linear::getrepo(fb).await;
+
+        let bonsai = CreateCommitContext::new_root(&ctx, &repo)
+            .add_file("file", "content")
+            .commit()
+            .await?
+            .load(&ctx, repo.blobstore())
+            .await?;
+        let tree = TreeHandle::derive(&ctx, &repo, bonsai.get_changeset_id()).await?;
+        let original: Commit = CommitBuilder::new(&bonsai, tree, vec![]).into();
+
+        // A commit that hg-git converted without losing anything gets its original hash back.
+        let lossless = CreateCommitContext::new_root(&ctx, &repo)
+            .add_file("file", "content")
+            .add_extra(HGGIT_SOURCE_EXTRA, "git")
+            .add_extra(CONVERT_REVISION_EXTRA, original.oid().sha1().to_string())
+            .commit()
+            .await?;
+        assert_eq!(
+            MappedGitCommitId::derive(&ctx, &repo, lossless)
+                .await?
+                .oid(),
+            original.oid().sha1()
+        );
+
+        // A commit whose original can't be rebuilt (e.g. it was signed) gets the hash of the
+        // commit we build instead, and its original hash isn't mapped to it.
+        let git_sha1 = GitSha1::from_str("1a2b3c4d5e6f708192a3b4c5d6e7f8091a2b3c4d")?;
+        let converted = CreateCommitContext::new_root(&ctx, &repo)
+            .add_file("file", "signed")
+            .add_extra(HGGIT_SOURCE_EXTRA, "git")
+            .add_extra(CONVERT_REVISION_EXTRA, git_sha1.to_string())
+            .commit()
+            .await?;
+        let child = CreateCommitContext::new(&ctx, &repo, vec![converted])
+            .add_file("file", "changed")
+            .commit()
+            .await?;
+
+        let derived = MappedGitCommitId::derive(&ctx, &repo, child).await?;
+        let converted_bonsai = converted.load(&ctx, repo.blobstore()).await?;
+        let converted_tree = TreeHandle::derive(&ctx, &repo, converted).await?;
+        let converted_commit: Commit =
+            CommitBuilder::new(&converted_bonsai, converted_tree, vec![]).into();
+        assert_ne!(converted_commit.oid().sha1(), git_sha1);
+        assert_eq!(
+            MappedGitCommitId::derive(&ctx, &repo, converted)
+                .await?
+                .oid(),
+            converted_commit.oid().sha1()
+        );
+
+        let bonsai = child.load(&ctx, repo.blobstore()).await?;
+        let tree = TreeHandle::derive(&ctx, &repo, child).await?;
+        let commit: Commit =
+            CommitBuilder::new(&bonsai, tree, vec![converted_commit.oid().sha1()]).into();
+        assert_eq!(derived.oid(), commit.oid().sha1());
+
+        assert_eq!(
+            repo.bonsai_git_mapping()
+                .get_bonsai_from_git_sha1(&ctx, git_sha1)
+                .await?,
+            None
+        );
+
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_hggit_mapping(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = fixtures::linear::getrepo(fb).await;
+        let mapping = MappedGitCommitId::default_mapping(&ctx, &repo)?;
+
+        // The hash hg-git recorded is mapped to the changeset when it is pushed, but isn't the
+        // one of the commit we build, so it isn't taken as derived.
+        let git_sha1 = GitSha1::from_str("1a2b3c4d5e6f708192a3b4c5d6e7f8091a2b3c4d")?;
+        let converted = CreateCommitContext::new_root(&ctx, &repo)
+            .add_file("file", "signed")
+            .add_extra(HGGIT_SOURCE_EXTRA, "git")
+            .add_extra(CONVERT_REVISION_EXTRA, git_sha1.to_string())
+            .commit()
+            .await?;
+        repo.bonsai_git_mapping()
+            .bulk_add(&ctx, &[BonsaiGitMappingEntry::new(git_sha1, converted)])
+            .await?;
+        assert!(mapping.get(&ctx, vec![converted]).await?.is_empty());
+
+        let derived = MappedGitCommitId::derive(&ctx, &repo, converted).await?;
+        assert_ne!(derived.oid(), git_sha1);
+        assert_eq!(
+            mapping
+                .get_changesets(&ctx, vec![derived.oid(), git_sha1])
+                .await?,
+            hashmap! { derived.oid() => converted }
+        );
+        assert_eq!(
+            repo.bonsai_git_mapping()
+                .get_git_sha1_from_bonsai(&ctx, converted)
+                .await?,
+            Some(git_sha1)
+        );
+
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_same_commit(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = fixtures::linear::getrepo(fb).await;
+        let mapping = MappedGitCommitId::default_mapping(&ctx, &repo)?;
+
+        // Changesets that only differ in their extras have the same Git commit.
+        let first = CreateCommitContext::new_root(&ctx, &repo)
+            .add_file("file", "content")
+            .commit()
+            .await?;
+        let second = CreateCommitContext::new_root(&ctx, &repo)
+            .add_file("file", "content")
+            .add_extra("extra", "value")
+            .commit()
+            .await?;
+        assert_ne!(first, second);
+
+        let first_derived = MappedGitCommitId::derive(&ctx, &repo, first).await?;
+        let second_derived = MappedGitCommitId::derive(&ctx, &repo, second).await?;
+        assert_eq!(first_derived, second_derived);
+        assert_eq!(
+            mapping
+                .get_changesets(&ctx, vec![first_derived.oid()])
+                .await?,
+            hashmap! { first_derived.oid() => first }
+        );
+
+        Ok(())
+    }
+}
@@ -7,7 +7,10 @@
 
 use anyhow::Error;
 use gotham::state::State;
-use hyper::{Body, StatusCode};
+use hyper::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    Body, StatusCode,
+};
 use mime::Mime;
 use rate_limiting::RateLimitReason;
 
@@ -22,6 +25,8 @@ pub trait ErrorFormatter {
 pub struct HttpError {
     pub error: Error,
     pub status_code: StatusCode,
+    /// Headers to add to the error response
+    pub headers: HeaderMap,
 }
 
 impl HttpError {
@@ -29,6 +34,7 @@ impl HttpError {
         Self {
             error: err.into(),
             status_code: StatusCode::BAD_REQUEST,
+            headers: HeaderMap::new(),
         }
     }
 
@@ -36,6 +42,7 @@ impl HttpError {
         Self {
             error: err.into(),
             status_code: StatusCode::FORBIDDEN,
+            headers: HeaderMap::new(),
         }
     }
 
@@ -43,6 +50,15 @@ impl HttpError {
         Self {
             error: err.into(),
             status_code: StatusCode::NOT_FOUND,
+            headers: HeaderMap::new(),
+        }
+    }
+
+    pub fn e409<E: Into<Error>>(err: E) -> Self {
+        Self {
+            error: err.into(),
+            status_code: StatusCode::CONFLICT,
+            headers: HeaderMap::new(),
         }
     }
 
@@ -50,6 +66,31 @@ impl HttpError {
         Self {
             error: err.into(),
             status_code: StatusCode::GONE,
+            headers: HeaderMap::new(),
+        }
+    }
+
+    pub fn e413<E: Into<Error>>(err: E) -> Self {
+        Self {
+            error: err.into(),
+            status_code: StatusCode::PAYLOAD_TOO_LARGE,
+            headers: HeaderMap::new(),
+        }
+    }
+
+    pub fn e416<E: Into<Error>>(err: E) -> Self {
+        Self {
+            error: err.into(),
+            status_code: StatusCode::RANGE_NOT_SATISFIABLE,
+            headers: HeaderMap::new(),
+        }
+    }
+
+    pub fn e422<E: Into<Error>>(err: E) -> Self {
+        Self {
+            error: err.into(),
+            status_code: StatusCode::UNPROCESSABLE_ENTITY,
+            headers: HeaderMap::new(),
         }
     }
 
@@ -57,6 +98,7 @@ impl HttpError {
         Self {
             error: err.into(),
             status_code: StatusCode::TOO_MANY_REQUESTS,
+            headers: HeaderMap::new(),
         }
     }
 
@@ -64,6 +106,7 @@ impl HttpError {
         Self {
             error: err.into(),
             status_code: StatusCode::INTERNAL_SERVER_ERROR,
+            headers: HeaderMap::new(),
         }
     }
 
@@ -71,8 +114,14 @@ impl HttpError {
         Self {
             error: err.into(),
             status_code: StatusCode::SERVICE_UNAVAILABLE,
+            headers: HeaderMap::new(),
         }
     }
+
+    pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
 }
 
 impl From<RateLimitReason> for HttpError {
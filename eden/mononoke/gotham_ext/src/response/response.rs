@@ -11,11 +11,12 @@ use anyhow::{Context, Error};
 use bytes::Bytes;
 use futures::{
     channel::oneshot,
+    future::Either,
     stream::{Stream, StreamExt},
 };
 use gotham::{handler::HandlerError, helpers::http::response::create_response, state::State};
 use hyper::{
-    header::{HeaderValue, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE},
+    header::{HeaderMap, HeaderValue, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE},
     Body, Response, StatusCode,
 };
 use mime::Mime;
@@ -32,6 +33,21 @@ pub trait TryIntoResponse {
     fn try_into_response(self, state: &mut State) -> Result<Response<Body>, Error>;
 }
 
+/// Allow handlers to return one of two different kinds of response (e.g. a body or an empty
+/// response) depending on the request.
+impl<A, B> TryIntoResponse for Either<A, B>
+where
+    A: TryIntoResponse,
+    B: TryIntoResponse,
+{
+    fn try_into_response(self, state: &mut State) -> Result<Response<Body>, Error> {
+        match self {
+            Self::Left(a) => a.try_into_response(state),
+            Self::Right(b) => b.try_into_response(state),
+        }
+    }
+}
+
 pub fn build_response<IR: TryIntoResponse, F: ErrorFormatter>(
     res: Result<IR, HttpError>,
     mut state: State,
@@ -60,18 +76,33 @@ pub fn build_error_response<F: ErrorFormatter>(
 
     match formatted {
         Ok((body, mime)) => {
-            let res = create_response(&state, err.status_code, mime, body);
+            let mut res = create_response(&state, err.status_code, mime, body);
+            res.headers_mut().extend(err.headers);
             Ok((state, res))
         }
         Err(error) => Err((state, error.into())),
     }
 }
 
-pub struct EmptyBody;
+pub struct EmptyBody {
+    status: StatusCode,
+    headers: HeaderMap,
+}
 
 impl EmptyBody {
     pub fn new() -> Self {
-        Self
+        Self {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+        }
+    }
+
+    pub fn with_status(self, status: StatusCode) -> Self {
+        Self { status, ..self }
+    }
+
+    pub fn with_headers(self, headers: HeaderMap) -> Self {
+        Self { headers, ..self }
     }
 }
 
@@ -79,22 +110,37 @@ impl TryIntoResponse for EmptyBody {
     fn try_into_response(self, state: &mut State) -> Result<Response<Body>, Error> {
         state.put(PendingResponseMeta::immediate(0));
 
-        Response::builder()
-            .status(StatusCode::OK)
-            .header(CONTENT_LENGTH, 0)
-            .body(Body::empty())
-            .map_err(Error::from)
+        let mut res = Response::builder().status(self.status);
+
+        // A 304 must not advertise a Content-Length other than that of the content it stands in
+        // for, so we leave it out entirely.
+        if self.status != StatusCode::NOT_MODIFIED {
+            res = res.header(CONTENT_LENGTH, 0);
+        }
+
+        let mut res = res.body(Body::empty())?;
+        res.headers_mut().extend(self.headers);
+        Ok(res)
     }
 }
 
 pub struct BytesBody<B> {
     bytes: B,
     mime: Mime,
+    status: StatusCode,
 }
 
 impl<B> BytesBody<B> {
     pub fn new(bytes: B, mime: Mime) -> Self {
-        Self { bytes, mime }
+        Self {
+            bytes,
+            mime,
+            status: StatusCode::OK,
+        }
+    }
+
+    pub fn with_status(self, status: StatusCode) -> Self {
+        Self { status, ..self }
     }
 }
 
@@ -111,7 +157,7 @@ where
 
         Response::builder()
             .header(CONTENT_TYPE, mime_header)
-            .status(StatusCode::OK)
+            .status(self.status)
             .body(bytes.into())
             .map_err(Error::from)
     }
@@ -120,6 +166,7 @@ where
 pub struct StreamBody<S> {
     stream: S,
     mime: Mime,
+    headers: HeaderMap,
     pub partial: bool,
 }
 
@@ -128,9 +175,15 @@ impl<S> StreamBody<S> {
         Self {
             stream,
             mime,
+            headers: HeaderMap::new(),
             partial: false,
         }
     }
+
+    /// Add extra headers to the response (e.g. ETag or Content-Range).
+    pub fn with_headers(self, headers: HeaderMap) -> Self {
+        Self { headers, ..self }
+    }
 }
 
 impl<S> TryIntoResponse for StreamBody<S>
@@ -141,6 +194,7 @@ where
         let Self {
             stream,
             mime,
+            headers,
             partial,
         } = self;
 
@@ -180,6 +234,8 @@ where
         // Turn the stream into a TryStream, as expected by hyper::Body.
         let stream = stream.map(<Result<_, Error>>::Ok);
 
-        Ok(res.body(Body::wrap_stream(stream))?)
+        let mut res = res.body(Body::wrap_stream(stream))?;
+        res.headers_mut().extend(headers);
+        Ok(res)
     }
 }
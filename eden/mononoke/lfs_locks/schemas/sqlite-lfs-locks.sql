@@ -0,0 +1,15 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+CREATE TABLE `lfs_locks` (
+  `id` INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  `repo_id` INT UNSIGNED NOT NULL,
+  `path` VARCHAR(1024) NOT NULL,
+  `owner` VARCHAR(255) NOT NULL,
+  `locked_at` BIGINT NOT NULL,
+  UNIQUE (`repo_id`, `path`)
+);
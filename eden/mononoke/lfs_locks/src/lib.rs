@@ -0,0 +1,246 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+#![deny(warnings)]
+
+//! Storage for Git LFS file locks. A lock is held on a path in a repository
+//! by a single owner, until it is deleted by that owner or by an admin.
+
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Error};
+use context::{CoreContext, PerfCounterType};
+use mononoke_types::{RepositoryId, Timestamp};
+use sql::{queries, Connection};
+use sql_construct::{SqlConstruct, SqlConstructFromMetadataDatabaseConfig};
+use sql_ext::SqlConnections;
+
+#[cfg(test)]
+mod tests;
+
+pub struct SqlLfsLocksStore {
+    write_connection: Connection,
+    read_master_connection: Connection,
+}
+
+impl SqlConstruct for SqlLfsLocksStore {
+    const LABEL: &'static str = "lfs_locks";
+
+    const CREATION_QUERY: &'static str = include_str!("../schemas/sqlite-lfs-locks.sql");
+
+    const POSTGRES_CREATION_QUERY: Option<&'static str> =
+        Some(include_str!("../schemas/postgres-lfs-locks.sql"));
+
+    fn from_sql_connections(connections: SqlConnections) -> Self {
+        Self {
+            write_connection: connections.write_connection,
+            read_master_connection: connections.read_master_connection,
+        }
+    }
+}
+
+impl SqlConstructFromMetadataDatabaseConfig for SqlLfsLocksStore {}
+
+/// Longest path that can be locked, in bytes. MySQL would truncate longer
+/// paths rather than fail the insert.
+pub const MAX_PATH_LEN: usize = 1024;
+/// Longest owner that can hold a lock, in bytes.
+pub const MAX_OWNER_LEN: usize = 255;
+
+// Locks that are deleted as fast as they are created could keep a lock from
+// being created forever, so give up after this many attempts.
+const MAX_CREATE_ATTEMPTS: usize = 10;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LfsLock {
+    pub id: u64,
+    pub path: String,
+    /// The identity of the owner, as formatted by `MononokeIdentity`.
+    pub owner: String,
+    pub locked_at: Timestamp,
+}
+
+impl From<(u64, String, String, Timestamp)> for LfsLock {
+    fn from((id, path, owner, locked_at): (u64, String, String, Timestamp)) -> Self {
+        Self {
+            id,
+            path,
+            owner,
+            locked_at,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CreateLockResult {
+    Created(LfsLock),
+    /// The path is already locked, by this lock.
+    AlreadyLocked(LfsLock),
+}
+
+#[derive(Clone)]
+pub struct LfsLocks {
+    repo_id: RepositoryId,
+    store: Arc<SqlLfsLocksStore>,
+}
+
+impl LfsLocks {
+    pub fn new(repo_id: RepositoryId, store: SqlLfsLocksStore) -> Self {
+        Self {
+            repo_id,
+            store: Arc::new(store),
+        }
+    }
+
+    /// Lock a path for an owner, unless it is already locked.
+    pub async fn create_lock(
+        &self,
+        ctx: &CoreContext,
+        path: &str,
+        owner: &str,
+    ) -> Result<CreateLockResult, Error> {
+        if path.len() > MAX_PATH_LEN {
+            bail!("Path is longer than {} bytes: {}", MAX_PATH_LEN, path);
+        }
+        if owner.len() > MAX_OWNER_LEN {
+            bail!("Owner is longer than {} bytes: {}", MAX_OWNER_LEN, owner);
+        }
+
+        for _ in 0..MAX_CREATE_ATTEMPTS {
+            ctx.perf_counters()
+                .increment_counter(PerfCounterType::SqlWrites);
+            let locked_at = Timestamp::now();
+            let res = InsertLock::query(
+                &self.store.write_connection,
+                &self.repo_id,
+                &path,
+                &owner,
+                &locked_at,
+            )
+            .await?;
+
+            if res.affected_rows() == 1 {
+                let id = res
+                    .last_insert_id()
+                    .ok_or_else(|| anyhow!("No id for the lock on {}", path))?;
+                return Ok(CreateLockResult::Created(LfsLock {
+                    id,
+                    path: path.to_string(),
+                    owner: owner.to_string(),
+                    locked_at,
+                }));
+            }
+
+            // The lock that prevented the insert may have been deleted since,
+            // in which case we can try again.
+            if let Some(lock) = self.get_lock_by_path(ctx, path).await? {
+                return Ok(CreateLockResult::AlreadyLocked(lock));
+            }
+        }
+        bail!(
+            "Failed to lock {} after {} attempts",
+            path,
+            MAX_CREATE_ATTEMPTS
+        )
+    }
+
+    pub async fn get_lock(&self, ctx: &CoreContext, id: u64) -> Result<Option<LfsLock>, Error> {
+        ctx.perf_counters()
+            .increment_counter(PerfCounterType::SqlReadsMaster);
+        let mut rows =
+            SelectLockById::query(&self.store.read_master_connection, &self.repo_id, &id).await?;
+        Ok(rows.pop().map(LfsLock::from))
+    }
+
+    pub async fn get_lock_by_path(
+        &self,
+        ctx: &CoreContext,
+        path: &str,
+    ) -> Result<Option<LfsLock>, Error> {
+        ctx.perf_counters()
+            .increment_counter(PerfCounterType::SqlReadsMaster);
+        let mut rows =
+            SelectLockByPath::query(&self.store.read_master_connection, &self.repo_id, &path)
+                .await?;
+        Ok(rows.pop().map(LfsLock::from))
+    }
+
+    /// List up to `limit` locks in order of id, starting from `min_id`. The
+    /// id after the last lock returned can be used to continue the listing.
+    pub async fn list_locks(
+        &self,
+        ctx: &CoreContext,
+        min_id: u64,
+        limit: u64,
+    ) -> Result<Vec<LfsLock>, Error> {
+        ctx.perf_counters()
+            .increment_counter(PerfCounterType::SqlReadsMaster);
+        let rows = SelectLocks::query(
+            &self.store.read_master_connection,
+            &self.repo_id,
+            &min_id,
+            &limit,
+        )
+        .await?;
+        Ok(rows.into_iter().map(LfsLock::from).collect())
+    }
+
+    /// Delete a lock, returning whether it existed.
+    pub async fn delete_lock(&self, ctx: &CoreContext, id: u64) -> Result<bool, Error> {
+        ctx.perf_counters()
+            .increment_counter(PerfCounterType::SqlWrites);
+        let res = DeleteLock::query(&self.store.write_connection, &self.repo_id, &id).await?;
+        Ok(res.affected_rows() > 0)
+    }
+}
+
+queries! {
+    write InsertLock(
+        repo_id: RepositoryId,
+        path: &str,
+        owner: &str,
+        locked_at: Timestamp,
+    ) {
+        none,
+        mysql(
+            "INSERT IGNORE INTO lfs_locks (repo_id, path, owner, locked_at)
+             VALUES ({repo_id}, {path}, {owner}, {locked_at})"
+        )
+        sqlite(
+            "INSERT OR IGNORE INTO lfs_locks (repo_id, path, owner, locked_at)
+             VALUES ({repo_id}, CAST({path} AS TEXT), CAST({owner} AS TEXT), {locked_at})"
+        )
+    }
+
+    read SelectLockById(repo_id: RepositoryId, id: u64) -> (u64, String, String, Timestamp) {
+        "SELECT id, path, owner, locked_at FROM lfs_locks
+         WHERE repo_id = {repo_id} AND id = {id}"
+    }
+
+    read SelectLockByPath(repo_id: RepositoryId, path: &str) -> (u64, String, String, Timestamp) {
+        mysql(
+            "SELECT id, path, owner, locked_at FROM lfs_locks
+             WHERE repo_id = {repo_id} AND path = {path}"
+        )
+        sqlite(
+            "SELECT id, path, owner, locked_at FROM lfs_locks
+             WHERE repo_id = {repo_id} AND path = CAST({path} AS TEXT)"
+        )
+    }
+
+    read SelectLocks(repo_id: RepositoryId, min_id: u64, limit: u64) -> (u64, String, String, Timestamp) {
+        "SELECT id, path, owner, locked_at FROM lfs_locks
+         WHERE repo_id = {repo_id} AND id >= {min_id}
+         ORDER BY id
+         LIMIT {limit}"
+    }
+
+    write DeleteLock(repo_id: RepositoryId, id: u64) {
+        none,
+        "DELETE FROM lfs_locks WHERE repo_id = {repo_id} AND id = {id}"
+    }
+}
@@ -0,0 +1,81 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use super::*;
+use fbinit::FacebookInit;
+
+fn created(res: CreateLockResult) -> Result<LfsLock, Error> {
+    match res {
+        CreateLockResult::Created(lock) => Ok(lock),
+        CreateLockResult::AlreadyLocked(lock) => Err(anyhow!("Already locked: {:?}", lock)),
+    }
+}
+
+#[fbinit::test]
+async fn test_create_and_delete(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let store = SqlLfsLocksStore::with_sqlite_in_memory()?;
+    let locks = LfsLocks::new(RepositoryId::new(0), store);
+
+    let lock = created(locks.create_lock(&ctx, "a/b.psd", "USER:alice").await?)?;
+    assert_eq!(lock.path, "a/b.psd");
+    assert_eq!(lock.owner, "USER:alice");
+    assert_eq!(locks.get_lock(&ctx, lock.id).await?, Some(lock.clone()));
+    assert_eq!(
+        locks.get_lock_by_path(&ctx, "a/b.psd").await?,
+        Some(lock.clone())
+    );
+
+    assert_eq!(
+        locks.create_lock(&ctx, "a/b.psd", "USER:bob").await?,
+        CreateLockResult::AlreadyLocked(lock.clone())
+    );
+
+    assert!(locks.delete_lock(&ctx, lock.id).await?);
+    assert!(!locks.delete_lock(&ctx, lock.id).await?);
+    assert_eq!(locks.get_lock(&ctx, lock.id).await?, None);
+
+    let relocked = created(locks.create_lock(&ctx, "a/b.psd", "USER:bob").await?)?;
+    assert_ne!(relocked.id, lock.id);
+    assert_eq!(relocked.owner, "USER:bob");
+
+    // Paths that don't fit are rejected rather than truncated.
+    let long_path = "a".repeat(MAX_PATH_LEN);
+    created(locks.create_lock(&ctx, &long_path, "USER:bob").await?)?;
+    assert!(locks
+        .create_lock(&ctx, &format!("{}b", long_path), "USER:bob")
+        .await
+        .is_err());
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_list(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let store = Arc::new(SqlLfsLocksStore::with_sqlite_in_memory()?);
+    let locks = LfsLocks {
+        repo_id: RepositoryId::new(0),
+        store: store.clone(),
+    };
+    let other_locks = LfsLocks {
+        repo_id: RepositoryId::new(1),
+        store,
+    };
+
+    let mut all = vec![];
+    for path in &["a", "b", "c"] {
+        all.push(created(locks.create_lock(&ctx, path, "USER:alice").await?)?);
+    }
+    // Locks are per repository
+    created(other_locks.create_lock(&ctx, "a", "USER:bob").await?)?;
+
+    assert_eq!(locks.list_locks(&ctx, 0, 10).await?, all);
+    assert_eq!(locks.list_locks(&ctx, 0, 2).await?, all[..2]);
+    assert_eq!(locks.list_locks(&ctx, all[2].id, 2).await?, all[2..]);
+    assert_eq!(other_locks.list_locks(&ctx, 0, 10).await?.len(), 1);
+    Ok(())
+}
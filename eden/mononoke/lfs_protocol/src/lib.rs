@@ -7,10 +7,15 @@
 
 #![deny(warnings)]
 
+mod locks;
 mod protocol;
 mod str_serialized;
 
+pub use locks::{
+    Lock, LockOwner, RequestCreateLock, RequestUnlock, RequestVerifyLocks, ResponseCreateLock,
+    ResponseListLocks, ResponseLockConflict, ResponseUnlock, ResponseVerifyLocks,
+};
 pub use protocol::{
-    git_lfs_mime, ObjectAction, ObjectError, ObjectStatus, Operation, RequestBatch, RequestObject,
-    ResponseBatch, ResponseError, ResponseObject, Sha256, Transfer,
+    git_lfs_mime, ObjectAction, ObjectError, ObjectStatus, Operation, Ref, RequestBatch,
+    RequestObject, ResponseBatch, ResponseError, ResponseObject, Sha256, Transfer,
 };
@@ -0,0 +1,260 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use quickcheck::{Arbitrary, Gen};
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::Ref;
+
+// This module provides types conforming to the Git-LFS file locking API:
+// https://github.com/git-lfs/git-lfs/blob/master/docs/api/locking.md
+
+#[derive(Clone, Serialize, Debug, Deserialize, Eq, PartialEq)]
+pub struct LockOwner {
+    pub name: String,
+}
+
+impl Arbitrary for LockOwner {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        Self {
+            name: String::arbitrary(g),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Debug, Deserialize, Eq, PartialEq)]
+pub struct Lock {
+    pub id: String,
+    pub path: String,
+    /// When the lock was created, in ISO 8601 format.
+    pub locked_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<LockOwner>,
+}
+
+impl Arbitrary for Lock {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        Self {
+            id: String::arbitrary(g),
+            path: String::arbitrary(g),
+            locked_at: String::arbitrary(g),
+            owner: Option::arbitrary(g),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Debug, Deserialize, Eq, PartialEq)]
+pub struct RequestCreateLock {
+    pub path: String,
+    pub r#ref: Option<Ref>,
+}
+
+impl Arbitrary for RequestCreateLock {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        Self {
+            path: String::arbitrary(g),
+            r#ref: Option::arbitrary(g),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Debug, Deserialize, Eq, PartialEq)]
+pub struct ResponseCreateLock {
+    pub lock: Lock,
+}
+
+impl Arbitrary for ResponseCreateLock {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        Self {
+            lock: Lock::arbitrary(g),
+        }
+    }
+}
+
+/// The response to a request to lock a path that is already locked.
+#[derive(Clone, Serialize, Debug, Deserialize, Eq, PartialEq)]
+pub struct ResponseLockConflict {
+    pub lock: Lock,
+    pub message: String,
+    pub request_id: Option<String>,
+}
+
+impl Arbitrary for ResponseLockConflict {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        Self {
+            lock: Lock::arbitrary(g),
+            message: String::arbitrary(g),
+            request_id: Option::arbitrary(g),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Debug, Deserialize, Eq, PartialEq)]
+pub struct ResponseListLocks {
+    pub locks: Vec<Lock>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+impl Arbitrary for ResponseListLocks {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        Self {
+            locks: Vec::arbitrary(g),
+            next_cursor: Option::arbitrary(g),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Debug, Deserialize, Eq, PartialEq)]
+pub struct RequestVerifyLocks {
+    pub cursor: Option<String>,
+    pub limit: Option<u64>,
+    pub r#ref: Option<Ref>,
+}
+
+impl Arbitrary for RequestVerifyLocks {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        Self {
+            cursor: Option::arbitrary(g),
+            limit: Option::arbitrary(g),
+            r#ref: Option::arbitrary(g),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Debug, Deserialize, Eq, PartialEq)]
+pub struct ResponseVerifyLocks {
+    /// Locks owned by the requester.
+    pub ours: Vec<Lock>,
+    /// Locks owned by anyone else.
+    pub theirs: Vec<Lock>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+impl Arbitrary for ResponseVerifyLocks {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        Self {
+            ours: Vec::arbitrary(g),
+            theirs: Vec::arbitrary(g),
+            next_cursor: Option::arbitrary(g),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Debug, Deserialize, Eq, PartialEq)]
+pub struct RequestUnlock {
+    /// Unlock even if the lock is owned by someone else.
+    #[serde(default)]
+    pub force: bool,
+    pub r#ref: Option<Ref>,
+}
+
+impl Arbitrary for RequestUnlock {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        Self {
+            force: bool::arbitrary(g),
+            r#ref: Option::arbitrary(g),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Debug, Deserialize, Eq, PartialEq)]
+pub struct ResponseUnlock {
+    pub lock: Lock,
+}
+
+impl Arbitrary for ResponseUnlock {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        Self {
+            lock: Lock::arbitrary(g),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use assert_matches::assert_matches;
+    use quickcheck::quickcheck;
+    use serde_json::{self, json};
+
+    #[test]
+    pub fn test_deserialize_create_lock() {
+        let j = json!({
+            "path": "foo/bar.zip",
+            "ref": {
+                "name": "refs/heads/my-feature"
+            }
+        });
+
+        assert_matches!(
+            serde_json::from_str::<RequestCreateLock>(&j.to_string()),
+            Ok(RequestCreateLock {
+                path,
+                r#ref: Some(Ref { name }),
+            }) if path == "foo/bar.zip" && name == "refs/heads/my-feature"
+        );
+    }
+
+    #[test]
+    pub fn test_deserialize_unlock_defaults() {
+        let j = json!({});
+
+        assert_matches!(
+            serde_json::from_str::<RequestUnlock>(&j.to_string()),
+            Ok(RequestUnlock {
+                force: false,
+                r#ref: None,
+            })
+        );
+    }
+
+    #[test]
+    pub fn test_serialize_list_locks() {
+        let res = ResponseListLocks {
+            locks: vec![Lock {
+                id: "1".to_string(),
+                path: "foo/bar.zip".to_string(),
+                locked_at: "2016-05-17T15:49:06+00:00".to_string(),
+                owner: Some(LockOwner {
+                    name: "Jane Doe".to_string(),
+                }),
+            }],
+            next_cursor: None,
+        };
+
+        assert_eq!(
+            serde_json::to_value(&res).unwrap(),
+            json!({
+                "locks": [{
+                    "id": "1",
+                    "path": "foo/bar.zip",
+                    "locked_at": "2016-05-17T15:49:06+00:00",
+                    "owner": {
+                        "name": "Jane Doe"
+                    }
+                }]
+            })
+        );
+    }
+
+    quickcheck! {
+        fn list_locks_roundtrip(res: ResponseListLocks) -> bool {
+            let json = serde_json::to_string(&res).unwrap();
+            let rt = serde_json::from_str::<ResponseListLocks>(&json).unwrap();
+            rt == res
+        }
+
+        fn verify_locks_roundtrip(res: ResponseVerifyLocks) -> bool {
+            let json = serde_json::to_string(&res).unwrap();
+            let rt = serde_json::from_str::<ResponseVerifyLocks>(&json).unwrap();
+            rt == res
+        }
+    }
+}
@@ -61,14 +61,21 @@ impl Arbitrary for Operation {
 pub enum Transfer {
     #[serde(rename = "basic")]
     Basic,
+    /// Resumable uploads, following the tus.io protocol.
+    #[serde(rename = "tus")]
+    Tus,
     #[serde(other)]
     Unknown,
 }
 
 impl Arbitrary for Transfer {
-    fn arbitrary<G: Gen>(_g: &mut G) -> Self {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
         // We don't generate invalid Transfer instances for testing.
-        Transfer::Basic
+        if bool::arbitrary(g) {
+            Transfer::Basic
+        } else {
+            Transfer::Tus
+        }
     }
 }
 
@@ -406,6 +413,21 @@ mod test {
         assert_eq!(res.expires_at, Some("2016-11-10T15:29:07Z".to_string()));
     }
 
+    #[test]
+    pub fn test_deserialize_transfers() {
+        let j = json!({
+            "operation": "upload",
+            "transfers": ["tus", "basic", "lfs-standalone-file"],
+            "objects": [],
+        });
+
+        assert_matches!(
+            serde_json::from_str::<RequestBatch>(&j.to_string()),
+            Ok(RequestBatch { transfers, .. })
+                if transfers == vec![Transfer::Tus, Transfer::Basic, Transfer::Unknown]
+        );
+    }
+
     quickcheck! {
         fn request_batch_roundtrip(batch: RequestBatch) -> bool {
             let json = serde_json::to_string(&batch).unwrap();
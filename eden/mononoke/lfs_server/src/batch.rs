@@ -190,7 +190,7 @@ async fn upstream_objects(
                 })
                 .collect()
         }
-        Transfer::Unknown => ServerObjects::empty(),
+        Transfer::Tus | Transfer::Unknown => ServerObjects::empty(),
     };
 
     Ok(UpstreamObjects::UpstreamPresence(objects))
@@ -333,6 +333,7 @@ async fn internal_objects(
 
 fn batch_upload_response_objects(
     uri_builder: &UriBuilder,
+    transfer: &Transfer,
     max_upload_size: Option<u64>,
     objects: &[RequestObject],
     upstream: &UpstreamObjects,
@@ -372,7 +373,10 @@ fn batch_upload_response_objects(
                 _ => {
                     // Object is missing in at least one location. Require uploading it.
                     STATS::upload_redirect.add_value(1);
-                    let uri = uri_builder.upload_uri(&object)?;
+                    let uri = match transfer {
+                        Transfer::Tus => uri_builder.upload_resumable_uri(&object)?,
+                        Transfer::Basic | Transfer::Unknown => uri_builder.upload_uri(&object)?,
+                    };
                     let action = ObjectAction::new(uri);
 
                     ObjectStatus::Ok {
@@ -401,18 +405,25 @@ async fn batch_upload(
     )
     .await?;
 
+    // Resumable uploads are only offered to clients that support them. Otherwise, fall back to
+    // basic uploads, which all clients support.
+    let transfer =
+        if ctx.config.enable_resumable_uploads() && batch.transfers.contains(&Transfer::Tus) {
+            Transfer::Tus
+        } else {
+            Transfer::Basic
+        };
+
     let objects = batch_upload_response_objects(
         &ctx.uri_builder,
+        &transfer,
         ctx.max_upload_size(),
         &batch.objects,
         &upstream,
         &internal,
     )?;
 
-    Ok(ResponseBatch {
-        transfer: Transfer::Basic,
-        objects,
-    })
+    Ok(ResponseBatch { transfer, objects })
 }
 
 /// This method peforms the routing logic for a given object being requested, given what's
@@ -901,6 +912,7 @@ mod test {
 
         let res = batch_upload_response_objects(
             &uri_builder,
+            &Transfer::Basic,
             Some(1000),
             &req,
             &UpstreamObjects::UpstreamPresence(upstream),
@@ -950,6 +962,45 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_upload_resumable() -> Result<(), Error> {
+        let o1 = obj(ONES_SHA256, 123);
+
+        let server = ServerUris::new(vec!["http://foo.com"], Some("http://bar.com"))?;
+        let uri_builder = UriBuilder {
+            repository: "repo123".to_string(),
+            server: Arc::new(server),
+            host: "foo.com".to_string(),
+        };
+
+        let res = batch_upload_response_objects(
+            &uri_builder,
+            &Transfer::Tus,
+            None,
+            &[o1],
+            &UpstreamObjects::NoUpstream,
+            &ServerObjects::empty(),
+        )?;
+
+        let uri = format!(
+            "http://foo.com/repo123/upload_resumable/{}/{}",
+            o1.oid, o1.size
+        );
+
+        assert_eq!(
+            vec![ResponseObject {
+                object: o1,
+                status: ObjectStatus::Ok {
+                    authenticated: false,
+                    actions: hashmap! { Operation::Upload => ObjectAction::new(uri.parse()?) }
+                }
+            }],
+            res
+        );
+
+        Ok(())
+    }
+
     #[fbinit::test]
     async fn test_resolve_missing(fb: FacebookInit) -> Result<(), Error> {
         let ctx = RepositoryRequestContext::test_builder(fb)?.build()?;
@@ -1119,7 +1170,6 @@ mod test {
             .get(&meta.sha256.into())
             .context("Missing v1")?;
 
-
         // Note: give the server the wrong size here.
         let (obj2, action2) = internal_objects(&ctx, &[RequestObject { size: 0, ..obj }])
             .await?
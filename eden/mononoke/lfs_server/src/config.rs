@@ -143,6 +143,7 @@ impl Default for ServerConfig {
             disable_compression: false,
             disable_compression_identities: vec![],
             enforce_authentication: false,
+            enable_resumable_uploads: false,
         };
 
         Self {
@@ -184,6 +185,9 @@ impl ServerConfig {
     pub fn tasks_per_content(&self) -> NonZeroU16 {
         self.tasks_per_content
     }
+    pub fn enable_resumable_uploads(&self) -> bool {
+        self.raw_server_config.enable_resumable_uploads
+    }
     pub fn disable_compression(&self) -> bool {
         self.raw_server_config.disable_compression
     }
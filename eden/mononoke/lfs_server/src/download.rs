@@ -7,22 +7,32 @@
 
 use std::str::FromStr;
 
-use anyhow::{Context, Error};
-use futures::stream::{StreamExt, TryStreamExt};
+use anyhow::{anyhow, Context, Error};
+use bytes::Bytes;
+use futures::{
+    future::{self, Either},
+    stream::{self, BoxStream, StreamExt, TryStreamExt},
+};
 use gotham::state::{FromState, State};
 use gotham_derive::{StateData, StaticResponseExtender};
+use mime::Mime;
+use rand::Rng;
 use serde::Deserialize;
 
-use filestore::{self, Alias, FetchKey, Range};
+use filestore::{self, Alias, FetchKey, FileContents, Range};
 use gotham_ext::{
     content_encoding::ContentEncoding,
     error::HttpError,
     middleware::{ClientIdentity, ScubaMiddlewareState},
     response::{
-        CompressedResponseStream, ResponseStream, ResponseTryStreamExt, StreamBody, TryIntoResponse,
+        CompressedResponseStream, EmptyBody, ResponseStream, ResponseTryStreamExt, StreamBody,
+        TryIntoResponse,
     },
 };
-use http::header::{HeaderMap, RANGE};
+use http::{
+    header::{HeaderMap, HeaderValue, ACCEPT_RANGES, CONTENT_RANGE, ETAG, IF_NONE_MATCH, RANGE},
+    StatusCode,
+};
 use mononoke_types::{hash::Sha256, ContentId};
 use permission_checker::MononokeIdentitySet;
 use redactedblobstore::has_redaction_root_cause;
@@ -43,6 +53,12 @@ define_stats! {
         Duration::from_secs(5), Duration::from_secs(15), Duration::from_secs(60)
     ),
 }
+
+/// Maximum number of ranges we will serve in a single request. Asking for more than this is not
+/// useful for any legitimate client, and would let a small request cause a lot of work, so we
+/// ignore the Range header and send the whole object instead.
+const MAX_RANGES: usize = 64;
+
 #[derive(Deserialize, StateData, StaticResponseExtender)]
 pub struct DownloadParamsContentId {
     repository: String,
@@ -55,36 +71,146 @@ pub struct DownloadParamsSha256 {
     oid: String,
 }
 
-fn parse_range(header: &str) -> Result<Range, Error> {
+/// A byte range, as requested in a Range header. This has to be resolved against the size of the
+/// object (see resolve_ranges) before it can be served.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum RangeSpec {
+    /// `start-end`, where both bounds are inclusive.
+    Bounded(u64, u64),
+    /// `start-`, i.e. everything from start to the end of the object.
+    From(u64),
+    /// `-len`, i.e. the last len bytes of the object.
+    Suffix(u64),
+}
+
+fn parse_range_spec(spec: &str) -> Result<RangeSpec, Error> {
     static RE: once_cell::sync::Lazy<regex::Regex> =
-        once_cell::sync::Lazy::new(|| regex::Regex::new(r"bytes=(\d+)-(\d+)").unwrap());
+        once_cell::sync::Lazy::new(|| regex::Regex::new(r"^(\d*)-(\d*)$").unwrap());
 
     let caps = RE
-        .captures(header)
-        .with_context(|| format!("Unsupported range: {}", header))?;
+        .captures(spec)
+        .with_context(|| format!("Unsupported range: {}", spec))?;
 
-    let range = Range::range_inclusive(
-        caps[1]
-            .parse()
-            .with_context(|| format!("Invalid range start: {}", &caps[1]))?,
-        caps[2]
+    let parse = |bound: &str, name: &str| -> Result<Option<u64>, Error> {
+        if bound.is_empty() {
+            return Ok(None);
+        }
+
+        let bound = bound
             .parse()
-            .with_context(|| format!("Invalid range end: {}", &caps[2]))?,
-    )?
-    .strict();
+            .with_context(|| format!("Invalid range {}: {}", name, bound))?;
+
+        Ok(Some(bound))
+    };
+
+    match (parse(&caps[1], "start")?, parse(&caps[2], "end")?) {
+        (Some(start), Some(end)) if start <= end => Ok(RangeSpec::Bounded(start, end)),
+        (Some(start), Some(end)) => Err(anyhow!("Invalid range bounds: {}-{}", start, end)),
+        (Some(start), None) => Ok(RangeSpec::From(start)),
+        (None, Some(len)) => Ok(RangeSpec::Suffix(len)),
+        (None, None) => Err(anyhow!("Unsupported range: {}", spec)),
+    }
+}
+
+fn parse_range(header: &str) -> Result<Vec<RangeSpec>, Error> {
+    let specs = header
+        .trim()
+        .strip_prefix("bytes=")
+        .with_context(|| format!("Unsupported range: {}", header))?;
+
+    let specs = specs
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .map(parse_range_spec)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if specs.is_empty() {
+        return Err(anyhow!("Unsupported range: {}", header));
+    }
+
+    if specs.len() > MAX_RANGES {
+        return Err(anyhow!("Too many ranges: {}", specs.len()));
+    }
+
+    Ok(specs)
+}
+
+/// Resolve the ranges a client requested against the size of an object, and return the inclusive
+/// bounds of those that can be satisfied. As per RFC 7233, ranges that start past the end of the
+/// object are dropped, and ranges that extend past it are truncated. Ranges that overlap or are
+/// adjacent are coalesced (in order of their start), so that we never send more than the object.
+fn resolve_ranges(specs: &[RangeSpec], size: u64) -> Vec<(u64, u64)> {
+    let mut ranges = specs
+        .iter()
+        .filter_map(|spec| {
+            let (start, end) = match *spec {
+                RangeSpec::Bounded(start, end) => (start, end),
+                RangeSpec::From(start) => (start, u64::MAX),
+                RangeSpec::Suffix(len) => (size.saturating_sub(len), u64::MAX),
+            };
+
+            if start >= size {
+                return None;
+            }
+
+            Some((start, std::cmp::min(end, size - 1)))
+        })
+        .collect::<Vec<_>>();
+
+    ranges.sort_unstable();
+
+    let mut coalesced: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match coalesced.last_mut() {
+            Some((_, last_end)) if start <= *last_end + 1 => {
+                *last_end = std::cmp::max(*last_end, end);
+            }
+            _ => coalesced.push((start, end)),
+        }
+    }
+
+    coalesced
+}
 
-    Ok(range)
+/// As per RFC 7233, a Range header we can't parse is ignored, and the whole object is served.
+fn extract_range(state: &State) -> Option<Vec<RangeSpec>> {
+    let header = HeaderMap::try_borrow_from(state).and_then(|h| h.get(RANGE))?;
+    let header = std::str::from_utf8(header.as_bytes()).ok()?;
+    parse_range(header).ok()
 }
 
-fn extract_range(state: &State) -> Result<Option<Range>, Error> {
-    let header = match HeaderMap::try_borrow_from(state).and_then(|h| h.get(RANGE)) {
-        Some(h) => h,
+fn extract_if_none_match(state: &State) -> Result<Option<String>, Error> {
+    let headers = match HeaderMap::try_borrow_from(state) {
+        Some(headers) => headers,
         None => return Ok(None),
     };
 
-    let header = std::str::from_utf8(header.as_bytes()).context("Invalid range")?;
+    let values = headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .map(|v| v.to_str().context("Invalid If-None-Match"))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if values.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(values.join(",")))
+}
+
+/// Objects are immutable, so their ContentId makes for a strong ETag.
+fn etag(content_id: &ContentId) -> String {
+    format!("\"{}\"", content_id)
+}
 
-    Ok(Some(parse_range(header)?))
+/// Check whether an If-None-Match header matches an ETag. If-None-Match uses the weak comparison
+/// function (RFC 7232), so weak validators match as well.
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match.split(',').map(str::trim).any(|tag| {
+        let tag = tag.strip_prefix("W/").unwrap_or(tag);
+        tag == "*" || tag == etag
+    })
 }
 
 fn should_disable_compression(
@@ -98,39 +224,174 @@ fn should_disable_compression(
     is_identity_subset(config.disable_compression_identities(), client_idents)
 }
 
+fn stream_range(
+    ctx: &RepositoryRequestContext,
+    contents: &FileContents,
+    range: Range,
+) -> Result<BoxStream<'static, Result<Bytes, Error>>, Error> {
+    let stream = filestore::stream_range(
+        ctx.repo.get_blobstore(),
+        ctx.ctx.clone(),
+        contents.clone(),
+        range,
+    )?;
+
+    Ok(stream.boxed())
+}
+
+/// Build a multipart/byteranges body for a set of ranges of an object, as described in RFC 7233.
+/// This returns the body along with its size, which we know in advance since we know the size of
+/// each range.
+fn stream_multipart_ranges(
+    ctx: &RepositoryRequestContext,
+    contents: &FileContents,
+    ranges: &[(u64, u64)],
+    boundary: &str,
+) -> Result<(BoxStream<'static, Result<Bytes, Error>>, u64), Error> {
+    let size = contents.size();
+    let mut parts = Vec::with_capacity(ranges.len() + 1);
+    let mut content_length = 0;
+
+    for (start, end) in ranges {
+        let header = Bytes::from(format!(
+            "--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+            boundary,
+            mime::APPLICATION_OCTET_STREAM,
+            start,
+            end,
+            size
+        ));
+        let trailer = Bytes::from_static(b"\r\n");
+
+        content_length += header.len() as u64 + (end - start + 1) + trailer.len() as u64;
+
+        let range = Range::range_inclusive(*start, *end)?.strict();
+        let part = stream::once(future::ready(Ok(header)))
+            .chain(stream_range(ctx, contents, range)?)
+            .chain(stream::once(future::ready(Ok(trailer))));
+
+        parts.push(part.boxed());
+    }
+
+    let end = Bytes::from(format!("--{}--\r\n", boundary));
+    content_length += end.len() as u64;
+    parts.push(stream::once(future::ready(Ok(end))).boxed());
+
+    Ok((stream::iter(parts).flatten().boxed(), content_length))
+}
+
 async fn fetch_by_key(
     ctx: RepositoryRequestContext,
     key: FetchKey,
     content_encoding: ContentEncoding,
-    range: Option<Range>,
+    range: Option<Vec<RangeSpec>>,
+    if_none_match: Option<String>,
     scuba: &mut Option<&mut ScubaMiddlewareState>,
 ) -> Result<impl TryIntoResponse, HttpError> {
-    // Query a stream out of the Filestore
-    let fetched = filestore::fetch_range_with_size(
-        ctx.repo.get_blobstore(),
-        ctx.ctx.clone(),
-        &key,
-        range.unwrap_or_else(Range::all),
-    )
-    .await
-    .map_err(|e| {
-        if has_redaction_root_cause(&e) {
-            HttpError::e410(e)
-        } else {
-            HttpError::e500(e.context(ErrorKind::FilestoreReadFailure))
-        }
-    })?;
-
-    // Return a 404 if the stream doesn't exist.
-    let (stream, size) = fetched
+    // Query the file out of the Filestore
+    let fetched = filestore::fetch_contents(ctx.repo.blobstore(), &ctx.ctx, &key)
+        .await
+        .map_err(|e| {
+            if has_redaction_root_cause(&e) {
+                HttpError::e410(e)
+            } else {
+                HttpError::e500(e.context(ErrorKind::FilestoreReadFailure))
+            }
+        })?;
+
+    // Return a 404 if the file doesn't exist.
+    let (content_id, contents) = fetched
         .ok_or_else(|| ErrorKind::ObjectDoesNotExist(key))
         .map_err(HttpError::e404)?;
 
+    let size = contents.size();
+
     ScubaMiddlewareState::maybe_add(scuba, LfsScubaKey::DownloadContentSize, size);
 
+    let etag = etag(&content_id);
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        ETAG,
+        HeaderValue::from_str(&etag).map_err(|e| HttpError::e500(Error::from(e)))?,
+    );
+
+    // If the client already has this object, there is nothing to send. This takes precedence over
+    // the Range header.
+    if let Some(if_none_match) = if_none_match {
+        if etag_matches(&if_none_match, &etag) {
+            let body = EmptyBody::new()
+                .with_status(StatusCode::NOT_MODIFIED)
+                .with_headers(headers);
+            return Ok(Either::Left(body));
+        }
+    }
+
+    headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+
+    let ranges = match range {
+        Some(range) => {
+            let ranges = resolve_ranges(&range, size);
+            if ranges.is_empty() {
+                let content_range = format!("bytes */{}", size);
+                let content_range = HeaderValue::from_str(&content_range)
+                    .map_err(|e| HttpError::e500(Error::from(e)))?;
+                return Err(HttpError::e416(ErrorKind::RangeNotSatisfiable(size))
+                    .with_header(CONTENT_RANGE, content_range));
+            }
+            Some(ranges)
+        }
+        None => None,
+    };
+
+    let (stream, content_length, mime, content_encoding) = match ranges.as_deref() {
+        None => {
+            let stream = stream_range(&ctx, &contents, Range::all()).map_err(HttpError::e500)?;
+            (
+                stream,
+                size,
+                mime::APPLICATION_OCTET_STREAM,
+                content_encoding,
+            )
+        }
+        Some([(start, end)]) => {
+            let stream = Range::range_inclusive(*start, *end)
+                .map(Range::strict)
+                .and_then(|range| stream_range(&ctx, &contents, range))
+                .map_err(HttpError::e500)?;
+
+            let content_range = format!("bytes {}-{}/{}", start, end, size);
+            headers.insert(
+                CONTENT_RANGE,
+                HeaderValue::from_str(&content_range)
+                    .map_err(|e| HttpError::e500(Error::from(e)))?,
+            );
+
+            // Byte ranges refer to the content as stored, so we don't compress partial responses.
+            (
+                stream,
+                end - start + 1,
+                mime::APPLICATION_OCTET_STREAM,
+                ContentEncoding::Identity,
+            )
+        }
+        Some(ranges) => {
+            let boundary = format!("{:016x}", rand::thread_rng().gen::<u64>());
+
+            let mime: Mime = format!("multipart/byteranges; boundary={}", boundary)
+                .parse()
+                .map_err(|e| HttpError::e500(Error::from(e)))?;
+
+            let (stream, content_length) =
+                stream_multipart_ranges(&ctx, &contents, ranges, &boundary)
+                    .map_err(HttpError::e500)?;
+
+            (stream, content_length, mime, ContentEncoding::Identity)
+        }
+    };
+
     let stream = match content_encoding {
         ContentEncoding::Identity => ResponseStream::new(stream)
-            .set_content_length(size)
+            .set_content_length(content_length)
             .left_stream(),
         ContentEncoding::Compressed(c) => CompressedResponseStream::new(stream, c).right_stream(),
     };
@@ -145,11 +406,11 @@ async fn fetch_by_key(
 
     let stream = stream.end_on_err();
 
-    let mut body = StreamBody::new(stream, mime::APPLICATION_OCTET_STREAM);
-    if range.is_some() {
+    let mut body = StreamBody::new(stream, mime).with_headers(headers);
+    if ranges.is_some() {
         body.partial = true;
     }
-    Ok(body)
+    Ok(Either::Right(body))
 }
 
 async fn download_inner(
@@ -158,7 +419,8 @@ async fn download_inner(
     key: FetchKey,
     method: LfsMethod,
 ) -> Result<impl TryIntoResponse, HttpError> {
-    let range = extract_range(state).map_err(HttpError::e400)?;
+    let range = extract_range(state);
+    let if_none_match = extract_if_none_match(state).map_err(HttpError::e400)?;
 
     let ctx = RepositoryRequestContext::instantiate(state, repository.clone(), method).await?;
 
@@ -176,7 +438,7 @@ async fn download_inner(
 
     let mut scuba = state.try_borrow_mut::<ScubaMiddlewareState>();
 
-    fetch_by_key(ctx, key, content_encoding, range, &mut scuba).await
+    fetch_by_key(ctx, key, content_encoding, range, if_none_match, &mut scuba).await
 }
 
 pub async fn download(state: &mut State) -> Result<impl TryIntoResponse, HttpError> {
@@ -212,8 +474,9 @@ mod test {
 
     use anyhow::Error;
     use fbinit::FacebookInit;
-    use http::StatusCode;
+    use filestore::StoreRequest;
     use maplit::hashmap;
+    use metaconfig_types::FilestoreParams;
     use mononoke_types::typed_hash::MononokeId;
     use mononoke_types_mocks::contentid::ONES_CTID;
     use permission_checker::MononokeIdentity;
@@ -221,6 +484,19 @@ mod test {
     use std::sync::Arc;
     use test_repo_factory::TestRepoFactory;
 
+    async fn store(ctx: &RepositoryRequestContext, data: &'static str) -> Result<FetchKey, Error> {
+        let metadata = filestore::store(
+            ctx.repo.blobstore(),
+            ctx.repo.filestore_config(),
+            &ctx.ctx,
+            &StoreRequest::new(data.len() as u64),
+            stream::once(future::ready(Ok(Bytes::from(data)))),
+        )
+        .await?;
+
+        Ok(FetchKey::Canonical(metadata.content_id))
+    }
+
     #[fbinit::test]
     async fn test_redacted_fetch(fb: FacebookInit) -> Result<(), Error> {
         let content_id = ONES_CTID;
@@ -241,7 +517,7 @@ mod test {
 
         let key = FetchKey::Canonical(content_id);
 
-        let err = fetch_by_key(ctx, key, ContentEncoding::Identity, None, &mut None)
+        let err = fetch_by_key(ctx, key, ContentEncoding::Identity, None, None, &mut None)
             .await
             .map(|_| ())
             .unwrap_err();
@@ -250,16 +526,149 @@ mod test {
         Ok(())
     }
 
+    #[fbinit::test]
+    async fn test_unsatisfiable_range(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = RepositoryRequestContext::test_builder(fb)?.build()?;
+        let key = store(&ctx, "foobarbaz").await?;
+
+        let err = fetch_by_key(
+            ctx,
+            key,
+            ContentEncoding::Identity,
+            Some(vec![RangeSpec::From(9), RangeSpec::Suffix(0)]),
+            None,
+            &mut None,
+        )
+        .await
+        .map(|_| ())
+        .unwrap_err();
+        assert_eq!(err.status_code, StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(
+            err.headers.get(CONTENT_RANGE),
+            Some(&HeaderValue::from_static("bytes */9"))
+        );
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_multipart_ranges(fb: FacebookInit) -> Result<(), Error> {
+        let repo = TestRepoFactory::new()?
+            .with_config_override(|config| {
+                config.filestore = Some(FilestoreParams {
+                    chunk_size: 4,
+                    concurrency: 1,
+                });
+            })
+            .build()?;
+
+        let ctx = RepositoryRequestContext::test_builder(fb)?
+            .repo(repo)
+            .build()?;
+
+        let key = store(&ctx, "foobarbaz").await?;
+        let (_, contents) = filestore::fetch_contents(ctx.repo.blobstore(), &ctx.ctx, &key)
+            .await?
+            .ok_or_else(|| Error::msg("Object does not exist"))?;
+
+        let (stream, content_length) =
+            stream_multipart_ranges(&ctx, &contents, &[(0, 1), (3, 7)], "xyz")?;
+
+        let body = stream
+            .try_fold(Vec::new(), |mut body, chunk| async move {
+                body.extend_from_slice(&chunk);
+                Result::<_, Error>::Ok(body)
+            })
+            .await?;
+
+        let expected = concat!(
+            "--xyz\r\n",
+            "Content-Type: application/octet-stream\r\n",
+            "Content-Range: bytes 0-1/9\r\n",
+            "\r\n",
+            "fo\r\n",
+            "--xyz\r\n",
+            "Content-Type: application/octet-stream\r\n",
+            "Content-Range: bytes 3-7/9\r\n",
+            "\r\n",
+            "barba\r\n",
+            "--xyz--\r\n",
+        );
+
+        assert_eq!(std::str::from_utf8(&body)?, expected);
+        assert_eq!(content_length, expected.len() as u64);
+        Ok(())
+    }
+
     #[test]
     fn test_parse_range() -> Result<(), Error> {
-        // NOTE: This range is inclusive, so here we want bytes 1, 2, 3, 5 (a 5-byte range starting
-        // at byte 1).
-        assert_eq!(parse_range("bytes=1-5")?, Range::sized(1, 5).strict());
+        // NOTE: This range is inclusive, so here we want bytes 1, 2, 3, 4, 5.
+        assert_eq!(parse_range("bytes=1-5")?, vec![RangeSpec::Bounded(1, 5)]);
+        assert_eq!(
+            parse_range("bytes=0-0, 10-, -20")?,
+            vec![
+                RangeSpec::Bounded(0, 0),
+                RangeSpec::From(10),
+                RangeSpec::Suffix(20)
+            ]
+        );
         assert!(parse_range("1-5").is_err());
         assert!(parse_range("foo=1-5").is_err());
+        assert!(parse_range("bytes=5-1").is_err());
+        assert!(parse_range("bytes=-").is_err());
+        assert!(parse_range("bytes=").is_err());
+        assert!(parse_range("bytes=1-5;").is_err());
+
+        let too_many = vec!["0-0"; MAX_RANGES + 1].join(",");
+        assert!(parse_range(&format!("bytes={}", too_many)).is_err());
         Ok(())
     }
 
+    #[test]
+    fn test_resolve_ranges() {
+        let specs = vec![
+            RangeSpec::Bounded(2, 4),
+            RangeSpec::Bounded(8, 20),
+            RangeSpec::Bounded(10, 20),
+            RangeSpec::From(5),
+            RangeSpec::From(10),
+            RangeSpec::Suffix(3),
+            RangeSpec::Suffix(20),
+            RangeSpec::Suffix(0),
+        ];
+
+        assert_eq!(resolve_ranges(&specs, 10), vec![(0, 9)]);
+        assert_eq!(resolve_ranges(&specs, 0), vec![]);
+
+        // Overlapping and adjacent ranges are coalesced, others are kept apart.
+        let specs = vec![
+            RangeSpec::Bounded(8, 9),
+            RangeSpec::Bounded(0, 1),
+            RangeSpec::Bounded(2, 3),
+            RangeSpec::Bounded(5, 6),
+            RangeSpec::Bounded(6, 6),
+            RangeSpec::Suffix(1),
+        ];
+        assert_eq!(
+            resolve_ranges(&specs, 20),
+            vec![(0, 3), (5, 6), (8, 9), (19, 19)]
+        );
+
+        // Asking for the whole object many times only sends it once.
+        let specs = vec![RangeSpec::From(0); MAX_RANGES];
+        assert_eq!(resolve_ranges(&specs, 10), vec![(0, 9)]);
+    }
+
+    #[test]
+    fn test_etag_matches() {
+        let etag = etag(&ONES_CTID);
+
+        assert!(etag_matches(&etag, &etag));
+        assert!(etag_matches("*", &etag));
+        assert!(etag_matches(&format!("\"foo\", W/{}", etag), &etag));
+        assert!(!etag_matches("\"foo\"", &etag));
+        assert!(!etag_matches(etag.trim_matches('"'), &etag));
+    }
+
     #[test]
     fn test_should_disable_compression() -> Result<(), Error> {
         let mut config = ServerConfig::default();
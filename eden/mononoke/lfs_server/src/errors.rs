@@ -66,6 +66,36 @@ pub enum ErrorKind {
     ObjectNotInternallyAvailableAndUpstreamUnavailable(lfs_protocol::Sha256),
     #[error("Object could not be synced from upstream: {0:?}")]
     ObjectCannotBeSynced(RequestObject),
+    #[error("No requested range can be satisfied for an object of size {0}")]
+    RangeNotSatisfiable(u64),
+    #[error("Missing Upload-Offset header")]
+    UploadOffsetMissing,
+    #[error("Upload-Offset ({0}) does not match the offset of the upload ({1})")]
+    UploadOffsetMismatch(u64, u64),
+    #[error("Upload exceeds the object size ({0})")]
+    UploadExceedsSize(u64),
+    #[error("Part {0} of the upload is missing")]
+    UploadPartMissing(u64),
+    #[error("Uploaded content does not match the object")]
+    UploadVerificationFailed,
+    #[error("Upload failed verification {0} times, and can be started over later")]
+    UploadAttemptsExhausted(u64),
+    #[error("Could not parse lock request")]
+    InvalidLockRequest,
+    #[error("Invalid lock cursor: {0}")]
+    InvalidLockCursor(String),
+    #[error("Could not access lock storage")]
+    LockStoreFailure,
+    #[error("Client has no identity that can own a lock")]
+    LockOwnerUnknown,
+    #[error("Lock path is longer than {0} bytes")]
+    LockPathTooLong(usize),
+    #[error("Lock does not exist: {0}")]
+    LockDoesNotExist(String),
+    #[error("Lock {0} is owned by someone else")]
+    LockNotOwned(String),
+    #[error("Not permitted to force unlock lock {0}")]
+    ForceUnlockNotPermitted(String),
 
     /// A generic error occurred, and we'd like to propagate it.
     #[error(transparent)]
@@ -28,7 +28,7 @@ use http::{
     uri::{Authority, Parts, PathAndQuery, Scheme, Uri},
 };
 use hyper::{header, Body, Request};
-use permission_checker::{ArcPermissionChecker, MononokeIdentitySet};
+use permission_checker::{ArcMembershipChecker, ArcPermissionChecker, MononokeIdentitySet};
 use slog::Logger;
 use tokio::runtime::Handle;
 
@@ -36,6 +36,7 @@ use blobrepo::BlobRepo;
 use context::CoreContext;
 use hyper::{client::HttpConnector, Client};
 use hyper_openssl::HttpsConnector;
+use lfs_locks::LfsLocks;
 use lfs_protocol::{RequestBatch, RequestObject, ResponseBatch};
 use metaconfig_types::RepoConfig;
 use mononoke_types::ContentId;
@@ -52,7 +53,8 @@ const ACL_CHECK_ACTION: &str = "read";
 const CLIENT_USER_AGENT: &str = "mononoke-lfs-server/0.1.0 git/2.15.1";
 
 struct LfsServerContextInner {
-    repositories: HashMap<String, (BlobRepo, ArcPermissionChecker, RepoConfig)>,
+    repositories: HashMap<String, (BlobRepo, ArcPermissionChecker, RepoConfig, LfsLocks)>,
+    admin_checker: ArcMembershipChecker,
     client: Arc<HttpsHyperClient>,
     server: Arc<ServerUris>,
     always_wait_for_upstream: bool,
@@ -68,7 +70,8 @@ pub struct LfsServerContext {
 
 impl LfsServerContext {
     pub fn new(
-        repositories: HashMap<String, (BlobRepo, ArcPermissionChecker, RepoConfig)>,
+        repositories: HashMap<String, (BlobRepo, ArcPermissionChecker, RepoConfig, LfsLocks)>,
+        admin_checker: ArcMembershipChecker,
         server: ServerUris,
         always_wait_for_upstream: bool,
         max_upload_size: Option<u64>,
@@ -82,6 +85,7 @@ impl LfsServerContext {
 
         let inner = LfsServerContextInner {
             repositories,
+            admin_checker,
             server: Arc::new(server),
             client: Arc::new(client),
             always_wait_for_upstream,
@@ -105,6 +109,8 @@ impl LfsServerContext {
         let (
             repo,
             aclchecker,
+            locks,
+            admin_checker,
             client,
             server,
             always_wait_for_upstream,
@@ -115,9 +121,11 @@ impl LfsServerContext {
             let inner = self.inner.lock().expect("poisoned lock");
 
             match inner.repositories.get(&repository) {
-                Some((repo, aclchecker, repo_config)) => (
+                Some((repo, aclchecker, repo_config, locks)) => (
                     repo.clone(),
                     aclchecker.clone(),
+                    locks.clone(),
+                    inner.admin_checker.clone(),
                     inner.client.clone(),
                     inner.server.clone(),
                     inner.always_wait_for_upstream,
@@ -147,6 +155,8 @@ impl LfsServerContext {
         Ok(RepositoryRequestContext {
             ctx,
             repo,
+            locks,
+            identities: identities.cloned().unwrap_or_default(),
             uri_builder: UriBuilder {
                 repository,
                 server,
@@ -156,6 +166,7 @@ impl LfsServerContext {
             config,
             always_wait_for_upstream,
             max_upload_size,
+            admin_checker,
         })
     }
 
@@ -214,11 +225,14 @@ enum HttpClient {
 pub struct RepositoryRequestContext {
     pub ctx: CoreContext,
     pub repo: BlobRepo,
+    pub locks: LfsLocks,
+    pub identities: MononokeIdentitySet,
     pub uri_builder: UriBuilder,
     pub config: Arc<ServerConfig>,
     always_wait_for_upstream: bool,
     max_upload_size: Option<u64>,
     client: HttpClient,
+    admin_checker: ArcMembershipChecker,
 }
 
 pub struct HttpClientResponse<S: Stream<Item = Result<Bytes, Error>> + Send + 'static> {
@@ -305,6 +319,11 @@ impl RepositoryRequestContext {
         self.max_upload_size
     }
 
+    /// Whether the client is an admin, and so can break other people's locks.
+    pub async fn is_admin(&self) -> Result<bool, Error> {
+        self.admin_checker.is_member(&self.identities).await
+    }
+
     pub async fn dispatch(
         &self,
         mut request: Request<Body>,
@@ -414,6 +433,15 @@ impl UriBuilder {
             .map_err(|e| ErrorKind::UriBuilderFailed("upload_uri", e))
     }
 
+    pub fn upload_resumable_uri(&self, object: &RequestObject) -> Result<Uri, ErrorKind> {
+        self.pick_uri()?
+            .build(format_args!(
+                "{}/upload_resumable/{}/{}",
+                &self.repository, object.oid, object.size
+            ))
+            .map_err(|e| ErrorKind::UriBuilderFailed("upload_resumable_uri", e))
+    }
+
     pub fn download_uri(&self, content_id: &ContentId) -> Result<Uri, ErrorKind> {
         self.pick_uri()?
             .build(format_args!("{}/download/{}", &self.repository, content_id))
@@ -517,9 +545,11 @@ mod test {
     use super::*;
     use anyhow::anyhow;
     use fbinit::FacebookInit;
+    use lfs_locks::SqlLfsLocksStore;
     use lfs_protocol::Sha256 as LfsSha256;
     use mononoke_types::{hash::Sha256, ContentId};
-    use permission_checker::PermissionCheckerBuilder;
+    use permission_checker::{MembershipCheckerBuilder, PermissionCheckerBuilder};
+    use sql_construct::SqlConstruct;
     use std::str::FromStr;
     use test_repo_factory::TestRepoFactory;
 
@@ -543,6 +573,9 @@ mod test {
     pub struct TestContextBuilder<'a> {
         fb: FacebookInit,
         repo: BlobRepo,
+        locks: Option<LfsLocks>,
+        identities: MononokeIdentitySet,
+        admin_checker: ArcMembershipChecker,
         self_uris: Vec<&'a str>,
         upstream_uri: Option<String>,
         config: ServerConfig,
@@ -555,6 +588,21 @@ mod test {
             self
         }
 
+        pub fn locks(mut self, locks: LfsLocks) -> Self {
+            self.locks = Some(locks);
+            self
+        }
+
+        pub fn identities(mut self, identities: MononokeIdentitySet) -> Self {
+            self.identities = identities;
+            self
+        }
+
+        pub fn admin_checker(mut self, admin_checker: ArcMembershipChecker) -> Self {
+            self.admin_checker = admin_checker;
+            self
+        }
+
         pub fn upstream_uri(mut self, upstream_uri: Option<String>) -> Self {
             self.upstream_uri = upstream_uri;
             self
@@ -569,6 +617,9 @@ mod test {
             let Self {
                 fb,
                 repo,
+                locks,
+                identities,
+                admin_checker,
                 self_uris,
                 upstream_uri,
                 config,
@@ -577,14 +628,25 @@ mod test {
 
             let uri_builder = uri_builder(self_uris, upstream_uri.as_deref(), host)?;
 
+            let locks = match locks {
+                Some(locks) => locks,
+                None => LfsLocks::new(
+                    repo.get_repoid(),
+                    SqlLfsLocksStore::with_sqlite_in_memory()?,
+                ),
+            };
+
             Ok(RepositoryRequestContext {
                 ctx: CoreContext::test_mock(fb),
                 repo,
+                locks,
+                identities,
                 config: Arc::new(config),
                 uri_builder,
                 always_wait_for_upstream: false,
                 max_upload_size: None,
                 client: HttpClient::Disabled,
+                admin_checker,
             })
         }
     }
@@ -594,6 +656,9 @@ mod test {
             Ok(TestContextBuilder {
                 fb,
                 repo: TestRepoFactory::new()?.build()?,
+                locks: None,
+                identities: MononokeIdentitySet::new(),
+                admin_checker: Arc::from(MembershipCheckerBuilder::never_member()),
                 self_uris: vec!["http://foo.com/"],
                 upstream_uri: Some("http://bar.com".to_string()),
                 config: ServerConfig::default(),
@@ -673,6 +738,23 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_upload_resumable_uri() -> Result<(), Error> {
+        let b = uri_builder(
+            vec!["http://foo.com/bar"],
+            Some("http://bar.com"),
+            "foo.com".to_string(),
+        )?;
+        assert_eq!(
+            b.upload_resumable_uri(&obj()?)?.to_string(),
+            format!(
+                "http://foo.com/bar/repo123/upload_resumable/{}/{}",
+                ONES_HASH, SIZE
+            ),
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_basic_download_uri() -> Result<(), Error> {
         let b = uri_builder(
@@ -0,0 +1,526 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::str::FromStr;
+
+use anyhow::Context;
+use bytes::Bytes;
+use gotham::state::{request_id, FromState, State};
+use gotham_derive::{StateData, StaticResponseExtender};
+use gotham_ext::{
+    body_ext::BodyExt,
+    error::HttpError,
+    response::{BytesBody, TryIntoResponse},
+};
+use http::header::HeaderMap;
+use hyper::{Body, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use lfs_locks::{CreateLockResult, LfsLock, MAX_PATH_LEN};
+use lfs_protocol::{
+    git_lfs_mime, Lock, LockOwner, RequestCreateLock, RequestUnlock, RequestVerifyLocks,
+    ResponseCreateLock, ResponseListLocks, ResponseLockConflict, ResponseUnlock,
+    ResponseVerifyLocks,
+};
+use mononoke_types::DateTime;
+use permission_checker::{MononokeIdentity, MononokeIdentitySet};
+
+use crate::errors::{ErrorKind, LfsServerContextErrorKind};
+use crate::lfs_server_context::RepositoryRequestContext;
+use crate::middleware::LfsMethod;
+
+// This module implements the Git-LFS file locking API:
+// https://github.com/git-lfs/git-lfs/blob/master/docs/api/locking.md
+
+const DEFAULT_LIMIT: u64 = 100;
+const MAX_LIMIT: u64 = 1000;
+// Locks are owned by the user making the request, if we know who they are.
+const OWNER_IDENTITY_TYPE: &str = "USER";
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct LocksParams {
+    repository: String,
+}
+
+// Locks are not per-ref, so the refspec parameter is ignored.
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct ListLocksQueryString {
+    path: Option<String>,
+    id: Option<String>,
+    cursor: Option<String>,
+    limit: Option<u64>,
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct UnlockParams {
+    repository: String,
+    id: String,
+}
+
+fn owner_identity(identities: &MononokeIdentitySet) -> Option<&MononokeIdentity> {
+    identities
+        .iter()
+        .find(|ident| ident.id_type() == OWNER_IDENTITY_TYPE)
+        .or_else(|| identities.iter().next())
+}
+
+fn is_owner(identities: &MononokeIdentitySet, lock: &LfsLock) -> bool {
+    matches!(
+        MononokeIdentity::from_str(&lock.owner),
+        Ok(owner) if identities.contains(&owner)
+    )
+}
+
+fn to_protocol_lock(lock: LfsLock) -> Lock {
+    let name = match MononokeIdentity::from_str(&lock.owner) {
+        Ok(owner) => owner.id_data().to_string(),
+        Err(_) => lock.owner,
+    };
+
+    Lock {
+        id: lock.id.to_string(),
+        path: lock.path,
+        locked_at: DateTime::from(lock.locked_at).as_chrono().to_rfc3339(),
+        owner: Some(LockOwner { name }),
+    }
+}
+
+async fn read_request<T: DeserializeOwned>(state: &mut State) -> Result<T, HttpError> {
+    let body = Body::take_from(state);
+    let headers = HeaderMap::try_borrow_from(state);
+
+    let body = body
+        .try_concat_body_opt(headers)
+        .map_err(HttpError::e400)?
+        .await
+        .context(ErrorKind::ClientCancelled)
+        .map_err(HttpError::e400)?;
+
+    serde_json::from_slice::<T>(&body)
+        .context(ErrorKind::InvalidLockRequest)
+        .map_err(HttpError::e400)
+}
+
+fn response<T: Serialize>(res: &T, status: StatusCode) -> Result<BytesBody<Bytes>, HttpError> {
+    let body = serde_json::to_vec(res).map_err(HttpError::e500)?;
+    Ok(BytesBody::new(Bytes::from(body), git_lfs_mime()).with_status(status))
+}
+
+/// Fetch a page of locks, along with the cursor for the next page if there
+/// is one. Cursors are the id of the next lock.
+async fn list_page(
+    ctx: &RepositoryRequestContext,
+    cursor: Option<String>,
+    limit: Option<u64>,
+) -> Result<(Vec<LfsLock>, Option<String>), HttpError> {
+    let min_id = match cursor {
+        Some(cursor) => cursor
+            .parse()
+            .context(ErrorKind::InvalidLockCursor(cursor))
+            .map_err(HttpError::e400)?,
+        None => 0,
+    };
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).max(1).min(MAX_LIMIT);
+
+    let mut locks = ctx
+        .locks
+        .list_locks(&ctx.ctx, min_id, limit + 1)
+        .await
+        .context(ErrorKind::LockStoreFailure)
+        .map_err(HttpError::e500)?;
+
+    let next_cursor = if locks.len() as u64 > limit {
+        locks.pop().map(|lock| lock.id.to_string())
+    } else {
+        None
+    };
+
+    Ok((locks, next_cursor))
+}
+
+async fn get_lock_by_id(
+    ctx: &RepositoryRequestContext,
+    id: &str,
+) -> Result<Option<LfsLock>, HttpError> {
+    // Ids we could not have handed out do not name any lock.
+    let id = match id.parse() {
+        Ok(id) => id,
+        Err(_) => return Ok(None),
+    };
+
+    ctx.locks
+        .get_lock(&ctx.ctx, id)
+        .await
+        .context(ErrorKind::LockStoreFailure)
+        .map_err(HttpError::e500)
+}
+
+async fn do_create_lock(
+    ctx: &RepositoryRequestContext,
+    request: RequestCreateLock,
+) -> Result<CreateLockResult, HttpError> {
+    let owner = owner_identity(&ctx.identities)
+        .ok_or(ErrorKind::LockOwnerUnknown)
+        .map_err(HttpError::e403)?;
+
+    if request.path.len() > MAX_PATH_LEN {
+        return Err(HttpError::e422(ErrorKind::LockPathTooLong(MAX_PATH_LEN)));
+    }
+
+    ctx.locks
+        .create_lock(&ctx.ctx, &request.path, &owner.to_string())
+        .await
+        .context(ErrorKind::LockStoreFailure)
+        .map_err(HttpError::e500)
+}
+
+async fn do_list_locks(
+    ctx: &RepositoryRequestContext,
+    query: ListLocksQueryString,
+) -> Result<ResponseListLocks, HttpError> {
+    let ListLocksQueryString {
+        path,
+        id,
+        cursor,
+        limit,
+    } = query;
+
+    let (locks, next_cursor) = if let Some(id) = id {
+        let lock = get_lock_by_id(ctx, &id).await?;
+        (lock.into_iter().collect(), None)
+    } else if let Some(path) = path {
+        let lock = ctx
+            .locks
+            .get_lock_by_path(&ctx.ctx, &path)
+            .await
+            .context(ErrorKind::LockStoreFailure)
+            .map_err(HttpError::e500)?;
+        (lock.into_iter().collect(), None)
+    } else {
+        list_page(ctx, cursor, limit).await?
+    };
+
+    Ok(ResponseListLocks {
+        locks: locks.into_iter().map(to_protocol_lock).collect(),
+        next_cursor,
+    })
+}
+
+async fn do_verify_locks(
+    ctx: &RepositoryRequestContext,
+    request: RequestVerifyLocks,
+) -> Result<ResponseVerifyLocks, HttpError> {
+    let (locks, next_cursor) = list_page(ctx, request.cursor, request.limit).await?;
+
+    let (ours, theirs): (Vec<_>, Vec<_>) = locks
+        .into_iter()
+        .partition(|lock| is_owner(&ctx.identities, lock));
+
+    Ok(ResponseVerifyLocks {
+        ours: ours.into_iter().map(to_protocol_lock).collect(),
+        theirs: theirs.into_iter().map(to_protocol_lock).collect(),
+        next_cursor,
+    })
+}
+
+async fn do_unlock(
+    ctx: &RepositoryRequestContext,
+    id: String,
+    request: RequestUnlock,
+) -> Result<ResponseUnlock, HttpError> {
+    let lock = get_lock_by_id(ctx, &id)
+        .await?
+        .ok_or_else(|| ErrorKind::LockDoesNotExist(id.clone()))
+        .map_err(HttpError::e404)?;
+
+    if !is_owner(&ctx.identities, &lock) {
+        // Only admins can break other people's locks, and only if they
+        // asked to.
+        if !request.force {
+            return Err(HttpError::e403(ErrorKind::LockNotOwned(id)));
+        }
+        let is_admin = ctx
+            .is_admin()
+            .await
+            .map_err(LfsServerContextErrorKind::PermissionCheckFailed)?;
+        if !is_admin {
+            return Err(HttpError::e403(ErrorKind::ForceUnlockNotPermitted(id)));
+        }
+    }
+
+    let deleted = ctx
+        .locks
+        .delete_lock(&ctx.ctx, lock.id)
+        .await
+        .context(ErrorKind::LockStoreFailure)
+        .map_err(HttpError::e500)?;
+    if !deleted {
+        return Err(HttpError::e404(ErrorKind::LockDoesNotExist(id)));
+    }
+
+    Ok(ResponseUnlock {
+        lock: to_protocol_lock(lock),
+    })
+}
+
+pub async fn create_lock(state: &mut State) -> Result<impl TryIntoResponse, HttpError> {
+    let LocksParams { repository } = state.take();
+
+    let ctx =
+        RepositoryRequestContext::instantiate(state, repository, LfsMethod::CreateLock).await?;
+    let request = read_request::<RequestCreateLock>(state).await?;
+
+    match do_create_lock(&ctx, request).await? {
+        CreateLockResult::Created(lock) => response(
+            &ResponseCreateLock {
+                lock: to_protocol_lock(lock),
+            },
+            StatusCode::CREATED,
+        ),
+        CreateLockResult::AlreadyLocked(lock) => response(
+            &ResponseLockConflict {
+                lock: to_protocol_lock(lock),
+                message: "already created lock".to_string(),
+                request_id: Some(request_id(state).to_string()),
+            },
+            StatusCode::CONFLICT,
+        ),
+    }
+}
+
+pub async fn list_locks(state: &mut State) -> Result<impl TryIntoResponse, HttpError> {
+    let LocksParams { repository } = state.take();
+    let query = ListLocksQueryString::take_from(state);
+
+    let ctx =
+        RepositoryRequestContext::instantiate(state, repository, LfsMethod::ListLocks).await?;
+
+    let res = do_list_locks(&ctx, query).await?;
+    response(&res, StatusCode::OK)
+}
+
+pub async fn verify_locks(state: &mut State) -> Result<impl TryIntoResponse, HttpError> {
+    let LocksParams { repository } = state.take();
+
+    let ctx =
+        RepositoryRequestContext::instantiate(state, repository, LfsMethod::VerifyLocks).await?;
+    let request = read_request::<RequestVerifyLocks>(state).await?;
+
+    let res = do_verify_locks(&ctx, request).await?;
+    response(&res, StatusCode::OK)
+}
+
+pub async fn unlock(state: &mut State) -> Result<impl TryIntoResponse, HttpError> {
+    let UnlockParams { repository, id } = state.take();
+
+    let ctx = RepositoryRequestContext::instantiate(state, repository, LfsMethod::Unlock).await?;
+    let request = read_request::<RequestUnlock>(state).await?;
+
+    let res = do_unlock(&ctx, id, request).await?;
+    response(&res, StatusCode::OK)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use anyhow::Error;
+    use fbinit::FacebookInit;
+    use maplit::btreeset;
+    use permission_checker::MembershipCheckerBuilder;
+    use std::sync::Arc;
+
+    fn ident(s: &str) -> Result<MononokeIdentity, Error> {
+        MononokeIdentity::from_str(s)
+    }
+
+    fn create_request(path: &str) -> RequestCreateLock {
+        RequestCreateLock {
+            path: path.to_string(),
+            r#ref: None,
+        }
+    }
+
+    fn unlock_request(force: bool) -> RequestUnlock {
+        RequestUnlock { force, r#ref: None }
+    }
+
+    // HttpError is not an Error, so unwrap it to use `?`
+    fn ok<T>(res: Result<T, HttpError>) -> Result<T, Error> {
+        res.map_err(|e| e.error)
+    }
+
+    fn created(res: CreateLockResult) -> Result<LfsLock, Error> {
+        match res {
+            CreateLockResult::Created(lock) => Ok(lock),
+            CreateLockResult::AlreadyLocked(_) => Err(Error::msg("Expected a new lock")),
+        }
+    }
+
+    #[test]
+    fn test_owner_identity() -> Result<(), Error> {
+        let idents = btreeset! { ident("MACHINE:devvm1")?, ident("USER:alice")? };
+        assert_eq!(owner_identity(&idents), Some(&ident("USER:alice")?));
+
+        let idents = btreeset! { ident("SERVICE_IDENTITY:builder")? };
+        assert_eq!(
+            owner_identity(&idents),
+            Some(&ident("SERVICE_IDENTITY:builder")?)
+        );
+
+        assert_eq!(owner_identity(&MononokeIdentitySet::new()), None);
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_create_and_verify(fb: FacebookInit) -> Result<(), Error> {
+        let alice = RepositoryRequestContext::test_builder(fb)?
+            .identities(btreeset! { ident("USER:alice")? })
+            .build()?;
+        let bob = RepositoryRequestContext::test_builder(fb)?
+            .locks(alice.locks.clone())
+            .identities(btreeset! { ident("USER:bob")? })
+            .build()?;
+
+        let lock = created(ok(do_create_lock(&alice, create_request("a.psd")).await)?)?;
+        assert_eq!(lock.owner, "USER:alice");
+        assert_eq!(to_protocol_lock(lock.clone()).owner.unwrap().name, "alice");
+
+        assert_eq!(
+            ok(do_create_lock(&bob, create_request("a.psd")).await)?,
+            CreateLockResult::AlreadyLocked(lock)
+        );
+        created(ok(do_create_lock(&bob, create_request("b.psd")).await)?)?;
+
+        let verify = RequestVerifyLocks {
+            cursor: None,
+            limit: None,
+            r#ref: None,
+        };
+        let res = ok(do_verify_locks(&alice, verify).await)?;
+        assert_eq!(res.ours.len(), 1);
+        assert_eq!(res.ours[0].path, "a.psd");
+        assert_eq!(res.theirs.len(), 1);
+        assert_eq!(res.theirs[0].path, "b.psd");
+        assert_eq!(res.next_cursor, None);
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_create_requires_identity(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = RepositoryRequestContext::test_builder(fb)?.build()?;
+        let err = do_create_lock(&ctx, create_request("a.psd"))
+            .await
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(err.status_code, StatusCode::FORBIDDEN);
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_create_path_too_long(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = RepositoryRequestContext::test_builder(fb)?
+            .identities(btreeset! { ident("USER:alice")? })
+            .build()?;
+        let err = do_create_lock(&ctx, create_request(&"a".repeat(MAX_PATH_LEN + 1)))
+            .await
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(err.status_code, StatusCode::UNPROCESSABLE_ENTITY);
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_list_pages(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = RepositoryRequestContext::test_builder(fb)?
+            .identities(btreeset! { ident("USER:alice")? })
+            .build()?;
+        for path in &["a", "b", "c"] {
+            created(ok(do_create_lock(&ctx, create_request(path)).await)?)?;
+        }
+
+        let query = |cursor: Option<String>| ListLocksQueryString {
+            path: None,
+            id: None,
+            cursor,
+            limit: Some(2),
+        };
+
+        let first = ok(do_list_locks(&ctx, query(None)).await)?;
+        assert_eq!(first.locks.len(), 2);
+        assert!(first.next_cursor.is_some());
+
+        let second = ok(do_list_locks(&ctx, query(first.next_cursor)).await)?;
+        assert_eq!(second.locks.len(), 1);
+        assert_eq!(second.locks[0].path, "c");
+        assert_eq!(second.next_cursor, None);
+
+        let by_path = do_list_locks(
+            &ctx,
+            ListLocksQueryString {
+                path: Some("b".to_string()),
+                ..query(None)
+            },
+        )
+        .await?;
+        assert_eq!(by_path.locks.len(), 1);
+        assert_eq!(by_path.locks[0].path, "b");
+        assert_eq!(by_path.next_cursor, None);
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_unlock(fb: FacebookInit) -> Result<(), Error> {
+        let alice = RepositoryRequestContext::test_builder(fb)?
+            .identities(btreeset! { ident("USER:alice")? })
+            .build()?;
+        let bob = RepositoryRequestContext::test_builder(fb)?
+            .locks(alice.locks.clone())
+            .identities(btreeset! { ident("USER:bob")? })
+            .build()?;
+        let admin = RepositoryRequestContext::test_builder(fb)?
+            .locks(alice.locks.clone())
+            .identities(btreeset! { ident("USER:root")? })
+            .admin_checker(Arc::from(MembershipCheckerBuilder::always_member()))
+            .build()?;
+
+        let lock = created(ok(do_create_lock(&alice, create_request("a.psd")).await)?)?;
+        let id = lock.id.to_string();
+
+        // Other users can't unlock, even by force
+        let err = do_unlock(&bob, id.clone(), unlock_request(false))
+            .await
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(err.status_code, StatusCode::FORBIDDEN);
+        let err = do_unlock(&bob, id.clone(), unlock_request(true))
+            .await
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(err.status_code, StatusCode::FORBIDDEN);
+
+        // Admins can, but only by force
+        let err = do_unlock(&admin, id.clone(), unlock_request(false))
+            .await
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(err.status_code, StatusCode::FORBIDDEN);
+        let res = ok(do_unlock(&admin, id.clone(), unlock_request(true)).await)?;
+        assert_eq!(res.lock.id, id);
+
+        let err = do_unlock(&alice, id, unlock_request(false))
+            .await
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(err.status_code, StatusCode::NOT_FOUND);
+
+        // Owners can unlock their own locks
+        let lock = created(ok(do_create_lock(&alice, create_request("a.psd")).await)?)?;
+        ok(do_unlock(&alice, lock.id.to_string(), unlock_request(false)).await)?;
+        Ok(())
+    }
+}
@@ -29,7 +29,10 @@ use gotham_ext::{
     serve,
 };
 use hyper::header::HeaderValue;
-use permission_checker::{ArcPermissionChecker, MononokeIdentitySet, PermissionCheckerBuilder};
+use permission_checker::{
+    ArcMembershipChecker, ArcPermissionChecker, MembershipCheckerBuilder, MononokeIdentitySet,
+    PermissionCheckerBuilder,
+};
 use slog::info;
 use std::collections::HashMap;
 use std::net::ToSocketAddrs;
@@ -43,6 +46,7 @@ use cmdlib::{
     helpers::serve_forever,
     monitoring::{start_fb303_server, AliveService},
 };
+use lfs_locks::{LfsLocks, SqlLfsLocksStore};
 use metaconfig_parser::RepoConfigs;
 use metaconfig_types::RepoConfig;
 use repo_factory::RepoFactory;
@@ -57,11 +61,13 @@ mod config;
 mod download;
 mod errors;
 mod lfs_server_context;
+mod locks;
 mod middleware;
 mod popularity;
 mod scuba;
 mod service;
 mod upload;
+mod upload_resumable;
 mod util;
 
 const ARG_SELF_URL: &str = "self-url";
@@ -262,6 +268,9 @@ fn main(fb: FacebookInit) -> Result<(), Error> {
     )?;
 
     let RepoConfigs { repos, common } = args::load_repo_configs(config_store, &matches)?;
+    if let Some(path) = &common.time_window_counters_path {
+        time_window_counter::init_shared_counters(&logger, path);
+    }
 
     let repo_factory = Arc::new(RepoFactory::new(matches.environment().clone(), &common));
 
@@ -275,6 +284,13 @@ fn main(fb: FacebookInit) -> Result<(), Error> {
                     .build(name.clone(), config.clone())
                     .map_err(Error::from);
 
+                let locks = async {
+                    repo_factory
+                        .sql_factory(&config.storage_config.metadata)
+                        .await?
+                        .open::<SqlLfsLocksStore>()
+                };
+
                 let hipster_acl = config.hipster_acl.as_ref();
                 let aclchecker = async {
                     if let Some(test_checker) = test_acl_checker {
@@ -297,17 +313,27 @@ fn main(fb: FacebookInit) -> Result<(), Error> {
                     }
                 };
 
-                let (repo, aclchecker) = try_join!(repo, aclchecker)?;
+                let (repo, aclchecker, locks) = try_join!(repo, aclchecker, locks)?;
+                let locks = LfsLocks::new(repo.get_repoid(), locks);
 
-                Result::<(String, (BlobRepo, ArcPermissionChecker, RepoConfig)), Error>::Ok((
-                    name,
-                    (repo, aclchecker, config),
-                ))
+                Result::<
+                    (
+                        String,
+                        (BlobRepo, ArcPermissionChecker, RepoConfig, LfsLocks),
+                    ),
+                    Error,
+                >::Ok((name, (repo, aclchecker, config, locks)))
             }
         });
 
     let repos: HashMap<_, _> = runtime.block_on(try_join_all(futs))?.into_iter().collect();
 
+    let admin_checker = ArcMembershipChecker::from(if disable_acl_checker {
+        MembershipCheckerBuilder::never_member()
+    } else {
+        runtime.block_on(MembershipCheckerBuilder::for_admin_group(fb))?
+    });
+
     let will_exit = Arc::new(AtomicBool::new(false));
 
     let config_handle = match matches.value_of(ARG_LIVE_CONFIG) {
@@ -324,6 +350,7 @@ fn main(fb: FacebookInit) -> Result<(), Error> {
 
     let ctx = LfsServerContext::new(
         repos,
+        admin_checker,
         server,
         matches.is_present(ARG_ALWAYS_WAIT_FOR_UPSTREAM),
         max_upload_size,
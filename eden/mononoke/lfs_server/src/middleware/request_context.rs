@@ -19,18 +19,28 @@ use slog::{o, Logger};
 #[derive(Copy, Clone)]
 pub enum LfsMethod {
     Upload,
+    UploadResumable,
     Download,
     DownloadSha256,
     Batch,
+    CreateLock,
+    ListLocks,
+    VerifyLocks,
+    Unlock,
 }
 
 impl fmt::Display for LfsMethod {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let name = match self {
             Self::Upload => "upload",
+            Self::UploadResumable => "upload_resumable",
             Self::Download => "download",
             Self::DownloadSha256 => "download_sha256",
             Self::Batch => "batch",
+            Self::CreateLock => "create_lock",
+            Self::ListLocks => "list_locks",
+            Self::VerifyLocks => "verify_locks",
+            Self::Unlock => "unlock",
         };
         write!(f, "{}", name)
     }
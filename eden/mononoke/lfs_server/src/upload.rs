@@ -139,7 +139,7 @@ fn find_actions(
                 } => Ok(actions),
                 _ => Err(ErrorKind::UpstreamInvalidObject(o).into()),
             }),
-        Transfer::Unknown => Err(ErrorKind::UpstreamInvalidTransfer.into()),
+        Transfer::Tus | Transfer::Unknown => Err(ErrorKind::UpstreamInvalidTransfer.into()),
     }
 }
 
@@ -303,7 +303,7 @@ where
     res.map(|_| ())
 }
 
-async fn sync_internal_and_upstream(
+pub(crate) async fn sync_internal_and_upstream(
     ctx: &RepositoryRequestContext,
     oid: Sha256,
     size: u64,
@@ -0,0 +1,669 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Error};
+use bytes::{Bytes, BytesMut};
+use cloned::cloned;
+use futures::{
+    future,
+    stream::{self, Stream, StreamExt, TryStreamExt},
+};
+use gotham::state::{FromState, State};
+use gotham_derive::{StateData, StaticResponseExtender};
+use gotham_ext::{
+    error::HttpError,
+    middleware::{HttpScubaKey, ScubaMiddlewareState},
+    response::{PendingResponseMeta, TryIntoResponse},
+};
+use http::header::CACHE_CONTROL;
+use hyper::{Body, Response, StatusCode};
+use serde::Deserialize;
+use stats::prelude::*;
+
+use blobstore::{Blobstore, BlobstoreBytes, Loadable, LoadableError};
+use filestore::{self, Alias, FetchKey, StoreRequest};
+use mononoke_types::{
+    content_chunk::new_blob_and_pointer, hash::Sha256, ContentChunk, ContentChunkId, MononokeId,
+};
+
+use crate::errors::ErrorKind;
+use crate::lfs_server_context::RepositoryRequestContext;
+use crate::middleware::LfsMethod;
+use crate::upload::sync_internal_and_upstream;
+use crate::util::read_header_value;
+
+// This module implements resumable uploads, as used by the Git LFS "tus" transfer adapter: the
+// client asks for the offset to upload from with a HEAD request, then sends the rest of the
+// object from there with a PATCH request (https://tus.io/protocols/resumable-upload.html).
+//
+// Uploads are received in parts of the Filestore's chunk size, and each part is stored as a
+// content chunk as soon as it is complete, so that if the upload is interrupted, the client can
+// resume it from the last complete part. Once the last part is received, the object is stored in
+// the Filestore from those parts, which verifies that it matches the object's SHA-256. This
+// reads every part back and puts it again, so the request that completes an upload costs as much
+// as a non-resumable upload of the whole object. Since the parts are the Filestore's chunks, the
+// puts are to the same keys, and don't take up more space.
+//
+// The records of an upload's progress are only kept for a limited time, after which the upload
+// has to start over. The Filestore chunks of uploads that were never completed are not
+// referenced by anything, and neither are the records, so both are removed by blobstore GC.
+
+define_stats! {
+    prefix ="mononoke.lfs.upload_resumable";
+    parts_received: timeseries(Rate, Sum),
+    uploads_completed: timeseries(Rate, Sum),
+    uploads_abandoned: timeseries(Rate, Sum),
+}
+
+const TUS_RESUMABLE: &str = "Tus-Resumable";
+const TUS_VERSION: &str = "1.0.0";
+const UPLOAD_OFFSET: &str = "Upload-Offset";
+
+// Part size used for repositories whose Filestore does not chunk.
+const DEFAULT_PART_SIZE: u64 = 4 * 1024 * 1024;
+
+// Small buffer for fetching parts when assembling the object.
+const BUFFER_SIZE: usize = 5;
+
+// Uploads are recorded per period of this length, and can be resumed during the period they were
+// started in and the next one.
+const UPLOAD_PERIOD_SECS: u64 = 24 * 60 * 60;
+
+// Number of times an upload can fail verification in a period before we stop accepting it.
+const MAX_ATTEMPTS: u64 = 10;
+
+// NOTE: We don't deserialize things beyond a String form, in order to report errors in our
+// controller, not in routing.
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct UploadResumableParams {
+    repository: String,
+    oid: String,
+    size: String,
+}
+
+/// The response to both HEAD and PATCH requests: the offset the client should upload from.
+struct UploadOffset {
+    status: StatusCode,
+    offset: u64,
+}
+
+impl TryIntoResponse for UploadOffset {
+    fn try_into_response(self, state: &mut State) -> Result<Response<Body>, Error> {
+        state.put(PendingResponseMeta::immediate(0));
+
+        Response::builder()
+            .status(self.status)
+            .header(TUS_RESUMABLE, TUS_VERSION)
+            .header(UPLOAD_OFFSET, self.offset)
+            .header(CACHE_CONTROL, "no-store")
+            .body(Body::empty())
+            .map_err(Error::from)
+    }
+}
+
+/// The parts of an upload received so far. Blobstores may refuse to overwrite keys, so all the
+/// keys used here are written once: each part is recorded under its own key, and an upload that
+/// cannot be completed from its parts is abandoned by starting over with a new attempt. Keys
+/// include the period the upload was started in, so that expired uploads start over too.
+struct UploadProgress {
+    prefix: String,
+    part_size: u64,
+    size: u64,
+    attempt: u64,
+    parts: u64,
+}
+
+impl UploadProgress {
+    async fn load(ctx: &RepositoryRequestContext, oid: Sha256, size: u64) -> Result<Self, Error> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let period = now / UPLOAD_PERIOD_SECS;
+
+        // Carry on with an upload that was started in the previous period, so that uploads don't
+        // lose their progress just because a period ended.
+        let previous = Self::load_in_period(ctx, oid, size, period.saturating_sub(1)).await?;
+        if previous.parts > 0 && !previous.is_exhausted() {
+            return Ok(previous);
+        }
+
+        Self::load_in_period(ctx, oid, size, period).await
+    }
+
+    async fn load_in_period(
+        ctx: &RepositoryRequestContext,
+        oid: Sha256,
+        size: u64,
+        period: u64,
+    ) -> Result<Self, Error> {
+        let part_size = ctx
+            .repo
+            .filestore_config()
+            .chunk_size
+            .unwrap_or(DEFAULT_PART_SIZE);
+
+        let mut progress = Self {
+            prefix: format!(
+                "lfs_upload.sha256.{}.{}.{}.{}",
+                oid, size, part_size, period
+            ),
+            part_size,
+            size,
+            attempt: 0,
+            parts: 0,
+        };
+
+        while progress.attempt < MAX_ATTEMPTS && is_present(ctx, &progress.abandoned_key()).await? {
+            progress.attempt += 1;
+        }
+
+        if !progress.is_exhausted() {
+            progress.parts = progress.count_parts(ctx).await?;
+        }
+
+        Ok(progress)
+    }
+
+    /// Whether the upload was abandoned too many times to accept another attempt.
+    fn is_exhausted(&self) -> bool {
+        self.attempt >= MAX_ATTEMPTS
+    }
+
+    fn part_key(&self, part: u64) -> String {
+        format!("{}.{}.part.{}", self.prefix, self.attempt, part)
+    }
+
+    fn abandoned_key(&self) -> String {
+        format!("{}.{}.abandoned", self.prefix, self.attempt)
+    }
+
+    /// The number of parts that are recorded, which excludes the last part of the object: once
+    /// that is received, the object is stored instead.
+    fn max_parts(&self) -> u64 {
+        self.size.saturating_sub(1) / self.part_size
+    }
+
+    fn offset(&self) -> u64 {
+        self.parts * self.part_size
+    }
+
+    async fn count_parts(&self, ctx: &RepositoryRequestContext) -> Result<u64, Error> {
+        // Parts are recorded in order, so rather than checking for each of them, search for the
+        // first one that is missing. The number of parts is in lo..hi.
+        let mut lo = 0;
+        let mut hi = 1;
+
+        while hi <= self.max_parts() && is_present(ctx, &self.part_key(hi - 1)).await? {
+            lo = hi;
+            hi = (hi * 2).min(self.max_parts() + 1);
+        }
+
+        while hi - lo > 1 {
+            let mid = lo + (hi - lo) / 2;
+            if is_present(ctx, &self.part_key(mid - 1)).await? {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+
+        Ok(lo)
+    }
+
+    async fn add_part(&mut self, ctx: &RepositoryRequestContext, data: Bytes) -> Result<(), Error> {
+        let (blob, pointer) = new_blob_and_pointer(data);
+
+        ctx.repo
+            .blobstore()
+            .put(&ctx.ctx, blob.id().blobstore_key(), blob.into())
+            .await?;
+
+        let record = BlobstoreBytes::from_bytes(Bytes::copy_from_slice(
+            pointer.chunk_id().blake2().as_ref(),
+        ));
+
+        ctx.repo
+            .blobstore()
+            .put(&ctx.ctx, self.part_key(self.parts), record)
+            .await?;
+
+        self.parts += 1;
+        STATS::parts_received.add_value(1);
+
+        Ok(())
+    }
+
+    /// The data of the recorded parts.
+    fn data(
+        &self,
+        ctx: &RepositoryRequestContext,
+    ) -> impl Stream<Item = Result<Bytes, Error>> + Send + 'static {
+        let blobstore = ctx.repo.get_blobstore();
+        let ctx = ctx.ctx.clone();
+        let keys = (0..self.parts)
+            .map(|part| (part, self.part_key(part)))
+            .collect::<Vec<_>>();
+
+        stream::iter(keys)
+            .map(move |(part, key)| {
+                cloned!(ctx, blobstore);
+                async move {
+                    let record = blobstore
+                        .get(&ctx, &key)
+                        .await?
+                        .ok_or(ErrorKind::UploadPartMissing(part))?;
+
+                    let chunk_id = ContentChunkId::from_bytes(record.into_raw_bytes())?;
+
+                    let chunk = chunk_id
+                        .load(&ctx, &blobstore)
+                        .await
+                        .map_err(|err| match err {
+                            LoadableError::Error(err) => err,
+                            LoadableError::Missing(_) => ErrorKind::UploadPartMissing(part).into(),
+                        })?;
+
+                    Result::<_, Error>::Ok(ContentChunk::into_bytes(chunk))
+                }
+            })
+            .buffered(BUFFER_SIZE)
+    }
+
+    async fn abandon(self, ctx: &RepositoryRequestContext) -> Result<(), Error> {
+        STATS::uploads_abandoned.add_value(1);
+
+        ctx.repo
+            .blobstore()
+            .put(&ctx.ctx, self.abandoned_key(), BlobstoreBytes::empty())
+            .await
+    }
+}
+
+async fn is_present(ctx: &RepositoryRequestContext, key: &str) -> Result<bool, Error> {
+    Ok(ctx
+        .repo
+        .blobstore()
+        .is_present(&ctx.ctx, key)
+        .await?
+        .assume_not_found_if_unsure())
+}
+
+async fn is_stored(ctx: &RepositoryRequestContext, oid: Sha256) -> Result<bool, Error> {
+    filestore::exists(
+        ctx.repo.blobstore(),
+        &ctx.ctx,
+        &FetchKey::Aliased(Alias::Sha256(oid)),
+    )
+    .await
+}
+
+/// Whether storing an upload failed because of its parts (as opposed to e.g. our storage being
+/// unavailable), in which case it cannot be completed, and must be started over.
+fn is_invalid_upload(err: &Error) -> bool {
+    match err.downcast_ref::<filestore::ErrorKind>() {
+        Some(filestore::ErrorKind::InvalidSize(..))
+        | Some(filestore::ErrorKind::InvalidSha256(..)) => true,
+        _ => matches!(
+            err.downcast_ref::<ErrorKind>(),
+            Some(ErrorKind::UploadPartMissing(..))
+        ),
+    }
+}
+
+async fn upload_offset(
+    ctx: &RepositoryRequestContext,
+    oid: Sha256,
+    size: u64,
+    scuba: &mut Option<&mut ScubaMiddlewareState>,
+) -> Result<u64, Error> {
+    if is_stored(ctx, oid).await? {
+        // There is nothing left to upload here, but the client expects the object to be
+        // available upstream too once it's uploaded.
+        sync_internal_and_upstream(ctx, oid, size, scuba).await?;
+        return Ok(size);
+    }
+
+    let progress = UploadProgress::load(ctx, oid, size).await?;
+    Ok(progress.offset())
+}
+
+async fn upload_from_offset<S>(
+    ctx: &RepositoryRequestContext,
+    oid: Sha256,
+    size: u64,
+    offset: u64,
+    mut body: S,
+    scuba: &mut Option<&mut ScubaMiddlewareState>,
+) -> Result<u64, HttpError>
+where
+    S: Stream<Item = Result<Bytes, Error>> + Unpin,
+{
+    if is_stored(ctx, oid).await.map_err(HttpError::e500)? {
+        // There is nothing left to upload. The client finds out with a HEAD request, which also
+        // syncs the object upstream.
+        return Err(HttpError::e409(ErrorKind::UploadOffsetMismatch(
+            offset, size,
+        )));
+    }
+
+    let mut progress = UploadProgress::load(ctx, oid, size)
+        .await
+        .map_err(HttpError::e500)?;
+
+    if progress.is_exhausted() {
+        return Err(HttpError::e429(ErrorKind::UploadAttemptsExhausted(
+            MAX_ATTEMPTS,
+        )));
+    }
+
+    if offset != progress.offset() {
+        return Err(HttpError::e409(ErrorKind::UploadOffsetMismatch(
+            offset,
+            progress.offset(),
+        )));
+    }
+
+    let mut received: usize = 0;
+    let mut buf = BytesMut::new();
+
+    let res = async {
+        loop {
+            while progress.parts < progress.max_parts() && buf.len() as u64 >= progress.part_size {
+                let data = buf.split_to(progress.part_size as usize).freeze();
+                progress
+                    .add_part(ctx, data)
+                    .await
+                    .map_err(HttpError::e500)?;
+            }
+
+            match body
+                .try_next()
+                .await
+                .context(ErrorKind::ClientCancelled)
+                .map_err(HttpError::e400)?
+            {
+                Some(data) => {
+                    received += data.len();
+                    buf.extend_from_slice(&data);
+                }
+                None => break,
+            }
+
+            if progress.offset() + buf.len() as u64 > size {
+                return Err(HttpError::e400(ErrorKind::UploadExceedsSize(size)));
+            }
+        }
+
+        Result::<_, HttpError>::Ok(())
+    }
+    .await;
+
+    ScubaMiddlewareState::maybe_add(scuba, HttpScubaKey::RequestBytesReceived, received);
+    res?;
+
+    if progress.offset() + (buf.len() as u64) < size {
+        // The client stopped early. Whatever it sent past the last complete part is discarded,
+        // and it can resume from there.
+        return Ok(progress.offset());
+    }
+
+    let data = progress
+        .data(ctx)
+        .chain(stream::once(future::ready(Ok(buf.freeze()))));
+
+    let res = filestore::store(
+        ctx.repo.blobstore(),
+        ctx.repo.filestore_config(),
+        &ctx.ctx,
+        &StoreRequest::with_sha256(size, oid),
+        data,
+    )
+    .await;
+
+    if let Err(err) = res {
+        if is_invalid_upload(&err) {
+            progress.abandon(ctx).await.map_err(HttpError::e500)?;
+            return Err(HttpError::e400(
+                err.context(ErrorKind::UploadVerificationFailed),
+            ));
+        }
+        return Err(HttpError::e500(
+            err.context(ErrorKind::FilestoreWriteFailure),
+        ));
+    }
+
+    STATS::uploads_completed.add_value(1);
+
+    sync_internal_and_upstream(ctx, oid, size, scuba)
+        .await
+        .map_err(HttpError::e500)?;
+
+    Ok(size)
+}
+
+async fn instantiate(
+    state: &mut State,
+) -> Result<(RepositoryRequestContext, Sha256, u64), HttpError> {
+    let UploadResumableParams {
+        repository,
+        oid,
+        size,
+    } = state.take();
+
+    let ctx = RepositoryRequestContext::instantiate(state, repository, LfsMethod::UploadResumable)
+        .await?;
+
+    let oid = Sha256::from_str(&oid).map_err(HttpError::e400)?;
+    let size = size.parse().map_err(Error::from).map_err(HttpError::e400)?;
+
+    if let Some(max_upload_size) = ctx.max_upload_size() {
+        if size > max_upload_size {
+            Err(HttpError::e400(ErrorKind::UploadTooLarge(
+                size,
+                max_upload_size,
+            )))?;
+        }
+    }
+
+    Ok((ctx, oid, size))
+}
+
+pub async fn upload_resumable_offset(state: &mut State) -> Result<impl TryIntoResponse, HttpError> {
+    let (ctx, oid, size) = instantiate(state).await?;
+
+    let mut scuba = state.try_borrow_mut::<ScubaMiddlewareState>();
+    let offset = upload_offset(&ctx, oid, size, &mut scuba)
+        .await
+        .map_err(HttpError::e500)?;
+
+    Ok(UploadOffset {
+        status: StatusCode::OK,
+        offset,
+    })
+}
+
+pub async fn upload_resumable(state: &mut State) -> Result<impl TryIntoResponse, HttpError> {
+    let (ctx, oid, size) = instantiate(state).await?;
+
+    let offset = read_header_value(state, UPLOAD_OFFSET)
+        .ok_or(ErrorKind::UploadOffsetMissing)
+        .map_err(HttpError::e400)?
+        .map_err(HttpError::e400)?;
+
+    let body = Body::take_from(state).map_err(Error::from);
+    let mut scuba = state.try_borrow_mut::<ScubaMiddlewareState>();
+    let offset = upload_from_offset(&ctx, oid, size, offset, body, &mut scuba).await?;
+
+    Ok(UploadOffset {
+        status: StatusCode::NO_CONTENT,
+        offset,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fbinit::FacebookInit;
+    use metaconfig_types::FilestoreParams;
+    use test_repo_factory::TestRepoFactory;
+
+    // SHA-256 of "foobarbaz".
+    const FOOBARBAZ_SHA256: &str =
+        "97df3588b5a3f24babc3851b372f0ba71a9dcdded43b14b9d06961bfc1707d9d";
+
+    fn test_context(fb: FacebookInit) -> Result<RepositoryRequestContext, Error> {
+        let repo = TestRepoFactory::new()?
+            .with_config_override(|config| {
+                config.filestore = Some(FilestoreParams {
+                    chunk_size: 4,
+                    concurrency: 1,
+                });
+            })
+            .build()?;
+
+        RepositoryRequestContext::test_builder(fb)?
+            .repo(repo)
+            .upstream_uri(None)
+            .build()
+    }
+
+    fn body(data: Vec<&'static str>) -> impl Stream<Item = Result<Bytes, Error>> + Unpin {
+        stream::iter(data.into_iter().map(|d| Ok(Bytes::from(d))))
+    }
+
+    async fn fetch(ctx: &RepositoryRequestContext, oid: Sha256) -> Result<Option<Bytes>, Error> {
+        filestore::fetch_concat_opt(
+            ctx.repo.blobstore(),
+            &ctx.ctx,
+            &FetchKey::Aliased(Alias::Sha256(oid)),
+        )
+        .await
+    }
+
+    #[fbinit::test]
+    async fn test_resume_upload(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = test_context(fb)?;
+        let oid = Sha256::from_str(FOOBARBAZ_SHA256)?;
+        let size = 9;
+
+        assert_eq!(upload_offset(&ctx, oid, size, &mut None).await?, 0);
+
+        // Only complete parts are kept.
+        let offset = upload_from_offset(&ctx, oid, size, 0, body(vec!["foo", "bar"]), &mut None)
+            .await
+            .map_err(|e| e.error)?;
+        assert_eq!(offset, 4);
+        assert_eq!(upload_offset(&ctx, oid, size, &mut None).await?, 4);
+        assert_eq!(fetch(&ctx, oid).await?, None);
+
+        let err = upload_from_offset(&ctx, oid, size, 0, body(vec!["foobarbaz"]), &mut None)
+            .await
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(err.status_code, StatusCode::CONFLICT);
+
+        let offset = upload_from_offset(&ctx, oid, size, 4, body(vec!["arb", "az"]), &mut None)
+            .await
+            .map_err(|e| e.error)?;
+        assert_eq!(offset, 9);
+        assert_eq!(upload_offset(&ctx, oid, size, &mut None).await?, 9);
+        assert_eq!(fetch(&ctx, oid).await?, Some(Bytes::from("foobarbaz")));
+
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_resume_upload_invalid(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = test_context(fb)?;
+        let oid = Sha256::from_str(FOOBARBAZ_SHA256)?;
+        let size = 9;
+
+        let offset = upload_from_offset(&ctx, oid, size, 0, body(vec!["quxq"]), &mut None)
+            .await
+            .map_err(|e| e.error)?;
+        assert_eq!(offset, 4);
+
+        let err = upload_from_offset(&ctx, oid, size, 4, body(vec!["arbaz"]), &mut None)
+            .await
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(err.status_code, StatusCode::BAD_REQUEST);
+
+        // The upload has to start over, and can then succeed.
+        assert_eq!(upload_offset(&ctx, oid, size, &mut None).await?, 0);
+
+        // Sending too much is rejected, but the parts received before that are kept.
+        let err = upload_from_offset(&ctx, oid, size, 0, body(vec!["foobarbazz"]), &mut None)
+            .await
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(err.status_code, StatusCode::BAD_REQUEST);
+        assert_eq!(upload_offset(&ctx, oid, size, &mut None).await?, 8);
+
+        let offset = upload_from_offset(&ctx, oid, size, 8, body(vec!["z"]), &mut None)
+            .await
+            .map_err(|e| e.error)?;
+        assert_eq!(offset, 9);
+        assert_eq!(fetch(&ctx, oid).await?, Some(Bytes::from("foobarbaz")));
+
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_resume_upload_attempts_exhausted(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = test_context(fb)?;
+        let oid = Sha256::from_str(FOOBARBAZ_SHA256)?;
+        let size = 9;
+
+        for _ in 0..MAX_ATTEMPTS {
+            let err = upload_from_offset(&ctx, oid, size, 0, body(vec!["quxquxqux"]), &mut None)
+                .await
+                .map(|_| ())
+                .unwrap_err();
+            assert_eq!(err.status_code, StatusCode::BAD_REQUEST);
+        }
+
+        let err = upload_from_offset(&ctx, oid, size, 0, body(vec!["foobarbaz"]), &mut None)
+            .await
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(err.status_code, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(fetch(&ctx, oid).await?, None);
+
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_resume_upload_from_previous_period(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = test_context(fb)?;
+        let oid = Sha256::from_str(FOOBARBAZ_SHA256)?;
+        let size = 9;
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let period = now / UPLOAD_PERIOD_SECS;
+
+        // An upload started in the previous period can be resumed.
+        let mut progress = UploadProgress::load_in_period(&ctx, oid, size, period - 1).await?;
+        progress.add_part(&ctx, Bytes::from("foob")).await?;
+        assert_eq!(upload_offset(&ctx, oid, size, &mut None).await?, 4);
+
+        // One started before that has expired.
+        let mut progress = UploadProgress::load_in_period(&ctx, oid, size, period - 2).await?;
+        progress.add_part(&ctx, Bytes::from("foob")).await?;
+        progress.add_part(&ctx, Bytes::from("arba")).await?;
+        assert_eq!(upload_offset(&ctx, oid, size, &mut None).await?, 4);
+
+        let offset = upload_from_offset(&ctx, oid, size, 4, body(vec!["arbaz"]), &mut None)
+            .await
+            .map_err(|e| e.error)?;
+        assert_eq!(offset, 9);
+        assert_eq!(fetch(&ctx, oid).await?, Some(Bytes::from("foobarbaz")));
+
+        Ok(())
+    }
+}
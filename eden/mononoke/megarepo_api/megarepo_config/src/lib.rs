@@ -34,9 +34,8 @@ pub use test_impl::TestMononokeMegarepoConfigs;
 #[derive(Clone, PartialEq, Eq)]
 pub enum MononokeMegarepoConfigsOptions {
     /// Create prod-style `MononokeMegarepoConfigs` implementation
-    /// (requires fb infra to function correctly, although will
-    /// successfully instantiate with `unimplemented!` methods
-    /// when built outside of fbcode)
+    /// (outside of fbcode, this keeps configs in the directory named by
+    /// `megarepo_configs_path` in the common config)
     Prod,
     /// Create a config implementation that writes JSON to disk at the
     /// given path instead of calling FB infra.
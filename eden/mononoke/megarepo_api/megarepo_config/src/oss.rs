@@ -5,40 +5,152 @@
  * GNU General Public License version 2.
  */
 
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::{anyhow, Context, Error};
 use async_trait::async_trait;
 use cached_config::ConfigStore;
 use context::CoreContext;
 use fbinit::FacebookInit;
+use fbthrift::simplejson_protocol;
 use megarepo_configs::types::{SyncConfigVersion, SyncTargetConfig, Target};
 use megarepo_error::MegarepoError;
-use slog::{warn, Logger};
-use std::path::PathBuf;
+use sha1::{Digest, Sha1};
+use slog::{info, warn, Logger};
+use tokio::io::AsyncWriteExt;
 
+use crate::verification::verify_config;
 use crate::MononokeMegarepoConfigs;
 
-/// This struct is a no-op stub, to allow for successful builds
-/// outside of fbcode. While it is allowed to instantiate this struct,
-/// (to allow creating of dependent structs that instantiate
-/// MononokeMegarepoConfigs as part of their setup) it is illegal
-/// to call any of the methods.
-pub struct CfgrMononokeMegarepoConfigs;
+/// Directory to keep configs in, relative to the local configerator path
+/// used by integration tests.
+const INTEGRATION_TEST_SUBDIR: &str = "megarepo_configs";
+
+const CONFIG_EXTENSION: &str = "json";
+
+/// Megarepo configs kept as files in a directory, which may be shared by
+/// any number of Mononoke processes.
+///
+/// Each `SyncTargetConfig` is stored as thrift JSON in
+/// `<repo id>/<bookmark hash>/<version hash>.json`, where the hashes are the
+/// hex SHA-1 of the names. This makes a valid file name of a bookmark or
+/// version of any length, and the version is read back from the config
+/// itself. Versions are immutable: a new version is written to a temporary
+/// file, which is then hard-linked into place. Linking fails if the version
+/// already exists, so concurrent writers of the same version can't overwrite
+/// each other, and readers never see a partially written config.
+pub struct CfgrMononokeMegarepoConfigs {
+    path: Option<PathBuf>,
+}
 
 impl CfgrMononokeMegarepoConfigs {
     pub fn new(
         _fb: FacebookInit,
         logger: &Logger,
         _config_store: ConfigStore,
-        _test_write_path: Option<PathBuf>,
+        test_write_path: Option<PathBuf>,
     ) -> Result<Self, MegarepoError> {
-        warn!(
+        Self::with_path(
             logger,
-            "CfgrMononokeMegarepoConfigs is not implemented for non-fbcode builds"
-        );
-        // While this struct should never be used in practice outside of fbcode, I think
-        // it shouldn't be an error to simply instantiate it in non-fbcode
-        // builds, so let's return `Ok` here.
-        Ok(Self)
+            test_write_path.map(|path| path.join(INTEGRATION_TEST_SUBDIR)),
+        )
+    }
+
+    /// Keep configs in `path`, which comes from `megarepo_configs_path` in
+    /// the common config for prod-style configs.
+    pub fn with_path(logger: &Logger, path: Option<PathBuf>) -> Result<Self, MegarepoError> {
+        match &path {
+            Some(path) => {
+                fs::create_dir_all(path)
+                    .with_context(|| format!("Failed to create {}", path.display()))
+                    .map_err(MegarepoError::internal)?;
+                info!(logger, "Keeping megarepo configs in {}", path.display());
+            }
+            // It shouldn't be an error to simply instantiate this without
+            // a path, as many users of it never touch megarepo configs, so
+            // only fail when configs are actually used.
+            None => warn!(
+                logger,
+                "megarepo_configs_path is not set, megarepo configs are unavailable"
+            ),
+        }
+        Ok(Self { path })
+    }
+
+    fn path(&self) -> Result<&Path, MegarepoError> {
+        self.path.as_deref().ok_or_else(|| {
+            MegarepoError::internal(anyhow!(
+                "Megarepo configs are unavailable: megarepo_configs_path is not set"
+            ))
+        })
+    }
+
+    fn target_dir(&self, target: &Target) -> Result<PathBuf, MegarepoError> {
+        Ok(self
+            .path()?
+            .join(target.repo_id.to_string())
+            .join(hash_name(&target.bookmark)))
+    }
+
+    fn config_path(
+        &self,
+        target: &Target,
+        version: &SyncConfigVersion,
+    ) -> Result<PathBuf, MegarepoError> {
+        let name = format!("{}.{}", hash_name(version), CONFIG_EXTENSION);
+        Ok(self.target_dir(target)?.join(name))
+    }
+}
+
+fn read_config(path: &Path) -> Result<SyncTargetConfig, Error> {
+    let contents = fs::read(path)?;
+    simplejson_protocol::deserialize(contents)
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// A name for a temporary file that no other writer, in this process or
+/// another, will use.
+fn temp_file_name() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    format!(
+        ".tmp.{}.{}",
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// A file name for a bookmark or version name, which may be too long or
+/// contain characters that aren't valid in a file name.
+fn hash_name(name: &str) -> String {
+    format!("{:x}", Sha1::digest(name.as_bytes()))
+}
+
+/// Write `contents` to `path`, unless it already exists. Returns whether the
+/// file was written.
+async fn write_new_file(path: &Path, contents: &[u8]) -> Result<bool, Error> {
+    let dir = path
+        .parent()
+        .ok_or_else(|| anyhow!("No parent directory"))?;
+    tokio::fs::create_dir_all(dir).await?;
+
+    let temp_path = dir.join(temp_file_name());
+    let res = async {
+        let mut file = tokio::fs::File::create(&temp_path).await?;
+        file.write_all(contents).await?;
+        file.sync_all().await?;
+        match tokio::fs::hard_link(&temp_path, path).await {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => Ok(false),
+            Err(e) => Err(e.into()),
+        }
     }
+    .await;
+    let _ = tokio::fs::remove_file(&temp_path).await;
+    res
 }
 
 #[async_trait]
@@ -46,25 +158,171 @@ impl MononokeMegarepoConfigs for CfgrMononokeMegarepoConfigs {
     fn get_target_config_versions(
         &self,
         _ctx: CoreContext,
-        _target: Target,
+        target: Target,
     ) -> Result<Vec<SyncConfigVersion>, MegarepoError> {
-        unimplemented!("OSS CfgrMononokeMegarepoConfigs::get_target_config_versions")
+        let dir = self.target_dir(&target)?;
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(MegarepoError::internal(e)),
+        };
+
+        let mut versions = vec![];
+        for entry in entries {
+            let path = entry.map_err(MegarepoError::internal)?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(CONFIG_EXTENSION) {
+                continue;
+            }
+            let config = read_config(&path).map_err(MegarepoError::internal)?;
+            versions.push(config.version);
+        }
+        versions.sort();
+        Ok(versions)
     }
 
     fn get_config_by_version(
         &self,
         _ctx: CoreContext,
-        _target: Target,
-        _version: SyncConfigVersion,
+        target: Target,
+        version: SyncConfigVersion,
     ) -> Result<SyncTargetConfig, MegarepoError> {
-        unimplemented!("OSS CfgrMononokeMegarepoConfigs::get_config_by_version")
+        let path = self.config_path(&target, &version)?;
+        if !path.exists() {
+            return Err(MegarepoError::request(anyhow!(
+                "{:?} not found",
+                (target, version)
+            )));
+        }
+        read_config(&path).map_err(MegarepoError::internal)
     }
 
     async fn add_config_version(
         &self,
-        _ctx: CoreContext,
-        _config: SyncTargetConfig,
+        ctx: CoreContext,
+        config: SyncTargetConfig,
     ) -> Result<(), MegarepoError> {
-        unimplemented!("OSS CfgrMononokeMegarepoConfigs::add_config_version")
+        verify_config(&ctx, &config).map_err(MegarepoError::request)?;
+        if config.version.is_empty() {
+            return Err(MegarepoError::request(anyhow!(
+                "Config version must not be empty"
+            )));
+        }
+        let path = self.config_path(&config.target, &config.version)?;
+        let contents = simplejson_protocol::serialize(&config);
+
+        let written = write_new_file(&path, &contents)
+            .await
+            .with_context(|| format!("Failed to write {}", path.display()))
+            .map_err(MegarepoError::internal)?;
+        if !written {
+            return Err(MegarepoError::request(anyhow!(
+                "Config version {} already exists for {:?}",
+                config.version,
+                config.target
+            )));
+        }
+
+        info!(
+            ctx.logger(),
+            "Added megarepo config version {} for {:?}", config.version, config.target
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use megarepo_configs::types::{Source, SourceMappingRules, SourceRevision};
+    use tempdir::TempDir;
+
+    fn config(bookmark: &str, version: &str, source_name: &str) -> SyncTargetConfig {
+        SyncTargetConfig {
+            target: Target {
+                repo_id: 1,
+                bookmark: bookmark.to_string(),
+            },
+            version: version.to_string(),
+            sources: vec![Source {
+                name: "name".to_string(),
+                source_name: source_name.to_string(),
+                revision: SourceRevision::bookmark("main".to_string()),
+                repo_id: 2,
+                mapping: SourceMappingRules {
+                    default_prefix: "prefix".to_string(),
+                    ..Default::default()
+                },
+            }],
+        }
+    }
+
+    #[fbinit::test]
+    async fn test_config_versions(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let dir = TempDir::new("megarepo_configs")?;
+        let configs = CfgrMononokeMegarepoConfigs {
+            path: Some(dir.path().to_path_buf()),
+        };
+        let target = config("megarepo/main", "", "").target;
+
+        assert!(configs
+            .get_target_config_versions(ctx.clone(), target.clone())?
+            .is_empty());
+
+        let v1 = config("megarepo/main", "v1", "first");
+        let v2 = config("megarepo/main", "v2 / with spaces", "second");
+        configs.add_config_version(ctx.clone(), v2.clone()).await?;
+        configs.add_config_version(ctx.clone(), v1.clone()).await?;
+        // Same version for another target.
+        let other = config("other", "v1", "other");
+        configs
+            .add_config_version(ctx.clone(), other.clone())
+            .await?;
+
+        assert_eq!(
+            configs.get_target_config_versions(ctx.clone(), target.clone())?,
+            vec!["v1".to_string(), "v2 / with spaces".to_string()]
+        );
+        assert_eq!(
+            configs.get_config_by_version(ctx.clone(), target.clone(), v2.version.clone())?,
+            v2
+        );
+        assert_eq!(
+            configs.get_config_by_version(ctx.clone(), other.target.clone(), "v1".to_string())?,
+            other
+        );
+
+        // Versions are immutable.
+        let res = configs
+            .add_config_version(ctx.clone(), config("megarepo/main", "v1", "changed"))
+            .await;
+        assert!(matches!(res, Err(MegarepoError::RequestError(_))));
+        assert_eq!(
+            configs.get_config_by_version(ctx.clone(), target.clone(), "v1".to_string())?,
+            v1
+        );
+
+        let res = configs.get_config_by_version(ctx.clone(), target, "v3".to_string());
+        assert!(matches!(res, Err(MegarepoError::RequestError(_))));
+
+        // Names longer than a file name can be are fine.
+        let long = config(&"b".repeat(300), &"v".repeat(300), "long");
+        configs
+            .add_config_version(ctx.clone(), long.clone())
+            .await?;
+        assert_eq!(
+            configs.get_target_config_versions(ctx.clone(), long.target.clone())?,
+            vec![long.version.clone()]
+        );
+        assert_eq!(
+            configs.get_config_by_version(
+                ctx.clone(),
+                long.target.clone(),
+                long.version.clone()
+            )?,
+            long
+        );
+
+        Ok(())
     }
 }
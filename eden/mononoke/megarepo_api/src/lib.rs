@@ -115,12 +115,20 @@ impl MegarepoApi {
 
         let megarepo_configs: Arc<dyn MononokeMegarepoConfigs> = match &env.megarepo_configs_options
         {
+            #[cfg(fbcode_build)]
             MononokeMegarepoConfigsOptions::Prod => Arc::new(CfgrMononokeMegarepoConfigs::new(
                 fb,
                 &logger,
                 env.config_store.clone(),
                 None,
             )?),
+            #[cfg(not(fbcode_build))]
+            MononokeMegarepoConfigsOptions::Prod => {
+                Arc::new(CfgrMononokeMegarepoConfigs::with_path(
+                    &logger,
+                    repo_configs.common.megarepo_configs_path.clone(),
+                )?)
+            }
             MononokeMegarepoConfigsOptions::IntegrationTest(path) => {
                 Arc::new(CfgrMononokeMegarepoConfigs::new(
                     fb,
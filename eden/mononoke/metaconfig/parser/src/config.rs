@@ -10,7 +10,7 @@
 use std::{
     collections::{HashMap, HashSet},
     convert::TryInto,
-    path::Path,
+    path::{Path, PathBuf},
     str,
 };
 
@@ -21,15 +21,17 @@ use cached_config::ConfigStore;
 use metaconfig_types::{
     AllowlistEntry, BackupRepoConfig, BlobConfig, CensoredScubaParams, CommitSyncConfig,
     CommonConfig, HgsqlGlobalrevsName, HgsqlName, Redaction, RedactionConfig, RepoConfig,
-    RepoReadOnly, StorageConfig,
+    RepoReadOnly, StorageConfig, TrustedProxyConfig,
 };
 use mononoke_types::RepositoryId;
+use permission_checker::{MononokeIdentity, MononokeIdentitySet};
 use repos::{
     RawCommitSyncConfig, RawCommonConfig, RawRepoConfig, RawRepoConfigs, RawStorageConfig,
 };
 
 const LIST_KEYS_PATTERNS_MAX_DEFAULT: u64 = 500_000;
 const HOOK_MAX_FILE_SIZE_DEFAULT: u64 = 8 * 1024 * 1024; // 8MiB
+const CLIENT_IP_HEADER_DEFAULT: &str = "x-forwarded-for";
 
 /// Load configuration common to all repositories.
 pub fn load_common_config(
@@ -199,12 +201,44 @@ fn parse_common_config(
         redaction_sets_location: redaction_config.redaction_sets_location,
     };
 
+    let trusted_proxy = common
+        .trusted_proxy
+        .map(|raw| {
+            // Without identities, any peer could forward headers for any client.
+            let identities = raw
+                .identities
+                .unwrap_or_default()
+                .into_iter()
+                .map(|identity| {
+                    MononokeIdentity::new(&identity.identity_type, &identity.identity_data)
+                })
+                .collect::<Result<MononokeIdentitySet>>()?;
+            if identities.is_empty() {
+                return Err(ConfigurationError::InvalidFileStructure(
+                    "trusted_proxy must have identities".into(),
+                )
+                .into());
+            }
+            Ok(TrustedProxyConfig {
+                client_cert_header: raw.client_cert_header,
+                client_ip_header: raw
+                    .client_ip_header
+                    .unwrap_or_else(|| CLIENT_IP_HEADER_DEFAULT.to_string()),
+                identities,
+            })
+        })
+        .transpose()?;
+
     Ok(CommonConfig {
         security_config,
         loadlimiter_category,
         enable_http_control_api: common.enable_http_control_api,
         censored_scuba_params,
         redaction_config,
+        trusted_proxy,
+        time_window_counters_path: common.time_window_counters_path.map(PathBuf::from),
+        region: common.region,
+        megarepo_configs_path: common.megarepo_configs_path.map(PathBuf::from),
     })
 }
 
@@ -474,10 +508,10 @@ mod test {
         EphemeralBlobstoreConfig, FilestoreParams, HookBypass, HookConfig, HookManagerParams,
         HookParams, InfinitepushNamespace, InfinitepushParams, LfsParams, LocalDatabaseConfig,
         MetadataDatabaseConfig, MultiplexId, MultiplexedStoreType, PushParams, PushrebaseFlags,
-        PushrebaseParams, RemoteDatabaseConfig, RemoteMetadataDatabaseConfig, RepoClientKnobs,
-        SegmentedChangelogConfig, ShardableRemoteDatabaseConfig, ShardedRemoteDatabaseConfig,
-        SmallRepoCommitSyncConfig, SourceControlServiceMonitoring, SourceControlServiceParams,
-        UnodeVersion, WireprotoLoggingConfig,
+        PushrebaseParams, RemoteDatabaseConfig, RemoteDatabaseType, RemoteMetadataDatabaseConfig,
+        RepoClientKnobs, SegmentedChangelogConfig, ShardableRemoteDatabaseConfig,
+        ShardedRemoteDatabaseConfig, SmallRepoCommitSyncConfig, SourceControlServiceMonitoring,
+        SourceControlServiceParams, UnodeVersion, WireprotoLoggingConfig,
     };
     use mononoke_types::MPath;
     use nonzero_ext::nonzero;
@@ -841,11 +875,21 @@ mod test {
             loadlimiter_category="test-category"
             scuba_censored_table="censored_table"
             scuba_local_path_censored="censored_local_path"
+            time_window_counters_path="/dev/shm/mononoke_counters"
+            region="eu"
+            megarepo_configs_path="/var/mononoke/megarepo_configs"
 
             [redaction_config]
             blobstore="main"
             redaction_sets_location="loc"
 
+            [trusted_proxy]
+            client_cert_header="x-client-cert"
+
+            [[trusted_proxy.identities]]
+            identity_type = "SERVICE_IDENTITY"
+            identity_data = "proxy"
+
             [[whitelist_entry]]
             tier = "tier1"
 
@@ -922,12 +966,14 @@ mod test {
                     MultiplexedStoreType::Normal,
                     BlobConfig::Files {
                         path: "/tmp/foo".into(),
+                        fanout: false,
                     },
                 ),
             ],
             minimum_successful_writes: nonzero!(2usize),
             queue_db: DatabaseConfig::Remote(RemoteDatabaseConfig {
                 db_address: "queue_db_address".into(),
+                db_type: RemoteDatabaseType::Mysql,
             }),
         };
         let main_storage_config = StorageConfig {
@@ -935,13 +981,16 @@ mod test {
             metadata: MetadataDatabaseConfig::Remote(RemoteMetadataDatabaseConfig {
                 primary: RemoteDatabaseConfig {
                     db_address: "db_address".into(),
+                    db_type: RemoteDatabaseType::Mysql,
                 },
                 filenodes: ShardableRemoteDatabaseConfig::Sharded(ShardedRemoteDatabaseConfig {
                     shard_map: "db_address_shards".into(),
                     shard_num: NonZeroUsize::new(123).unwrap(),
+                    db_type: RemoteDatabaseType::Mysql,
                 }),
                 mutation: RemoteDatabaseConfig {
                     db_address: "mutation_db_address".into(),
+                    db_type: RemoteDatabaseType::Mysql,
                 },
             }),
             ephemeral_blobstore: None,
@@ -1134,10 +1183,12 @@ mod test {
                     }),
                     blobstore: BlobConfig::Files {
                         path: "/tmp/www".into(),
+                        fanout: false,
                     },
                     ephemeral_blobstore: Some(EphemeralBlobstoreConfig {
                         blobstore: BlobConfig::Files {
                             path: "/tmp/www-ephemeral".into(),
+                            fanout: false,
                         },
                         metadata: DatabaseConfig::Local(LocalDatabaseConfig {
                             path: "/tmp/www-ephemeral".into(),
@@ -1213,6 +1264,16 @@ mod test {
                     darkstorm_blobstore: None,
                     redaction_sets_location: "loc".to_string(),
                 },
+                trusted_proxy: Some(TrustedProxyConfig {
+                    client_cert_header: "x-client-cert".to_string(),
+                    client_ip_header: "x-forwarded-for".to_string(),
+                    identities: vec![MononokeIdentity::new("SERVICE_IDENTITY", "proxy").unwrap()]
+                        .into_iter()
+                        .collect(),
+                }),
+                time_window_counters_path: Some(PathBuf::from("/dev/shm/mononoke_counters")),
+                region: Some("eu".to_string()),
+                megarepo_configs_path: Some(PathBuf::from("/var/mononoke/megarepo_configs")),
             }
         );
         assert_eq!(
@@ -1398,25 +1459,29 @@ mod test {
                         scuba_sample_rate: nonzero!(100u64),
                         blobstores: vec![
                             (BlobstoreId::new(1), MultiplexedStoreType::Normal, BlobConfig::Files {
-                                path: "/tmp/foo".into()
+                                path: "/tmp/foo".into(),
+                                fanout: false,
                             })
                         ],
                         minimum_successful_writes: nonzero!(1usize),
                         queue_db: DatabaseConfig::Remote(
                             RemoteDatabaseConfig {
                                 db_address: "queue_db_address".into(),
+                                db_type: RemoteDatabaseType::Mysql,
                             }
                         ),
                     },
                     metadata: MetadataDatabaseConfig::Remote(RemoteMetadataDatabaseConfig {
                         primary: RemoteDatabaseConfig {
                             db_address: "some_db".into(),
+                            db_type: RemoteDatabaseType::Mysql,
                         },
                         filenodes: ShardableRemoteDatabaseConfig::Sharded(ShardedRemoteDatabaseConfig {
-                            shard_map: "some-shards".into(), shard_num: NonZeroUsize::new(123).unwrap()
+                            shard_map: "some-shards".into(), shard_num: NonZeroUsize::new(123).unwrap(), db_type: RemoteDatabaseType::Mysql
                         }),
                         mutation: RemoteDatabaseConfig {
                             db_address: "some_db".into(),
+                            db_type: RemoteDatabaseType::Mysql,
                         },
                     }),
                     ephemeral_blobstore: None,
@@ -1498,9 +1563,9 @@ mod test {
                 storage_config: StorageConfig {
                     blobstore: BlobConfig::Disabled,
                     metadata: MetadataDatabaseConfig::Remote( RemoteMetadataDatabaseConfig {
-                        primary: RemoteDatabaseConfig { db_address: "other_other_db".into(), },
-                        filenodes: ShardableRemoteDatabaseConfig::Sharded(ShardedRemoteDatabaseConfig { shard_map: "other-other-shards".into(), shard_num: NonZeroUsize::new(789).unwrap() }),
-                        mutation: RemoteDatabaseConfig { db_address: "other_other_mutation_db".into(), },
+                        primary: RemoteDatabaseConfig { db_address: "other_other_db".into(), db_type: RemoteDatabaseType::Mysql, },
+                        filenodes: ShardableRemoteDatabaseConfig::Sharded(ShardedRemoteDatabaseConfig { shard_map: "other-other-shards".into(), shard_num: NonZeroUsize::new(789).unwrap(), db_type: RemoteDatabaseType::Mysql }),
+                        mutation: RemoteDatabaseConfig { db_address: "other_other_mutation_db".into(), db_type: RemoteDatabaseType::Mysql, },
                     }),
 
                     ephemeral_blobstore: None,
@@ -1600,6 +1665,7 @@ mod test {
                     MultiplexedStoreType::Normal,
                     BlobConfig::Files {
                         path: "/tmp/foo1".into(),
+                        fanout: false,
                     },
                 ),
                 (
@@ -1607,6 +1673,7 @@ mod test {
                     MultiplexedStoreType::Normal,
                     BlobConfig::Files {
                         path: "/tmp/foo2".into(),
+                        fanout: false,
                     },
                 ),
                 (
@@ -1614,6 +1681,7 @@ mod test {
                     MultiplexedStoreType::WriteMostly,
                     BlobConfig::Files {
                         path: "/tmp/foo3".into(),
+                        fanout: false,
                     },
                 ),
             ];
@@ -14,13 +14,13 @@ use anyhow::{anyhow, bail, Context, Result};
 use metaconfig_types::{
     BlobConfig, BlobstoreId, DatabaseConfig, EphemeralBlobstoreConfig, FilestoreParams,
     LocalDatabaseConfig, MetadataDatabaseConfig, MultiplexId, MultiplexedStoreType, PackConfig,
-    PackFormat, RemoteDatabaseConfig, RemoteMetadataDatabaseConfig, ShardableRemoteDatabaseConfig,
-    ShardedRemoteDatabaseConfig, StorageConfig,
+    PackFormat, RemoteDatabaseConfig, RemoteDatabaseType, RemoteMetadataDatabaseConfig,
+    ShardableRemoteDatabaseConfig, ShardedRemoteDatabaseConfig, StorageConfig,
 };
 use nonzero_ext::nonzero;
 use repos::{
     RawBlobstoreConfig, RawBlobstorePackConfig, RawBlobstorePackFormat, RawDbConfig, RawDbLocal,
-    RawDbRemote, RawDbShardableRemote, RawDbShardedRemote, RawEphemeralBlobstoreConfig,
+    RawDbRemote, RawDbShardableRemote, RawDbShardedRemote, RawDbType, RawEphemeralBlobstoreConfig,
     RawFilestoreParams, RawMetadataConfig, RawMultiplexedStoreType, RawStorageConfig,
 };
 
@@ -70,6 +70,7 @@ impl Convert for RawBlobstoreConfig {
             RawBlobstoreConfig::disabled(_) => BlobConfig::Disabled,
             RawBlobstoreConfig::blob_files(raw) => BlobConfig::Files {
                 path: PathBuf::from(raw.path),
+                fanout: raw.fanout.unwrap_or(false),
             },
             RawBlobstoreConfig::blob_sqlite(raw) => BlobConfig::Sqlite {
                 path: PathBuf::from(raw.path),
@@ -153,6 +154,18 @@ impl Convert for RawBlobstoreConfig {
                     .map(|x| x.try_into())
                     .transpose()?,
             },
+            RawBlobstoreConfig::disk_cache(raw) => BlobConfig::DiskCache {
+                blobconfig: Box::new(raw.blobstore.convert()?),
+                path: PathBuf::from(raw.path),
+                max_size_bytes: raw.max_size_bytes.try_into()?,
+            },
+            RawBlobstoreConfig::encrypted(raw) => BlobConfig::Encrypted {
+                blobconfig: Box::new(raw.blobstore.convert()?),
+                keyring_path: PathBuf::from(raw.keyring_path),
+            },
+            RawBlobstoreConfig::checksummed(raw) => BlobConfig::Checksummed {
+                blobconfig: Box::new(raw.blobstore.convert()?),
+            },
             RawBlobstoreConfig::UnknownField(f) => {
                 return Err(anyhow!("unsupported blobstore configuration ({})", f));
             }
@@ -213,6 +226,7 @@ impl Convert for RawDbRemote {
     fn convert(self) -> Result<Self::Output> {
         Ok(RemoteDatabaseConfig {
             db_address: self.db_address,
+            db_type: convert_db_type(self.db_type)?,
         })
     }
 }
@@ -227,10 +241,19 @@ impl Convert for RawDbShardedRemote {
         Ok(ShardedRemoteDatabaseConfig {
             shard_map: self.shard_map,
             shard_num,
+            db_type: convert_db_type(self.db_type)?,
         })
     }
 }
 
+fn convert_db_type(db_type: Option<RawDbType>) -> Result<RemoteDatabaseType> {
+    match db_type {
+        None | Some(RawDbType::MYSQL) => Ok(RemoteDatabaseType::Mysql),
+        Some(RawDbType::POSTGRES) => Ok(RemoteDatabaseType::Postgres),
+        Some(other) => Err(anyhow!("unknown remote database type: {:?}", other)),
+    }
+}
+
 impl Convert for RawDbShardableRemote {
     type Output = ShardableRemoteDatabaseConfig;
 
@@ -28,7 +28,7 @@ use ascii::AsciiString;
 use bookmarks_types::BookmarkName;
 use fbinit::FacebookInit;
 use mononoke_types::{BonsaiChangeset, ChangesetId, MPath, PrefixTrie, RepositoryId};
-use permission_checker::{BoxMembershipChecker, MembershipCheckerBuilder};
+use permission_checker::{BoxMembershipChecker, MembershipCheckerBuilder, MononokeIdentitySet};
 use regex::Regex;
 use scuba::ScubaValue;
 use serde_derive::Deserialize;
@@ -122,6 +122,26 @@ pub struct CommonConfig {
     pub enable_http_control_api: bool,
     /// Configuration for redaction of blobs
     pub redaction_config: RedactionConfig,
+    /// Proxy that forwards client details in headers on HTTP connections
+    pub trusted_proxy: Option<TrustedProxyConfig>,
+    /// File in which time window counters are shared with other processes
+    pub time_window_counters_path: Option<PathBuf>,
+    /// Region this deployment runs in
+    pub region: Option<String>,
+    /// Directory that megarepo configs are kept in outside of fbcode
+    pub megarepo_configs_path: Option<PathBuf>,
+}
+
+/// Configuration for a proxy that terminates TLS in front of Mononoke
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TrustedProxyConfig {
+    /// Header with the client's certificate as PEM, possibly percent-encoded
+    pub client_cert_header: String,
+    /// Header with the client's address. Only the last address is used.
+    pub client_ip_header: String,
+    /// Identities of the proxy. Headers are only used on connections from peers that have one of
+    /// them.
+    pub identities: MononokeIdentitySet,
 }
 
 /// Configuration for logging of censored blobstore accesses
@@ -882,11 +902,13 @@ pub enum BlobConfig {
     /// Administratively disabled blobstore
     Disabled,
     /// Blob repository with path pointing to on-disk files with data. Blobs are stored in
-    /// separate files.
-    /// NOTE: this is read-only and for development/testing only. Production uses will break things.
+    /// separate files, which are written atomically and synced to disk.
     Files {
         /// Path to directory containing files
         path: PathBuf,
+        /// Whether blobs are spread over subdirectories rather than all being in one directory.
+        /// Enabling this moves the blobs of an existing store, and can't be undone.
+        fanout: bool,
     },
     /// Blob repository with path pointing to on-disk files with data. The files are stored in a
     /// Sqlite database
@@ -962,6 +984,31 @@ pub enum BlobConfig {
         /// Limit the number of concurrent operations to S3 blobstore.
         num_concurrent_operations: Option<usize>,
     },
+    /// A blobstore that keeps a bounded cache of blobs on local disk in front of another
+    /// blobstore
+    DiskCache {
+        /// The config for the blobstore that is wrapped.
+        blobconfig: Box<BlobConfig>,
+        /// Directory to keep the cached blobs in
+        path: PathBuf,
+        /// Total size of the cached blobs, beyond which the least recently used are evicted
+        max_size_bytes: u64,
+    },
+    /// A blobstore that encrypts every blob before storing it in another blobstore. Blobs are
+    /// bound to their key, so it can't be put inside Pack, which links keys to packs.
+    Encrypted {
+        /// The config for the blobstore that is wrapped.
+        blobconfig: Box<BlobConfig>,
+        /// File with the keys to encrypt blobs with
+        keyring_path: PathBuf,
+    },
+    /// A blobstore that stores a checksum with every blob in another blobstore, and checks it
+    /// on read. Put it directly around each physical blobstore, so that it checks what is
+    /// actually stored, and within a multiplex so that bad blobs can be read from the others.
+    Checksummed {
+        /// The config for the blobstore that is wrapped.
+        blobconfig: Box<BlobConfig>,
+    },
 }
 
 impl BlobConfig {
@@ -979,6 +1026,9 @@ impl BlobConfig {
                 .all(BlobConfig::is_local),
             Logging { blobconfig, .. } => blobconfig.is_local(),
             Pack { blobconfig, .. } => blobconfig.is_local(),
+            DiskCache { blobconfig, .. } => blobconfig.is_local(),
+            Encrypted { blobconfig, .. } => blobconfig.is_local(),
+            Checksummed { blobconfig } => blobconfig.is_local(),
         }
     }
 
@@ -1015,20 +1065,39 @@ pub struct LocalDatabaseConfig {
     pub path: PathBuf,
 }
 
-/// Configuration for a remote MySQL database
+/// Kind of server a remote database is hosted on
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum RemoteDatabaseType {
+    /// MySQL or MariaDB server
+    Mysql,
+    /// PostgreSQL server
+    Postgres,
+}
+
+impl Default for RemoteDatabaseType {
+    fn default() -> Self {
+        RemoteDatabaseType::Mysql
+    }
+}
+
+/// Configuration for a remote MySQL or PostgreSQL database
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub struct RemoteDatabaseConfig {
     /// SQL database to connect to
     pub db_address: String,
+    /// Kind of server the database is hosted on
+    pub db_type: RemoteDatabaseType,
 }
 
-/// Configuration for a sharded remote MySQL database
+/// Configuration for a sharded remote MySQL or PostgreSQL database
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub struct ShardedRemoteDatabaseConfig {
     /// SQL database shard map to connect to
     pub shard_map: String,
     /// Number of shards to distribute data across.
     pub shard_num: NonZeroUsize,
+    /// Kind of server the shards are hosted on
+    pub db_type: RemoteDatabaseType,
 }
 
 /// Configuration for a potentially sharded remote MySQL database
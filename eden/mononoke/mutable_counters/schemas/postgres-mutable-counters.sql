@@ -0,0 +1,13 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+CREATE TABLE mutable_counters (
+  repo_id BIGINT NOT NULL,
+  name VARCHAR(128) NOT NULL,
+  value BIGINT NOT NULL,
+  PRIMARY KEY (repo_id, name)
+);
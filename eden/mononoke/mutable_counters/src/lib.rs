@@ -109,6 +109,9 @@ impl SqlConstruct for SqlMutableCounters {
 
     const CREATION_QUERY: &'static str = include_str!("../schemas/sqlite-mutable-counters.sql");
 
+    const POSTGRES_CREATION_QUERY: Option<&'static str> =
+        Some(include_str!("../schemas/postgres-mutable-counters.sql"));
+
     fn from_sql_connections(connections: SqlConnections) -> Self {
         Self {
             write_connection: connections.write_connection,
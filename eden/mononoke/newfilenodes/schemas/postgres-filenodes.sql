@@ -0,0 +1,35 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+CREATE TABLE filenodes (
+  repo_id BIGINT NOT NULL,
+  path_hash BYTEA NOT NULL,
+  is_tree SMALLINT NOT NULL,
+  filenode BYTEA NOT NULL,
+  linknode BYTEA NOT NULL,
+  p1 BYTEA,
+  p2 BYTEA,
+  has_copyinfo SMALLINT NOT NULL,
+  PRIMARY KEY (repo_id, path_hash, is_tree, filenode)
+);
+
+CREATE TABLE fixedcopyinfo (
+  repo_id BIGINT NOT NULL,
+  topath_hash BYTEA NOT NULL,
+  tonode BYTEA NOT NULL,
+  is_tree SMALLINT NOT NULL,
+  frompath_hash BYTEA NOT NULL,
+  fromnode BYTEA NOT NULL,
+  PRIMARY KEY (repo_id, topath_hash, tonode, is_tree)
+);
+
+CREATE TABLE paths (
+  repo_id BIGINT NOT NULL,
+  path_hash BYTEA NOT NULL,
+  path BYTEA NOT NULL,
+  PRIMARY KEY (repo_id, path_hash)
+);
@@ -35,6 +35,9 @@ impl SqlConstruct for NewFilenodesBuilder {
 
     const CREATION_QUERY: &'static str = include_str!("../schemas/sqlite-filenodes.sql");
 
+    const POSTGRES_CREATION_QUERY: Option<&'static str> =
+        Some(include_str!("../schemas/postgres-filenodes.sql"));
+
     fn from_sql_connections(connections: SqlConnections) -> Self {
         let SqlConnections {
             write_connection,
@@ -61,6 +64,9 @@ impl SqlShardedConstruct for NewFilenodesBuilder {
 
     const CREATION_QUERY: &'static str = <NewFilenodesBuilder as SqlConstruct>::CREATION_QUERY;
 
+    const POSTGRES_CREATION_QUERY: Option<&'static str> =
+        <NewFilenodesBuilder as SqlConstruct>::POSTGRES_CREATION_QUERY;
+
     fn from_sql_shard_connections(shard_connections: SqlShardedConnections) -> Self {
         if shard_connections.is_empty() {
             // It should be impossible for shard_connections to be empty, as the configured
@@ -0,0 +1,344 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! ACLs and groups read from a JSON config, for builds without access to
+//! Facebook's ACL services.
+//!
+//! The config is loaded through a `ConfigStore`, so edits are picked up
+//! without restarting the server. It looks like this:
+//!
+//! ```json
+//! {
+//!   "repos": {
+//!     "myrepo": {
+//!       "read": ["GROUP:engineers", "SERVICE_IDENTITY:ci"],
+//!       "write": ["GROUP:engineers"],
+//!       "draft": ["GROUP:engineers"],
+//!       "bypass_readonly": ["USER:oncall"]
+//!     }
+//!   },
+//!   "tiers": {
+//!     "mononoke": { "trusted_parties": ["X509_SUBJECT_NAME:CN=proxy"] }
+//!   },
+//!   "groups": {
+//!     "engineers": ["USER:alice", "GROUP:contractors"],
+//!     "contractors": ["USER:bob"],
+//!     "admins": ["USER:alice"]
+//!   },
+//!   "admin_group": "admins",
+//!   "reviewers_group": "engineers"
+//! }
+//! ```
+//!
+//! Entries of the form `GROUP:name` refer to a group from `groups`, and
+//! groups may include other groups. An ACL that isn't in the config grants
+//! nothing.
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use cached_config::{ConfigHandle, ConfigStore};
+use serde::Deserialize;
+
+use crate::checker::{BoxPermissionChecker, PermissionChecker};
+use crate::identity::{MononokeIdentity, MononokeIdentitySet};
+use crate::membership::{BoxMembershipChecker, MembershipChecker};
+use crate::provider::AclProvider;
+
+/// Identity type used to refer to a group from the config.
+const GROUP_IDENTITY_TYPE: &str = "GROUP";
+
+/// Actions in an ACL, mapped to the identities allowed to perform them.
+pub type Acl = HashMap<String, Vec<MononokeIdentity>>;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AclsConfig {
+    #[serde(default)]
+    pub repos: HashMap<String, Acl>,
+    #[serde(default)]
+    pub tiers: HashMap<String, Acl>,
+    #[serde(default)]
+    pub groups: HashMap<String, Vec<MononokeIdentity>>,
+    #[serde(default)]
+    pub admin_group: Option<String>,
+    #[serde(default)]
+    pub reviewers_group: Option<String>,
+}
+
+impl AclsConfig {
+    /// Whether any of `identities` is `entry`, or is a member of the group
+    /// that `entry` refers to.
+    fn entry_matches(&self, entry: &MononokeIdentity, identities: &MononokeIdentitySet) -> bool {
+        if entry.id_type() == GROUP_IDENTITY_TYPE {
+            self.is_member(entry.id_data(), identities)
+        } else {
+            identities.contains(entry)
+        }
+    }
+
+    /// Whether any of `identities` belongs to `group`, directly or through
+    /// nested groups. Unknown groups have no members.
+    pub fn is_member(&self, group: &str, identities: &MononokeIdentitySet) -> bool {
+        let mut visited = HashSet::new();
+        let mut to_visit = vec![group];
+
+        while let Some(group) = to_visit.pop() {
+            // Groups may include each other, so guard against cycles.
+            if !visited.insert(group) {
+                continue;
+            }
+
+            for member in self.groups.get(group).into_iter().flatten() {
+                if member.id_type() == GROUP_IDENTITY_TYPE {
+                    to_visit.push(member.id_data());
+                } else if identities.contains(member) {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Whether `identities` are allowed to perform all of `actions` under
+    /// `acl`.
+    pub fn check_acl(
+        &self,
+        acl: Option<&Acl>,
+        identities: &MononokeIdentitySet,
+        actions: &[&str],
+    ) -> bool {
+        let acl = match acl {
+            Some(acl) => acl,
+            None => return false,
+        };
+
+        actions.iter().all(|action| {
+            acl.get(*action)
+                .into_iter()
+                .flatten()
+                .any(|entry| self.entry_matches(entry, identities))
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+enum AclKind {
+    Repo,
+    Tier,
+}
+
+struct FileAclChecker {
+    config: ConfigHandle<AclsConfig>,
+    kind: AclKind,
+    name: String,
+}
+
+#[async_trait]
+impl PermissionChecker for FileAclChecker {
+    async fn check_set(&self, accessors: &MononokeIdentitySet, actions: &[&str]) -> Result<bool> {
+        let config = self.config.get();
+        let acl = match self.kind {
+            AclKind::Repo => config.repos.get(&self.name),
+            AclKind::Tier => config.tiers.get(&self.name),
+        };
+        Ok(config.check_acl(acl, accessors, actions))
+    }
+}
+
+/// Which group a membership checker looks at. Admin and reviewer groups are
+/// looked up by name on every check, so that changing which group they
+/// point to in the config takes effect immediately.
+enum GroupRef {
+    Named(String),
+    Admin,
+    Reviewers,
+}
+
+struct FileMembershipChecker {
+    config: ConfigHandle<AclsConfig>,
+    group: GroupRef,
+}
+
+#[async_trait]
+impl MembershipChecker for FileMembershipChecker {
+    async fn is_member(&self, identities: &MononokeIdentitySet) -> Result<bool> {
+        let config = self.config.get();
+        let group = match &self.group {
+            GroupRef::Named(name) => Some(name),
+            GroupRef::Admin => config.admin_group.as_ref(),
+            GroupRef::Reviewers => config.reviewers_group.as_ref(),
+        };
+        Ok(group.map_or(false, |group| config.is_member(group, identities)))
+    }
+}
+
+/// An `AclProvider` backed by an `AclsConfig` in a `ConfigStore`.
+pub struct FileAclProvider {
+    config: ConfigHandle<AclsConfig>,
+}
+
+impl FileAclProvider {
+    pub fn new(config_store: &ConfigStore, path: &str) -> Result<Self> {
+        let config = config_store
+            .get_config_handle(path.to_string())
+            .with_context(|| format!("Failed to load ACLs from {}", path))?;
+        Ok(Self { config })
+    }
+
+    pub fn from_handle(config: ConfigHandle<AclsConfig>) -> Self {
+        Self { config }
+    }
+
+    fn checker(&self, kind: AclKind, name: &str) -> BoxPermissionChecker {
+        Box::new(FileAclChecker {
+            config: self.config.clone(),
+            kind,
+            name: name.to_string(),
+        })
+    }
+
+    fn membership(&self, group: GroupRef) -> BoxMembershipChecker {
+        Box::new(FileMembershipChecker {
+            config: self.config.clone(),
+            group,
+        })
+    }
+}
+
+impl AclProvider for FileAclProvider {
+    fn repo_acl(&self, name: &str) -> Result<BoxPermissionChecker> {
+        Ok(self.checker(AclKind::Repo, name))
+    }
+
+    fn tier_acl(&self, name: &str) -> Result<BoxPermissionChecker> {
+        Ok(self.checker(AclKind::Tier, name))
+    }
+
+    fn group(&self, name: &str) -> Result<BoxMembershipChecker> {
+        Ok(self.membership(GroupRef::Named(name.to_string())))
+    }
+
+    fn admin_group(&self) -> Result<BoxMembershipChecker> {
+        Ok(self.membership(GroupRef::Admin))
+    }
+
+    fn reviewers_group(&self) -> Result<BoxMembershipChecker> {
+        Ok(self.membership(GroupRef::Reviewers))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use cached_config::{ModificationTime, TestSource};
+
+    const ACLS_PATH: &str = "scm/mononoke/acls";
+
+    const ACLS: &str = r#"{
+        "repos": {
+            "repo": {
+                "read": ["GROUP:engineers", "SERVICE_IDENTITY:ci"],
+                "write": ["GROUP:writers"]
+            }
+        },
+        "tiers": {
+            "tier": { "trusted_parties": ["USER:proxy"] }
+        },
+        "groups": {
+            "engineers": ["USER:alice", "GROUP:writers"],
+            "writers": ["USER:bob", "GROUP:engineers"],
+            "admins": ["USER:carol"]
+        },
+        "admin_group": "admins"
+    }"#;
+
+    fn provider() -> Result<FileAclProvider> {
+        let source = Arc::new(TestSource::new());
+        source.insert_config(ACLS_PATH, ACLS, ModificationTime::UnixTimestamp(0));
+        let store = ConfigStore::new(source, Duration::from_secs(1), None);
+        FileAclProvider::new(&store, ACLS_PATH)
+    }
+
+    fn idents(ids: &[&str]) -> MononokeIdentitySet {
+        ids.iter().map(|id| id.parse().unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_repo_acl() -> Result<()> {
+        let acl = provider()?.repo_acl("repo")?;
+
+        assert!(acl.check_set(&idents(&["USER:alice"]), &["read"]).await?);
+        assert!(!acl.check_set(&idents(&["USER:alice"]), &["write"]).await?);
+        assert!(
+            !acl.check_set(&idents(&["USER:alice"]), &["read", "write"])
+                .await?
+        );
+        assert!(
+            acl.check_set(&idents(&["USER:bob"]), &["read", "write"])
+                .await?
+        );
+        assert!(
+            acl.check_set(&idents(&["SERVICE_IDENTITY:ci"]), &["read"])
+                .await?
+        );
+        assert!(!acl.check_set(&idents(&["USER:mallory"]), &["read"]).await?);
+        assert!(!acl.check_set(&idents(&["USER:alice"]), &["draft"]).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unknown_acl() -> Result<()> {
+        let provider = provider()?;
+
+        let repo = provider.repo_acl("other")?;
+        assert!(!repo.check_set(&idents(&["USER:alice"]), &["read"]).await?);
+
+        // Repo and tier ACLs live in separate namespaces.
+        let tier = provider.tier_acl("repo")?;
+        assert!(!tier.check_set(&idents(&["USER:alice"]), &["read"]).await?);
+
+        let tier = provider.tier_acl("tier")?;
+        assert!(
+            tier.check_set(&idents(&["USER:proxy"]), &["trusted_parties"])
+                .await?
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_groups() -> Result<()> {
+        let provider = provider()?;
+
+        // Nested groups resolve, and cycles between them terminate.
+        let engineers = provider.group("engineers")?;
+        assert!(engineers.is_member(&idents(&["USER:alice"])).await?);
+        assert!(engineers.is_member(&idents(&["USER:bob"])).await?);
+        assert!(!engineers.is_member(&idents(&["USER:carol"])).await?);
+
+        let admins = provider.admin_group()?;
+        assert!(admins.is_member(&idents(&["USER:carol"])).await?);
+        assert!(!admins.is_member(&idents(&["USER:alice"])).await?);
+
+        // No reviewers group is configured, so there are no reviewers.
+        let reviewers = provider.reviewers_group()?;
+        assert!(!reviewers.is_member(&idents(&["USER:alice"])).await?);
+
+        let unknown = provider.group("unknown")?;
+        assert!(!unknown.is_member(&idents(&["USER:alice"])).await?);
+
+        Ok(())
+    }
+}
@@ -6,6 +6,7 @@
  */
 
 use anyhow::{bail, Error, Result};
+use serde::de::{Deserialize, Deserializer, Error as _};
 use serde::ser::{Serialize, Serializer};
 use std::collections::BTreeSet;
 use std::fmt;
@@ -32,6 +33,24 @@ impl MononokeIdentity {
         Ok(Self { id_type, id_data })
     }
 
+    /// The identities of a client that authenticated without a certificate,
+    /// known only by its unix username. This is always exactly one `USER`
+    /// identity: names that could be mistaken for a list of principals or a
+    /// typed identity are rejected rather than decoded.
+    pub fn try_from_unix_name(unix_name: &str) -> Result<MononokeIdentitySet> {
+        if unix_name.is_empty()
+            || unix_name
+                .chars()
+                .any(|c| c == ',' || c == ':' || c.is_whitespace() || c.is_control())
+        {
+            bail!("Invalid unix name: {:?}", unix_name);
+        }
+
+        let mut idents = MononokeIdentitySet::new();
+        idents.insert(Self::new("USER", unix_name)?);
+        Ok(idents)
+    }
+
     pub fn id_type(&self) -> &str {
         &self.id_type
     }
@@ -72,6 +91,16 @@ impl Serialize for MononokeIdentity {
     }
 }
 
+impl<'de> Deserialize<'de> for MononokeIdentity {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(D::Error::custom)
+    }
+}
+
 pub trait MononokeIdentitySetExt {
     fn is_quicksand(&self) -> bool;
 
@@ -81,3 +110,25 @@ pub trait MononokeIdentitySetExt {
 
     fn hostname(&self) -> Option<&str>;
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_unix_name() -> Result<()> {
+        let idents = MononokeIdentity::try_from_unix_name("alice")?;
+        assert_eq!(
+            idents.into_iter().collect::<Vec<_>>(),
+            vec![MononokeIdentity::new("USER", "alice")?]
+        );
+
+        assert!(MononokeIdentity::try_from_unix_name("").is_err());
+        assert!(MononokeIdentity::try_from_unix_name("alice,GROUP:admins").is_err());
+        assert!(MononokeIdentity::try_from_unix_name("alice GROUP:admins").is_err());
+        assert!(MononokeIdentity::try_from_unix_name("GROUP:admins").is_err());
+        assert!(MononokeIdentity::try_from_unix_name("alice\nbob").is_err());
+
+        Ok(())
+    }
+}
@@ -8,10 +8,14 @@
 mod checker;
 #[cfg(fbcode_build)]
 mod facebook;
+#[cfg(not(fbcode_build))]
+mod file_acls;
 mod identity;
 mod membership;
 #[cfg(not(fbcode_build))]
 mod oss;
+#[cfg(not(fbcode_build))]
+mod provider;
 
 pub use checker::{
     ArcPermissionChecker, BoxPermissionChecker, PermissionChecker, PermissionCheckerBuilder,
@@ -20,3 +24,7 @@ pub use identity::{MononokeIdentity, MononokeIdentitySet, MononokeIdentitySetExt
 pub use membership::{
     ArcMembershipChecker, BoxMembershipChecker, MembershipChecker, MembershipCheckerBuilder,
 };
+#[cfg(not(fbcode_build))]
+pub use file_acls::{Acl, AclsConfig, FileAclProvider};
+#[cfg(not(fbcode_build))]
+pub use provider::{set_acl_provider, AclProvider, ArcAclProvider, DefaultAclProvider};
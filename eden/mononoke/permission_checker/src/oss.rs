@@ -5,25 +5,98 @@
  * GNU General Public License version 2.
  */
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use fbinit::FacebookInit;
 use openssl::x509::X509;
+use serde::Deserialize;
 
 use crate::checker::{BoxPermissionChecker, PermissionCheckerBuilder};
 use crate::identity::{MononokeIdentity, MononokeIdentitySet, MononokeIdentitySetExt};
 use crate::membership::{BoxMembershipChecker, MembershipCheckerBuilder};
+use crate::provider::acl_provider;
+
+/// Identity type for the host a client connects from.
+const MACHINE_IDENTITY_TYPE: &str = "MACHINE";
+
+/// Identity type given to SSH principals that don't specify one.
+const USER_IDENTITY_TYPE: &str = "USER";
+
+/// One identity in the JSON encoding accepted by `try_from_json_encoded`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonIdentity {
+    /// `{"type": "USER", "data": "alice"}`
+    Object {
+        #[serde(rename = "type")]
+        id_type: String,
+        data: String,
+    },
+    /// `"USER:alice"`
+    Encoded(String),
+}
 
 impl MononokeIdentity {
     pub fn reviewer_identities(_username: &str) -> MononokeIdentitySet {
         MononokeIdentitySet::new()
     }
 
-    pub fn try_from_ssh_encoded(_encoded: &str) -> Result<MononokeIdentitySet> {
-        bail!("Decoding from SSH Principals is not yet implemented for MononokeIdentity")
+    /// Decode the principals of an SSH certificate, as exported by sshrelay.
+    ///
+    /// Principals are separated by commas or whitespace. Each one is either
+    /// `TYPE:data`, such as `MACHINE:host1.example.com`, or a bare name,
+    /// which is taken to be a username. Types are case-insensitive. Clients
+    /// without a certificate are identified by `try_from_unix_name` instead.
+    pub fn try_from_ssh_encoded(encoded: &str) -> Result<MononokeIdentitySet> {
+        let mut idents = MononokeIdentitySet::new();
+
+        for principal in encoded
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|p| !p.is_empty())
+        {
+            let ident = match principal.split_once(':') {
+                Some((id_type, data)) if !id_type.is_empty() && !data.is_empty() => {
+                    MononokeIdentity::new(id_type.to_ascii_uppercase(), data)?
+                }
+                Some(_) => bail!("Invalid SSH principal: {:?}", principal),
+                None => MononokeIdentity::new(USER_IDENTITY_TYPE, principal)?,
+            };
+            idents.insert(ident);
+        }
+
+        if idents.is_empty() {
+            bail!("No identities in SSH principals {:?}", encoded);
+        }
+
+        Ok(idents)
     }
 
-    pub fn try_from_json_encoded(_: &str) -> Result<MononokeIdentitySet> {
-        bail!("Decoding from JSON is not yet implemented for MononokeIdentity")
+    /// Decode identities forwarded by a trusted proxy as JSON. The encoding
+    /// is an array whose items are either `{"type": TYPE, "data": DATA}`
+    /// objects or `"TYPE:DATA"` strings, e.g.:
+    ///
+    /// ```json
+    /// [{"type": "USER", "data": "alice"}, "MACHINE:host1.example.com"]
+    /// ```
+    ///
+    /// An empty array is an error: a proxy that forwards no identities
+    /// hasn't authenticated the client.
+    pub fn try_from_json_encoded(encoded: &str) -> Result<MononokeIdentitySet> {
+        let json_idents: Vec<JsonIdentity> =
+            serde_json::from_str(encoded).context("Invalid JSON-encoded identities")?;
+
+        let idents = json_idents
+            .into_iter()
+            .map(|ident| match ident {
+                JsonIdentity::Object { id_type, data } => MononokeIdentity::new(id_type, data),
+                JsonIdentity::Encoded(encoded) => encoded.parse(),
+            })
+            .collect::<Result<MononokeIdentitySet>>()?;
+
+        if idents.is_empty() {
+            bail!("No identities in JSON-encoded identities {:?}", encoded);
+        }
+
+        Ok(idents)
     }
 
     pub fn try_from_x509(cert: &X509) -> Result<MononokeIdentitySet> {
@@ -55,35 +128,118 @@ impl MononokeIdentitySetExt for MononokeIdentitySet {
         false
     }
 
+    /// The hostname without its domain and trailing digits, so that e.g.
+    /// `devvm123.lla1.example.com` has the prefix `devvm`.
     fn hostprefix(&self) -> Option<&str> {
-        None
+        let hostname = self.hostname()?;
+        let shortname = hostname.split('.').next().unwrap_or(hostname);
+        let prefix = shortname.trim_end_matches(|c: char| c.is_ascii_digit());
+        if prefix.is_empty() {
+            None
+        } else {
+            Some(prefix)
+        }
     }
 
     fn hostname(&self) -> Option<&str> {
-        None
+        self.iter()
+            .find(|ident| ident.id_type() == MACHINE_IDENTITY_TYPE)
+            .map(|ident| ident.id_data())
     }
 }
 
 impl PermissionCheckerBuilder {
-    pub async fn acl_for_repo(_fb: FacebookInit, _name: &str) -> Result<BoxPermissionChecker> {
-        Ok(Self::always_allow())
+    pub async fn acl_for_repo(_fb: FacebookInit, name: &str) -> Result<BoxPermissionChecker> {
+        acl_provider().repo_acl(name)
     }
 
-    pub async fn acl_for_tier(_fb: FacebookInit, _name: &str) -> Result<BoxPermissionChecker> {
-        Ok(Self::always_allow())
+    pub async fn acl_for_tier(_fb: FacebookInit, name: &str) -> Result<BoxPermissionChecker> {
+        acl_provider().tier_acl(name)
     }
 }
 
 impl MembershipCheckerBuilder {
     pub async fn for_reviewers_group(_fb: FacebookInit) -> Result<BoxMembershipChecker> {
-        Ok(Self::always_member())
+        acl_provider().reviewers_group()
     }
 
     pub async fn for_admin_group(_fb: FacebookInit) -> Result<BoxMembershipChecker> {
-        Ok(Self::never_member())
+        acl_provider().admin_group()
     }
 
-    pub async fn for_group(_fb: FacebookInit, _group_name: &str) -> Result<BoxMembershipChecker> {
-        Ok(Self::never_member())
+    pub async fn for_group(_fb: FacebookInit, group_name: &str) -> Result<BoxMembershipChecker> {
+        acl_provider().group(group_name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn idents(ids: &[&str]) -> MononokeIdentitySet {
+        ids.iter().map(|id| id.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_ssh_encoded() -> Result<()> {
+        assert_eq!(
+            MononokeIdentity::try_from_ssh_encoded("alice")?,
+            idents(&["USER:alice"])
+        );
+        assert_eq!(
+            MononokeIdentity::try_from_ssh_encoded(
+                "alice, machine:host1.example.com\tSERVICE_IDENTITY:ci"
+            )?,
+            idents(&[
+                "USER:alice",
+                "MACHINE:host1.example.com",
+                "SERVICE_IDENTITY:ci"
+            ])
+        );
+
+        assert!(MononokeIdentity::try_from_ssh_encoded("").is_err());
+        assert!(MononokeIdentity::try_from_ssh_encoded(" , ").is_err());
+        assert!(MononokeIdentity::try_from_ssh_encoded(":alice").is_err());
+        assert!(MononokeIdentity::try_from_ssh_encoded("user:").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_encoded() -> Result<()> {
+        assert_eq!(
+            MononokeIdentity::try_from_json_encoded(
+                r#"[{"type": "USER", "data": "alice"}, "MACHINE:host1.example.com"]"#
+            )?,
+            idents(&["USER:alice", "MACHINE:host1.example.com"])
+        );
+
+        assert!(MononokeIdentity::try_from_json_encoded("[]").is_err());
+        assert!(MononokeIdentity::try_from_json_encoded("{}").is_err());
+        assert!(MononokeIdentity::try_from_json_encoded(r#"["alice"]"#).is_err());
+        assert!(MononokeIdentity::try_from_json_encoded(r#"[{"type": "USER"}]"#).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hostname() -> Result<()> {
+        let ssh =
+            MononokeIdentity::try_from_ssh_encoded("alice,MACHINE:devvm123.lla1.example.com")?;
+        assert_eq!(ssh.hostname(), Some("devvm123.lla1.example.com"));
+        assert_eq!(ssh.hostprefix(), Some("devvm"));
+
+        let json = MononokeIdentity::try_from_json_encoded(r#"["MACHINE:build7"]"#)?;
+        assert_eq!(json.hostname(), Some("build7"));
+        assert_eq!(json.hostprefix(), Some("build"));
+
+        let no_machine = idents(&["USER:alice"]);
+        assert_eq!(no_machine.hostname(), None);
+        assert_eq!(no_machine.hostprefix(), None);
+
+        let numeric = idents(&["MACHINE:1234"]);
+        assert_eq!(numeric.hostprefix(), None);
+
+        Ok(())
     }
 }
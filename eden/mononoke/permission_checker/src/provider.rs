@@ -0,0 +1,75 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use once_cell::sync::OnceCell;
+
+use crate::checker::{BoxPermissionChecker, PermissionCheckerBuilder};
+use crate::membership::{BoxMembershipChecker, MembershipCheckerBuilder};
+
+pub type ArcAclProvider = Arc<dyn AclProvider + Send + Sync + 'static>;
+
+/// Source of ACLs and group memberships for `PermissionCheckerBuilder` and
+/// `MembershipCheckerBuilder`.
+///
+/// A provider is installed once per process with `set_acl_provider`. Until
+/// then, `DefaultAclProvider` is used, which allows everything.
+pub trait AclProvider {
+    fn repo_acl(&self, name: &str) -> Result<BoxPermissionChecker>;
+
+    fn tier_acl(&self, name: &str) -> Result<BoxPermissionChecker>;
+
+    fn group(&self, name: &str) -> Result<BoxMembershipChecker>;
+
+    fn admin_group(&self) -> Result<BoxMembershipChecker>;
+
+    fn reviewers_group(&self) -> Result<BoxMembershipChecker>;
+}
+
+static ACL_PROVIDER: OnceCell<ArcAclProvider> = OnceCell::new();
+
+/// Install the process-wide ACL provider. This can only be done once, and
+/// should happen before any permission checkers are built.
+pub fn set_acl_provider(provider: ArcAclProvider) -> Result<()> {
+    ACL_PROVIDER
+        .set(provider)
+        .map_err(|_| anyhow!("ACL provider has already been set"))
+}
+
+pub(crate) fn acl_provider() -> ArcAclProvider {
+    ACL_PROVIDER
+        .get_or_init(|| Arc::new(DefaultAclProvider))
+        .clone()
+}
+
+/// The provider used when no ACLs are configured: every repo and tier is
+/// open to everyone, reviewers are everyone and nobody is an admin.
+pub struct DefaultAclProvider;
+
+impl AclProvider for DefaultAclProvider {
+    fn repo_acl(&self, _name: &str) -> Result<BoxPermissionChecker> {
+        Ok(PermissionCheckerBuilder::always_allow())
+    }
+
+    fn tier_acl(&self, _name: &str) -> Result<BoxPermissionChecker> {
+        Ok(PermissionCheckerBuilder::always_allow())
+    }
+
+    fn group(&self, _name: &str) -> Result<BoxMembershipChecker> {
+        Ok(MembershipCheckerBuilder::never_member())
+    }
+
+    fn admin_group(&self) -> Result<BoxMembershipChecker> {
+        Ok(MembershipCheckerBuilder::never_member())
+    }
+
+    fn reviewers_group(&self) -> Result<BoxMembershipChecker> {
+        Ok(MembershipCheckerBuilder::always_member())
+    }
+}
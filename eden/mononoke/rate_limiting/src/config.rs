@@ -8,6 +8,7 @@
 #![deny(warnings)]
 
 use std::convert::{TryFrom, TryInto};
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::time::Duration;
 
@@ -189,12 +190,15 @@ impl<'de> Deserialize<'de> for MononokeRateLimitConfig {
             .transpose()
             .map_err(|e| D::Error::custom(format!("{:?}", e)))?;
 
+        let shared_counters_path = raw_config.shared_counters_path.clone().map(PathBuf::from);
+
         Ok(Self {
             region_weight,
             rate_limits,
             load_shed_limits,
             commits_per_author,
             total_file_changes,
+            shared_counters_path,
         })
     }
 }
@@ -7,9 +7,9 @@
 
 #![deny(warnings)]
 
-use std::collections::hash_map::DefaultHasher;
 use std::convert::{TryFrom, TryInto};
 use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
 use std::time::Duration;
 
 use anyhow::{anyhow, Error};
@@ -19,11 +19,14 @@ use fbinit::FacebookInit;
 use permission_checker::{MononokeIdentity, MononokeIdentitySet, MononokeIdentitySetExt};
 use stats::prelude::*;
 use thiserror::Error;
+use twox_hash::XxHash64;
 
 #[cfg(fbcode_build)]
 mod facebook;
 #[cfg(not(fbcode_build))]
 mod oss;
+#[cfg(not(fbcode_build))]
+mod token_bucket;
 
 #[cfg(fbcode_build)]
 pub use facebook::{create_rate_limiter, get_region_capacity};
@@ -100,6 +103,7 @@ pub struct MononokeRateLimitConfig {
     pub load_shed_limits: Vec<LoadShedLimit>,
     commits_per_author: RateLimitBody,
     total_file_changes: Option<RateLimitBody>,
+    pub shared_counters_path: Option<PathBuf>,
 }
 
 #[derive(Debug, Clone)]
@@ -109,7 +113,6 @@ pub struct RateLimit {
     metric: Metric,
 }
 
-#[cfg(fbcode_build)]
 impl RateLimit {
     fn applies_to_client(&self, identities: &MononokeIdentitySet) -> bool {
         match &self.target {
@@ -139,12 +142,20 @@ impl LoadShedLimit {
 
         let metric = self.raw_config.metric.to_string();
 
-        match STATS::load_shed_counter.get_value(fb, (metric.clone(),)) {
-            Some(value) if value > self.raw_config.limit => Err(RateLimitReason::LoadShedMetric(
-                metric,
-                value,
-                self.raw_config.limit,
-            )),
+        let value = STATS::load_shed_counter
+            .get_value(fb, (metric.clone(),))
+            .map(|value| value as f64);
+        // Without fb303, QPS counters are only exported by the qps crate.
+        #[cfg(not(fbcode_build))]
+        let value = value.or_else(|| qps::get_counter(&metric));
+
+        // Compare as floats, so that a fractional rate above the limit (e.g.
+        // 10.5 QPS against a limit of 10) sheds load rather than being
+        // truncated to the limit.
+        match value {
+            Some(value) if value > self.raw_config.limit as f64 => Err(
+                RateLimitReason::LoadShedMetric(metric, value.ceil() as i64, self.raw_config.limit),
+            ),
             _ => Ok(()),
         }
     }
@@ -231,7 +242,7 @@ fn in_throttled_slice(
         return false;
     };
 
-    let mut hasher = DefaultHasher::new();
+    let mut hasher = XxHash64::with_seed(0);
     hostname.hash(&mut hasher);
     nonce.hash(&mut hasher);
 
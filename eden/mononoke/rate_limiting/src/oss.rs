@@ -5,61 +5,469 @@
  * GNU General Public License version 2.
  */
 
-use std::collections::BTreeMap;
-use std::sync::Arc;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 
 use anyhow::Error;
 use async_trait::async_trait;
 use fbinit::FacebookInit;
+use once_cell::sync::Lazy;
 use permission_checker::MononokeIdentitySet;
+use stats::prelude::*;
+use twox_hash::XxHash64;
 
+use crate::token_bucket::{BucketParams, BucketTable, DEFAULT_TABLE_SLOTS};
 use crate::{
-    BoxRateLimiter, LoadCost, Metric, MononokeRateLimitConfig, RateLimitBody, RateLimitReason,
-    RateLimiter,
+    BoxRateLimiter, LoadCost, Metric, MononokeRateLimitConfig, RateLimit, RateLimitBody,
+    RateLimitReason, RateLimitStatus, RateLimiter,
 };
 
+define_stats! {
+    prefix = "mononoke.rate_limiting";
+    tracked_exceeded: dynamic_timeseries("{}.{}.tracked_exceeded", (category: String, metric: String); Rate, Sum),
+    enforced_exceeded: dynamic_timeseries("{}.{}.enforced_exceeded", (category: String, metric: String); Rate, Sum),
+    table_full: timeseries(Rate, Sum),
+    shared_counters_unavailable: timeseries(Rate, Sum),
+}
+
+/// Counters local to this process, used unless the config asks for shared
+/// counters.
+static LOCAL_BUCKETS: Lazy<Arc<BucketTable>> =
+    Lazy::new(|| Arc::new(BucketTable::local(DEFAULT_TABLE_SLOTS)));
+
+/// Shared counter files we have mapped, by path. Rate limiters are recreated
+/// whenever the config changes, so this makes sure they keep using the same
+/// mapping rather than mapping the file again each time.
+static SHARED_BUCKETS: Lazy<Mutex<HashMap<PathBuf, Arc<BucketTable>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn bucket_table(config: &MononokeRateLimitConfig) -> Arc<BucketTable> {
+    let path = match &config.shared_counters_path {
+        Some(path) => path,
+        None => return LOCAL_BUCKETS.clone(),
+    };
+
+    let mut shared = SHARED_BUCKETS.lock().expect("poisoned lock");
+    if let Some(table) = shared.get(path) {
+        return table.clone();
+    }
+
+    match BucketTable::shared(path, DEFAULT_TABLE_SLOTS) {
+        Ok(table) => {
+            let table = Arc::new(table);
+            shared.insert(path.clone(), table.clone());
+            table
+        }
+        Err(_) => {
+            // Limits still apply, just per process rather than per host.
+            // We'll try to open the file again next time the config changes.
+            STATS::shared_counters_unavailable.add_value(1);
+            LOCAL_BUCKETS.clone()
+        }
+    }
+}
+
 pub fn get_region_capacity(_datacenter_capacity: &BTreeMap<String, i32>) -> Option<i32> {
     None
 }
 
 pub fn create_rate_limiter(
-    _fb: FacebookInit,
+    fb: FacebookInit,
     category: String,
-    _config: Arc<MononokeRateLimitConfig>,
+    config: Arc<MononokeRateLimitConfig>,
 ) -> BoxRateLimiter {
-    Box::new(FakeLimiter { category })
+    Box::new(LocalLimiter {
+        fb,
+        category,
+        buckets: bucket_table(&config),
+        config,
+        client: Mutex::new(None),
+    })
 }
 
-#[derive(Debug)]
-struct FakeLimiter {
+/// A rate limiter that keeps one token bucket per (limit, client) pair.
+///
+/// Limits are applied per client: every distinct identity set gets its own
+/// bucket, holding `limit * region_weight` tokens and refilling over the
+/// limit's window. Load is charged to the client whose identities were last
+/// checked by this limiter, which is always the client of the session that
+/// owns it.
+struct LocalLimiter {
+    fb: FacebookInit,
     category: String,
+    config: Arc<MononokeRateLimitConfig>,
+    buckets: Arc<BucketTable>,
+    client: Mutex<Option<ClientKey>>,
+}
+
+#[derive(Clone)]
+struct ClientKey {
+    identities: MononokeIdentitySet,
+    hash: u64,
+}
+
+impl ClientKey {
+    fn new(identities: &MononokeIdentitySet) -> Self {
+        let mut hasher = XxHash64::with_seed(0);
+        identities.hash(&mut hasher);
+        Self {
+            identities: identities.clone(),
+            hash: hasher.finish(),
+        }
+    }
+}
+
+impl LocalLimiter {
+    fn remember_client(&self, identities: &MononokeIdentitySet) -> ClientKey {
+        let mut client = self.client.lock().expect("poisoned lock");
+        match &*client {
+            Some(key) if &key.identities == identities => key.clone(),
+            _ => {
+                let key = ClientKey::new(identities);
+                *client = Some(key.clone());
+                key
+            }
+        }
+    }
+
+    /// The limits for this metric that are active and apply to this client.
+    fn applicable_limits<'a>(
+        &'a self,
+        metric: Metric,
+        identities: &'a MononokeIdentitySet,
+    ) -> impl Iterator<Item = &'a RateLimit> + 'a {
+        self.config.rate_limits.iter().filter(move |limit| {
+            limit.metric == metric
+                && limit.body.raw_config.status != RateLimitStatus::Disabled
+                && limit.applies_to_client(identities)
+        })
+    }
+
+    /// The bucket for a limit is keyed on what the limit is rather than on
+    /// where it appears in the config, so that adding, removing or
+    /// reordering other limits doesn't hand this client someone else's
+    /// bucket. Changing the limit itself starts a fresh bucket. Processes
+    /// that share the counter file must agree on keys, so this uses a hash
+    /// that doesn't depend on how they were built.
+    fn bucket_key(&self, limit: &RateLimit, client: &ClientKey) -> u64 {
+        let mut hasher = XxHash64::with_seed(0);
+        self.category.hash(&mut hasher);
+        format!("{:?}", limit.metric).hash(&mut hasher);
+        format!("{:?}", limit.target).hash(&mut hasher);
+        limit.body.window.hash(&mut hasher);
+        limit.body.raw_config.limit.to_bits().hash(&mut hasher);
+        client.hash.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn bucket_params(&self, body: &RateLimitBody) -> BucketParams {
+        BucketParams {
+            capacity: body.raw_config.limit * self.config.region_weight,
+            window: body.window,
+        }
+    }
 }
 
 #[async_trait]
-impl RateLimiter for FakeLimiter {
+impl RateLimiter for LocalLimiter {
     async fn check_rate_limit(
         &self,
-        _metric: Metric,
-        _identities: &MononokeIdentitySet,
+        metric: Metric,
+        identities: &MononokeIdentitySet,
     ) -> Result<Result<(), RateLimitReason>, Error> {
+        let client = self.remember_client(identities);
+
+        for limit in self.applicable_limits(metric, identities) {
+            let key = self.bucket_key(limit, &client);
+            let tokens = match self
+                .buckets
+                .take(key, &self.bucket_params(&limit.body), 0.0)
+            {
+                Some(tokens) => tokens,
+                None => {
+                    STATS::table_full.add_value(1);
+                    continue;
+                }
+            };
+
+            if tokens > 0.0 {
+                continue;
+            }
+
+            match limit.body.raw_config.status {
+                RateLimitStatus::Enforced => {
+                    STATS::enforced_exceeded
+                        .add_value(1, (self.category.clone(), format!("{:?}", metric)));
+                    return Ok(Err(RateLimitReason::RateLimitedMetric(
+                        metric,
+                        limit.body.window,
+                    )));
+                }
+                _ => {
+                    STATS::tracked_exceeded
+                        .add_value(1, (self.category.clone(), format!("{:?}", metric)));
+                }
+            }
+        }
+
         Ok(Ok(()))
     }
 
-    fn check_load_shed(&self, _identities: &MononokeIdentitySet) -> Result<(), RateLimitReason> {
+    fn check_load_shed(&self, identities: &MononokeIdentitySet) -> Result<(), RateLimitReason> {
+        self.remember_client(identities);
+
+        for limit in &self.config.load_shed_limits {
+            if let Err(reason) = limit.should_load_shed(self.fb, Some(identities)) {
+                if limit.raw_config.status == RateLimitStatus::Enforced {
+                    return Err(reason);
+                }
+            }
+        }
+
         Ok(())
     }
 
-    fn bump_load(&self, _metric: Metric, _load: LoadCost) {}
+    fn bump_load(&self, metric: Metric, load: LoadCost) {
+        let client = match &*self.client.lock().expect("poisoned lock") {
+            Some(client) => client.clone(),
+            // We've never been told who the client is, so there is no
+            // bucket to charge.
+            None => return,
+        };
+
+        for limit in self.applicable_limits(metric, &client.identities) {
+            let key = self.bucket_key(limit, &client);
+            if self
+                .buckets
+                .take(key, &self.bucket_params(&limit.body), load)
+                .is_none()
+            {
+                STATS::table_full.add_value(1);
+            }
+        }
+    }
 
     fn category(&self) -> &str {
         &self.category
     }
 
     fn commits_per_author_limit(&self) -> Option<RateLimitBody> {
-        None
+        match self.config.commits_per_author.raw_config.status {
+            RateLimitStatus::Disabled => None,
+            _ => Some(self.config.commits_per_author.clone()),
+        }
     }
 
     fn total_file_changes_limit(&self) -> Option<RateLimitBody> {
-        None
+        self.config
+            .total_file_changes
+            .as_ref()
+            .filter(|body| body.raw_config.status != RateLimitStatus::Disabled)
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::time::Duration;
+
+    use permission_checker::MononokeIdentity;
+
+    use crate::Target;
+
+    fn body(status: RateLimitStatus, limit: f64) -> RateLimitBody {
+        RateLimitBody {
+            raw_config: rate_limiting_config::RateLimitBody {
+                status,
+                limit,
+                window: 3600,
+            },
+            window: Duration::from_secs(3600),
+        }
+    }
+
+    fn limiter(
+        fb: FacebookInit,
+        category: &str,
+        rate_limits: Vec<RateLimit>,
+    ) -> (LocalLimiter, Arc<BucketTable>) {
+        let buckets = Arc::new(BucketTable::local(1024));
+        let config = MononokeRateLimitConfig {
+            region_weight: 1.0,
+            rate_limits,
+            load_shed_limits: vec![],
+            commits_per_author: body(RateLimitStatus::Disabled, 0.0),
+            total_file_changes: None,
+            shared_counters_path: None,
+        };
+        let limiter = LocalLimiter {
+            fb,
+            category: category.to_string(),
+            config: Arc::new(config),
+            buckets: buckets.clone(),
+            client: Mutex::new(None),
+        };
+        (limiter, buckets)
+    }
+
+    fn idents(user: &str) -> MononokeIdentitySet {
+        let mut idents = MononokeIdentitySet::new();
+        idents.insert(MononokeIdentity::new("USER", user).unwrap());
+        idents
+    }
+
+    #[fbinit::test]
+    async fn test_enforced_limit(fb: FacebookInit) -> Result<(), Error> {
+        let (limiter, _) = limiter(
+            fb,
+            "test",
+            vec![RateLimit {
+                body: body(RateLimitStatus::Enforced, 10.0),
+                target: None,
+                metric: Metric::Commits,
+            }],
+        );
+        let foo = idents("foo");
+
+        assert!(limiter
+            .check_rate_limit(Metric::Commits, &foo)
+            .await?
+            .is_ok());
+        limiter.bump_load(Metric::Commits, 20.0);
+        assert!(limiter
+            .check_rate_limit(Metric::Commits, &foo)
+            .await?
+            .is_err());
+
+        // Other metrics are unaffected.
+        assert!(limiter
+            .check_rate_limit(Metric::EgressBytes, &foo)
+            .await?
+            .is_ok());
+
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_limits_are_per_client(fb: FacebookInit) -> Result<(), Error> {
+        let rate_limits = vec![RateLimit {
+            body: body(RateLimitStatus::Enforced, 5.0),
+            target: None,
+            metric: Metric::GetpackFiles,
+        }];
+        let (heavy, buckets) = limiter(fb, "test", rate_limits);
+        let light = LocalLimiter {
+            fb,
+            category: "test".to_string(),
+            config: heavy.config.clone(),
+            buckets,
+            client: Mutex::new(None),
+        };
+
+        assert!(heavy
+            .check_rate_limit(Metric::GetpackFiles, &idents("heavy"))
+            .await?
+            .is_ok());
+        heavy.bump_load(Metric::GetpackFiles, 100.0);
+
+        assert!(heavy
+            .check_rate_limit(Metric::GetpackFiles, &idents("heavy"))
+            .await?
+            .is_err());
+        assert!(light
+            .check_rate_limit(Metric::GetpackFiles, &idents("light"))
+            .await?
+            .is_ok());
+
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_tracked_and_targeted_limits(fb: FacebookInit) -> Result<(), Error> {
+        let (limiter, _) = limiter(
+            fb,
+            "test",
+            vec![
+                RateLimit {
+                    body: body(RateLimitStatus::Tracked, 1.0),
+                    target: None,
+                    metric: Metric::TotalManifests,
+                },
+                RateLimit {
+                    body: body(RateLimitStatus::Enforced, 1.0),
+                    target: Some(Target::Identity(
+                        MononokeIdentity::new("USER", "bar").unwrap(),
+                    )),
+                    metric: Metric::TotalManifests,
+                },
+            ],
+        );
+        let foo = idents("foo");
+
+        assert!(limiter
+            .check_rate_limit(Metric::TotalManifests, &foo)
+            .await?
+            .is_ok());
+        limiter.bump_load(Metric::TotalManifests, 10.0);
+        // Only the tracked limit applies to foo, so it is not throttled.
+        assert!(limiter
+            .check_rate_limit(Metric::TotalManifests, &foo)
+            .await?
+            .is_ok());
+
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_buckets_follow_limits(fb: FacebookInit) -> Result<(), Error> {
+        let commits = RateLimit {
+            body: body(RateLimitStatus::Enforced, 5.0),
+            target: None,
+            metric: Metric::Commits,
+        };
+        let other = RateLimit {
+            body: body(RateLimitStatus::Enforced, 5.0),
+            target: Some(Target::Identity(
+                MononokeIdentity::new("USER", "foo").unwrap(),
+            )),
+            metric: Metric::Commits,
+        };
+        let (before, buckets) = limiter(fb, "test", vec![commits.clone()]);
+        let foo = idents("foo");
+
+        assert!(before
+            .check_rate_limit(Metric::Commits, &foo)
+            .await?
+            .is_ok());
+        before.bump_load(Metric::Commits, 10.0);
+
+        // A config update puts another limit in front of this one: foo must
+        // still be throttled by it, while the new limit starts out full.
+        let config = MononokeRateLimitConfig {
+            rate_limits: vec![other.clone(), commits.clone()],
+            ..(*before.config).clone()
+        };
+        let after = LocalLimiter {
+            fb,
+            category: "test".to_string(),
+            config: Arc::new(config),
+            buckets,
+            client: Mutex::new(None),
+        };
+        let client = ClientKey::new(&foo);
+        assert_ne!(
+            after.bucket_key(&other, &client),
+            after.bucket_key(&commits, &client)
+        );
+        assert!(after
+            .check_rate_limit(Metric::Commits, &foo)
+            .await?
+            .is_err());
+
+        Ok(())
     }
 }
@@ -0,0 +1,248 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Token buckets for the local rate limiter.
+//!
+//! Buckets are kept in a `SlotTable`, either private to this process or
+//! shared by the Mononoke processes on this host.
+
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Error;
+use slot_table::{SlotData, SlotTable};
+
+/// Number of slots in a bucket table. Each slot is 24 bytes.
+pub const DEFAULT_TABLE_SLOTS: usize = 16384;
+
+/// Written into the header of shared counter files so we don't map
+/// something that isn't ours.
+const SHARED_TABLE_MAGIC: u64 = 0x6d6f_6e6f_726c_0001;
+
+#[repr(C)]
+struct Bucket {
+    /// Refill window of this bucket, used to decide when it has been idle
+    /// long enough to be reused for another key.
+    window_ms: AtomicU64,
+    /// Packed `BucketState`.
+    state: AtomicU64,
+}
+
+// SAFETY: `Bucket` is `repr(C)` and made only of atomics.
+unsafe impl SlotData for Bucket {}
+
+/// Parameters of a single bucket: it holds up to `capacity` tokens and
+/// refills completely over `window`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BucketParams {
+    pub capacity: f64,
+    pub window: Duration,
+}
+
+/// The state of a bucket, packed into 64 bits so that it can be updated with
+/// a single atomic operation. The high half is the time of the last update
+/// in (wrapping) milliseconds, the low half is the number of tokens left as
+/// an `f32`. Tokens may go negative when a client is charged for more load
+/// than it had tokens for: it then has to wait for the debt to refill.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct BucketState {
+    updated_ms: u32,
+    tokens: f32,
+}
+
+impl BucketState {
+    fn unpack(packed: u64) -> Self {
+        Self {
+            updated_ms: (packed >> 32) as u32,
+            tokens: f32::from_bits(packed as u32),
+        }
+    }
+
+    fn pack(self) -> u64 {
+        (u64::from(self.updated_ms) << 32) | u64::from(self.tokens.to_bits())
+    }
+
+    fn refill(self, params: &BucketParams, now_ms: u32) -> Self {
+        let window_ms = params.window.as_millis().max(1) as f64;
+        let elapsed_ms = f64::from(now_ms.wrapping_sub(self.updated_ms));
+        let tokens = f64::from(self.tokens) + elapsed_ms * params.capacity / window_ms;
+
+        Self {
+            updated_ms: now_ms,
+            tokens: tokens.min(params.capacity) as f32,
+        }
+    }
+}
+
+fn now_ms() -> u32 {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    // Wrapping is fine: we only ever look at differences between timestamps.
+    since_epoch.as_millis() as u32
+}
+
+/// A table of token buckets, either private to this process or shared with
+/// other processes through a memory-mapped file.
+pub struct BucketTable {
+    table: SlotTable<Bucket>,
+}
+
+impl BucketTable {
+    /// Create a table whose counters are only visible to this process.
+    pub fn local(num_slots: usize) -> Self {
+        Self {
+            table: SlotTable::local(num_slots),
+        }
+    }
+
+    /// Create (or open) a table backed by the file at `path`. Every process
+    /// that opens the same path shares the same counters.
+    pub fn shared(path: &Path, num_slots: usize) -> Result<Self, Error> {
+        Ok(Self {
+            table: SlotTable::shared(path, num_slots, SHARED_TABLE_MAGIC)?,
+        })
+    }
+
+    /// Find the bucket for `key`, starting a full one if the key isn't in
+    /// the table yet. Returns `None` if the table is full, in which case
+    /// callers should fail open.
+    fn find_bucket(&self, key: u64, params: &BucketParams, now_ms: u32) -> Option<&Bucket> {
+        self.table.find_slot(
+            key,
+            // A bucket that hasn't been touched for longer than its window
+            // would be full again, so dropping it is indistinguishable from
+            // keeping it.
+            |bucket| {
+                let state = BucketState::unpack(bucket.state.load(Ordering::Acquire));
+                let idle_ms = u64::from(now_ms.wrapping_sub(state.updated_ms));
+                idle_ms > bucket.window_ms.load(Ordering::Acquire)
+            },
+            |bucket| {
+                let full = BucketState {
+                    updated_ms: now_ms,
+                    tokens: params.capacity as f32,
+                };
+                bucket
+                    .window_ms
+                    .store(params.window.as_millis() as u64, Ordering::Release);
+                bucket.state.store(full.pack(), Ordering::Release);
+            },
+        )
+    }
+
+    /// Refill the bucket for `key`, take `cost` tokens from it, and return
+    /// how many tokens are left. A cost of 0 just reads the bucket. Returns
+    /// `None` if there was no room in the table for this bucket.
+    pub fn take(&self, key: u64, params: &BucketParams, cost: f64) -> Option<f64> {
+        let now_ms = now_ms();
+        let bucket = self.find_bucket(key, params, now_ms)?;
+
+        let mut current = bucket.state.load(Ordering::Acquire);
+        loop {
+            let mut next = BucketState::unpack(current).refill(params, now_ms);
+            // Don't let a client build up more debt than one full window,
+            // otherwise a single huge request could lock it out for ages.
+            next.tokens = (f64::from(next.tokens) - cost).max(-params.capacity) as f32;
+
+            match bucket.state.compare_exchange_weak(
+                current,
+                next.pack(),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return Some(f64::from(next.tokens)),
+                Err(actual) => current = actual,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn params(capacity: f64, window_secs: u64) -> BucketParams {
+        BucketParams {
+            capacity,
+            window: Duration::from_secs(window_secs),
+        }
+    }
+
+    #[test]
+    fn test_pack_roundtrip() {
+        let state = BucketState {
+            updated_ms: 0xdead_beef,
+            tokens: -12.5,
+        };
+        assert_eq!(BucketState::unpack(state.pack()), state);
+    }
+
+    #[test]
+    fn test_refill() {
+        let params = params(100.0, 10);
+        let empty = BucketState {
+            updated_ms: 1000,
+            tokens: 0.0,
+        };
+
+        assert_eq!(empty.refill(&params, 6000).tokens, 50.0);
+        assert_eq!(empty.refill(&params, 60000).tokens, 100.0);
+
+        // Refill works across wrapping of the millisecond clock.
+        let wrapped = BucketState {
+            updated_ms: u32::MAX - 999,
+            tokens: 0.0,
+        };
+        assert_eq!(wrapped.refill(&params, 4000).tokens, 50.0);
+    }
+
+    #[test]
+    fn test_take() {
+        let table = BucketTable::local(16);
+        let params = params(10.0, 3600);
+
+        assert_eq!(table.take(1, &params, 0.0), Some(10.0));
+        assert_eq!(table.take(1, &params, 4.0), Some(6.0));
+        assert_eq!(table.take(1, &params, 10.0), Some(-4.0));
+        // Debt is capped at one window's worth of tokens.
+        assert_eq!(table.take(1, &params, 100.0), Some(-10.0));
+
+        // Other keys are not affected.
+        assert_eq!(table.take(2, &params, 0.0), Some(10.0));
+    }
+
+    #[test]
+    fn test_table_full() {
+        let table = BucketTable::local(2);
+        let params = params(10.0, 3600);
+
+        assert!(table.take(1, &params, 1.0).is_some());
+        assert!(table.take(2, &params, 1.0).is_some());
+        assert!(table.take(3, &params, 1.0).is_none());
+    }
+
+    #[test]
+    fn test_shared() -> Result<(), Error> {
+        let dir = tempdir::TempDir::new("rate_limiting")?;
+        let path = dir.path().join("counters");
+        let params = params(10.0, 3600);
+
+        let first = BucketTable::shared(&path, 64)?;
+        let second = BucketTable::shared(&path, 64)?;
+
+        assert_eq!(first.take(42, &params, 3.0), Some(7.0));
+        assert_eq!(second.take(42, &params, 3.0), Some(4.0));
+        assert_eq!(first.take(42, &params, 0.0), Some(4.0));
+
+        // Opening with a different geometry is refused.
+        assert!(BucketTable::shared(&path, 128).is_err());
+
+        Ok(())
+    }
+}
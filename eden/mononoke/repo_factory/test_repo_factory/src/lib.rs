@@ -35,7 +35,7 @@ use fastlog::RootFastlog;
 use filenodes::ArcFilenodes;
 use filestore::{ArcFilestoreConfig, FilestoreConfig};
 use fsnodes::RootFsnodeId;
-use git_types::TreeHandle;
+use git_types::{MappedGitCommitId, TreeHandle};
 use maplit::hashset;
 use megarepo_mapping::MegarepoMapping;
 use memblob::Memblob;
@@ -104,6 +104,7 @@ pub fn default_test_repo_config() -> RepoConfig {
                     RootDeletedManifestId::NAME.to_string(),
                     RootUnodeManifestId::NAME.to_string(),
                     TreeHandle::NAME.to_string(),
+                    MappedGitCommitId::NAME.to_string(),
                     MappedHgChangesetId::NAME.to_string(),
                 },
                 unode_version: UnodeVersion::V2,
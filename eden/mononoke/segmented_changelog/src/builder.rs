@@ -35,6 +35,9 @@ impl SqlConstruct for SegmentedChangelogSqlConnections {
 
     const CREATION_QUERY: &'static str = include_str!("../schemas/sqlite-segmented-changelog.sql");
 
+    const POSTGRES_CREATION_QUERY: Option<&'static str> =
+        Some(include_str!("../schemas/postgres-segmented-changelog.sql"));
+
     fn from_sql_connections(connections: SqlConnections) -> Self {
         Self(connections)
     }
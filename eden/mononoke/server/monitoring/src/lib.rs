@@ -19,6 +19,9 @@ use slog::{info, Logger};
 
 use cmdlib::{args::MononokeMatches, monitoring::ReadyFlagService};
 
+#[cfg(not(fbcode_build))]
+mod oss;
+
 // TODO: Stop using this one-off for Mononoke server, and instead use the one from cmdlib.
 pub fn start_thrift_service<'a>(
     fb: FacebookInit,
@@ -30,17 +33,28 @@ pub fn start_thrift_service<'a>(
         let port = port.parse().expect("Failed to parse thrift_port as number");
         info!(logger, "Initializing thrift server on port {}", port);
 
+        #[cfg(not(fbcode_build))]
+        let logger = logger.clone();
+
         thread::Builder::new()
             .name("thrift_service".to_owned())
             .spawn(move || {
-                services::run_service_framework(
+                #[cfg(fbcode_build)]
+                let res = services::run_service_framework(
                     fb,
                     "mononoke_server",
                     port,
                     0, // Disables separate status http server
                     Box::new(service),
-                )
-                .expect("failure while running thrift service framework")
+                );
+
+                #[cfg(not(fbcode_build))]
+                let res = {
+                    let _ = fb;
+                    oss::serve_status_and_counters(logger, port, service)
+                };
+
+                res.expect("failure while running thrift service framework")
             })
             .map_err(Error::from)
     })
@@ -0,0 +1,116 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Without the fb303 service framework, serve the same information over
+//! plain HTTP: `/status` (or `/`) reports whether the server is ready, and
+//! `/counters` returns the exported counters as a JSON object.
+
+use std::convert::Infallible;
+use std::net::Ipv6Addr;
+use std::time::Duration;
+
+use anyhow::Result;
+use cmdlib::monitoring::ReadyFlagService;
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{header, Body, Method, Request, Response, StatusCode};
+use services::{Fb303Service, FbStatus};
+use slog::{debug, info, Logger};
+use tokio::net::TcpListener;
+
+/// How long a client has to send its request and read the response.
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub fn serve_status_and_counters(
+    logger: Logger,
+    port: u16,
+    service: ReadyFlagService,
+) -> Result<!> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(serve(logger, port, service))
+}
+
+async fn serve(logger: Logger, port: u16, service: ReadyFlagService) -> Result<!> {
+    let listener = TcpListener::bind((Ipv6Addr::UNSPECIFIED, port)).await?;
+    info!(
+        logger,
+        "Serving status and counters over HTTP on port {}", port
+    );
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let logger = logger.clone();
+        let service = service.clone();
+        tokio::spawn(async move {
+            let svc = service_fn(move |req| {
+                let response = handle_request(&req, &service);
+                async move { Ok::<_, Infallible>(response) }
+            });
+            let conn = Http::new()
+                .http1_only(true)
+                .http1_keep_alive(false)
+                .serve_connection(stream, svc);
+            match tokio::time::timeout(CONNECTION_TIMEOUT, conn).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => debug!(
+                    logger,
+                    "Failed to handle monitoring request from {}: {:?}", peer, e
+                ),
+                Err(_) => debug!(logger, "Monitoring request from {} timed out", peer),
+            }
+        });
+    }
+}
+
+fn handle_request(req: &Request<Body>, service: &ReadyFlagService) -> Response<Body> {
+    if req.method() != Method::GET {
+        return text_response(StatusCode::METHOD_NOT_ALLOWED, "GET only\n");
+    }
+
+    match req.uri().path() {
+        "/" | "/status" => {
+            if service.getStatus() == FbStatus::Alive {
+                text_response(StatusCode::OK, "ALIVE\n")
+            } else {
+                text_response(StatusCode::SERVICE_UNAVAILABLE, "STARTING\n")
+            }
+        }
+        "/counters" => match serde_json::to_string(&counters()) {
+            Ok(body) => response(StatusCode::OK, "application/json", body),
+            Err(e) => text_response(StatusCode::INTERNAL_SERVER_ERROR, format!("{}\n", e)),
+        },
+        _ => text_response(StatusCode::NOT_FOUND, "Not found\n"),
+    }
+}
+
+fn text_response(status: StatusCode, body: impl Into<Body>) -> Response<Body> {
+    response(status, "text/plain", body)
+}
+
+fn response(status: StatusCode, content_type: &str, body: impl Into<Body>) -> Response<Body> {
+    let mut response = Response::new(body.into());
+    *response.status_mut() = status;
+    if let Ok(content_type) = header::HeaderValue::from_str(content_type) {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, content_type);
+    }
+    response
+}
+
+fn counters() -> serde_json::Map<String, serde_json::Value> {
+    let mut counters = serde_json::Map::new();
+    for (name, value) in qps::get_counters() {
+        counters.insert(name, value.into());
+    }
+    for (name, value) in cachelib::get_counters() {
+        counters.insert(name, value.into());
+    }
+    counters
+}
@@ -9,8 +9,10 @@
 mod facebook;
 #[cfg(not(fbcode_build))]
 mod oss;
+#[cfg(not(fbcode_build))]
+mod rates;
 
 #[cfg(fbcode_build)]
 pub use facebook::Qps;
 #[cfg(not(fbcode_build))]
-pub use oss::Qps;
+pub use oss::{get_counter, get_counters, Qps};
@@ -5,21 +5,206 @@
  * GNU General Public License version 2.
  */
 
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::iter;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use anyhow::Error;
-use cached_config::ConfigStore;
+use cached_config::{ConfigHandle, ConfigStore};
 use fbinit::FacebookInit;
-pub struct Qps {}
+use once_cell::sync::Lazy;
+use qps_config::QpsConfig;
+use time_window_counter::{BoxGlobalTimeWindowCounter, GlobalTimeWindowCounterBuilder};
+
+use crate::rates::Rates;
+
+const CONFIGERATOR_QPS_CONFIG: &str = "scm/mononoke/qps/qps_config";
+
+/// The region of servers whose config doesn't name one.
+const DEFAULT_REGION: &str = "local";
+
+/// Bounds of the time windows that shared counters can be queried over.
+const MIN_TIME_WINDOW: u32 = 1;
+const MAX_TIME_WINDOW: u32 = 60;
+
+/// Request rates seen by this process, for every counter bumped by any
+/// `Qps`.
+static RATES: Lazy<Rates> = Lazy::new(Rates::new);
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
+
+/// The requests per second over the last minute for the counter with this
+/// name, as seen by this process.
+pub fn get_counter(name: &str) -> Option<f64> {
+    RATES.get(name, now())
+}
+
+/// The requests per second over the last minute for every counter, as seen
+/// by this process.
+pub fn get_counters() -> BTreeMap<String, f64> {
+    RATES.all(now())
+}
+
+/// Counts requests by the region they were proxied from.
+///
+/// Every counters config in the QPS config whose tier is this server's
+/// top-level tier gets a counter named
+/// `{prefix}:{top_level_tier}:{src_region}:{dst_region}`. Each request is
+/// counted both in this process (see `get_counters`) and in the time window
+/// counter for the config's category, which Mononoke processes on the same
+/// host can share.
+pub struct Qps {
+    fb: FacebookInit,
+    top_level_tier: String,
+    region: String,
+    config: ConfigHandle<QpsConfig>,
+    shared_counters: Mutex<HashMap<String, BoxGlobalTimeWindowCounter>>,
+}
 
 impl Qps {
     pub fn new(
-        _fb: FacebookInit,
-        _top_level_tier: String,
-        _config_store: &ConfigStore,
+        fb: FacebookInit,
+        top_level_tier: String,
+        config_store: &ConfigStore,
+    ) -> Result<Qps, Error> {
+        Self::with_region(fb, top_level_tier, DEFAULT_REGION.to_string(), config_store)
+    }
+
+    /// Count requests for a server in `region`, which is the destination
+    /// region of every request it counts.
+    pub fn with_region(
+        fb: FacebookInit,
+        top_level_tier: String,
+        region: String,
+        config_store: &ConfigStore,
     ) -> Result<Qps, Error> {
-        Ok(Self {})
+        let config: ConfigHandle<QpsConfig> =
+            config_store.get_config_handle(CONFIGERATOR_QPS_CONFIG.to_string())?;
+        Ok(Self {
+            fb,
+            top_level_tier,
+            region,
+            config,
+            shared_counters: Mutex::new(HashMap::new()),
+        })
     }
 
-    pub fn bump(&self, _region: &str) -> Result<(), Error> {
+    pub fn bump(&self, region: &str) -> Result<(), Error> {
+        let config = self.config.get();
+        let now = now();
+        let mut seen = HashSet::new();
+        let counters_configs = iter::once(&config.counters_config)
+            .chain(config.counters_configs.iter())
+            .filter(|counters_config| counters_config.top_level_tier == self.top_level_tier);
+
+        for counters_config in counters_configs {
+            let name = format!(
+                "{}:{}:{}:{}",
+                counters_config.prefix, self.top_level_tier, region, self.region
+            );
+            // The same counter may be configured more than once, e.g. while
+            // moving from `counters_config` to `counters_configs`.
+            if !seen.insert((counters_config.category.as_str(), name.clone())) {
+                continue;
+            }
+
+            RATES.bump(&name, now);
+
+            let mut shared_counters = self.shared_counters.lock().expect("poisoned lock");
+            let key = format!("{}\0{}", counters_config.category, name);
+            shared_counters
+                .entry(key)
+                .or_insert_with(|| {
+                    GlobalTimeWindowCounterBuilder::build(
+                        self.fb,
+                        &counters_config.category,
+                        &name,
+                        MIN_TIME_WINDOW,
+                        MAX_TIME_WINDOW,
+                    )
+                })
+                .bump(1.0);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cached_config::{ModificationTime, TestSource};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    const CONFIG: &str = r#"{
+        "counters_config": {
+            "category": "test",
+            "prefix": "qps_test_old",
+            "top_level_tier": "mononoke.tier"
+        },
+        "counters_configs": [
+            {"category": "test", "prefix": "qps_test_old", "top_level_tier": "mononoke.tier"},
+            {"category": "test", "prefix": "qps_test_new", "top_level_tier": "mononoke.tier"},
+            {"category": "test", "prefix": "qps_test_other", "top_level_tier": "mononoke.other"}
+        ]
+    }"#;
+
+    #[fbinit::test]
+    fn test_bump(fb: FacebookInit) -> Result<(), Error> {
+        let test_source = Arc::new(TestSource::new());
+        test_source.insert_config(
+            CONFIGERATOR_QPS_CONFIG,
+            CONFIG,
+            ModificationTime::UnixTimestamp(0),
+        );
+        let config_store = ConfigStore::new(test_source, Duration::from_millis(2), None);
+        let qps = Qps::with_region(
+            fb,
+            "mononoke.tier".to_string(),
+            "here".to_string(),
+            &config_store,
+        )?;
+
+        qps.bump("there")?;
+        qps.bump("there")?;
+        qps.bump("elsewhere")?;
+
+        let counters = get_counters()
+            .into_iter()
+            .filter(|(name, _)| name.starts_with("qps_test_"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            counters,
+            vec![
+                (
+                    "qps_test_new:mononoke.tier:elsewhere:here".to_string(),
+                    1.0 / 60.0
+                ),
+                (
+                    "qps_test_new:mononoke.tier:there:here".to_string(),
+                    2.0 / 60.0
+                ),
+                (
+                    "qps_test_old:mononoke.tier:elsewhere:here".to_string(),
+                    1.0 / 60.0
+                ),
+                (
+                    "qps_test_old:mononoke.tier:there:here".to_string(),
+                    2.0 / 60.0
+                ),
+            ]
+        );
+        assert_eq!(
+            get_counter("qps_test_new:mononoke.tier:there:here"),
+            Some(2.0 / 60.0)
+        );
+
         Ok(())
     }
 }
@@ -0,0 +1,124 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+/// Rates are averaged over this many seconds.
+const WINDOW_SECS: usize = 60;
+
+/// Events per second over the last minute, for each of a set of named
+/// counters.
+pub struct Rates {
+    windows: Mutex<HashMap<String, Window>>,
+}
+
+/// Per-second event counts for the last `WINDOW_SECS` seconds, indexed by
+/// time modulo `WINDOW_SECS`.
+struct Window {
+    buckets: [u64; WINDOW_SECS],
+    latest: u64,
+}
+
+impl Window {
+    fn new(now: u64) -> Self {
+        Self {
+            buckets: [0; WINDOW_SECS],
+            latest: now,
+        }
+    }
+
+    /// Clear the buckets for the seconds between the latest bump and `now`.
+    /// If the clock goes backwards, events are counted in the latest second.
+    fn advance(&mut self, now: u64) -> u64 {
+        if now > self.latest {
+            if now - self.latest >= WINDOW_SECS as u64 {
+                self.buckets = [0; WINDOW_SECS];
+            } else {
+                for sec in self.latest + 1..=now {
+                    self.buckets[sec as usize % WINDOW_SECS] = 0;
+                }
+            }
+            self.latest = now;
+        }
+        self.latest
+    }
+
+    fn bump(&mut self, now: u64) {
+        let now = self.advance(now);
+        self.buckets[now as usize % WINDOW_SECS] += 1;
+    }
+
+    fn rate(&mut self, now: u64) -> f64 {
+        self.advance(now);
+        self.buckets.iter().sum::<u64>() as f64 / WINDOW_SECS as f64
+    }
+}
+
+impl Rates {
+    pub fn new() -> Self {
+        Self {
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn bump(&self, name: &str, now: u64) {
+        let mut windows = self.windows.lock().expect("poisoned lock");
+        match windows.get_mut(name) {
+            Some(window) => window.bump(now),
+            None => {
+                let mut window = Window::new(now);
+                window.bump(now);
+                windows.insert(name.to_string(), window);
+            }
+        }
+    }
+
+    pub fn get(&self, name: &str, now: u64) -> Option<f64> {
+        let mut windows = self.windows.lock().expect("poisoned lock");
+        windows.get_mut(name).map(|window| window.rate(now))
+    }
+
+    pub fn all(&self, now: u64) -> BTreeMap<String, f64> {
+        let mut windows = self.windows.lock().expect("poisoned lock");
+        windows
+            .iter_mut()
+            .map(|(name, window)| (name.clone(), window.rate(now)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rates() {
+        let rates = Rates::new();
+        for _ in 0..30 {
+            rates.bump("a", 1000);
+        }
+        for _ in 0..60 {
+            rates.bump("a", 1030);
+        }
+        rates.bump("b", 1030);
+
+        assert_eq!(rates.get("a", 1030), Some(1.5));
+        assert_eq!(rates.get("b", 1030), Some(1.0 / 60.0));
+        assert_eq!(rates.get("c", 1030), None);
+
+        // Events from second 1000 drop out of the window after a minute.
+        assert_eq!(rates.get("a", 1060), Some(1.0));
+        // An old time counts as the latest one.
+        rates.bump("a", 1000);
+        assert_eq!(rates.get("a", 1060), Some(61.0 / 60.0));
+        assert_eq!(rates.get("a", 2000), Some(0.0));
+
+        let all = rates.all(1031);
+        assert_eq!(all.keys().collect::<Vec<_>>(), vec!["a", "b"]);
+    }
+}
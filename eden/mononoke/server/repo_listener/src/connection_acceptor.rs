@@ -31,7 +31,7 @@ use futures_util::compat::Stream01CompatExt;
 use futures_util::future::{AbortHandle, FutureExt};
 use futures_util::stream::{StreamExt, TryStreamExt};
 use lazy_static::lazy_static;
-use metaconfig_types::CommonConfig;
+use metaconfig_types::{CommonConfig, TrustedProxyConfig};
 use openssl::ssl::{Ssl, SslAcceptor};
 use permission_checker::{MononokeIdentity, MononokeIdentitySet};
 use rate_limiting::RateLimitEnvironment;
@@ -107,6 +107,8 @@ pub async fn connection_acceptor(
     wireproto_scuba: MononokeScubaSampleBuilder,
 ) -> Result<()> {
     let enable_http_control_api = common_config.enable_http_control_api;
+    let trusted_proxy = common_config.trusted_proxy.clone();
+    let region = common_config.region.clone();
 
     let security_checker =
         ConnectionsSecurityChecker::new(fb, common_config, &repo_handlers, &root_log).await?;
@@ -119,7 +121,8 @@ pub async fn connection_acceptor(
 
     let qps = match cslb_config {
         Some(config) => Some(Arc::new(
-            Qps::new(fb, config, config_store).with_context(|| "Failed to initialize QPS")?,
+            new_qps(fb, config, region, config_store)
+                .with_context(|| "Failed to initialize QPS")?,
         )),
         None => None,
     };
@@ -137,6 +140,7 @@ pub async fn connection_acceptor(
         logger: root_log.clone(),
         edenapi,
         enable_http_control_api,
+        trusted_proxy,
         server_hostname: get_hostname().unwrap_or_else(|_| "unknown_hostname".to_string()),
         will_exit,
         config_store: config_store.clone(),
@@ -164,6 +168,20 @@ pub async fn connection_acceptor(
     }
 }
 
+/// Count requests in this server's region, if the config names one.
+fn new_qps(
+    fb: FacebookInit,
+    top_level_tier: String,
+    region: Option<String>,
+    config_store: &ConfigStore,
+) -> Result<Qps> {
+    match region {
+        #[cfg(not(fbcode_build))]
+        Some(region) => Qps::with_region(fb, top_level_tier, region, config_store),
+        _ => Qps::new(fb, top_level_tier, config_store),
+    }
+}
+
 /// Our environment for accepting connections.
 pub struct Acceptor {
     pub fb: FacebookInit,
@@ -175,6 +193,7 @@ pub struct Acceptor {
     pub logger: Logger,
     pub edenapi: EdenApi,
     pub enable_http_control_api: bool,
+    pub trusted_proxy: Option<TrustedProxyConfig>,
     pub server_hostname: String,
     pub will_exit: Arc<AtomicBool>,
     pub config_store: ConfigStore,
@@ -351,7 +370,6 @@ where
         .await
     };
 
-
     let metadata = Arc::new(metadata);
 
     let ChannelConn {
@@ -571,34 +589,17 @@ async fn try_convert_preamble_to_metadata(preamble: &Preamble, addr: IpAddr) ->
         Err(..) => Priority::Default,
     };
 
-    let identity = {
-        #[cfg(fbcode_build)]
-        {
-            // SSH Connections are either authentication via ssh certificate principals or
-            // via some form of keyboard-interactive. In the case of certificates we should always
-            // rely on these. If they are not present, we should fallback to use the unix username
-            // as the primary principal.
-            let ssh_identities = match vars.ssh_cert_principals {
-                Some(ssh_identities) => ssh_identities,
-                None => preamble
-                    .unix_name()
-                    .ok_or_else(|| anyhow!("missing username and principals from preamble"))?
-                    .to_string(),
-            };
-
-            MononokeIdentity::try_from_ssh_encoded(&ssh_identities)?
-        }
-        #[cfg(not(fbcode_build))]
-        {
-            use maplit::btreeset;
-            btreeset! { MononokeIdentity::new(
-                "USER",
-               preamble
-                    .unix_name()
-                    .ok_or_else(|| anyhow!("missing username from preamble"))?
-                    .to_string(),
-            )?}
-        }
+    // SSH Connections are either authentication via ssh certificate principals or
+    // via some form of keyboard-interactive. In the case of certificates we should always
+    // rely on these. If they are not present, we should fallback to use the unix username
+    // as the primary principal.
+    let identity = match vars.ssh_cert_principals {
+        Some(ssh_identities) => MononokeIdentity::try_from_ssh_encoded(&ssh_identities)?,
+        None => MononokeIdentity::try_from_unix_name(
+            preamble
+                .unix_name()
+                .ok_or_else(|| anyhow!("missing username and principals from preamble"))?,
+        )?,
     };
 
     Ok(Metadata::new(
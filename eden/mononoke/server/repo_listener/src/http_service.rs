@@ -199,10 +199,17 @@ where
             .header(http::header::UPGRADE, "websocket")
             .header(HEADER_WEBSOCKET_ACCEPT, websocket_key);
 
-        let metadata = try_convert_headers_to_metadata(self.conn.is_trusted, &req.headers())
-            .await
-            .context("Invalid metadata")
-            .map_err(HttpError::BadRequest)?;
+        let metadata = try_convert_headers_to_metadata(
+            self.conn.is_trusted,
+            &req.headers(),
+            #[cfg(not(fbcode_build))]
+            self.acceptor().trusted_proxy.as_ref(),
+            #[cfg(not(fbcode_build))]
+            &self.conn.identities,
+        )
+        .await
+        .context("Invalid metadata")
+        .map_err(HttpError::BadRequest)?;
 
         let zstd_level: i32 = tunables::tunables()
             .get_zstd_compression_level()
@@ -493,8 +500,250 @@ async fn try_convert_headers_to_metadata(
 
 #[cfg(not(fbcode_build))]
 async fn try_convert_headers_to_metadata(
-    _is_trusted: bool,
-    _headers: &HeaderMap<HeaderValue>,
+    is_trusted: bool,
+    headers: &HeaderMap<HeaderValue>,
+    trusted_proxy: Option<&metaconfig_types::TrustedProxyConfig>,
+    peer_identities: &permission_checker::MononokeIdentitySet,
 ) -> Result<Option<Metadata>> {
-    Ok(None)
+    use openssl::x509::X509;
+    use percent_encoding::percent_decode;
+    use permission_checker::MononokeIdentity;
+    use serde::Deserialize;
+    use session_id::generate_session_id;
+    use sshrelay::Priority;
+    use std::net::IpAddr;
+
+    const HEADER_CLIENT_INFO: &str = "x-client-info";
+
+    // The subset of the client's clientinfo that we use
+    #[derive(Deserialize)]
+    struct ClientInfo {
+        hostname: Option<String>,
+    }
+
+    // Any peer can send the headers, so they are only used from the proxy.
+    let trusted_proxy = match trusted_proxy {
+        Some(trusted_proxy)
+            if is_trusted && !trusted_proxy.identities.is_disjoint(peer_identities) =>
+        {
+            trusted_proxy
+        }
+        _ => return Ok(None),
+    };
+
+    if let (Some(encoded_cert), Some(client_address)) = (
+        headers.get(&trusted_proxy.client_cert_header),
+        headers.get(&trusted_proxy.client_ip_header),
+    ) {
+        let pem: Vec<u8> = percent_decode(encoded_cert.as_ref()).collect();
+        let cert = X509::from_pem(&pem).context("Invalid client certificate")?;
+        let identities = MononokeIdentity::try_from_x509(&cert).context("Invalid identities")?;
+
+        // Proxies append the address they received the request from, so the
+        // last address is the only one that our trusted proxy vouches for.
+        let ip_addr = client_address
+            .to_str()?
+            .rsplit(',')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .parse::<IpAddr>()
+            .context("Invalid IP Address")?;
+
+        let client_info = headers
+            .get(HEADER_CLIENT_INFO)
+            .map(|info| serde_json::from_slice::<ClientInfo>(info.as_ref()))
+            .transpose()
+            .context("Invalid client info")?;
+
+        // As with the identities forwarded by proxies in fbcode builds, the
+        // client itself has not been checked, so it is not trusted.
+        let metadata = Metadata::new(
+            Some(&generate_session_id().to_string()),
+            false,
+            identities,
+            Priority::Default,
+            headers.contains_key(HEADER_CLIENT_DEBUG),
+            Some(ip_addr),
+            None,
+            None,
+        )
+        .await;
+
+        // Prefer the hostname from DNS, as the client can claim any hostname.
+        let metadata = match (metadata.client_hostname(), client_info) {
+            (None, Some(client_info)) => metadata.set_client_hostname(client_info.hostname),
+            _ => metadata,
+        };
+
+        Ok(Some(metadata))
+    } else {
+        Ok(None)
+    }
+}
+
+#[cfg(all(test, not(fbcode_build)))]
+mod test {
+    use super::*;
+    use metaconfig_types::TrustedProxyConfig;
+    use openssl::asn1::Asn1Time;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::PKey;
+    use openssl::x509::{X509Builder, X509NameBuilder};
+    use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+    use permission_checker::{MononokeIdentity, MononokeIdentitySet};
+    use std::net::IpAddr;
+
+    const CERT_HEADER: &str = "x-client-cert";
+    const IP_HEADER: &str = "x-forwarded-for";
+
+    fn trusted_proxy() -> TrustedProxyConfig {
+        TrustedProxyConfig {
+            client_cert_header: CERT_HEADER.to_string(),
+            client_ip_header: IP_HEADER.to_string(),
+            identities: proxy_identities(),
+        }
+    }
+
+    fn proxy_identities() -> MononokeIdentitySet {
+        let mut identities = MononokeIdentitySet::new();
+        identities.insert(MononokeIdentity::new("SERVICE_IDENTITY", "proxy").unwrap());
+        identities
+    }
+
+    /// A self-signed certificate for `common_name`, percent-encoded as a
+    /// proxy would forward it.
+    fn encoded_cert(common_name: &str) -> Result<String> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+        let key = PKey::from_ec_key(EcKey::generate(&group)?)?;
+        let mut name = X509NameBuilder::new()?;
+        name.append_entry_by_nid(Nid::COMMONNAME, common_name)?;
+        let name = name.build();
+
+        let mut builder = X509Builder::new()?;
+        builder.set_version(2)?;
+        builder.set_subject_name(&name)?;
+        builder.set_issuer_name(&name)?;
+        builder.set_pubkey(&key)?;
+        builder.set_not_before(&*Asn1Time::days_from_now(0)?)?;
+        builder.set_not_after(&*Asn1Time::days_from_now(1)?)?;
+        builder.sign(&key, MessageDigest::sha256())?;
+        let pem = String::from_utf8(builder.build().to_pem()?)?;
+
+        Ok(utf8_percent_encode(&pem, NON_ALPHANUMERIC).to_string())
+    }
+
+    /// Convert headers on a connection from the trusted proxy.
+    async fn from_proxy(
+        is_trusted: bool,
+        headers: &HeaderMap<HeaderValue>,
+    ) -> Result<Option<Metadata>> {
+        try_convert_headers_to_metadata(
+            is_trusted,
+            headers,
+            Some(&trusted_proxy()),
+            &proxy_identities(),
+        )
+        .await
+    }
+
+    fn headers(values: &[(&'static str, &str)]) -> Result<HeaderMap<HeaderValue>> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in values {
+            headers.insert(*name, HeaderValue::from_str(value)?);
+        }
+        Ok(headers)
+    }
+
+    #[tokio::test]
+    async fn test_trusted_proxy() -> Result<()> {
+        let cert = encoded_cert("client.example.com")?;
+        // 192.0.2.0/24 is reserved for documentation, so it has no reverse
+        // DNS and the hostname comes from the client info.
+        let headers = headers(&[
+            (CERT_HEADER, cert.as_str()),
+            (IP_HEADER, "203.0.113.7, 192.0.2.1"),
+            ("x-client-info", r#"{"hostname": "client.example.com"}"#),
+        ])?;
+
+        let metadata = from_proxy(true, &headers).await?.expect("metadata missing");
+
+        let mut identities = MononokeIdentitySet::new();
+        identities.insert(MononokeIdentity::new(
+            "X509_SUBJECT_NAME",
+            "CN=client.example.com",
+        )?);
+        assert_eq!(metadata.identities(), &identities);
+        // Only the hop added by the trusted proxy is used
+        assert_eq!(metadata.client_ip(), Some(&"192.0.2.1".parse::<IpAddr>()?));
+        assert_eq!(metadata.client_hostname(), Some("client.example.com"));
+        assert!(!metadata.is_trusted_client());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_untrusted_proxy() -> Result<()> {
+        let cert = encoded_cert("client.example.com")?;
+        let headers = headers(&[(CERT_HEADER, cert.as_str()), (IP_HEADER, "192.0.2.1")])?;
+
+        // The connection doesn't come from a trusted proxy
+        assert!(from_proxy(false, &headers).await?.is_none());
+        // The connection comes from a trusted peer that isn't the proxy
+        let mut identities = MononokeIdentitySet::new();
+        identities.insert(MononokeIdentity::new("SERVICE_IDENTITY", "other")?);
+        assert!(try_convert_headers_to_metadata(
+            true,
+            &headers,
+            Some(&trusted_proxy()),
+            &identities
+        )
+        .await?
+        .is_none());
+        // No proxy is configured as trusted
+        assert!(
+            try_convert_headers_to_metadata(true, &headers, None, &proxy_identities())
+                .await?
+                .is_none()
+        );
+        // The proxy didn't forward a client
+        assert!(from_proxy(true, &HeaderMap::new()).await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_malformed_headers() -> Result<()> {
+        let cert = encoded_cert("client.example.com")?;
+
+        for values in [
+            vec![
+                (CERT_HEADER, "not%20a%20certificate"),
+                (IP_HEADER, "192.0.2.1"),
+            ],
+            vec![
+                (CERT_HEADER, cert.as_str()),
+                (IP_HEADER, "192.0.2.1, not-an-ip"),
+            ],
+            vec![(CERT_HEADER, cert.as_str()), (IP_HEADER, "192.0.2.1, ")],
+            vec![
+                (CERT_HEADER, cert.as_str()),
+                (IP_HEADER, "192.0.2.1"),
+                ("x-client-info", "{"),
+            ],
+        ]
+        .iter()
+        {
+            let headers = headers(values)?;
+            assert!(
+                from_proxy(true, &headers).await.is_err(),
+                "{:?} should be rejected",
+                values
+            );
+        }
+
+        Ok(())
+    }
 }
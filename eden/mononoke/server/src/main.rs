@@ -107,6 +107,9 @@ fn main(fb: FacebookInit) -> Result<()> {
     info!(root_log, "Starting up");
 
     let config = args::load_repo_configs(&config_store, &matches)?;
+    if let Some(path) = &config.common.time_window_counters_path {
+        time_window_counter::init_shared_counters(root_log, path);
+    }
 
     let acceptor = {
         let cert = matches.value_of(ARG_CERT).unwrap().to_string();
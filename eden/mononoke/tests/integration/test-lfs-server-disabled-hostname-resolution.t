@@ -32,6 +32,7 @@
     "disable_compression_identities": [],
     "disable_hostname_logging": true,
     "enable_consistent_routing": false,
+    "enable_resumable_uploads": false,
     "enforce_acl_check": false,
     "enforce_authentication": false,
     "loadshedding_limits": [],
@@ -31,6 +31,7 @@
     "disable_compression_identities": [],
     "disable_hostname_logging": false,
     "enable_consistent_routing": false,
+    "enable_resumable_uploads": false,
     "enforce_acl_check": false,
     "enforce_authentication": false,
     "loadshedding_limits": [],
@@ -64,6 +65,7 @@
     "disable_compression_identities": [],
     "disable_hostname_logging": false,
     "enable_consistent_routing": false,
+    "enable_resumable_uploads": false,
     "enforce_acl_check": false,
     "enforce_authentication": false,
     "loadshedding_limits": [],
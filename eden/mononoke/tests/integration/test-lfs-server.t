@@ -77,6 +77,17 @@
   $ curl "${lfs_uri}/download/d28548bc21aabf04d143886d717d72375e3deecd0dafb3d110676b70a192cb5d" -sf --range 2048-2049 -o chunk2
   [22]
 
+  $ curl "${lfs_uri}/download/d28548bc21aabf04d143886d717d72375e3deecd0dafb3d110676b70a192cb5d" -sf --range -37 -o chunk3
+  $ wc -c chunk3
+  37 chunk3
+
+# Conditional downloads
+
+  $ curl "${lfs_uri}/download/d28548bc21aabf04d143886d717d72375e3deecd0dafb3d110676b70a192cb5d" -s -o /dev/null -w "%{http_code}\n" -H 'If-None-Match: "d28548bc21aabf04d143886d717d72375e3deecd0dafb3d110676b70a192cb5d"'
+  304
+  $ curl "${lfs_uri}/download/d28548bc21aabf04d143886d717d72375e3deecd0dafb3d110676b70a192cb5d" -s -o /dev/null -w "%{http_code}\n" -H 'If-None-Match: "foo"'
+  200
+
   $ cat > request <<EOF
   > {
   > "operation": "download",
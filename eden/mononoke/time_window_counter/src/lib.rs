@@ -9,9 +9,13 @@
 mod facebook;
 #[cfg(not(fbcode_build))]
 mod oss;
+#[cfg(not(fbcode_build))]
+mod window_table;
 
 use anyhow::Result;
 use async_trait::async_trait;
+use slog::Logger;
+use std::path::Path;
 use std::sync::Arc;
 
 pub type ArcGlobalTimeWindowCounter = Arc<dyn GlobalTimeWindowCounter + Send + Sync + 'static>;
@@ -25,3 +29,18 @@ pub trait GlobalTimeWindowCounter {
 }
 
 pub struct GlobalTimeWindowCounterBuilder;
+
+/// Share the counters of this process with the other processes on this host
+/// that use the same `shared_counters_path`. This must be called before any
+/// counter is built, otherwise counters stay local to this process.
+pub fn init_shared_counters(logger: &Logger, shared_counters_path: &Path) {
+    #[cfg(not(fbcode_build))]
+    {
+        oss::init_shared_counters(logger, shared_counters_path);
+    }
+    #[cfg(fbcode_build)]
+    {
+        // Counters are always global in fbcode.
+        let _ = (logger, shared_counters_path);
+    }
+}
@@ -5,31 +5,133 @@
  * GNU General Public License version 2.
  */
 
-use anyhow::Result;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use fbinit::FacebookInit;
+use once_cell::sync::OnceCell;
+use slog::{info, warn, Logger};
+use stats::prelude::*;
 
+use crate::window_table::{WindowParams, WindowTable, DEFAULT_TABLE_SLOTS};
 use crate::{BoxGlobalTimeWindowCounter, GlobalTimeWindowCounter, GlobalTimeWindowCounterBuilder};
 
-struct AlwaysZeroCounter {}
+define_stats! {
+    prefix = "mononoke.time_window_counter";
+    table_full: timeseries(Rate, Sum),
+}
+
+/// The counters of this process: local ones unless `init_shared_counters`
+/// was called before the first counter was built.
+static COUNTERS: OnceCell<WindowTable> = OnceCell::new();
+
+fn counters() -> &'static WindowTable {
+    COUNTERS.get_or_init(|| WindowTable::local(DEFAULT_TABLE_SLOTS))
+}
+
+pub fn init_shared_counters(logger: &Logger, shared_counters_path: &Path) {
+    let table = match WindowTable::shared(shared_counters_path, DEFAULT_TABLE_SLOTS) {
+        Ok(table) => table,
+        Err(e) => {
+            warn!(
+                logger,
+                "Failed to open shared time window counters at {}, using local counters: {:?}",
+                shared_counters_path.display(),
+                e
+            );
+            return;
+        }
+    };
+
+    if COUNTERS.set(table).is_err() {
+        warn!(
+            logger,
+            "Time window counters are already in use, not sharing them through {}",
+            shared_counters_path.display()
+        );
+    } else {
+        info!(
+            logger,
+            "Sharing time window counters through {}",
+            shared_counters_path.display()
+        );
+    }
+}
+
+/// A counter kept in this process, or in a table shared by the Mononoke
+/// processes on this host. Counters with the same category, key and windows
+/// are the same counter, no matter how many times they are built.
+struct LocalCounter {
+    table: &'static WindowTable,
+    key: u64,
+    params: WindowParams,
+}
 
 #[async_trait]
-impl GlobalTimeWindowCounter for AlwaysZeroCounter {
-    async fn get(&self, _time_window: u32) -> Result<f64> {
-        Ok(0.0)
+impl GlobalTimeWindowCounter for LocalCounter {
+    async fn get(&self, time_window: u32) -> Result<f64> {
+        self.table
+            .get(self.key, &self.params, time_window)
+            .ok_or_else(|| {
+                STATS::table_full.add_value(1);
+                anyhow!("Time window counter table is full")
+            })
     }
 
-    fn bump(&self, _value: f64) {}
+    fn bump(&self, value: f64) {
+        if !self.table.bump(self.key, &self.params, value) {
+            STATS::table_full.add_value(1);
+        }
+    }
 }
 
 impl GlobalTimeWindowCounterBuilder {
     pub fn build(
         _fb: FacebookInit,
-        _category: impl AsRef<str>,
-        _key: impl AsRef<str>,
-        _min_time_window: u32,
-        _max_time_window: u32,
+        category: impl AsRef<str>,
+        key: impl AsRef<str>,
+        min_time_window: u32,
+        max_time_window: u32,
     ) -> BoxGlobalTimeWindowCounter {
-        Box::new(AlwaysZeroCounter {})
+        let params = WindowParams {
+            min_time_window,
+            max_time_window,
+        };
+
+        let mut hasher = DefaultHasher::new();
+        category.as_ref().hash(&mut hasher);
+        key.as_ref().hash(&mut hasher);
+        min_time_window.hash(&mut hasher);
+        max_time_window.hash(&mut hasher);
+
+        Box::new(LocalCounter {
+            table: counters(),
+            key: hasher.finish(),
+            params,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[fbinit::test]
+    async fn test_counters_are_shared_by_key(fb: FacebookInit) -> Result<()> {
+        let first = GlobalTimeWindowCounterBuilder::build(fb, "test", "shared", 1, 3600);
+        let second = GlobalTimeWindowCounterBuilder::build(fb, "test", "shared", 1, 3600);
+        let other = GlobalTimeWindowCounterBuilder::build(fb, "test", "other", 1, 3600);
+
+        first.bump(2.0);
+        second.bump(3.0);
+
+        assert_eq!(first.get(60).await?, 5.0);
+        assert_eq!(second.get(60).await?, 5.0);
+        assert_eq!(other.get(60).await?, 0.0);
+
+        Ok(())
     }
 }
@@ -0,0 +1,307 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Sliding window counters for the local `GlobalTimeWindowCounter`.
+//!
+//! Each counter splits its longest time window into `BUCKETS_PER_COUNTER`
+//! buckets, and bumps go into the bucket for the current time. Reading a
+//! window adds up the buckets that fall in it, so windows are rounded up to
+//! a whole number of buckets.
+//!
+//! Counters are kept in a `SlotTable`, either private to this process or
+//! shared by the Mononoke processes on this host.
+
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Error;
+use slot_table::{SlotData, SlotTable};
+
+/// Number of slots in a counter table. Each slot is 496 bytes.
+pub const DEFAULT_TABLE_SLOTS: usize = 8192;
+
+/// Number of buckets the longest window of a counter is split into.
+const BUCKETS_PER_COUNTER: usize = 60;
+
+/// Written into the header of shared counter files so we don't map
+/// something that isn't ours.
+const SHARED_TABLE_MAGIC: u64 = 0x6d6f_6e6f_7477_0001;
+
+#[repr(C)]
+struct Counter {
+    /// Width of the buckets of this counter, in seconds.
+    bucket_secs: AtomicU64,
+    /// Packed `BucketState`s, indexed by bucket number modulo
+    /// `BUCKETS_PER_COUNTER`.
+    buckets: [AtomicU64; BUCKETS_PER_COUNTER],
+}
+
+// SAFETY: `Counter` is `repr(C)` and made only of atomics.
+unsafe impl SlotData for Counter {}
+
+/// The state of a bucket, packed into 64 bits so that it can be updated with
+/// a single atomic operation. The high half is the bucket number (the time
+/// divided by the bucket width), the low half is the sum of the bumps in
+/// that bucket as an `f32`. A bucket whose number is not current holds a
+/// stale sum that is ignored.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct BucketState {
+    number: u32,
+    sum: f32,
+}
+
+impl BucketState {
+    fn unpack(packed: u64) -> Self {
+        Self {
+            number: (packed >> 32) as u32,
+            sum: f32::from_bits(packed as u32),
+        }
+    }
+
+    fn pack(self) -> u64 {
+        (u64::from(self.number) << 32) | u64::from(self.sum.to_bits())
+    }
+
+    /// How many buckets ago, counting the current one as 0, this bucket was
+    /// last written.
+    fn age(self, current: u32) -> u32 {
+        current.wrapping_sub(self.number)
+    }
+}
+
+/// Time windows of a counter, in seconds.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WindowParams {
+    pub min_time_window: u32,
+    pub max_time_window: u32,
+}
+
+impl WindowParams {
+    fn bucket_secs(&self) -> u64 {
+        let max = u64::from(self.max_time_window.max(self.min_time_window).max(1));
+        let buckets = BUCKETS_PER_COUNTER as u64;
+        (max + buckets - 1) / buckets
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// A table of sliding window counters, either private to this process or
+/// shared with other processes through a memory-mapped file.
+pub struct WindowTable {
+    table: SlotTable<Counter>,
+}
+
+impl WindowTable {
+    /// Create a table whose counters are only visible to this process.
+    pub fn local(num_slots: usize) -> Self {
+        Self {
+            table: SlotTable::local(num_slots),
+        }
+    }
+
+    /// Create (or open) a table backed by the file at `path`. Every process
+    /// that opens the same path shares the same counters.
+    pub fn shared(path: &Path, num_slots: usize) -> Result<Self, Error> {
+        Ok(Self {
+            table: SlotTable::shared(path, num_slots, SHARED_TABLE_MAGIC)?,
+        })
+    }
+
+    /// Find the counter for `key`, starting an empty one if the key isn't in
+    /// the table yet. Returns `None` if the table is full.
+    fn find_counter(&self, key: u64, params: &WindowParams, now_secs: u64) -> Option<&Counter> {
+        self.table.find_slot(
+            key,
+            // A counter none of whose buckets are in its longest window any
+            // more would read as zero, so dropping it is indistinguishable
+            // from keeping it.
+            |counter| {
+                let bucket_secs = counter.bucket_secs.load(Ordering::Acquire).max(1);
+                let number = (now_secs / bucket_secs) as u32;
+                counter.buckets.iter().all(|bucket| {
+                    let state = BucketState::unpack(bucket.load(Ordering::Acquire));
+                    state.age(number) as usize >= BUCKETS_PER_COUNTER
+                })
+            },
+            |counter| {
+                counter
+                    .bucket_secs
+                    .store(params.bucket_secs(), Ordering::Release);
+                for bucket in counter.buckets.iter() {
+                    bucket.store(0, Ordering::Release);
+                }
+            },
+        )
+    }
+
+    /// Add `value` to the counter for `key`. Returns `false` if there was
+    /// no room in the table for this counter.
+    pub fn bump(&self, key: u64, params: &WindowParams, value: f64) -> bool {
+        let now_secs = now_secs();
+        let counter = match self.find_counter(key, params, now_secs) {
+            Some(counter) => counter,
+            None => return false,
+        };
+
+        let number = (now_secs / params.bucket_secs()) as u32;
+        let bucket = &counter.buckets[number as usize % BUCKETS_PER_COUNTER];
+
+        let mut current = bucket.load(Ordering::Acquire);
+        loop {
+            let state = BucketState::unpack(current);
+            let sum = if state.number == number {
+                f64::from(state.sum) + value
+            } else {
+                value
+            };
+            let next = BucketState {
+                number,
+                sum: sum as f32,
+            };
+
+            match bucket.compare_exchange_weak(
+                current,
+                next.pack(),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return true,
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    /// Sum of the bumps of the counter for `key` over the last
+    /// `time_window` seconds, which is clamped to the windows of the
+    /// counter. Returns `None` if there was no room in the table for this
+    /// counter.
+    pub fn get(&self, key: u64, params: &WindowParams, time_window: u32) -> Option<f64> {
+        let now_secs = now_secs();
+        let counter = self.find_counter(key, params, now_secs)?;
+
+        let bucket_secs = params.bucket_secs();
+        let time_window = time_window
+            .max(params.min_time_window)
+            .min(params.max_time_window.max(params.min_time_window));
+        let window_buckets =
+            ((u64::from(time_window) + bucket_secs - 1) / bucket_secs).max(1) as u32;
+
+        let number = (now_secs / bucket_secs) as u32;
+        let sum = counter
+            .buckets
+            .iter()
+            .map(|bucket| BucketState::unpack(bucket.load(Ordering::Acquire)))
+            .filter(|state| state.age(number) < window_buckets)
+            .map(|state| f64::from(state.sum))
+            .sum();
+
+        Some(sum)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn params(min_time_window: u32, max_time_window: u32) -> WindowParams {
+        WindowParams {
+            min_time_window,
+            max_time_window,
+        }
+    }
+
+    #[test]
+    fn test_pack_roundtrip() {
+        let state = BucketState {
+            number: 0xdead_beef,
+            sum: 12.5,
+        };
+        assert_eq!(BucketState::unpack(state.pack()), state);
+    }
+
+    #[test]
+    fn test_bucket_secs() {
+        assert_eq!(params(1, 60).bucket_secs(), 1);
+        assert_eq!(params(1, 61).bucket_secs(), 2);
+        assert_eq!(params(60, 3600).bucket_secs(), 60);
+        assert_eq!(params(0, 0).bucket_secs(), 1);
+    }
+
+    #[test]
+    fn test_bump_and_get() {
+        let table = WindowTable::local(16);
+        let params = params(1, 3600);
+
+        assert_eq!(table.get(1, &params, 60), Some(0.0));
+        assert!(table.bump(1, &params, 4.0));
+        assert!(table.bump(1, &params, 2.5));
+        assert_eq!(table.get(1, &params, 60), Some(6.5));
+        // Windows are clamped to the windows of the counter.
+        assert_eq!(table.get(1, &params, 0), Some(6.5));
+        assert_eq!(table.get(1, &params, 100_000), Some(6.5));
+
+        // Other keys are not affected.
+        assert_eq!(table.get(2, &params, 60), Some(0.0));
+    }
+
+    #[test]
+    fn test_expiry() {
+        let table = WindowTable::local(16);
+        let params = params(1, 60);
+        let counter = table.find_counter(1, &params, now_secs()).unwrap();
+
+        // Pretend bumps happened 10 and 100 seconds ago.
+        let now = (now_secs() / params.bucket_secs()) as u32;
+        for (age, sum) in &[(10, 3.0), (100, 5.0)] {
+            let number = now - age;
+            counter.buckets[number as usize % BUCKETS_PER_COUNTER]
+                .store(BucketState { number, sum: *sum }.pack(), Ordering::Release);
+        }
+
+        assert_eq!(table.get(1, &params, 5), Some(0.0));
+        assert_eq!(table.get(1, &params, 30), Some(3.0));
+        assert_eq!(table.get(1, &params, 60), Some(3.0));
+    }
+
+    #[test]
+    fn test_table_full() {
+        let table = WindowTable::local(2);
+        let params = params(1, 3600);
+
+        assert!(table.bump(1, &params, 1.0));
+        assert!(table.bump(2, &params, 1.0));
+        assert!(!table.bump(3, &params, 1.0));
+        assert_eq!(table.get(3, &params, 60), None);
+    }
+
+    #[test]
+    fn test_shared() -> Result<(), Error> {
+        let dir = tempdir::TempDir::new("time_window_counter")?;
+        let path = dir.path().join("counters");
+        let params = params(1, 3600);
+
+        let first = WindowTable::shared(&path, 64)?;
+        let second = WindowTable::shared(&path, 64)?;
+
+        assert!(first.bump(42, &params, 3.0));
+        assert!(second.bump(42, &params, 4.0));
+        assert_eq!(first.get(42, &params, 60), Some(7.0));
+        assert_eq!(second.get(42, &params, 60), Some(7.0));
+
+        // Opening with a different geometry is refused.
+        assert!(WindowTable::shared(&path, 128).is_err());
+
+        Ok(())
+    }
+}
@@ -20,6 +20,7 @@ mod corpus;
 #[macro_use]
 mod graph;
 mod log;
+mod mark;
 mod pack;
 mod parse_node;
 mod progress;
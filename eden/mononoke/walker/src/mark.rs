@@ -0,0 +1,55 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use anyhow::{Context, Error};
+use mononoke_types::Timestamp;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    sync::Mutex,
+};
+
+// Line prefixes in the mark file, the format blobstore_gc's sweep reads
+const RUN_START: &str = "run_start";
+const KEY: &str = "key";
+const COMPLETE: &str = "complete";
+
+/// Records every blobstore key a walk reaches, so that a later sweep can
+/// remove keys that were not reached. Also records when each walk started,
+/// as keys written after that may be reachable without having been marked,
+/// and whether all the walks completed, as otherwise the marks are partial.
+pub struct GcMarkWriter {
+    out: Mutex<BufWriter<File>>,
+}
+
+impl GcMarkWriter {
+    pub fn create(path: &str) -> Result<Self, Error> {
+        let file = File::create(path).with_context(|| format!("Could not create {}", path))?;
+        Ok(Self {
+            out: Mutex::new(BufWriter::new(file)),
+        })
+    }
+
+    pub fn record_run_start(&self, run_start: Timestamp) -> Result<(), Error> {
+        let mut out = self.out.lock().expect("lock poisoned");
+        writeln!(out, "{}\t{}", RUN_START, run_start.timestamp_seconds())?;
+        Ok(())
+    }
+
+    pub fn record_key(&self, key: &str) -> Result<(), Error> {
+        let mut out = self.out.lock().expect("lock poisoned");
+        writeln!(out, "{}\t{}", KEY, key)?;
+        Ok(())
+    }
+
+    pub fn record_complete(&self) -> Result<(), Error> {
+        let mut out = self.out.lock().expect("lock poisoned");
+        writeln!(out, "{}", COMPLETE)?;
+        out.flush()?;
+        Ok(())
+    }
+}
@@ -7,6 +7,7 @@
 
 use crate::graph::{FileContentData, Node, NodeData, NodeType, WrappedPathHash, WrappedPathLike};
 use crate::log;
+use crate::mark::GcMarkWriter;
 use crate::pack::{PackInfo, PackInfoLogOptions, PackInfoLogger};
 use crate::progress::{
     progress_stream, report_state, ProgressOptions, ProgressReporter, ProgressReporterUnprotected,
@@ -17,9 +18,10 @@ use crate::sampling::{
     WalkSampleMapping,
 };
 use crate::setup::{
-    parse_node_types, parse_pack_info_log_args, parse_progress_args, parse_sampling_args,
-    setup_common, JobWalkParams, OutputFormat, RepoSubcommandParams, EXCLUDE_OUTPUT_NODE_TYPE_ARG,
-    INCLUDE_OUTPUT_NODE_TYPE_ARG, LIMIT_DATA_FETCH_ARG, OUTPUT_FORMAT_ARG, SCRUB,
+    parse_gc_mark_args, parse_node_types, parse_pack_info_log_args, parse_progress_args,
+    parse_sampling_args, setup_common, JobWalkParams, OutputFormat, RepoSubcommandParams,
+    EXCLUDE_OUTPUT_NODE_TYPE_ARG, INCLUDE_OUTPUT_NODE_TYPE_ARG, LIMIT_DATA_FETCH_ARG,
+    OUTPUT_FORMAT_ARG, SCRUB,
 };
 use crate::sizing::SizingSample;
 use crate::tail::walk_exact_tail;
@@ -115,6 +117,7 @@ fn loading_stream<InStream, SS, L>(
     output_node_types: HashSet<NodeType>,
     output_format: OutputFormat,
     pack_info_logger: Option<L>,
+    gc_mark_writer: Option<Arc<GcMarkWriter>>,
 ) -> impl Stream<Item = Result<(Node, Option<NodeData>, Option<ScrubStats>), Error>>
 where
     InStream: Stream<
@@ -172,9 +175,14 @@ where
         }
     })
     .try_buffer_unordered(scheduled_max)
-    .map_ok(move |(walk_key, mtime, data_opt, sample)| {
+    .and_then(move |(walk_key, mtime, data_opt, sample)| {
         let size = if let Some(sample) = sample {
             let size = ScrubStats::from(sample.as_ref());
+            if let Some(writer) = gc_mark_writer.as_ref() {
+                if let Err(e) = record_for_gc(writer, sample.as_ref()) {
+                    return future::err(e);
+                }
+            }
             if let Some(logger) = pack_info_logger.as_ref() {
                 record_for_packer(logger, &walk_key, mtime, sample);
             }
@@ -182,10 +190,19 @@ where
         } else {
             None
         };
-        (walk_key.node, data_opt, size)
+        future::ok((walk_key.node, data_opt, size))
     })
 }
 
+fn record_for_gc(writer: &GcMarkWriter, sample: Option<&ScrubSample>) -> Result<(), Error> {
+    if let Some(sample) = sample {
+        for blobstore_key in sample.data.keys() {
+            writer.record_key(blobstore_key)?;
+        }
+    }
+    Ok(())
+}
+
 fn record_for_packer<L>(
     logger: &L,
     walk_key: &WalkKeyOptPath<WrappedPathHash>,
@@ -422,6 +439,7 @@ struct ScrubCommand {
     progress_options: ProgressOptions,
     sampling_options: SamplingOptions,
     pack_info_log_options: Option<PackInfoLogOptions>,
+    gc_mark_writer: Option<Arc<GcMarkWriter>>,
     sampler: Arc<WalkSampleMapping<Node, ScrubSample>>,
 }
 
@@ -469,9 +487,12 @@ pub async fn scrub_objects<'a>(
         progress_options: parse_progress_args(&sub_m),
         sampling_options: parse_sampling_args(&sub_m, 1)?,
         pack_info_log_options: parse_pack_info_log_args(fb, &sub_m)?,
+        gc_mark_writer: parse_gc_mark_args(matches, &sub_m)?.map(Arc::new),
         sampler: component_sampler,
     };
 
+    let gc_mark_writer = command.gc_mark_writer.clone();
+
     let mut all_walks = Vec::new();
     for (sub_params, repo_params) in per_repo {
         cloned!(mut command, job_params);
@@ -481,7 +502,13 @@ pub async fn scrub_objects<'a>(
         let walk = run_one(fb, job_params, sub_params, repo_params, command);
         all_walks.push(walk);
     }
-    try_join_all(all_walks).await.map(|_| ())
+    try_join_all(all_walks).await?;
+
+    // Only a mark from complete walks is safe to sweep with
+    if let Some(writer) = gc_mark_writer {
+        writer.record_complete()?;
+    }
+    Ok(())
 }
 
 async fn run_one(
@@ -507,6 +534,9 @@ async fn run_one(
             let repo_name = repo_params.repo.name().clone();
             cloned!(ctx, repo_params.scheduled_max);
             async move |walk_output, run_start, chunk_num, checkpoint_name| {
+                if let Some(writer) = command.gc_mark_writer.as_ref() {
+                    writer.record_run_start(run_start)?;
+                }
                 let walk_progress = progress_stream(quiet, &progress_state, walk_output);
                 let loading = loading_stream(
                     command.limit_data_fetch,
@@ -518,6 +548,7 @@ async fn run_one(
                     command
                         .pack_info_log_options
                         .map(|o| o.make_logger(repo_name, run_start, chunk_num, checkpoint_name)),
+                    command.gc_mark_writer.clone(),
                 );
                 let report_sizing = progress_stream(quiet, &sizing_progress_state, loading);
 
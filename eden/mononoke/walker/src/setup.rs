@@ -9,6 +9,7 @@ use crate::blobstore;
 use crate::checkpoint::{CheckpointsByName, SqlCheckpoints};
 use crate::graph::{EdgeType, Node, NodeType, SqlShardInfo};
 use crate::log;
+use crate::mark::GcMarkWriter;
 use crate::pack::PackInfoLogOptions;
 use crate::parse_node::parse_node;
 use crate::progress::{
@@ -35,6 +36,7 @@ use cmdlib::args::{
 use derived_data::BonsaiDerivable;
 use derived_data_filenodes::FilenodesOnlyPublic;
 use derived_data_manager::BonsaiDerivable as NewBonsaiDerivable;
+use environment::Caching;
 use fbinit::FacebookInit;
 use itertools::{process_results, Itertools};
 use maplit::hashset;
@@ -142,6 +144,7 @@ const EXCLUDE_PACK_LOG_NODE_TYPE_ARG: &str = "exclude-pack-log-node-type";
 const INCLUDE_PACK_LOG_NODE_TYPE_ARG: &str = "include-pack-log-node-type";
 const PACK_LOG_SCUBA_TABLE_ARG: &str = "pack-log-scuba-table";
 const PACK_LOG_SCUBA_FILE_ARG: &str = "pack-log-scuba-file";
+const GC_MARK_FILE_ARG: &str = "gc-mark-file";
 
 const DEFAULT_VALUE_ARG: &str = "default";
 const DERIVED_VALUE_ARG: &str = "derived";
@@ -525,6 +528,22 @@ pub fn parse_pack_info_log_args(
     Ok(opt)
 }
 
+pub fn parse_gc_mark_args(
+    matches: &MononokeMatches,
+    sub_m: &ArgMatches,
+) -> Result<Option<GcMarkWriter>, Error> {
+    match sub_m.value_of(GC_MARK_FILE_ARG) {
+        Some(path) => {
+            // Blobs served from a cache never reach the sampler, so would not be marked
+            if !matches!(matches.caching(), Caching::Disabled) {
+                bail!("--{} requires caching to be disabled", GC_MARK_FILE_ARG);
+            }
+            Ok(Some(GcMarkWriter::create(path)?))
+        }
+        None => Ok(None),
+    }
+}
+
 pub fn parse_sampling_args(
     sub_m: &ArgMatches,
     default_sample_rate: u64,
@@ -632,6 +651,23 @@ pub fn setup_toplevel_app<'a, 'b>(
                 .required(false)
                 .requires(INCLUDE_PACK_LOG_NODE_TYPE_ARG)
                 .help("A log file to write Scuba pack info logs to (primarily useful in testing)"),
+        )
+        .arg(
+            Arg::with_name(GC_MARK_FILE_ARG)
+                .long(GC_MARK_FILE_ARG)
+                .takes_value(true)
+                .multiple(false)
+                .required(false)
+                .conflicts_with_all(&[
+                    SAMPLE_RATE_ARG,
+                    INCLUDE_SAMPLE_NODE_TYPE_ARG,
+                    EXCLUDE_SAMPLE_NODE_TYPE_ARG,
+                    SAMPLE_PATH_REGEX_ARG,
+                    LIMIT_DATA_FETCH_ARG,
+                    TAIL_INTERVAL_ARG,
+                    CHECKPOINT_NAME_ARG,
+                ])
+                .help("Write every blobstore key reached to this file, as the mark phase of garbage collection. Needs a complete walk of the repos with caching disabled, and marks only keys of the node types walked"),
         );
 
     let compression_benefit = setup_subcommand_args(